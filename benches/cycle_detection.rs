@@ -0,0 +1,208 @@
+//! Cycle detection and sizing throughput on synthetic graphs: `cargo bench`.
+//!
+//! Graph sizes (in pools) default to 1k and 10k and can be set with
+//! `POLYGON_BENCH_POOLS=500,2000,50000`. Uses a small std-only timing loop rather than an
+//! external harness, so the numbers are means over a fixed time budget, not full statistics.
+
+use alloy_primitives::{Address, U256};
+use polygon::common::graph::{GraphEdge, GraphManager};
+use polygon::common::pool::{LiquidityPool, PoolVariant, UniswapV2Pool, UniswapV3Pool};
+use polygon::common::solver::{SolveConfig, optimal_amount_in};
+use polygon::common::spfa::find_all_negative_cycles;
+use polygon::common::token::Token;
+use std::collections::BTreeMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// Time spent measuring each benchmark (after one warm-up run)
+const BUDGET: Duration = Duration::from_secs(2);
+
+fn main() {
+    let sizes: Vec<usize> = std::env::var("POLYGON_BENCH_POOLS")
+        .ok()
+        .map(|sizes| {
+            sizes
+                .split(',')
+                .filter_map(|s| s.trim().parse().ok())
+                .collect()
+        })
+        .unwrap_or_else(|| vec![1_000, 10_000]);
+
+    for &pools in &sizes {
+        let (mut manager, base) = synthetic_graph(pools);
+        let source = manager.node_map[&base];
+        println!(
+            "-- {pools} pools, {} tokens, {} edges",
+            manager.graph.node_count(),
+            manager.graph.edge_count()
+        );
+
+        bench("find_negative_cycle", || {
+            manager.find_negative_cycle(source)
+        });
+        bench("find_all_negative_cycles", || {
+            find_all_negative_cycles(&manager.graph, &[source])
+        });
+
+        match manager.find_negative_cycle(source) {
+            Some(cycle) => {
+                let edges: Vec<GraphEdge> =
+                    cycle.iter().map(|&e| manager.graph[e].clone()).collect();
+                let max_in = U256::from(10u128.pow(21));
+                let config = SolveConfig::default();
+                bench(&format!("optimal_amount_in ({} hops)", edges.len()), || {
+                    optimal_amount_in(&edges, max_in, &config)
+                });
+            }
+            None => println!("optimal_amount_in: no cycle through the base token, skipped"),
+        }
+
+        dispatch(pools);
+    }
+}
+
+/// Quoting every pool through the `PoolVariant` enum vs through `Box<dyn LiquidityPool>`
+fn dispatch(pools: usize) {
+    let mut rng = XorShift(0x5eed ^ pools as u64);
+    let variants: Vec<PoolVariant> = (0..pools)
+        .map(|i| {
+            let (a, b) = (token(i), token(i + 1));
+            if i % 2 == 0 {
+                PoolVariant::V2(v2_pool(&mut rng, i, a, b))
+            } else {
+                PoolVariant::V3(v3_pool(i, a, b))
+            }
+        })
+        .collect();
+    let boxed: Vec<Box<dyn LiquidityPool>> = variants
+        .iter()
+        .map(|pool| -> Box<dyn LiquidityPool> {
+            match pool {
+                PoolVariant::V2(p) => Box::new(p.clone()),
+                PoolVariant::V3(p) => Box::new(p.clone()),
+                _ => unreachable!("only V2 and V3 pools are generated"),
+            }
+        })
+        .collect();
+
+    let amount = U256::from(10u128.pow(18));
+    bench("quote all pools, enum dispatch", || {
+        variants
+            .iter()
+            .filter_map(|pool| pool.get_amount_out(black_box(amount), true).ok())
+            .fold(U256::ZERO, |sum, out| sum.wrapping_add(out))
+    });
+    bench("quote all pools, dyn dispatch", || {
+        boxed
+            .iter()
+            .filter_map(|pool| pool.get_amount_out(black_box(amount), true).ok())
+            .fold(U256::ZERO, |sum, out| sum.wrapping_add(out))
+    });
+}
+
+/// Runs `f` repeatedly for `BUDGET` and prints the mean time per run
+fn bench<T>(name: &str, mut f: impl FnMut() -> T) {
+    black_box(f());
+    let start = Instant::now();
+    let mut runs = 0u32;
+    while start.elapsed() < BUDGET {
+        black_box(f());
+        runs += 1;
+    }
+    let per_run = start.elapsed() / runs;
+    let per_sec = 1.0 / per_run.as_secs_f64();
+    println!("{name:<40} {per_run:>12.2?}/run {per_sec:>12.1} runs/s");
+}
+
+/// `pools` V2 pools between roughly `pools / 4` tokens, priced around 1:1 with noise of about a
+/// swap fee, so that some of them close profitable cycles, plus one pool of the base token
+/// mispriced by 2%. Returns the graph and the base token, which every other token trades
+/// against.
+fn synthetic_graph(pools: usize) -> (GraphManager, Address) {
+    let tokens = (pools / 4).max(8);
+    let mut rng = XorShift(0xdecaf ^ pools as u64);
+    let mut manager = GraphManager::new();
+    for i in 0..tokens {
+        manager.add_or_get_token(Token::new(token(i), format!("T{i}"), 18));
+    }
+
+    for i in 0..pools {
+        // The first `tokens - 1` pools connect every token to the base
+        let (a, b) = if i + 1 < tokens {
+            (0, i + 1)
+        } else {
+            let a = rng.below(tokens);
+            let b = (a + 1 + rng.below(tokens - 1)) % tokens;
+            (a, b)
+        };
+        let (a, b) = (token(a.min(b)), token(a.max(b)));
+        let mut pool = v2_pool(&mut rng, i, a, b);
+        if i == 0 {
+            // Guarantees a profitable cycle through the base for the sizing benchmark
+            pool.reserve1 += pool.reserve1 / 50;
+        }
+        manager.add_pool(PoolVariant::V2(pool));
+    }
+    (manager, token(0))
+}
+
+fn v2_pool(rng: &mut XorShift, i: usize, token0: Address, token1: Address) -> UniswapV2Pool {
+    let reserve = 10u128.pow(24);
+    // +-0.25%, about one swap fee
+    let noise = reserve / 10_000 * rng.below(50) as u128;
+    UniswapV2Pool {
+        address: pool_address(i),
+        token0,
+        token1,
+        reserve0: reserve,
+        reserve1: reserve - reserve / 400 + noise,
+        fee_bps: 30,
+        ..Default::default()
+    }
+}
+
+fn v3_pool(i: usize, token0: Address, token1: Address) -> UniswapV3Pool {
+    let mut tick_bitmap = BTreeMap::new();
+    tick_bitmap.insert(-600, 10i128.pow(22));
+    tick_bitmap.insert(600, -(10i128.pow(22)));
+    UniswapV3Pool {
+        address: pool_address(i),
+        token0,
+        token1,
+        fee: 3000,
+        tick_spacing: 60,
+        sqrt_price_x96: U256::from(1) << 96,
+        liquidity: 10u128.pow(22),
+        tick: 0,
+        tick_bitmap,
+        dynamic_fee: None,
+        last_updated_block: 0,
+        weight_cache: Default::default(),
+    }
+}
+
+fn token(i: usize) -> Address {
+    let mut bytes = [0u8; 20];
+    bytes[0] = 0x70;
+    bytes[12..].copy_from_slice(&(i as u64).to_be_bytes());
+    Address::from(bytes)
+}
+
+fn pool_address(i: usize) -> Address {
+    let mut bytes = [0u8; 20];
+    bytes[0] = 0x90;
+    bytes[12..].copy_from_slice(&(i as u64).to_be_bytes());
+    Address::from(bytes)
+}
+
+/// Deterministic xorshift64, so every run benchmarks the same graphs
+struct XorShift(u64);
+
+impl XorShift {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
@@ -0,0 +1,597 @@
+use crate::common::graph::GraphEdge;
+use crate::common::pool::{LiquidityPool, PoolVariant};
+use crate::common::solver::quote_route;
+use crate::common::tickmath::{MAX_SQRT_RATIO, MIN_SQRT_RATIO};
+use alloy_primitives::{Address, Bytes, I256, U256, address, aliases::U24};
+use alloy_sol_types::{SolCall, sol};
+use anyhow::{Result, anyhow, ensure};
+use std::collections::HashMap;
+
+/// Uniswap's SwapRouter02, the spender and target of V3 hops (same address on mainnet and Polygon)
+pub const V3_SWAP_ROUTER: Address = address!("68b3465833fb72A70ecDF485E0e4C7bD8665Fc45");
+
+/// The Uniswap V4 PoolManager singleton on Polygon
+pub const V4_POOL_MANAGER: Address = address!("67366782805870060151383f4bbff9dab53e5cd6");
+
+/// The Balancer V2 Vault, which holds and swaps the tokens of every Balancer pool
+pub const BALANCER_VAULT: Address = address!("BA12222222228d8Ba445958a75a0704d566BF2C8");
+
+sol! {
+    struct Call {
+        address target;
+        bytes callData;
+    }
+
+    /// The executing router: runs each call in order, from its own balance
+    function multicall(Call[] calls) external payable;
+
+    interface IERC20 {
+        function approve(address spender, uint256 amount) external returns (bool);
+        function transfer(address to, uint256 amount) external returns (bool);
+    }
+
+    interface IUniswapV2Pair {
+        function swap(uint256 amount0Out, uint256 amount1Out, address to, bytes data) external;
+    }
+
+    struct ExactInputParams {
+        bytes path;
+        address recipient;
+        uint256 amountIn;
+        uint256 amountOutMinimum;
+    }
+
+    interface ISwapRouter02 {
+        function exactInput(ExactInputParams params) external payable returns (uint256 amountOut);
+    }
+
+    struct V4PoolKey {
+        address currency0;
+        address currency1;
+        uint24 fee;
+        int24 tickSpacing;
+        address hooks;
+    }
+
+    struct SwapParams {
+        bool zeroForOne;
+        int256 amountSpecified;
+        uint160 sqrtPriceLimitX96;
+    }
+
+    interface IPoolManager {
+        function unlock(bytes data) external returns (bytes result);
+        function swap(V4PoolKey key, SwapParams params, bytes hookData)
+            external
+            returns (int256 swapDelta);
+    }
+
+    interface ICurvePool {
+        function exchange(int128 i, int128 j, uint256 dx, uint256 min_dy)
+            external
+            returns (uint256);
+    }
+
+    struct SingleSwap {
+        bytes32 poolId;
+        uint8 kind;
+        address assetIn;
+        address assetOut;
+        uint256 amount;
+        bytes userData;
+    }
+
+    struct FundManagement {
+        address sender;
+        bool fromInternalBalance;
+        address recipient;
+        bool toInternalBalance;
+    }
+
+    interface IBalancerVault {
+        function swap(
+            SingleSwap singleSwap,
+            FundManagement funds,
+            uint256 limit,
+            uint256 deadline
+        ) external payable returns (uint256);
+    }
+}
+
+/// The executor contract that runs the hops of each pool version, e.g. one router for V2/V3
+/// and another with a V4 `unlockCallback`. Versions are keyed like `PoolVariant::version`.
+#[derive(Debug, Clone)]
+pub struct RouterConfig {
+    /// Router for versions without one of their own
+    pub default: Address,
+    pub by_version: HashMap<&'static str, Address>,
+}
+
+impl RouterConfig {
+    /// Every hop through `router`
+    pub fn single(router: Address) -> Self {
+        Self {
+            default: router,
+            by_version: HashMap::new(),
+        }
+    }
+
+    /// Routes the hops through pools of `version` ("V2", "V3", ...) to `router`
+    pub fn with_router(mut self, version: &'static str, router: Address) -> Self {
+        self.by_version.insert(version, router);
+        self
+    }
+
+    pub fn router_for(&self, pool: &PoolVariant) -> Address {
+        self.by_version
+            .get(pool.version())
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// One router's share of a route: call `router` with `calldata`, a `multicall(Call[])`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouterCall {
+    pub router: Address,
+    pub calldata: Bytes,
+}
+
+/// Encodes `cycle` as `multicall(Call[])`s for routers that execute the calls in order and
+/// hold the tokens in between, one per run of consecutive hops sharing a router in `routers`
+/// (a single call with `RouterConfig::single`). Each router receives the output of its own
+/// hops and ends its run by transferring the last hop's quoted output to the next router; the
+/// last router keeps the final output and sweeps the profit. The calls must be executed in
+/// order (e.g. within one transaction).
+///
+/// Per-hop amounts come from `quote_route` at the current pool state; every hop must return at
+/// least its quoted output, except the last, which only has to return `min_out`. Funding is
+/// emitted before each hop as the pool version needs it:
+/// - V2: `transfer` to the pair (skipped when the previous V2 hop already paid into it), then
+///   the pair's `swap`
+/// - V3: `approve` for `V3_SWAP_ROUTER`, then its single-pool `exactInput` (Uniswap pools
+///   only; Algebra pools need their own router)
+/// - V4: `PoolManager.unlock` wrapping the encoded `swap` (exact input); the router's
+///   `unlockCallback` forwards it and settles the deltas, so there's nothing to approve
+/// - StableSwap: `approve` for the pool, then its `exchange`
+/// - Weighted: `approve` for `BALANCER_VAULT`, then its `swap` (GIVEN_IN, no deadline)
+pub fn encode_swap_calldata(
+    cycle: &[GraphEdge],
+    amount_in: U256,
+    min_out: U256,
+    routers: &RouterConfig,
+) -> Result<Vec<RouterCall>> {
+    ensure!(!cycle.is_empty(), "empty route");
+
+    let amounts = quote_route(cycle, amount_in)?;
+    let quoted_out = *amounts.last().expect("one amount per hop plus the input");
+    ensure!(
+        quoted_out >= min_out,
+        "route quotes {quoted_out}, below the minimum output {min_out}"
+    );
+
+    // (router, calls) per run of hops
+    let mut runs: Vec<(Address, Vec<Call>)> = Vec::new();
+    for (i, edge) in cycle.iter().enumerate() {
+        let (amount_in, quoted) = (amounts[i], amounts[i + 1]);
+        let is_last = i + 1 == cycle.len();
+        let min_out = if is_last { min_out } else { quoted };
+
+        let (token0, token1) = edge.pool.tokens();
+        let token_in = if edge.zero_for_one { token0 } else { token1 };
+
+        let recipient = routers.router_for(&edge.pool);
+        match runs.last_mut() {
+            Some((router, _)) if *router == recipient => {}
+            Some((_, calls)) => {
+                // Hand this hop's input over from the previous run's router
+                let transfer = IERC20::transferCall {
+                    to: recipient,
+                    amount: amount_in,
+                };
+                calls.push(call(token_in, transfer.abi_encode()));
+                runs.push((recipient, Vec::new()));
+            }
+            None => runs.push((recipient, Vec::with_capacity(cycle.len() * 2))),
+        }
+        let (_, calls) = runs.last_mut().expect("just pushed");
+
+        match &edge.pool {
+            PoolVariant::V2(pool) => {
+                // Consecutive V2 hops always share a router, so they are in the same run
+                let paid_by_previous = i > 0 && matches!(cycle[i - 1].pool, PoolVariant::V2(_));
+                if !paid_by_previous {
+                    let transfer = IERC20::transferCall {
+                        to: pool.address,
+                        amount: amount_in,
+                    };
+                    calls.push(call(token_in, transfer.abi_encode()));
+                }
+
+                // Chained V2 hops pay straight into the next pair, saving a transfer
+                let to = match cycle.get(i + 1).map(|next| &next.pool) {
+                    Some(PoolVariant::V2(next)) => next.address,
+                    _ => recipient,
+                };
+                let (amount0_out, amount1_out) = if edge.zero_for_one {
+                    (U256::ZERO, quoted)
+                } else {
+                    (quoted, U256::ZERO)
+                };
+                let swap = IUniswapV2Pair::swapCall {
+                    amount0Out: amount0_out,
+                    amount1Out: amount1_out,
+                    to,
+                    data: Bytes::new(),
+                };
+                calls.push(call(pool.address, swap.abi_encode()));
+            }
+            PoolVariant::V3(pool) => {
+                ensure!(
+                    pool.dynamic_fee.is_none(),
+                    "no swap router for Algebra pool {}",
+                    pool.address
+                );
+                let approve = IERC20::approveCall {
+                    spender: V3_SWAP_ROUTER,
+                    amount: amount_in,
+                };
+                calls.push(call(token_in, approve.abi_encode()));
+
+                let token_out = if edge.zero_for_one { token1 } else { token0 };
+                let params = ExactInputParams {
+                    path: v3_path(token_in, pool.fee, token_out),
+                    recipient,
+                    amountIn: amount_in,
+                    amountOutMinimum: min_out,
+                };
+                let exact_input = ISwapRouter02::exactInputCall { params };
+                calls.push(call(V3_SWAP_ROUTER, exact_input.abi_encode()));
+            }
+            PoolVariant::V4(pool) => {
+                let key = &pool.key;
+                // Negative amountSpecified means exact input in V4
+                let amount = I256::try_from(amount_in)
+                    .map_err(|_| anyhow!("amount {amount_in} overflows int256"))?;
+                let sqrt_price_limit = if edge.zero_for_one {
+                    MIN_SQRT_RATIO + U256::from(1)
+                } else {
+                    MAX_SQRT_RATIO - U256::from(1)
+                };
+                let swap = IPoolManager::swapCall {
+                    key: V4PoolKey {
+                        currency0: key.currency0,
+                        currency1: key.currency1,
+                        fee: U24::from(key.fee),
+                        tickSpacing: key.tick_spacing.try_into().map_err(|_| {
+                            anyhow!("tick spacing {} overflows int24", key.tick_spacing)
+                        })?,
+                        hooks: key.hooks,
+                    },
+                    params: SwapParams {
+                        zeroForOne: edge.zero_for_one,
+                        amountSpecified: -amount,
+                        sqrtPriceLimitX96: sqrt_price_limit.to(),
+                    },
+                    hookData: Bytes::new(),
+                };
+                let unlock = IPoolManager::unlockCall {
+                    data: swap.abi_encode().into(),
+                };
+                calls.push(call(V4_POOL_MANAGER, unlock.abi_encode()));
+            }
+            PoolVariant::Stable(pool) => {
+                let approve = IERC20::approveCall {
+                    spender: pool.address,
+                    amount: amount_in,
+                };
+                calls.push(call(token_in, approve.abi_encode()));
+
+                let (i, j) = if edge.zero_for_one { (0, 1) } else { (1, 0) };
+                let exchange = ICurvePool::exchangeCall {
+                    i,
+                    j,
+                    dx: amount_in,
+                    min_dy: min_out,
+                };
+                calls.push(call(pool.address, exchange.abi_encode()));
+            }
+            PoolVariant::Weighted(pool) => {
+                let approve = IERC20::approveCall {
+                    spender: BALANCER_VAULT,
+                    amount: amount_in,
+                };
+                calls.push(call(token_in, approve.abi_encode()));
+
+                let token_out = if edge.zero_for_one { token1 } else { token0 };
+                let swap = IBalancerVault::swapCall {
+                    singleSwap: SingleSwap {
+                        poolId: pool.pool_id,
+                        // SwapKind.GIVEN_IN
+                        kind: 0,
+                        assetIn: token_in,
+                        assetOut: token_out,
+                        amount: amount_in,
+                        userData: Bytes::new(),
+                    },
+                    funds: FundManagement {
+                        sender: recipient,
+                        fromInternalBalance: false,
+                        recipient,
+                        toInternalBalance: false,
+                    },
+                    // For GIVEN_IN the limit is the minimum output
+                    limit: min_out,
+                    deadline: U256::MAX,
+                };
+                calls.push(call(BALANCER_VAULT, swap.abi_encode()));
+            }
+        }
+    }
+
+    Ok(runs
+        .into_iter()
+        .map(|(router, calls)| RouterCall {
+            router,
+            calldata: multicallCall { calls }.abi_encode().into(),
+        })
+        .collect())
+}
+
+/// The `(token, spender)` allowances the executing contract needs for `cycle`, deduplicated,
+/// in hop order, for batching them (e.g. as max approvals) ahead of execution instead of
+/// approving per hop. `router` is the V3 router to swap through (`V3_SWAP_ROUTER` for
+/// `encode_swap_calldata`).
+///
+/// V2 pairs are paid by transfer and V4 settles through the PoolManager, so neither needs an
+/// allowance. StableSwap pools pull from the caller themselves, and Balancer pools go through
+/// `BALANCER_VAULT`.
+pub fn required_approvals(cycle: &[GraphEdge], router: Address) -> Vec<(Address, Address)> {
+    let mut approvals = Vec::new();
+    for edge in cycle {
+        let spender = match &edge.pool {
+            PoolVariant::V2(_) | PoolVariant::V4(_) => continue,
+            PoolVariant::V3(_) => router,
+            PoolVariant::Stable(pool) => pool.address,
+            PoolVariant::Weighted(_) => BALANCER_VAULT,
+        };
+        let (token0, token1) = edge.pool.tokens();
+        let token_in = if edge.zero_for_one { token0 } else { token1 };
+        if !approvals.contains(&(token_in, spender)) {
+            approvals.push((token_in, spender));
+        }
+    }
+    approvals
+}
+
+fn call(target: Address, data: Vec<u8>) -> Call {
+    Call {
+        target,
+        callData: data.into(),
+    }
+}
+
+/// A single-pool V3 path: token_in (20 bytes) | fee (3 bytes) | token_out (20 bytes)
+fn v3_path(token_in: Address, fee: u32, token_out: Address) -> Bytes {
+    let mut path = Vec::with_capacity(43);
+    path.extend_from_slice(token_in.as_slice());
+    path.extend_from_slice(&fee.to_be_bytes()[1..]);
+    path.extend_from_slice(token_out.as_slice());
+    path.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::test_util::{addr, v2_pool, v3_pool, v4_pool};
+
+    const E24: u128 = 1_000_000_000_000_000_000_000_000;
+    const ROUTER: Address = Address::repeat_byte(0xaa);
+
+    /// A -> B -> C over two V2 pairs, C -> D through V3 and D -> A through V4, all balanced
+    fn route() -> Vec<GraphEdge> {
+        let one = U256::from(1) << 96;
+        vec![
+            GraphEdge::new(
+                PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), E24, E24)),
+                true,
+            ),
+            GraphEdge::new(
+                PoolVariant::V2(v2_pool(addr(11), addr(2), addr(3), E24, E24)),
+                true,
+            ),
+            GraphEdge::new(
+                PoolVariant::V3(v3_pool(addr(12), addr(3), addr(4), one, E24)),
+                true,
+            ),
+            GraphEdge::new(
+                PoolVariant::V4(v4_pool(addr(1), addr(4), 500, one, E24)),
+                false,
+            ),
+        ]
+    }
+
+    fn decode_calls(calldata: &[u8]) -> Vec<Call> {
+        multicallCall::abi_decode(calldata).unwrap().calls
+    }
+
+    #[test]
+    fn encodes_each_hop_for_its_pool_version() {
+        let cycle = route();
+        let amount_in = U256::from(10u128.pow(20));
+        let amounts = quote_route(&cycle, amount_in).unwrap();
+        let encoded = encode_swap_calldata(
+            &cycle,
+            amount_in,
+            U256::from(1),
+            &RouterConfig::single(ROUTER),
+        )
+        .unwrap();
+        assert_eq!(encoded.len(), 1);
+        assert_eq!(encoded[0].router, ROUTER);
+
+        let calls = decode_calls(&encoded[0].calldata);
+        let targets: Vec<Address> = calls.iter().map(|call| call.target).collect();
+        assert_eq!(
+            targets,
+            [
+                addr(1),
+                addr(10),
+                addr(11),
+                addr(3),
+                V3_SWAP_ROUTER,
+                V4_POOL_MANAGER
+            ]
+        );
+
+        // The first pair is paid by transfer and pays straight into the second
+        let transfer = IERC20::transferCall::abi_decode(&calls[0].callData).unwrap();
+        assert_eq!((transfer.to, transfer.amount), (addr(10), amount_in));
+        let swap = IUniswapV2Pair::swapCall::abi_decode(&calls[1].callData).unwrap();
+        assert_eq!(
+            (swap.amount0Out, swap.amount1Out, swap.to),
+            (U256::ZERO, amounts[1], addr(11))
+        );
+        let swap = IUniswapV2Pair::swapCall::abi_decode(&calls[2].callData).unwrap();
+        assert_eq!(
+            (swap.amount0Out, swap.amount1Out, swap.to),
+            (U256::ZERO, amounts[2], ROUTER)
+        );
+
+        let approve = IERC20::approveCall::abi_decode(&calls[3].callData).unwrap();
+        assert_eq!(
+            (approve.spender, approve.amount),
+            (V3_SWAP_ROUTER, amounts[2])
+        );
+        let params = ISwapRouter02::exactInputCall::abi_decode(&calls[4].callData)
+            .unwrap()
+            .params;
+        assert_eq!(params.path, v3_path(addr(3), 3000, addr(4)));
+        assert_eq!(params.recipient, ROUTER);
+        assert_eq!(
+            (params.amountIn, params.amountOutMinimum),
+            (amounts[2], amounts[3])
+        );
+
+        let unlock = IPoolManager::unlockCall::abi_decode(&calls[5].callData).unwrap();
+        let swap = IPoolManager::swapCall::abi_decode(&unlock.data).unwrap();
+        assert_eq!(
+            (swap.key.currency0, swap.key.currency1, swap.key.fee),
+            (addr(1), addr(4), U24::from(500))
+        );
+        assert!(!swap.params.zeroForOne);
+        assert_eq!(
+            swap.params.amountSpecified,
+            -I256::try_from(amounts[3]).unwrap()
+        );
+    }
+
+    #[test]
+    fn hands_tokens_over_between_routers() {
+        let cycle = route();
+        let amount_in = U256::from(10u128.pow(20));
+        let amounts = quote_route(&cycle, amount_in).unwrap();
+        let v4_router = Address::repeat_byte(0xbb);
+        let routers = RouterConfig::single(ROUTER).with_router("V4", v4_router);
+        let encoded = encode_swap_calldata(&cycle, amount_in, U256::ZERO, &routers).unwrap();
+
+        let routers: Vec<Address> = encoded.iter().map(|call| call.router).collect();
+        assert_eq!(routers, [ROUTER, v4_router]);
+        let first = decode_calls(&encoded[0].calldata);
+        let handover = first.last().unwrap();
+        assert_eq!(handover.target, addr(4));
+        let transfer = IERC20::transferCall::abi_decode(&handover.callData).unwrap();
+        assert_eq!((transfer.to, transfer.amount), (v4_router, amounts[3]));
+
+        let second = decode_calls(&encoded[1].calldata);
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].target, V4_POOL_MANAGER);
+    }
+
+    #[test]
+    fn splits_a_v2_and_v3_cycle_between_two_routers() {
+        // A -> B through a V2 pair, back to A through V3
+        let one = U256::from(1) << 96;
+        let cycle = [
+            GraphEdge::new(
+                PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), E24, E24)),
+                true,
+            ),
+            GraphEdge::new(
+                PoolVariant::V3(v3_pool(addr(12), addr(1), addr(2), one, E24)),
+                false,
+            ),
+        ];
+        let amount_in = U256::from(10u128.pow(20));
+        let amounts = quote_route(&cycle, amount_in).unwrap();
+        let v3_router = Address::repeat_byte(0xcc);
+        let routers = RouterConfig::single(ROUTER).with_router("V3", v3_router);
+        let encoded = encode_swap_calldata(&cycle, amount_in, U256::ZERO, &routers).unwrap();
+
+        let targets: Vec<Address> = encoded.iter().map(|call| call.router).collect();
+        assert_eq!(targets, [ROUTER, v3_router]);
+
+        // The V2 router pays the pair, swaps and hands the tokens B over
+        let first = decode_calls(&encoded[0].calldata);
+        let targets: Vec<Address> = first.iter().map(|call| call.target).collect();
+        assert_eq!(targets, [addr(1), addr(10), addr(2)]);
+        let handover = IERC20::transferCall::abi_decode(&first[2].callData).unwrap();
+        assert_eq!((handover.to, handover.amount), (v3_router, amounts[1]));
+
+        // The V3 router approves and swaps them back into A for itself
+        let second = decode_calls(&encoded[1].calldata);
+        let targets: Vec<Address> = second.iter().map(|call| call.target).collect();
+        assert_eq!(targets, [addr(2), V3_SWAP_ROUTER]);
+        let params = ISwapRouter02::exactInputCall::abi_decode(&second[1].callData)
+            .unwrap()
+            .params;
+        assert_eq!(params.path, v3_path(addr(2), 3000, addr(1)));
+        assert_eq!((params.recipient, params.amountIn), (v3_router, amounts[1]));
+    }
+
+    #[test]
+    fn rejects_a_minimum_above_the_quote() {
+        let cycle = route();
+        let amount_in = U256::from(10u128.pow(20));
+        let quoted = *quote_route(&cycle, amount_in).unwrap().last().unwrap();
+        let routers = RouterConfig::single(ROUTER);
+        assert!(encode_swap_calldata(&cycle, amount_in, quoted, &routers).is_ok());
+        assert!(encode_swap_calldata(&cycle, amount_in, quoted + U256::from(1), &routers).is_err());
+        assert!(encode_swap_calldata(&[], amount_in, U256::ZERO, &routers).is_err());
+    }
+
+    #[test]
+    fn approvals_skip_v2_and_v4_hops() {
+        assert_eq!(
+            required_approvals(&route(), V3_SWAP_ROUTER),
+            [(addr(3), V3_SWAP_ROUTER)]
+        );
+    }
+
+    #[test]
+    fn approvals_collapse_repeated_tokens() {
+        // 1 -> 2 -> 1 -> 3 through three V3 pools spends token 1 twice through the same router
+        let one = U256::from(1) << 96;
+        let hop = |pool: u8, token1: u8, zero_for_one: bool| {
+            let pool = v3_pool(addr(pool), addr(1), addr(token1), one, E24);
+            GraphEdge::new(PoolVariant::V3(pool), zero_for_one)
+        };
+        let cycle = [hop(12, 2, true), hop(13, 2, false), hop(14, 3, true)];
+        assert_eq!(
+            required_approvals(&cycle, V3_SWAP_ROUTER),
+            [(addr(1), V3_SWAP_ROUTER), (addr(2), V3_SWAP_ROUTER)]
+        );
+    }
+
+    #[test]
+    fn rejects_algebra_hops() {
+        let mut cycle = route();
+        if let PoolVariant::V3(pool) = &mut cycle[2].pool {
+            pool.dynamic_fee = Some(3000);
+        }
+        let routers = RouterConfig::single(ROUTER);
+        let error = encode_swap_calldata(&cycle, U256::from(10u128.pow(20)), U256::ZERO, &routers)
+            .unwrap_err();
+        assert!(error.to_string().contains("Algebra"), "{error}");
+    }
+}
@@ -0,0 +1,89 @@
+use crate::common::token::{MAINNET_WETH, POLYGON_WMATIC};
+use alloy_primitives::{Address, address};
+
+pub const MAINNET_CHAIN_ID: u64 = 1;
+pub const POLYGON_CHAIN_ID: u64 = 137;
+pub const ARBITRUM_CHAIN_ID: u64 = 42161;
+
+/// Well-known token addresses of one chain: its wrapped native token and the main stables
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainConstants {
+    pub chain_id: u64,
+    pub wrapped_native: Address,
+    pub usdc: Address,
+    pub usdt: Address,
+    pub dai: Address,
+}
+
+pub const MAINNET: ChainConstants = ChainConstants {
+    chain_id: MAINNET_CHAIN_ID,
+    wrapped_native: MAINNET_WETH,
+    usdc: address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"),
+    usdt: address!("dAC17F958D2ee523a2206206994597C13D831ec7"),
+    dai: address!("6B175474E89094C44Da98b954EedeAC495271d0F"),
+};
+
+/// Polygon PoS; `usdc` is Circle's native USDC, not the bridged USDC.e
+pub const POLYGON: ChainConstants = ChainConstants {
+    chain_id: POLYGON_CHAIN_ID,
+    wrapped_native: POLYGON_WMATIC,
+    usdc: address!("3c499c542cEF5E3811e1192ce70d8cC03d5c3359"),
+    usdt: address!("c2132D05D31c914a87C6611C10748AEb04B58e8F"),
+    dai: address!("8f3Cf7ad23Cd3CaDbD9735AFf958023239c6A063"),
+};
+
+/// Arbitrum One; `usdc` is the native USDC, not the bridged USDC.e
+pub const ARBITRUM: ChainConstants = ChainConstants {
+    chain_id: ARBITRUM_CHAIN_ID,
+    wrapped_native: address!("82aF49447D8a07e3bd95BD0d56f35241523fBab1"),
+    usdc: address!("af88d065e77c8cC2239327C5EDb3A432268e5831"),
+    usdt: address!("Fd086bC7CD5C481DCC9C85ebE478A1C0b69FCbb9"),
+    dai: address!("DA10009cBd5D07dd0CeCc66161FC93D7c9000da1"),
+};
+
+impl ChainConstants {
+    /// The constants of a supported chain, `None` for any other chain id
+    pub fn for_chain(chain_id: u64) -> Option<Self> {
+        [MAINNET, POLYGON, ARBITRUM]
+            .into_iter()
+            .find(|chain| chain.chain_id == chain_id)
+    }
+
+    /// Whether `token` is one of the chain's USD stables
+    pub fn is_stable(&self, token: Address) -> bool {
+        [self.usdc, self.usdt, self.dai].contains(&token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::token::Token;
+
+    #[test]
+    fn supported_chains_are_found_by_id() {
+        assert_eq!(ChainConstants::for_chain(137), Some(POLYGON));
+        assert_eq!(ChainConstants::for_chain(1), Some(MAINNET));
+        assert_eq!(ChainConstants::for_chain(42161), Some(ARBITRUM));
+        assert_eq!(ChainConstants::for_chain(10), None);
+    }
+
+    #[test]
+    fn polygon_flags_wmatic_and_its_stables() {
+        let wmatic = Token::new_on_chain(
+            POLYGON_CHAIN_ID,
+            POLYGON.wrapped_native,
+            "WMATIC".into(),
+            18,
+        );
+        assert!(wmatic.is_weth);
+        // Mainnet WETH is just another token there
+        let weth = Token::new_on_chain(POLYGON_CHAIN_ID, MAINNET_WETH, "WETH".into(), 18);
+        assert!(!weth.is_weth);
+
+        assert!(POLYGON.is_stable(POLYGON.usdc) && POLYGON.is_stable(POLYGON.dai));
+        assert!(!POLYGON.is_stable(POLYGON.wrapped_native));
+        // Stables are per chain
+        assert!(!POLYGON.is_stable(MAINNET.usdc));
+    }
+}
@@ -0,0 +1,83 @@
+use alloy_primitives::{Address, B256};
+use std::fmt;
+
+/// Result of the pool math and log handling in `pool`
+pub type PoolResult<T> = std::result::Result<T, PoolError>;
+
+/// Why a pool couldn't quote a swap or apply a log.
+///
+/// Converts into `anyhow::Error` with `?`, so callers that don't care about the variant can
+/// keep using `anyhow::Result`. The messages are the same as when the pool math used `anyhow`.
+#[derive(Debug)]
+pub enum PoolError {
+    /// The pool can't pay out (or absorb) the requested amount
+    InsufficientLiquidity(String),
+    /// An amount or balance doesn't fit its integer type
+    Overflow(String),
+    /// The pool's parameters (fees, weights, precisions) or state can't be traded against
+    InvalidState(String),
+    /// An argument the pool can't quote for, e.g. a price limit on the wrong side of the price
+    InvalidInput(String),
+    /// A log whose event (topic 0) this pool type doesn't handle
+    UnknownEvent(Option<B256>),
+    /// A log of a known event whose topics or data couldn't be decoded
+    MalformedLog(anyhow::Error),
+    /// A log emitted by another contract
+    AddressMismatch { pool: Address, log: Address },
+    /// A singleton log (Balancer Vault) for another pool id
+    PoolIdMismatch { pool: B256, log: B256 },
+    /// Newton's method (StableSwap D or y) ran out of iterations
+    NoConvergence(String),
+    /// Any other failure of the shared fixed-point math
+    Math(anyhow::Error),
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolError::InsufficientLiquidity(msg)
+            | PoolError::Overflow(msg)
+            | PoolError::InvalidState(msg)
+            | PoolError::InvalidInput(msg)
+            | PoolError::NoConvergence(msg) => f.write_str(msg),
+            PoolError::UnknownEvent(Some(topic)) => write!(f, "unhandled event {topic}"),
+            PoolError::UnknownEvent(None) => f.write_str("log without topics"),
+            PoolError::MalformedLog(e) | PoolError::Math(e) => write!(f, "{e}"),
+            PoolError::AddressMismatch { pool, log } => {
+                write!(f, "log from {log} applied to pool {pool}")
+            }
+            PoolError::PoolIdMismatch { pool, log } => {
+                write!(f, "log for pool {log} applied to pool {pool}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PoolError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PoolError::MalformedLog(e) | PoolError::Math(e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+/// Errors bubbling up from `swap_math`, `tickmath` and the like
+impl From<anyhow::Error> for PoolError {
+    fn from(e: anyhow::Error) -> Self {
+        PoolError::Math(e)
+    }
+}
+
+/// `ensure!` for `PoolResult`: returns `PoolError::$variant(format!(...))` unless `$cond` holds
+macro_rules! pool_ensure {
+    ($cond:expr, $variant:ident, $($msg:tt)+) => {
+        // Bound first so `!` never applies to a float comparison (clippy's
+        // neg_cmp_op_on_partial_ord)
+        let holds: bool = $cond;
+        if !holds {
+            return Err($crate::common::error::PoolError::$variant(format!($($msg)+)));
+        }
+    };
+}
+pub(crate) use pool_ensure;
@@ -0,0 +1,456 @@
+use alloy_primitives::{Address, B256, I256, Log, U256, b256};
+use anyhow::{Result, anyhow, ensure};
+
+/// keccak256("Sync(uint112,uint112)")
+pub const V2_SYNC_EVENT: B256 =
+    b256!("1c411e9a96e071241c2f21f7726b17ae89e3cab4c78be50e062b03a9fffbbad1");
+
+/// keccak256("Swap(address,address,int256,int256,uint160,uint128,int24)")
+pub const V3_SWAP_EVENT: B256 =
+    b256!("c42079f94a6350d7e6235f29174924f928cc2ac818eb64fed8004e115fbcca67");
+/// keccak256("Mint(address,address,int24,int24,uint128,uint256,uint256)")
+pub const V3_MINT_EVENT: B256 =
+    b256!("7a53080ba414158be7ec69b987b5fb7d07dee101fe85488f0853ae16239d0bde");
+/// keccak256("Burn(address,int24,int24,uint128,uint256,uint256)")
+pub const V3_BURN_EVENT: B256 =
+    b256!("0c396cd989a39f4459b5fa1aed6a9a8dcdbc45908acfd67e028cd568da98982c");
+/// keccak256("Swap(bytes32,address,int128,int128,uint160,uint128,int24,uint24)"), emitted by
+/// the V4 PoolManager
+pub const V4_SWAP_EVENT: B256 =
+    b256!("40e9cecb9f5f1f1c5b9c97dec2917b7ee92e57ba5563708daca94dd84ad7112f");
+/// keccak256("ModifyLiquidity(bytes32,address,int24,int24,int256,bytes32)"), emitted by the V4
+/// PoolManager
+pub const V4_MODIFY_LIQUIDITY_EVENT: B256 =
+    b256!("f208f4912782fd25c7f114ca3723a2d5dd6f3bcc3ac8db5af63baa85f711d5ec");
+
+/// A V2 pair's `Sync(uint112 reserve0, uint112 reserve1)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncEvent {
+    pub reserve0: u128,
+    pub reserve1: u128,
+}
+
+/// A V3 pool's `Swap(address indexed sender, address indexed recipient, int256 amount0,
+/// int256 amount1, uint160 sqrtPriceX96, uint128 liquidity, int24 tick)`, with the pool state
+/// after the swap. Amounts are the pool's deltas: positive in, negative out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct V3SwapEvent {
+    pub sender: Address,
+    pub recipient: Address,
+    pub amount0: I256,
+    pub amount1: I256,
+    pub sqrt_price_x96: U256,
+    pub liquidity: u128,
+    pub tick: i32,
+}
+
+/// A V3 pool's `Mint(address sender, address indexed owner, int24 indexed tickLower,
+/// int24 indexed tickUpper, uint128 amount, uint256 amount0, uint256 amount1)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MintEvent {
+    pub sender: Address,
+    pub owner: Address,
+    pub tick_lower: i32,
+    pub tick_upper: i32,
+    /// Liquidity added
+    pub amount: u128,
+    pub amount0: U256,
+    pub amount1: U256,
+}
+
+/// A V3 pool's `Burn(address indexed owner, int24 indexed tickLower, int24 indexed tickUpper,
+/// uint128 amount, uint256 amount0, uint256 amount1)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BurnEvent {
+    pub owner: Address,
+    pub tick_lower: i32,
+    pub tick_upper: i32,
+    /// Liquidity removed
+    pub amount: u128,
+    pub amount0: U256,
+    pub amount1: U256,
+}
+
+/// The V4 PoolManager's `Swap(bytes32 indexed id, address indexed sender, int128 amount0,
+/// int128 amount1, uint160 sqrtPriceX96, uint128 liquidity, int24 tick, uint24 fee)`, with the
+/// pool state after the swap. Unlike V3, amounts are the swapper's deltas: negative in,
+/// positive out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct V4SwapEvent {
+    pub pool_id: B256,
+    pub sender: Address,
+    pub amount0: i128,
+    pub amount1: i128,
+    pub sqrt_price_x96: U256,
+    pub liquidity: u128,
+    pub tick: i32,
+    /// The swap fee charged, in hundredths of a bip
+    pub fee: u32,
+}
+
+/// The V4 PoolManager's `ModifyLiquidity(bytes32 indexed id, address indexed sender,
+/// int24 tickLower, int24 tickUpper, int256 liquidityDelta, bytes32 salt)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModifyLiquidityEvent {
+    pub pool_id: B256,
+    pub sender: Address,
+    pub tick_lower: i32,
+    pub tick_upper: i32,
+    /// Liquidity added (positive) or removed (negative)
+    pub liquidity_delta: I256,
+    pub salt: B256,
+}
+
+/// Decodes a V2 Sync log
+pub fn decode_sync(log: &Log) -> Result<SyncEvent> {
+    expect_event(log, V2_SYNC_EVENT, 1, "Sync")?;
+
+    // Both values are in the data section
+    let data = &log.data.data;
+    ensure!(
+        data.len() == 64,
+        "malformed Sync data ({} bytes)",
+        data.len()
+    );
+    let reserve0 = U256::from_be_slice(abi_word(data, 0)?);
+    let reserve1 = U256::from_be_slice(abi_word(data, 1)?);
+
+    Ok(SyncEvent {
+        reserve0: u128::try_from(reserve0).map_err(|_| anyhow!("reserve0 overflow"))?,
+        reserve1: u128::try_from(reserve1).map_err(|_| anyhow!("reserve1 overflow"))?,
+    })
+}
+
+/// Decodes a V3 (or Algebra) Swap log
+pub fn decode_v3_swap(log: &Log) -> Result<V3SwapEvent> {
+    expect_event(log, V3_SWAP_EVENT, 3, "Swap")?;
+    let topics = log.topics();
+    let data = &log.data.data;
+
+    Ok(V3SwapEvent {
+        sender: Address::from_word(topics[1]),
+        recipient: Address::from_word(topics[2]),
+        amount0: I256::from_be_bytes::<32>(abi_word(data, 0)?.try_into()?),
+        amount1: I256::from_be_bytes::<32>(abi_word(data, 1)?.try_into()?),
+        sqrt_price_x96: U256::from_be_slice(abi_word(data, 2)?),
+        liquidity: abi_u128(abi_word(data, 3)?),
+        tick: abi_i32(abi_word(data, 4)?),
+    })
+}
+
+/// Decodes a V3 Mint log
+pub fn decode_mint(log: &Log) -> Result<MintEvent> {
+    expect_event(log, V3_MINT_EVENT, 4, "Mint")?;
+    let topics = log.topics();
+    let data = &log.data.data;
+
+    Ok(MintEvent {
+        sender: Address::from_word(B256::from_slice(abi_word(data, 0)?)),
+        owner: Address::from_word(topics[1]),
+        tick_lower: abi_i32(&topics[2].0),
+        tick_upper: abi_i32(&topics[3].0),
+        amount: abi_u128(abi_word(data, 1)?),
+        amount0: U256::from_be_slice(abi_word(data, 2)?),
+        amount1: U256::from_be_slice(abi_word(data, 3)?),
+    })
+}
+
+/// Decodes a V3 Burn log
+pub fn decode_burn(log: &Log) -> Result<BurnEvent> {
+    expect_event(log, V3_BURN_EVENT, 4, "Burn")?;
+    let topics = log.topics();
+    let data = &log.data.data;
+
+    Ok(BurnEvent {
+        owner: Address::from_word(topics[1]),
+        tick_lower: abi_i32(&topics[2].0),
+        tick_upper: abi_i32(&topics[3].0),
+        amount: abi_u128(abi_word(data, 0)?),
+        amount0: U256::from_be_slice(abi_word(data, 1)?),
+        amount1: U256::from_be_slice(abi_word(data, 2)?),
+    })
+}
+
+/// Decodes a V4 PoolManager Swap log
+pub fn decode_v4_swap(log: &Log) -> Result<V4SwapEvent> {
+    expect_event(log, V4_SWAP_EVENT, 3, "Swap")?;
+    let topics = log.topics();
+    let data = &log.data.data;
+
+    Ok(V4SwapEvent {
+        pool_id: topics[1],
+        sender: Address::from_word(topics[2]),
+        amount0: abi_u128(abi_word(data, 0)?) as i128,
+        amount1: abi_u128(abi_word(data, 1)?) as i128,
+        sqrt_price_x96: U256::from_be_slice(abi_word(data, 2)?),
+        liquidity: abi_u128(abi_word(data, 3)?),
+        tick: abi_i32(abi_word(data, 4)?),
+        fee: abi_u128(abi_word(data, 5)?) as u32,
+    })
+}
+
+/// Decodes a V4 PoolManager ModifyLiquidity log
+pub fn decode_modify_liquidity(log: &Log) -> Result<ModifyLiquidityEvent> {
+    expect_event(log, V4_MODIFY_LIQUIDITY_EVENT, 3, "ModifyLiquidity")?;
+    let topics = log.topics();
+    let data = &log.data.data;
+
+    Ok(ModifyLiquidityEvent {
+        pool_id: topics[1],
+        sender: Address::from_word(topics[2]),
+        tick_lower: abi_i32(abi_word(data, 0)?),
+        tick_upper: abi_i32(abi_word(data, 1)?),
+        liquidity_delta: I256::from_be_bytes::<32>(abi_word(data, 2)?.try_into()?),
+        salt: B256::from_slice(abi_word(data, 3)?),
+    })
+}
+
+/// Checks that `log` is a `name` event with the expected number of topics (signature included)
+fn expect_event(log: &Log, signature: B256, topics: usize, name: &str) -> Result<()> {
+    ensure!(
+        log.topics().first() == Some(&signature),
+        "not a {name} event"
+    );
+    ensure!(
+        log.topics().len() == topics,
+        "malformed {name} topics ({} instead of {topics})",
+        log.topics().len()
+    );
+    Ok(())
+}
+
+// --- ABI helpers for decoding event data ---
+
+/// Returns the `index`-th 32-byte word of ABI-encoded data
+pub(crate) fn abi_word(data: &[u8], index: usize) -> Result<&[u8]> {
+    data.get(index * 32..(index + 1) * 32)
+        .ok_or_else(|| anyhow!("event data too short for word {index}"))
+}
+
+/// Decodes a uint128 (or smaller) from a word
+pub(crate) fn abi_u128(word: &[u8]) -> u128 {
+    u128::from_be_bytes(word[16..32].try_into().unwrap())
+}
+
+/// Decodes a sign-extended int24 from a word
+pub(crate) fn abi_i32(word: &[u8]) -> i32 {
+    i32::from_be_bytes(word[28..32].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::{address, hex, keccak256};
+
+    /// The USDC/WETH 0.05% pool, the Universal Router and the V3 position manager
+    const POOL: Address = address!("88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640");
+    const ROUTER: Address = address!("3fC91A3afd70395Cd496C647d5a6CC9D4B2b7FAD");
+    const POSITION_MANAGER: Address = address!("C36442b4a4522E871399CD717aBDD847Ab11FE88");
+
+    /// A log as an RPC node returns it: hex topics and data, one 32-byte word per entry
+    fn fixture(address: Address, topics: &[B256], words: &[&str]) -> Log {
+        let data: Vec<u8> = words
+            .iter()
+            .flat_map(|word| hex::decode(word).expect("hex word"))
+            .collect();
+        Log::new_unchecked(address, topics.to_vec(), data.into())
+    }
+
+    fn topic(word: &str) -> B256 {
+        B256::from_slice(&hex::decode(word).expect("hex word"))
+    }
+
+    #[test]
+    fn signatures_are_the_event_hashes() {
+        let events = [
+            ("Sync(uint112,uint112)", V2_SYNC_EVENT),
+            (
+                "Swap(address,address,int256,int256,uint160,uint128,int24)",
+                V3_SWAP_EVENT,
+            ),
+            (
+                "Mint(address,address,int24,int24,uint128,uint256,uint256)",
+                V3_MINT_EVENT,
+            ),
+            (
+                "Burn(address,int24,int24,uint128,uint256,uint256)",
+                V3_BURN_EVENT,
+            ),
+            (
+                "Swap(bytes32,address,int128,int128,uint160,uint128,int24,uint24)",
+                V4_SWAP_EVENT,
+            ),
+            (
+                "ModifyLiquidity(bytes32,address,int24,int24,int256,bytes32)",
+                V4_MODIFY_LIQUIDITY_EVENT,
+            ),
+        ];
+        for (signature, hash) in events {
+            assert_eq!(keccak256(signature), hash, "{signature}");
+        }
+    }
+
+    #[test]
+    fn decodes_sync() {
+        let log = fixture(
+            POOL,
+            &[V2_SYNC_EVENT],
+            &[
+                "00000000000000000000000000000000000000000001056e0f36a6443de2df79",
+                "000000000000000000000000000000000000000000000000000000e5f4c8f3ca",
+            ],
+        );
+        let sync = decode_sync(&log).unwrap();
+        assert_eq!(sync.reserve0, 1_234_567_890_123_456_789_012_345);
+        assert_eq!(sync.reserve1, 987_654_321_098);
+    }
+
+    #[test]
+    fn decodes_a_v3_swap_with_a_negative_amount() {
+        // 2500 USDC out for 1 WETH in
+        let log = fixture(
+            POOL,
+            &[V3_SWAP_EVENT, ROUTER.into_word(), ROUTER.into_word()],
+            &[
+                "ffffffffffffffffffffffffffffffffffffffffffffffffffffffff6afd0700",
+                "0000000000000000000000000000000000000000000000000de0b6b3a7640000",
+                "00000000000000000000000000000000000061ffb97edec2183ed8fd6f5fc5eb",
+                "000000000000000000000000000000000000000000000000ab54a98ceb1f0ad2",
+                "000000000000000000000000000000000000000000000000000000000002fa33",
+            ],
+        );
+        let swap = decode_v3_swap(&log).unwrap();
+        assert_eq!((swap.sender, swap.recipient), (ROUTER, ROUTER));
+        assert_eq!(swap.amount0, I256::try_from(-2_500_000_000i64).unwrap());
+        assert_eq!(swap.amount1, I256::try_from(10u64.pow(18)).unwrap());
+        assert_eq!(
+            swap.sqrt_price_x96,
+            U256::from(1_987_654_321_098_765_432_109_876_543_210_987u128)
+        );
+        assert_eq!(swap.liquidity, 12_345_678_901_234_567_890);
+        assert_eq!(swap.tick, 195_123);
+    }
+
+    /// Indexed int24s are sign-extended to a whole topic
+    fn negative_tick_topics() -> (B256, B256) {
+        (
+            topic("fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffcf298"),
+            topic("fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffcf2d4"),
+        )
+    }
+
+    #[test]
+    fn decodes_mint_and_burn() {
+        let (lower, upper) = negative_tick_topics();
+        let owner = POSITION_MANAGER.into_word();
+        let amounts = [
+            "0000000000000000000000000000000000000000000000000011c37937e08000",
+            "00000000000000000000000000000000000000000000000000000000498d5880",
+            "000000000000000000000000000000000000000000000000058d15e176280000",
+        ];
+
+        let mut words = vec!["000000000000000000000000c36442b4a4522e871399cd717abdd847ab11fe88"];
+        words.extend(amounts);
+        let mint = decode_mint(&fixture(
+            POOL,
+            &[V3_MINT_EVENT, owner, lower, upper],
+            &words,
+        ));
+        assert_eq!(
+            mint.unwrap(),
+            MintEvent {
+                sender: POSITION_MANAGER,
+                owner: POSITION_MANAGER,
+                tick_lower: -200_040,
+                tick_upper: -199_980,
+                amount: 5 * 10u128.pow(15),
+                amount0: U256::from(1_234_000_000),
+                amount1: U256::from(4 * 10u128.pow(17)),
+            }
+        );
+
+        let burn = decode_burn(&fixture(
+            POOL,
+            &[V3_BURN_EVENT, owner, lower, upper],
+            &amounts,
+        ));
+        assert_eq!(
+            burn.unwrap(),
+            BurnEvent {
+                owner: POSITION_MANAGER,
+                tick_lower: -200_040,
+                tick_upper: -199_980,
+                amount: 5 * 10u128.pow(15),
+                amount0: U256::from(1_234_000_000),
+                amount1: U256::from(4 * 10u128.pow(17)),
+            }
+        );
+    }
+
+    #[test]
+    fn decodes_v4_swap_and_modify_liquidity() {
+        let id = keccak256("pool id");
+        let sender = ROUTER.into_word();
+
+        // 1 ETH in (negative: the swapper pays it) for 2500 USDC out
+        let log = fixture(
+            POOL,
+            &[V4_SWAP_EVENT, id, sender],
+            &[
+                "fffffffffffffffffffffffffffffffffffffffffffffffff21f494c589c0000",
+                "000000000000000000000000000000000000000000000000000000009502f900",
+                "0000000000000000000000000000000000000032000000000000000000000000",
+                "00000000000000000000000000000000000000000000021e19e0c9bab2400000",
+                "fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffcfe78",
+                "00000000000000000000000000000000000000000000000000000000000001f4",
+            ],
+        );
+        let swap = decode_v4_swap(&log).unwrap();
+        assert_eq!((swap.pool_id, swap.sender), (id, ROUTER));
+        assert_eq!(
+            (swap.amount0, swap.amount1),
+            (-(10i128.pow(18)), 2_500_000_000)
+        );
+        assert_eq!(swap.sqrt_price_x96, U256::from(50u128) << 96);
+        assert_eq!(
+            (swap.liquidity, swap.tick, swap.fee),
+            (10u128.pow(22), -197_000, 500)
+        );
+
+        // 1e20 liquidity removed from the full range
+        let log = fixture(
+            POOL,
+            &[V4_MODIFY_LIQUIDITY_EVENT, id, sender],
+            &[
+                "fffffffffffffffffffffffffffffffffffffffffffffffffffffffffff2764c",
+                "00000000000000000000000000000000000000000000000000000000000d89b4",
+                "fffffffffffffffffffffffffffffffffffffffffffffffa9438a1d29cf00000",
+                "0000000000000000000000000000000000000000000000000000000000000000",
+            ],
+        );
+        let modify = decode_modify_liquidity(&log).unwrap();
+        assert_eq!((modify.tick_lower, modify.tick_upper), (-887_220, 887_220));
+        assert_eq!(
+            modify.liquidity_delta,
+            I256::try_from(-(10i128.pow(20))).unwrap()
+        );
+        assert_eq!(modify.salt, B256::ZERO);
+    }
+
+    #[test]
+    fn rejects_mismatched_or_truncated_logs() {
+        let word = "0000000000000000000000000000000000000000000000000000000000000001";
+        let sync = fixture(POOL, &[V2_SYNC_EVENT], &[word, word]);
+        assert!(decode_v3_swap(&sync).is_err());
+        assert!(decode_sync(&fixture(POOL, &[V2_SYNC_EVENT], &[word])).is_err());
+        // Mint with its indexed ticks missing
+        let owner = POSITION_MANAGER.into_word();
+        assert!(decode_mint(&fixture(POOL, &[V3_MINT_EVENT, owner], &[word; 4])).is_err());
+        // A reserve beyond uint128
+        let huge = "0000000000000000000000000000000100000000000000000000000000000000";
+        assert!(decode_sync(&fixture(POOL, &[V2_SYNC_EVENT], &[huge, word])).is_err());
+        // A Swap cut short before its tick
+        let swap = fixture(POOL, &[V3_SWAP_EVENT, owner, owner], &[word; 4]);
+        assert!(decode_v3_swap(&swap).is_err());
+    }
+}
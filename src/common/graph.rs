@@ -1,32 +1,259 @@
-use crate::common::pool::PoolVariant;
+use crate::common::error::PoolResult;
+use crate::common::guard::PriceGuard;
+use crate::common::pool::{LiquidityPool, PoolVariant};
+use crate::common::scan::{Opportunity, OpportunityCache, ScanConfig, ScanFilter, rank_cycles};
+use crate::common::spfa::{
+    DEFAULT_MIN_PROFIT_THRESHOLD, find_all_negative_cycles_excluding, find_negative_cycle_excluding,
+};
 use crate::common::token::Token;
-use petgraph::graph::DiGraph;
-use std::collections::HashMap;
+use alloy_primitives::{Address, B256, Log, U256};
+use anyhow::{Context, Result, ensure};
+use futures::{Stream, StreamExt, future, stream};
+use petgraph::graph::{DiGraph, EdgeIndex, NodeIndex};
+use petgraph::visit::EdgeRef;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
 #[derive(Debug, Clone)]
 pub struct GraphEdge {
     pub pool: PoolVariant,
     pub zero_for_one: bool, // Direction of the trade on this edge
+
+    // Strength of the shallow-pool penalty in the search cost (0 = off), see `depth_cost`
+    pub depth_penalty: f64,
 }
 
 impl GraphEdge {
     pub fn new(pool: PoolVariant, zero_for_one: bool) -> Self {
-        Self { pool, zero_for_one }
+        Self {
+            pool,
+            zero_for_one,
+            depth_penalty: 0.0,
+        }
     }
 
     /// Fast access to weight for SPFA
     pub fn weight(&self) -> f64 {
         self.pool.get_log_weight(self.zero_for_one)
     }
+
+    /// Weight of the decimals-normalized price, so that e.g. WETH(18)->USDC(6) is centered on
+    /// the human price (~3000) instead of the raw-unit ratio (~3e-9).
+    /// human_price = raw_price * 10^(dec_in - dec_out), i.e. the weight shifts by
+    /// -(dec_in - dec_out) * ln(10). Around a closed cycle these shifts cancel out.
+    pub fn weight_with_decimals(&self, dec_in: u8, dec_out: u8) -> f64 {
+        let shift = (dec_in as f64 - dec_out as f64) * std::f64::consts::LN_10;
+        self.weight() - shift
+    }
+
+    /// Extra search cost of routing through this pool: `depth_penalty / ln(1 + depth)`, so of
+    /// two pools at the same price the deeper one is cheaper. Always positive, so it can only
+    /// hide marginal cycles, never create them.
+    pub fn depth_cost(&self) -> f64 {
+        if self.depth_penalty == 0.0 {
+            return 0.0;
+        }
+        self.depth_penalty / self.pool.depth().ln_1p()
+    }
 }
 
 /// The concrete Graph type for our Arbitrage Bot
 pub type ArbGraph = DiGraph<Token, GraphEdge>;
 
+/// Decimals-normalized weight of an edge, as used by the cycle search (including the edge's
+/// `depth_cost`)
+pub fn edge_cost(graph: &ArbGraph, edge: EdgeIndex) -> f64 {
+    spot_cost(graph, edge) + graph[edge].depth_cost()
+}
+
+/// Decimals-normalized weight of an edge, i.e. minus the log of its spot rate in whole tokens
+pub fn spot_cost(graph: &ArbGraph, edge: EdgeIndex) -> f64 {
+    let (from, to) = graph.edge_endpoints(edge).expect("edge belongs to graph");
+    graph[edge].weight_with_decimals(graph[from].decimals, graph[to].decimals)
+}
+
+/// Whether `a` beats `b` as the route between the same two tokens: the lower cost wins, and on a
+/// tie the pool with the lowest address (then pool id), so the choice doesn't depend on edge or
+/// hash map order
+fn preferred_edge(graph: &ArbGraph, a: (EdgeIndex, f64), b: (EdgeIndex, f64)) -> bool {
+    let key = |edge: EdgeIndex| {
+        let pool = &graph[edge].pool;
+        (pool.address(), pool.id())
+    };
+    match a.1.total_cmp(&b.1) {
+        Ordering::Less => true,
+        Ordering::Greater => false,
+        Ordering::Equal => key(a.0) < key(b.0),
+    }
+}
+
+/// For every neighbor of `from`, the cheapest of the (possibly several) parallel pools leading
+/// to it, as (target, edge, cost), ordered by target. Edges with infinite cost (empty pools)
+/// are skipped; ties go to the lowest pool address.
+pub fn best_out_edges(graph: &ArbGraph, from: NodeIndex) -> Vec<(NodeIndex, EdgeIndex, f64)> {
+    best_out_edges_excluding(graph, from, &HashSet::new())
+}
+
+/// `best_out_edges`, ignoring the edges in `excluded`
+pub fn best_out_edges_excluding(
+    graph: &ArbGraph,
+    from: NodeIndex,
+    excluded: &HashSet<EdgeIndex>,
+) -> Vec<(NodeIndex, EdgeIndex, f64)> {
+    let mut best: HashMap<NodeIndex, (EdgeIndex, f64)> = HashMap::new();
+    for edge in graph.edges(from) {
+        if excluded.contains(&edge.id()) {
+            continue;
+        }
+        let cost = edge_cost(graph, edge.id());
+        if !cost.is_finite() {
+            continue;
+        }
+        best.entry(edge.target())
+            .and_modify(|current| {
+                if preferred_edge(graph, (edge.id(), cost), *current) {
+                    *current = (edge.id(), cost);
+                }
+            })
+            .or_insert((edge.id(), cost));
+    }
+
+    let mut best: Vec<(NodeIndex, EdgeIndex, f64)> = best
+        .into_iter()
+        .map(|(to, (edge, cost))| (to, edge, cost))
+        .collect();
+    best.sort_unstable_by_key(|&(to, _, _)| to);
+    best
+}
+
+/// The cheapest edge from `from` to `to` among parallel pools, if any is tradable (ties go to
+/// the lowest pool address)
+pub fn best_edge(graph: &ArbGraph, from: NodeIndex, to: NodeIndex) -> Option<EdgeIndex> {
+    graph
+        .edges_connecting(from, to)
+        .map(|edge| (edge.id(), edge_cost(graph, edge.id())))
+        .filter(|(_, cost)| cost.is_finite())
+        .reduce(|best, candidate| {
+            if preferred_edge(graph, candidate, best) {
+                candidate
+            } else {
+                best
+            }
+        })
+        .map(|(edge, _)| edge)
+}
+
+/// A serializable copy of a `GraphManager`'s tokens and pool states, for warm restarts.
+/// Cached weights aren't stored; they are recomputed on restore.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphSnapshot {
+    /// Tokens in node-index order
+    pub tokens: Vec<Token>,
+    /// Edges in edge-index order
+    pub edges: Vec<EdgeSnapshot>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EdgeSnapshot {
+    pub from: usize,
+    pub to: usize,
+    pub pool: PoolVariant,
+    pub zero_for_one: bool,
+}
+
+/// An edge `GraphManager::validate` found inconsistent with its pool, whose weight would price
+/// the wrong trade
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// One of the pool's tokens has no node
+    UnknownToken { edge: EdgeIndex, token: Address },
+    /// The edge joins the pool's tokens, but `zero_for_one` says the opposite direction
+    DirectionMismatch { edge: EdgeIndex, zero_for_one: bool },
+    /// The edge doesn't join the pool's two tokens at all
+    EndpointMismatch { edge: EdgeIndex },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::UnknownToken { edge, token } => {
+                write!(
+                    f,
+                    "edge {}: pool token {token} isn't in the graph",
+                    edge.index()
+                )
+            }
+            ValidationError::DirectionMismatch { edge, zero_for_one } => write!(
+                f,
+                "edge {}: zero_for_one is {zero_for_one} but the edge runs the other way",
+                edge.index()
+            ),
+            ValidationError::EndpointMismatch { edge } => {
+                write!(
+                    f,
+                    "edge {}: endpoints aren't the pool's tokens",
+                    edge.index()
+                )
+            }
+        }
+    }
+}
+
+/// Default `GraphManager::max_simulation_failures`
+pub const DEFAULT_MAX_SIMULATION_FAILURES: u32 = 3;
+
 /// A lookup table to quickly find NodeIndices by Address
 pub struct GraphManager {
     pub graph: ArbGraph,
-    pub node_map: HashMap<Address, petgraph::graph::NodeIndex>,
+    pub node_map: HashMap<Address, NodeIndex>,
+
+    /// Edges of the most recently found negative cycle (highlighted in DOT exports)
+    pub last_cycle: Vec<EdgeIndex>,
+
+    /// `add_pool` skips V3/V4 pools with less active liquidity than this...
+    pub min_liquidity: u128,
+    /// ...and V2/StableSwap pools where either reserve is below this
+    pub min_reserve: u128,
+
+    /// Longest cycle `find_negative_cycle` may return, in hops; `None` for no limit
+    pub max_hops: Option<usize>,
+    /// How far below zero (in log space) a cycle's cost must be for it to count, so float
+    /// rounding around break-even isn't reported as arbitrage
+    pub min_profit_threshold: f64,
+
+    /// Strength of the penalty steering the search toward deeper pools (see
+    /// `GraphEdge::depth_cost`); 0 (the default) ranks pools by spot price alone
+    pub depth_penalty: f64,
+
+    /// Pools whose simulation (see `simulate`) failed this many times since their last update
+    /// are left out of the cycle search until a log for them applies successfully
+    pub max_simulation_failures: u32,
+    /// Simulation failures per pool since its last update, by `PoolVariant::id`
+    failure_counts: HashMap<B256, u32>,
+
+    /// Cycles through pools priced too far from a reference are dropped when set
+    pub price_guard: Option<PriceGuard>,
+
+    /// Leave pools trading a rebasing token (`Token::is_rebasing`) out of the cycle search
+    pub exclude_rebasing: bool,
+
+    /// Route native ETH and WETH through a single node (see `with_native_weth_merged`)
+    pub merge_native_weth: bool,
+    /// The shared native/WETH node, once either token has been added
+    native_weth_node: Option<NodeIndex>,
+
+    /// Edges carrying each V2/V3/StableSwap pool, by pool address, for O(1) log routing
+    pub address_to_edge: HashMap<Address, Vec<EdgeIndex>>,
+    /// Edges carrying each V4 or Balancer pool, by singleton pool id (`PoolVariant::singleton_id`)
+    pub pool_id_to_edge: HashMap<B256, Vec<EdgeIndex>>,
+}
+
+impl Default for GraphManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl GraphManager {
@@ -34,19 +261,1662 @@ impl GraphManager {
         Self {
             graph: ArbGraph::new(),
             node_map: HashMap::new(),
+            last_cycle: Vec::new(),
+            min_liquidity: 0,
+            min_reserve: 0,
+            max_hops: None,
+            min_profit_threshold: DEFAULT_MIN_PROFIT_THRESHOLD,
+            depth_penalty: 0.0,
+            max_simulation_failures: DEFAULT_MAX_SIMULATION_FAILURES,
+            failure_counts: HashMap::new(),
+            price_guard: None,
+            exclude_rebasing: false,
+            merge_native_weth: false,
+            native_weth_node: None,
+            address_to_edge: HashMap::new(),
+            pool_id_to_edge: HashMap::new(),
+        }
+    }
+
+    /// Sets `depth_penalty` for new pools and every edge already in the graph
+    pub fn set_depth_penalty(&mut self, strength: f64) {
+        self.depth_penalty = strength;
+        for edge in self.graph.edge_weights_mut() {
+            edge.depth_penalty = strength;
         }
     }
 
+    /// Treats native ETH (V4's `Address::ZERO` currency) and WETH as the same token: whichever
+    /// is added first becomes the node, and the other's address maps to it. Wrapping and
+    /// unwrapping are then free hops, so cycles can enter through a V4 native pool and leave
+    /// through a V2/V3 WETH pool. The executor is responsible for the actual (un)wrap.
+    pub fn with_native_weth_merged(mut self) -> Self {
+        self.merge_native_weth = true;
+        self
+    }
+
+    /// Leaves cycles through rebasing tokens out of the search (see `exclude_rebasing`)
+    pub fn with_rebasing_excluded(mut self) -> Self {
+        self.exclude_rebasing = true;
+        self
+    }
+
     /// Adds a token if it doesn't exist, returns its index
-    pub fn add_or_get_token(&mut self, token: Token) -> petgraph::graph::NodeIndex {
+    pub fn add_or_get_token(&mut self, token: Token) -> NodeIndex {
         if let Some(&index) = self.node_map.get(&token.address) {
             return index;
         }
 
+        let native_or_weth = self.merge_native_weth && (token.is_native || token.is_weth);
+        if native_or_weth && let Some(index) = self.native_weth_node {
+            self.node_map.insert(token.address, index);
+            return index;
+        }
+
         // Clone address before moving token into graph
         let addr = token.address;
         let index = self.graph.add_node(token);
         self.node_map.insert(addr, index);
+        if native_or_weth {
+            self.native_weth_node = Some(index);
+        }
         index
     }
+
+    /// Start nodes for the cycle search from configured base token addresses (WETH, USDC, ...),
+    /// in the given order. Tokens not in the graph are skipped, and addresses sharing a node
+    /// (native ETH and WETH when merged) yield it once.
+    pub fn base_nodes(&self, bases: &[Address]) -> Vec<NodeIndex> {
+        let mut nodes = Vec::with_capacity(bases.len());
+        for base in bases {
+            if let Some(&node) = self.node_map.get(base)
+                && !nodes.contains(&node)
+            {
+                nodes.push(node);
+            }
+        }
+        nodes
+    }
+
+    /// Adds `pool` to the graph as two directed edges, token0 -> token1 (`zero_for_one`) and
+    /// token1 -> token0, each holding its own copy of the pool state. Pools below the liquidity
+    /// floor (or joining a node to itself) are skipped. Both tokens must already have been added
+    /// with `add_or_get_token`.
+    /// Returns the `(zero_for_one, one_for_zero)` edges, or `None` if the pool was skipped.
+    pub fn add_pool(&mut self, mut pool: PoolVariant) -> Option<(EdgeIndex, EdgeIndex)> {
+        if !self.meets_liquidity_floor(&pool) {
+            return None;
+        }
+
+        let (token0, token1) = pool.tokens();
+        let node0 = *self.node_map.get(&token0)?;
+        let node1 = *self.node_map.get(&token1)?;
+        // e.g. an ETH/WETH pool once the two are merged
+        if node0 == node1 {
+            return None;
+        }
+
+        pool.refresh_weights();
+        let edge = |zero_for_one| GraphEdge {
+            depth_penalty: self.depth_penalty,
+            ..GraphEdge::new(pool.clone(), zero_for_one)
+        };
+        let (forward, backward) = (edge(true), edge(false));
+        let forward = self.graph.add_edge(node0, node1, forward);
+        let backward = self.graph.add_edge(node1, node0, backward);
+        self.register_edge(forward);
+        self.register_edge(backward);
+        Some((forward, backward))
+    }
+
+    /// Adds the tokens and pools of a pool list (see `loader::load_pools_from_json`). Returns
+    /// how many pools were added; those failing the liquidity floor are skipped as in `add_pool`.
+    pub fn ingest(&mut self, pools: Vec<(PoolVariant, Token, Token)>) -> usize {
+        let mut added = 0;
+        for (pool, token0, token1) in pools {
+            self.add_or_get_token(token0);
+            self.add_or_get_token(token1);
+            if self.add_pool(pool).is_some() {
+                added += 1;
+            }
+        }
+        added
+    }
+
+    /// Adds `edge` to the pool registry
+    fn register_edge(&mut self, edge: EdgeIndex) {
+        let pool = &self.graph[edge].pool;
+        let edges = match pool.singleton_id() {
+            Some(id) => self.pool_id_to_edge.entry(id).or_default(),
+            None => self.address_to_edge.entry(pool.address()).or_default(),
+        };
+        edges.push(edge);
+    }
+
+    /// Removes both edges of the V2/V3 pool at `address`. Returns false if it isn't in the graph.
+    pub fn remove_pool(&mut self, address: Address) -> bool {
+        match self.address_to_edge.remove(&address) {
+            Some(edges) => {
+                self.remove_edges(edges);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes both edges of the V4 (or Balancer) pool with singleton id `pool_id`
+    pub fn remove_v4_pool(&mut self, pool_id: B256) -> bool {
+        match self.pool_id_to_edge.remove(&pool_id) {
+            Some(edges) => {
+                self.remove_edges(edges);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes edges that have already been dropped from the registry.
+    ///
+    /// petgraph fills the hole left by `remove_edge` with the last edge, so that edge's index
+    /// changes and its registry entry is patched. Removing from the highest index down keeps
+    /// the indices still to be removed valid.
+    fn remove_edges(&mut self, mut edges: Vec<EdgeIndex>) {
+        edges.sort_unstable_by(|a, b| b.cmp(a));
+        for edge in edges {
+            let last = EdgeIndex::new(self.graph.edge_count() - 1);
+            self.graph.remove_edge(edge);
+            if edge == last {
+                continue;
+            }
+
+            let pool = &self.graph[edge].pool;
+            let moved = match pool.singleton_id() {
+                Some(id) => self.pool_id_to_edge.get_mut(&id),
+                None => self.address_to_edge.get_mut(&pool.address()),
+            };
+            if let Some(slot) = moved.and_then(|edges| edges.iter_mut().find(|e| **e == last)) {
+                *slot = edge;
+            }
+        }
+
+        // The stored cycle may reference removed or moved edges
+        self.last_cycle.clear();
+    }
+
+    /// Removes every token left without pools (e.g. after `remove_pool`) and returns how many
+    /// were removed.
+    ///
+    /// petgraph fills the hole left by a removed node with the last node, so `node_map` (and the
+    /// shared native/WETH node) are updated to follow it. Edge indices are unaffected since
+    /// isolated nodes have no edges.
+    pub fn prune_isolated(&mut self) -> usize {
+        let mut isolated: Vec<NodeIndex> = self
+            .graph
+            .node_indices()
+            .filter(|&node| self.graph.neighbors_undirected(node).next().is_none())
+            .collect();
+
+        // Highest first, so the node moved into each hole is never one still to be removed
+        isolated.sort_unstable_by(|a, b| b.cmp(a));
+        for &node in &isolated {
+            let last = NodeIndex::new(self.graph.node_count() - 1);
+            self.graph.remove_node(node);
+
+            // Native ETH and WETH may share a node, so more than one address can point at it
+            self.node_map.retain(|_, index| *index != node);
+            if node != last {
+                for index in self.node_map.values_mut().filter(|index| **index == last) {
+                    *index = node;
+                }
+            }
+            self.native_weth_node = match self.native_weth_node {
+                Some(index) if index == node => None,
+                Some(index) if index == last => Some(node),
+                other => other,
+            };
+        }
+
+        isolated.len()
+    }
+
+    /// Swaps in a fresh copy of a pool's state (matched by address, or pool id for V4/Balancer)
+    /// on all of its edges, keeping each edge's direction. Returns false if the pool isn't in
+    /// the graph.
+    pub fn replace_pool(&mut self, mut pool: PoolVariant) -> bool {
+        let edges = match pool.singleton_id() {
+            Some(id) => self.pool_id_to_edge.get(&id),
+            None => self.address_to_edge.get(&pool.address()),
+        };
+        let Some(edges) = edges else {
+            return false;
+        };
+
+        pool.refresh_weights();
+        for &edge in edges {
+            self.graph[edge].pool = pool.clone();
+        }
+        true
+    }
+
+    /// Edges carrying `pool` (matched by address, or pool id for V4/Balancer), if it's in the
+    /// registry
+    fn registered_edges(&self, pool: &PoolVariant) -> Option<&Vec<EdgeIndex>> {
+        match pool.singleton_id() {
+            Some(id) => self.pool_id_to_edge.get(&id),
+            None => self.address_to_edge.get(&pool.address()),
+        }
+    }
+
+    /// Adds the tokens and pools of `other`, e.g. a scout fed by another pool source. Tokens are
+    /// matched by address and pools like in `replace_pool`; a pool in both graphs keeps the
+    /// state with the later `last_updated_block` (this graph's on a tie). New pools go through
+    /// `add_pool`, so this graph's liquidity floor and native/WETH merging apply to them.
+    /// Returns how many pools were added.
+    pub fn merge(&mut self, other: GraphManager) -> usize {
+        let (nodes, edges) = other.graph.into_nodes_edges();
+        for node in nodes {
+            self.add_or_get_token(node.weight);
+        }
+
+        let mut added = 0;
+        // Each pool comes twice, once per direction; the second copy finds the first registered
+        for edge in edges {
+            let pool = edge.weight.pool;
+            match self.registered_edges(&pool) {
+                Some(edges) => {
+                    let current = self.graph[edges[0]].pool.last_updated_block();
+                    if pool.last_updated_block() > current {
+                        self.replace_pool(pool);
+                    }
+                }
+                None => {
+                    if self.add_pool(pool).is_some() {
+                        added += 1;
+                    }
+                }
+            }
+        }
+        added
+    }
+
+    /// Rebuilds the pool registry from scratch; only needed after adding edges to `graph`
+    /// directly instead of through `add_pool`
+    pub fn reindex_pools(&mut self) {
+        self.address_to_edge.clear();
+        self.pool_id_to_edge.clear();
+        for edge in self.graph.edge_indices() {
+            self.register_edge(edge);
+        }
+    }
+
+    /// Edges carrying the pool that emitted `log`: V2/V3 pools are matched on the emitting
+    /// address, V4 pools on the pool id that PoolManager events carry in `topics[1]`
+    pub fn edges_for_log(&self, log: &Log) -> &[EdgeIndex] {
+        pool_edges_for_log(&self.address_to_edge, &self.pool_id_to_edge, log)
+    }
+
+    /// Applies `log` to every edge carrying the emitting pool without refreshing the cached
+    /// weights, and returns the edges whose state changed. Logs from unknown pools are ignored;
+    /// a registry entry pointing past the graph (edges removed without `reindex_pools`) is an
+    /// error.
+    pub fn apply_log(&mut self, log: &Log) -> Result<Vec<EdgeIndex>> {
+        let mut changed = Vec::new();
+        for &edge in pool_edges_for_log(&self.address_to_edge, &self.pool_id_to_edge, log) {
+            let pool = &mut self
+                .graph
+                .edge_weight_mut(edge)
+                .with_context(|| {
+                    format!(
+                        "edge {} registered for {} isn't in the graph, reindex_pools",
+                        edge.index(),
+                        log.address
+                    )
+                })?
+                .pool;
+            if pool.apply_log(log)? {
+                changed.push(edge);
+            }
+        }
+
+        // Fresh state gets a fresh chance (see `max_simulation_failures`)
+        for &edge in &changed {
+            self.failure_counts.remove(&self.graph[edge].pool.id());
+        }
+        Ok(changed)
+    }
+
+    /// Applies `log` to the pool that emitted it and refreshes the weights of its edges only,
+    /// returning those edges (empty if the log changed nothing)
+    pub fn update_from_log(&mut self, log: &Log) -> Result<Vec<EdgeIndex>> {
+        let changed = self.apply_log(log)?;
+        for &edge in &changed {
+            self.graph[edge].pool.refresh_weights();
+        }
+        Ok(changed)
+    }
+
+    /// Applies the logs of `block` in order, then refreshes the weights of every touched edge
+    /// once and stamps its pool's `last_updated_block`. Returns the touched edges, so that only
+    /// cycles through them need rescanning; every other weight is unchanged.
+    ///
+    /// Logs from untracked pools are ignored. A log that fails to apply doesn't stop the
+    /// batch; the first such error is returned once the rest has been applied (the touched
+    /// edges are refreshed all the same, but a caller that got an error should rescan fully).
+    pub fn apply_logs(&mut self, block: u64, logs: &[Log]) -> Result<HashSet<EdgeIndex>> {
+        let mut touched = HashSet::new();
+        let mut first_error = None;
+
+        for log in logs {
+            match self.apply_log(log) {
+                Ok(changed) => touched.extend(changed),
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+
+        for &edge in &touched {
+            let pool = &mut self.graph[edge].pool;
+            pool.refresh_weights();
+            pool.set_last_updated_block(block);
+        }
+
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(touched),
+        }
+    }
+
+    /// Turns a stream of blocks, as `(block number, logs)`, into the opportunities each of them
+    /// opens up: the logs are applied (see `apply_logs`), the `top_n` cycles through `bases` are
+    /// sized (see `GraphManager::scan_top_n`), and cycles reported within the last
+    /// `dedup_window` blocks are dropped unless their profit moved (see `OpportunityCache`).
+    ///
+    /// Pull-based: the next block is only taken from `blocks` once the consumer has drained the
+    /// previous one's opportunities, so at most `top_n` are ever buffered and a slow consumer
+    /// slows down block intake. A log that fails to apply doesn't stop the stream: the block's
+    /// first such error is yielded ahead of its opportunities, the rest of its block is applied
+    /// and the scan covers the whole graph anyway.
+    pub fn opportunity_stream<'a, S>(
+        &'a mut self,
+        blocks: S,
+        bases: &'a [NodeIndex],
+        config: ScanConfig,
+    ) -> impl Stream<Item = Result<Opportunity>> + 'a
+    where
+        S: Stream<Item = (u64, Vec<Log>)> + 'a,
+    {
+        let cache = OpportunityCache::new(config.dedup_window);
+        blocks
+            .scan((self, cache), move |(manager, cache), (block, logs)| {
+                let failed = manager
+                    .apply_logs(block, &logs)
+                    .err()
+                    .map(|e| Err(e.context(format!("applying the logs of block {block}"))));
+                let found = manager.scan_top_n(bases, block, &config);
+                let fresh = cache.filter(&manager.graph, found, block);
+                cache.prune(block);
+                let items = failed.into_iter().chain(fresh.into_iter().map(Ok));
+                future::ready(Some(stream::iter(items)))
+            })
+            .flatten()
+    }
+
+    /// Checks every edge against its pool: both of the pool's tokens must have nodes, and the
+    /// edge must run token0 -> token1 exactly when `zero_for_one` is set. Edges added to `graph`
+    /// directly can get this wrong, and their weights then silently price the opposite trade.
+    /// Returns every problem found, in edge order (empty for a consistent graph).
+    pub fn validate(&self) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+        for edge in self.graph.edge_indices() {
+            let (from, to) = self
+                .graph
+                .edge_endpoints(edge)
+                .expect("edge belongs to graph");
+            let GraphEdge {
+                pool, zero_for_one, ..
+            } = &self.graph[edge];
+            let (token0, token1) = pool.tokens();
+
+            let mut node = |token: Address| {
+                let node = self.node_map.get(&token).copied();
+                if node.is_none() {
+                    errors.push(ValidationError::UnknownToken { edge, token });
+                }
+                node
+            };
+            let (Some(node0), Some(node1)) = (node(token0), node(token1)) else {
+                continue;
+            };
+
+            let forward = (from, to) == (node0, node1);
+            let backward = (from, to) == (node1, node0);
+            if !forward && !backward {
+                errors.push(ValidationError::EndpointMismatch { edge });
+            } else if forward != *zero_for_one {
+                errors.push(ValidationError::DirectionMismatch {
+                    edge,
+                    zero_for_one: *zero_for_one,
+                });
+            }
+        }
+        errors
+    }
+
+    /// Whether `pool` is deep enough to route through (see `min_liquidity`/`min_reserve`)
+    fn meets_liquidity_floor(&self, pool: &PoolVariant) -> bool {
+        match pool {
+            PoolVariant::V2(p) => p.reserve0 >= self.min_reserve && p.reserve1 >= self.min_reserve,
+            PoolVariant::V3(p) => p.liquidity >= self.min_liquidity,
+            PoolVariant::V4(p) => p.liquidity >= self.min_liquidity,
+            PoolVariant::Stable(p) => {
+                p.balance0 >= U256::from(self.min_reserve)
+                    && p.balance1 >= U256::from(self.min_reserve)
+            }
+            PoolVariant::Weighted(p) => {
+                p.balance0 >= U256::from(self.min_reserve)
+                    && p.balance1 >= U256::from(self.min_reserve)
+            }
+        }
+    }
+
+    /// Runs the negative-cycle search from `source` (bounded by `max_hops`, if set, and
+    /// thresholded by `min_profit_threshold`), remembering the result for `to_dot`. A cycle
+    /// rejected by `price_guard` isn't reported.
+    pub fn find_negative_cycle(&mut self, source: NodeIndex) -> Option<Vec<EdgeIndex>> {
+        let cycle = self.search_negative_cycle(source);
+        self.last_cycle = cycle.clone().unwrap_or_default();
+        cycle
+    }
+
+    /// Same search as `find_negative_cycle`, without recording `last_cycle`, so it only needs
+    /// shared access
+    pub fn search_negative_cycle(&self, source: NodeIndex) -> Option<Vec<EdgeIndex>> {
+        self.search_negative_cycle_excluding(source, &HashSet::new())
+    }
+
+    /// `search_negative_cycle` as if the `excluded` edges (and those of `tripped_edges` and, if
+    /// `exclude_rebasing` is set, `rebasing_edges`) weren't in the graph
+    pub fn search_negative_cycle_excluding(
+        &self,
+        source: NodeIndex,
+        excluded: &HashSet<EdgeIndex>,
+    ) -> Option<Vec<EdgeIndex>> {
+        let skipped = self.skipped_edges();
+        let excluded = if skipped.is_empty() {
+            excluded
+        } else {
+            &excluded.union(&skipped).copied().collect()
+        };
+        let cycle = find_negative_cycle_excluding(
+            &self.graph,
+            source,
+            excluded,
+            self.max_hops,
+            self.min_profit_threshold,
+        )?;
+        self.passes_price_guard(&cycle).then_some(cycle)
+    }
+
+    /// The distinct negative cycles through any of `bases` (see
+    /// `spfa::find_all_negative_cycles`), held to the same settings as `search_negative_cycle`:
+    /// without the edges it skips, at most `max_hops` long, clearing `min_profit_threshold` and
+    /// passing `price_guard`
+    pub fn search_all_negative_cycles(&self, bases: &[NodeIndex]) -> Vec<Vec<EdgeIndex>> {
+        let mut cycles = find_all_negative_cycles_excluding(
+            &self.graph,
+            bases,
+            &self.skipped_edges(),
+            self.min_profit_threshold,
+        );
+        cycles.retain(|cycle| {
+            self.max_hops.is_none_or(|max_hops| cycle.len() <= max_hops)
+                && self.passes_price_guard(cycle)
+        });
+        cycles
+    }
+
+    /// `scan::scan_top_n` over the cycles of `search_all_negative_cycles`, with the settings
+    /// of `config`
+    pub fn scan_top_n(
+        &self,
+        bases: &[NodeIndex],
+        block: u64,
+        config: &ScanConfig,
+    ) -> Vec<Opportunity> {
+        if config.top_n == 0 {
+            return Vec::new();
+        }
+        let cycles = self.search_all_negative_cycles(bases);
+        rank_cycles(
+            &self.graph,
+            cycles,
+            block,
+            config,
+            &ScanFilter::default(),
+            None,
+        )
+    }
+
+    /// Edges every search leaves out: `tripped_edges`, plus `rebasing_edges` if
+    /// `exclude_rebasing` is set
+    fn skipped_edges(&self) -> HashSet<EdgeIndex> {
+        let mut skipped = self.tripped_edges();
+        if self.exclude_rebasing {
+            skipped.extend(self.rebasing_edges());
+        }
+        skipped
+    }
+
+    /// Runs the negative-cycle search from `source` skipping every stale edge (see
+    /// `stale_edges`), remembering the result for `to_dot`
+    pub fn find_fresh_negative_cycle(
+        &mut self,
+        source: NodeIndex,
+        current_block: u64,
+        max_block_lag: u64,
+    ) -> Option<Vec<EdgeIndex>> {
+        let stale = self.stale_edges(current_block, max_block_lag);
+        let cycle = self.search_negative_cycle_excluding(source, &stale);
+        self.last_cycle = cycle.clone().unwrap_or_default();
+        cycle
+    }
+
+    /// Edges whose pool hasn't been updated in the last `max_block_lag` blocks before
+    /// `current_block`
+    pub fn stale_edges(&self, current_block: u64, max_block_lag: u64) -> HashSet<EdgeIndex> {
+        let oldest_fresh = current_block.saturating_sub(max_block_lag);
+        self.graph
+            .edge_indices()
+            .filter(|&edge| self.graph[edge].pool.last_updated_block() < oldest_fresh)
+            .collect()
+    }
+
+    /// Every edge with its quote for `amount_in` raw units of its input token, computed lazily
+    /// as the iterator is advanced. Pools aren't charged for failed quotes here (unlike
+    /// `simulate`).
+    pub fn probe_edges(
+        &self,
+        amount_in: U256,
+    ) -> impl Iterator<Item = (EdgeIndex, PoolResult<U256>)> + '_ {
+        self.graph.edge_indices().map(move |edge| {
+            let GraphEdge {
+                pool, zero_for_one, ..
+            } = &self.graph[edge];
+            (edge, pool.get_amount_out(amount_in, *zero_for_one))
+        })
+    }
+
+    /// Output of trading `amount_in` around `cycle` (see `solver::simulate_cycle`). A hop that
+    /// fails counts against its pool, which the search skips once it has failed
+    /// `max_simulation_failures` times without an update in between.
+    pub fn simulate(&mut self, cycle: &[EdgeIndex], amount_in: U256) -> Result<U256> {
+        let mut amount = amount_in;
+        for &edge in cycle {
+            let GraphEdge {
+                pool, zero_for_one, ..
+            } = &self.graph[edge];
+            match pool.get_amount_out(amount, *zero_for_one) {
+                Ok(out) => amount = out,
+                Err(e) => {
+                    *self.failure_counts.entry(pool.id()).or_insert(0) += 1;
+                    return Err(anyhow::Error::new(e).context(format!(
+                        "simulating {} pool {}",
+                        pool.version(),
+                        pool.id()
+                    )));
+                }
+            }
+        }
+        Ok(amount)
+    }
+
+    /// Simulation failures of every pool that has failed since its last update, by
+    /// `PoolVariant::id`, for monitoring
+    pub fn failure_counts(&self) -> &HashMap<B256, u32> {
+        &self.failure_counts
+    }
+
+    /// Edges of the pools that reached `max_simulation_failures`
+    pub fn tripped_edges(&self) -> HashSet<EdgeIndex> {
+        if self.failure_counts.is_empty() {
+            return HashSet::new();
+        }
+        self.graph
+            .edge_indices()
+            .filter(|&edge| {
+                self.failure_counts
+                    .get(&self.graph[edge].pool.id())
+                    .is_some_and(|&failures| failures >= self.max_simulation_failures)
+            })
+            .collect()
+    }
+
+    /// Edges into or out of a rebasing token
+    pub fn rebasing_edges(&self) -> HashSet<EdgeIndex> {
+        self.graph
+            .edge_indices()
+            .filter(|&edge| {
+                self.graph.edge_endpoints(edge).is_some_and(|(from, to)| {
+                    self.graph[from].is_rebasing || self.graph[to].is_rebasing
+                })
+            })
+            .collect()
+    }
+
+    /// Whether `cycle` passes `price_guard` (always, if there's none)
+    pub fn passes_price_guard(&self, cycle: &[EdgeIndex]) -> bool {
+        self.price_guard
+            .as_ref()
+            .is_none_or(|guard| guard.accepts(&self.graph, cycle))
+    }
+
+    /// `PoolVariant::describe` for `edge`, labeled with the symbols of its tokens
+    pub fn describe_edge(&self, edge: EdgeIndex) -> Option<String> {
+        let (from, to) = self.graph.edge_endpoints(edge)?;
+        let edge = &self.graph[edge];
+        Some(edge.pool.describe_with_symbols(
+            edge.zero_for_one,
+            &self.graph[from].symbol,
+            &self.graph[to].symbol,
+        ))
+    }
+
+    /// Exports the graph in Graphviz DOT format: tokens are labeled by symbol, edges by pool
+    /// version, fee and current weight. Edges of `last_cycle` are drawn in red.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph ArbGraph {\n");
+
+        for index in self.graph.node_indices() {
+            let label = dot_escape(&self.graph[index].to_string());
+            dot.push_str(&format!("    n{} [label=\"{label}\"];\n", index.index()));
+        }
+
+        for edge in self.graph.edge_references() {
+            let pool = &edge.weight().pool;
+            let label = format!(
+                "{} fee={} w={:.6}",
+                pool.version(),
+                pool.fee_pips(),
+                edge.weight().weight()
+            );
+            let style = if self.last_cycle.contains(&edge.id()) {
+                " color=\"red\" penwidth=2"
+            } else {
+                ""
+            };
+            dot.push_str(&format!(
+                "    n{} -> n{} [label=\"{}\"{style}];\n",
+                edge.source().index(),
+                edge.target().index(),
+                dot_escape(&label)
+            ));
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Captures every token and pool state so the graph can be rebuilt without hitting the chain
+    pub fn snapshot(&self) -> GraphSnapshot {
+        GraphSnapshot {
+            tokens: self.graph.node_weights().cloned().collect(),
+            edges: self
+                .graph
+                .edge_references()
+                .map(|edge| EdgeSnapshot {
+                    from: edge.source().index(),
+                    to: edge.target().index(),
+                    pool: edge.weight().pool.clone(),
+                    zero_for_one: edge.weight().zero_for_one,
+                })
+                .collect(),
+        }
+    }
+
+    /// Rebuilds a graph from `snapshot`, preserving node and edge indices
+    pub fn from_snapshot(snapshot: GraphSnapshot) -> Result<Self> {
+        let mut manager = Self::new();
+        for token in snapshot.tokens {
+            let address = token.address;
+            let index = manager.graph.add_node(token);
+            ensure!(
+                manager.node_map.insert(address, index).is_none(),
+                "duplicate token {address} in snapshot"
+            );
+        }
+
+        let node_count = manager.graph.node_count();
+        for edge in snapshot.edges {
+            ensure!(
+                edge.from < node_count && edge.to < node_count,
+                "edge {} -> {} references a missing token",
+                edge.from,
+                edge.to
+            );
+            let mut pool = edge.pool;
+            pool.refresh_weights();
+            let index = manager.graph.add_edge(
+                NodeIndex::new(edge.from),
+                NodeIndex::new(edge.to),
+                GraphEdge::new(pool, edge.zero_for_one),
+            );
+            manager.register_edge(index);
+        }
+
+        Ok(manager)
+    }
+
+    /// Builds a graph from `initial_pools` (typically empty, freshly deployed pools) and then
+    /// replays `logs` through `update_from_log` in order, so the final state depends on the logs
+    /// alone. Meant for reproducible tests, which also exercises every event parser.
+    ///
+    /// Logs from pools not in `initial_pools` are ignored; the first log that fails to apply
+    /// aborts the replay.
+    pub fn from_log_replay(
+        initial_pools: Vec<(PoolVariant, Token, Token)>,
+        logs: &[Log],
+    ) -> Result<Self> {
+        let mut manager = Self::new();
+        let expected = initial_pools.len();
+        let added = manager.ingest(initial_pools);
+        ensure!(
+            added == expected,
+            "only {added} of {expected} initial pools could be added"
+        );
+
+        for (i, log) in logs.iter().enumerate() {
+            manager
+                .update_from_log(log)
+                .with_context(|| format!("replaying log {i} (from {})", log.address))?;
+        }
+        Ok(manager)
+    }
+
+    /// Picks the most favorable pool for trading `from` -> `to` when several connect the pair
+    pub fn best_edge(&self, from: NodeIndex, to: NodeIndex) -> Option<EdgeIndex> {
+        best_edge(&self.graph, from, to)
+    }
+}
+
+/// `GraphManager::edges_for_log` over borrowed registries, so callers can mutate the graph
+fn pool_edges_for_log<'a>(
+    address_to_edge: &'a HashMap<Address, Vec<EdgeIndex>>,
+    pool_id_to_edge: &'a HashMap<B256, Vec<EdgeIndex>>,
+    log: &Log,
+) -> &'a [EdgeIndex] {
+    address_to_edge
+        .get(&log.address)
+        .or_else(|| pool_id_to_edge.get(log.topics().get(1)?))
+        .map_or(&[], Vec::as_slice)
+}
+
+/// Escapes a string for use inside a double-quoted DOT label
+fn dot_escape(label: &str) -> String {
+    let mut escaped = String::with_capacity(label.len());
+    for c in label.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::pool::{PRICE_X96_ONE, UniswapV2Pool, UniswapV3Pool};
+    use crate::common::test_util::{
+        addr, graph_with, log, modify_liquidity_log, position_log, sync_log, token, v2_pool,
+        v3_pool, v3_swap_log, v4_pool, v4_swap_log,
+    };
+    use crate::common::tickmath::get_sqrt_ratio_at_tick;
+    use crate::common::token::{MAINNET_WETH, TokenBuilder};
+    use futures::executor::block_on;
+    use std::collections::BTreeMap;
+
+    const E18: u128 = 1_000_000_000_000_000_000;
+
+    #[test]
+    fn decimals_center_the_weight_on_the_human_price() {
+        // 1000 WETH (18 decimals) against 3,000,000 USDC (6 decimals): 3000 USDC per WETH
+        let pool = v2_pool(
+            addr(1),
+            addr(2),
+            addr(3),
+            1000 * 10u128.pow(18),
+            3 * 10u128.pow(12),
+        );
+        let sell_weth = GraphEdge::new(PoolVariant::V2(pool.clone()), true);
+        let sell_usdc = GraphEdge::new(PoolVariant::V2(pool), false);
+
+        let weth_weight = sell_weth.weight_with_decimals(18, 6);
+        let usdc_weight = sell_usdc.weight_with_decimals(6, 18);
+        assert!((weth_weight + (3000.0 * 0.997f64).ln()).abs() < 1e-9);
+        assert!((usdc_weight + (0.997f64 / 3000.0).ln()).abs() < 1e-9);
+        // The shifts cancel over the round trip
+        assert!(
+            (weth_weight + usdc_weight - (sell_weth.weight() + sell_usdc.weight())).abs() < 1e-9
+        );
+    }
+    #[test]
+    fn best_edge_picks_the_cheaper_parallel_pool() {
+        let standard = v2_pool(addr(10), addr(1), addr(2), 1000 * E18, 1000 * E18);
+        let cheap = UniswapV2Pool {
+            fee_bps: 5,
+            ..v2_pool(addr(11), addr(1), addr(2), 1000 * E18, 1000 * E18)
+        };
+        let manager = graph_with([PoolVariant::V2(standard), PoolVariant::V2(cheap)]);
+        let (a, b) = (manager.node_map[&addr(1)], manager.node_map[&addr(2)]);
+
+        for (from, to) in [(a, b), (b, a)] {
+            let best = manager.best_edge(from, to).unwrap();
+            assert_eq!(manager.graph[best].pool.address(), addr(11));
+        }
+    }
+
+    #[test]
+    fn dot_export_labels_tokens_and_highlights_the_last_cycle() {
+        let mut manager = graph_with([
+            PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), 1000 * E18, 1000 * E18)),
+            PoolVariant::V2(v2_pool(addr(11), addr(2), addr(3), 1000 * E18, 1000 * E18)),
+            PoolVariant::V2(v2_pool(addr(12), addr(3), addr(1), 1000 * E18, 1100 * E18)),
+        ]);
+        let a = manager.node_map[&addr(1)];
+        manager.graph[a].symbol = "A\"x".to_string();
+        let cycle = manager.find_negative_cycle(a).unwrap();
+
+        let dot = manager.to_dot();
+        assert!(dot.starts_with("digraph ArbGraph {\n"));
+        assert!(dot.contains(&format!("    n{} [label=\"A\\\"x\"];\n", a.index())));
+        let (from, to) = manager.graph.edge_endpoints(cycle[0]).unwrap();
+        let highlighted = format!(
+            "    n{} -> n{} [label=\"V2 fee=3000 w={:.6}\" color=\"red\" penwidth=2];\n",
+            from.index(),
+            to.index(),
+            manager.graph[cycle[0]].weight()
+        );
+        assert!(dot.contains(&highlighted), "{dot}");
+        assert_eq!(dot.matches("color=\"red\"").count(), 3);
+        assert_eq!(dot.matches(" -> ").count(), 6);
+    }
+
+    #[test]
+    fn snapshot_round_trips_through_json() {
+        let mut v3 = v3_pool(addr(11), addr(2), addr(3), PRICE_X96_ONE, E18);
+        v3.tick_bitmap = BTreeMap::from([(-60, E18 as i128), (60, -(E18 as i128))]);
+        let manager = graph_with([
+            PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), 1000 * E18, 2000 * E18)),
+            PoolVariant::V3(v3),
+            PoolVariant::V4(v4_pool(addr(1), addr(3), 500, PRICE_X96_ONE << 1, E18)),
+        ]);
+
+        let json = serde_json::to_string(&manager.snapshot()).unwrap();
+        let restored = GraphManager::from_snapshot(serde_json::from_str(&json).unwrap()).unwrap();
+
+        assert_eq!(serde_json::to_string(&restored.snapshot()).unwrap(), json);
+        assert_eq!(restored.node_map, manager.node_map);
+        for (token, original) in restored
+            .graph
+            .node_weights()
+            .zip(manager.graph.node_weights())
+        {
+            assert!(token.structurally_eq(original));
+        }
+        for edge in manager.graph.edge_indices() {
+            assert_eq!(
+                restored.graph.edge_endpoints(edge),
+                manager.graph.edge_endpoints(edge)
+            );
+            assert_eq!(restored.graph[edge].weight(), manager.graph[edge].weight());
+        }
+    }
+
+    #[test]
+    fn pools_below_the_liquidity_floor_are_skipped() {
+        let mut manager = GraphManager::new();
+        for byte in 1..=3 {
+            manager.add_or_get_token(token(addr(byte)));
+        }
+        manager.min_reserve = E18;
+        manager.min_liquidity = E18;
+
+        let shallow_v2 = v2_pool(addr(10), addr(1), addr(2), E18, E18 - 1);
+        let deep_v2 = v2_pool(addr(11), addr(1), addr(2), E18, E18);
+        let shallow_v3 = v3_pool(addr(12), addr(2), addr(3), PRICE_X96_ONE, E18 - 1);
+        let deep_v4 = v4_pool(addr(2), addr(3), 3000, PRICE_X96_ONE, E18);
+        assert_eq!(manager.add_pool(PoolVariant::V2(shallow_v2)), None);
+        assert!(manager.add_pool(PoolVariant::V2(deep_v2)).is_some());
+        assert_eq!(manager.add_pool(PoolVariant::V3(shallow_v3)), None);
+        assert!(manager.add_pool(PoolVariant::V4(deep_v4)).is_some());
+
+        assert_eq!(manager.graph.edge_count(), 4);
+        assert!(!manager.address_to_edge.contains_key(&addr(10)));
+    }
+
+    #[test]
+    fn v4_pools_sharing_a_hook_are_told_apart_by_pool_id() {
+        let low = v4_pool(addr(1), addr(2), 500, PRICE_X96_ONE, E18);
+        let high = v4_pool(addr(1), addr(2), 3000, PRICE_X96_ONE, E18);
+        let (low_id, high_id) = (low.key.id(), high.key.id());
+        let manager = graph_with([PoolVariant::V4(low), PoolVariant::V4(high)]);
+
+        for (id, fee) in [(low_id, 500), (high_id, 3000)] {
+            let edges = manager.edges_for_log(&v4_swap_log(id, PRICE_X96_ONE, E18, 0, fee));
+            assert_eq!(edges.len(), 2);
+            for &edge in edges {
+                assert_eq!(manager.graph[edge].pool.id(), id);
+                assert_eq!(manager.graph[edge].pool.fee_pips(), fee);
+            }
+        }
+    }
+
+    #[test]
+    fn add_pool_models_both_directions() {
+        let mut manager = GraphManager::new();
+        let a = manager.add_or_get_token(token(addr(1)));
+        let b = manager.add_or_get_token(token(addr(2)));
+        let pool = v2_pool(addr(10), addr(1), addr(2), 1000 * E18, 3000 * E18);
+        let (forward, backward) = manager.add_pool(PoolVariant::V2(pool)).unwrap();
+
+        assert_eq!(manager.graph.edge_endpoints(forward), Some((a, b)));
+        assert_eq!(manager.graph.edge_endpoints(backward), Some((b, a)));
+        assert!(manager.graph[forward].zero_for_one);
+        assert!(!manager.graph[backward].zero_for_one);
+        // Selling token0 fetches 3 token1 and vice versa, less the fee each way
+        let (w_forward, w_backward) = (
+            manager.graph[forward].weight(),
+            manager.graph[backward].weight(),
+        );
+        assert!((w_forward + (3.0 * 0.997f64).ln()).abs() < 1e-12);
+        assert!((w_backward + (0.997f64 / 3.0).ln()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn merged_native_and_weth_route_across_v4_and_v3() {
+        let usdc = addr(5);
+        // 1.1 USDC per ETH on V4, 1 USDC per WETH on V3 (USDC sorts first there)
+        let v4 = v4_pool(
+            Address::ZERO,
+            usdc,
+            3000,
+            get_sqrt_ratio_at_tick(953).unwrap(),
+            1000 * E18,
+        );
+        let v3 = v3_pool(addr(11), usdc, MAINNET_WETH, PRICE_X96_ONE, 1000 * E18);
+
+        let build = |manager: GraphManager| {
+            let mut manager = manager;
+            manager.add_or_get_token(Token::new(Address::ZERO, "ETH".into(), 18));
+            manager.add_or_get_token(Token::new(MAINNET_WETH, "WETH".into(), 18));
+            manager.add_or_get_token(token(usdc));
+            manager.add_pool(PoolVariant::V4(v4.clone())).unwrap();
+            manager.add_pool(PoolVariant::V3(v3.clone())).unwrap();
+            manager
+        };
+
+        let mut merged = build(GraphManager::new().with_native_weth_merged());
+        assert_eq!(merged.graph.node_count(), 2);
+        let weth = merged.node_map[&MAINNET_WETH];
+        assert_eq!(merged.node_map[&Address::ZERO], weth);
+        let cycle = merged.find_negative_cycle(weth).unwrap();
+        let versions: Vec<_> = cycle
+            .iter()
+            .map(|&e| merged.graph[e].pool.version())
+            .collect();
+        assert_eq!(versions, ["V4", "V3"]);
+
+        // Kept apart, neither pool closes a loop on its own
+        let mut split = build(GraphManager::new());
+        assert_eq!(split.graph.node_count(), 3);
+        let weth = split.node_map[&MAINNET_WETH];
+        assert_eq!(split.find_negative_cycle(weth), None);
+    }
+
+    /// A -> B -> C -> A over V2 pools 10, 11 and 12, 10% mispriced, plus a 1% A/B V3 pool (13)
+    /// and a 1% B/C V4 pool at balanced prices
+    fn mixed_graph() -> GraphManager {
+        graph_with([
+            PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), 1000 * E18, 1000 * E18)),
+            PoolVariant::V2(v2_pool(addr(11), addr(2), addr(3), 1000 * E18, 1000 * E18)),
+            PoolVariant::V2(v2_pool(addr(12), addr(3), addr(1), 1000 * E18, 1100 * E18)),
+            PoolVariant::V3(UniswapV3Pool {
+                fee: 10_000,
+                ..v3_pool(addr(13), addr(1), addr(2), PRICE_X96_ONE, E18)
+            }),
+            PoolVariant::V4(v4_pool(addr(2), addr(3), 10_000, PRICE_X96_ONE, E18)),
+        ])
+    }
+
+    #[test]
+    fn every_pool_is_found_by_its_address_or_pool_id() {
+        let manager = mixed_graph();
+        for address in [10, 11, 12, 13].map(addr) {
+            let edges = &manager.address_to_edge[&address];
+            assert_eq!(edges.len(), 2);
+            assert!(
+                edges
+                    .iter()
+                    .all(|&e| manager.graph[e].pool.address() == address)
+            );
+            assert_eq!(
+                manager.edges_for_log(&sync_log(address, 1, 1)),
+                edges.as_slice()
+            );
+        }
+
+        let v4_id = v4_pool(addr(2), addr(3), 10_000, PRICE_X96_ONE, E18)
+            .key
+            .id();
+        let edges = manager.edges_for_log(&v4_swap_log(v4_id, PRICE_X96_ONE, E18, 0, 10_000));
+        assert_eq!(edges, manager.pool_id_to_edge[&v4_id].as_slice());
+        assert_eq!(edges.len(), 2);
+        assert!(manager.edges_for_log(&sync_log(addr(99), 1, 1)).is_empty());
+    }
+
+    /// Every registry entry still points at edges carrying that pool
+    fn assert_registry_consistent(manager: &GraphManager) {
+        for (address, edges) in &manager.address_to_edge {
+            assert!(
+                edges
+                    .iter()
+                    .all(|&e| manager.graph[e].pool.address() == *address)
+            );
+        }
+        for (id, edges) in &manager.pool_id_to_edge {
+            assert!(edges.iter().all(|&e| manager.graph[e].pool.id() == *id));
+        }
+        let registered: usize = manager
+            .address_to_edge
+            .values()
+            .chain(manager.pool_id_to_edge.values())
+            .map(Vec::len)
+            .sum();
+        assert_eq!(registered, manager.graph.edge_count());
+    }
+
+    fn cycle_pools(manager: &mut GraphManager) -> Option<Vec<B256>> {
+        let a = manager.node_map[&addr(1)];
+        let cycle = manager.find_negative_cycle(a)?;
+        Some(cycle.iter().map(|&e| manager.graph[e].pool.id()).collect())
+    }
+
+    #[test]
+    fn removed_pools_leave_the_search() {
+        let mut manager = mixed_graph();
+        assert!(
+            cycle_pools(&mut manager)
+                .unwrap()
+                .contains(&addr(11).into_word())
+        );
+
+        // The B -> C leg moves to the V4 pool...
+        assert!(manager.remove_pool(addr(11)));
+        assert!(!manager.remove_pool(addr(11)));
+        assert_registry_consistent(&manager);
+        let v4_id = v4_pool(addr(2), addr(3), 10_000, PRICE_X96_ONE, E18)
+            .key
+            .id();
+        assert!(cycle_pools(&mut manager).unwrap().contains(&v4_id));
+
+        // ...and without it there is no loop left
+        assert!(manager.remove_v4_pool(v4_id));
+        assert_registry_consistent(&manager);
+        assert_eq!(manager.graph.edge_count(), 6);
+        assert_eq!(cycle_pools(&mut manager), None);
+    }
+
+    #[test]
+    fn replaced_pool_state_is_used_by_the_search() {
+        let mut manager = mixed_graph();
+        let balanced = v2_pool(addr(12), addr(3), addr(1), 1000 * E18, 1000 * E18);
+        assert!(manager.replace_pool(PoolVariant::V2(balanced)));
+        assert_registry_consistent(&manager);
+        assert_eq!(cycle_pools(&mut manager), None);
+
+        let unknown = v2_pool(addr(99), addr(3), addr(1), E18, E18);
+        assert!(!manager.replace_pool(PoolVariant::V2(unknown)));
+    }
+
+    #[test]
+    fn stale_edges_are_left_out_of_the_fresh_search() {
+        let mut manager = graph_with([
+            PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), 1000 * E18, 1000 * E18)),
+            PoolVariant::V2(v2_pool(addr(11), addr(2), addr(3), 1000 * E18, 1000 * E18)),
+            PoolVariant::V2(v2_pool(addr(12), addr(3), addr(1), 1000 * E18, 1000 * E18)),
+        ]);
+        // The mispriced pool last moved at block 50, the others at block 100
+        manager
+            .apply_logs(50, &[sync_log(addr(12), 1000 * E18, 1100 * E18)])
+            .unwrap();
+        let balanced = [10, 11].map(|pool| sync_log(addr(pool), 1000 * E18, 1000 * E18));
+        manager.apply_logs(100, &balanced).unwrap();
+        let a = manager.node_map[&addr(1)];
+
+        let stale = manager.stale_edges(100, 10);
+        assert_eq!(
+            stale,
+            manager.address_to_edge[&addr(12)].iter().copied().collect()
+        );
+        assert_eq!(manager.find_fresh_negative_cycle(a, 100, 10), None);
+        assert!(manager.last_cycle.is_empty());
+
+        // Within the lag the pool counts as fresh again
+        assert!(manager.stale_edges(100, 50).is_empty());
+        let cycle = manager.find_fresh_negative_cycle(a, 100, 50).unwrap();
+        assert_eq!(cycle.len(), 3);
+        assert_eq!(manager.last_cycle, cycle);
+        for &edge in &cycle {
+            let expected = if manager.graph[edge].pool.address() == addr(12) {
+                50
+            } else {
+                100
+            };
+            assert_eq!(manager.graph[edge].pool.last_updated_block(), expected);
+        }
+    }
+
+    #[test]
+    fn pruning_removes_tokens_left_without_pools() {
+        // Pool 11 is the only one trading tokens 3 and 4; token 6 never had a pool
+        let mut manager = graph_with([
+            PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), E18, E18)),
+            PoolVariant::V2(v2_pool(addr(11), addr(3), addr(4), E18, E18)),
+            PoolVariant::V2(v2_pool(addr(12), addr(2), addr(5), E18, E18)),
+        ]);
+        manager.add_or_get_token(token(addr(6)));
+        assert_eq!(manager.prune_isolated(), 1);
+        assert!(manager.remove_pool(addr(11)));
+        assert_eq!(manager.prune_isolated(), 2);
+        assert_eq!(manager.prune_isolated(), 0);
+
+        assert_eq!(manager.graph.node_count(), 3);
+        let mut remaining: Vec<Address> = manager.node_map.keys().copied().collect();
+        remaining.sort();
+        assert_eq!(remaining, [addr(1), addr(2), addr(5)]);
+        for (address, &node) in &manager.node_map {
+            assert_eq!(manager.graph[node].address, *address);
+        }
+        // The surviving edges still join the tokens of their pools
+        assert_registry_consistent(&manager);
+        for edge in manager.graph.edge_references() {
+            let (token0, token1) = edge.weight().pool.tokens();
+            let (token_in, token_out) = if edge.weight().zero_for_one {
+                (token0, token1)
+            } else {
+                (token1, token0)
+            };
+            assert_eq!(edge.source(), manager.node_map[&token_in]);
+            assert_eq!(edge.target(), manager.node_map[&token_out]);
+        }
+    }
+
+    #[test]
+    fn equal_parallel_pools_tie_break_on_the_lowest_address() {
+        let twin = |pool| PoolVariant::V2(v2_pool(addr(pool), addr(1), addr(2), E18, E18));
+        for order in [[0x20, 0x10], [0x10, 0x20]] {
+            // Every graph (and so every HashMap) is new, with its own iteration order
+            for _ in 0..20 {
+                let manager = graph_with(order.map(twin));
+                let (a, b) = (manager.node_map[&addr(1)], manager.node_map[&addr(2)]);
+                for (from, to) in [(a, b), (b, a)] {
+                    let best = manager.best_edge(from, to).unwrap();
+                    assert_eq!(manager.graph[best].pool.address(), addr(0x10));
+                    let (_, edge, _) = best_out_edges(&manager.graph, from)[0];
+                    assert_eq!(edge, best);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn equal_v4_pools_tie_break_on_the_pool_id() {
+        // Same hooks (none), fee and price: only the tick spacing, and so the id, differs
+        let wide = v4_pool(addr(1), addr(2), 3000, PRICE_X96_ONE, E18);
+        let mut narrow = wide.clone();
+        narrow.key.tick_spacing = 10;
+        let lowest = wide.key.id().min(narrow.key.id());
+
+        for pools in [[wide.clone(), narrow.clone()], [narrow, wide]] {
+            let manager = graph_with(pools.map(PoolVariant::V4));
+            let (a, b) = (manager.node_map[&addr(1)], manager.node_map[&addr(2)]);
+            let best = manager.best_edge(a, b).unwrap();
+            assert_eq!(manager.graph[best].pool.id(), lowest);
+        }
+    }
+
+    #[test]
+    fn the_depth_penalty_prefers_the_deeper_pool() {
+        // Same price; the shallow pool has the lower address, so it wins without the penalty
+        let mut manager = graph_with([
+            PoolVariant::V2(v2_pool(addr(0x10), addr(1), addr(2), E18, E18)),
+            PoolVariant::V2(v2_pool(
+                addr(0x20),
+                addr(1),
+                addr(2),
+                1_000_000 * E18,
+                1_000_000 * E18,
+            )),
+        ]);
+        let (a, b) = (manager.node_map[&addr(1)], manager.node_map[&addr(2)]);
+        let best = |manager: &GraphManager| {
+            manager.graph[manager.best_edge(a, b).unwrap()]
+                .pool
+                .address()
+        };
+        assert_eq!(best(&manager), addr(0x10));
+        assert!(
+            manager
+                .graph
+                .edge_weights()
+                .all(|edge| edge.depth_cost() == 0.0)
+        );
+
+        manager.set_depth_penalty(1e-3);
+        assert_eq!(best(&manager), addr(0x20));
+        // Pools added later get the penalty too, and the deepest one still wins
+        let deepest = v2_pool(
+            addr(0x30),
+            addr(1),
+            addr(2),
+            1_000_000_000 * E18,
+            1_000_000_000 * E18,
+        );
+        manager.add_pool(PoolVariant::V2(deepest)).unwrap();
+        assert!(
+            manager
+                .graph
+                .edge_weights()
+                .all(|edge| edge.depth_cost() > 0.0)
+        );
+        assert_eq!(best(&manager), addr(0x30));
+
+        manager.set_depth_penalty(0.0);
+        assert_eq!(best(&manager), addr(0x10));
+    }
+
+    #[test]
+    fn the_depth_penalty_keeps_real_cycles() {
+        let mut manager = mixed_graph();
+        manager.set_depth_penalty(1e-3);
+        let pools = cycle_pools(&mut manager).unwrap();
+        assert!(
+            [10, 11, 12]
+                .iter()
+                .all(|&pool| pools.contains(&addr(pool).into_word()))
+        );
+    }
+
+    #[test]
+    fn repeatedly_failing_pools_are_skipped_until_updated() {
+        let mut manager = mixed_graph();
+        let a = manager.node_map[&addr(1)];
+        let cycle = manager.find_negative_cycle(a).unwrap();
+        let first = manager.graph[cycle[0]].pool.id();
+        assert_eq!(first, addr(10).into_word());
+
+        // An input no pool can take overflows the first hop every time
+        for failures in 1..=DEFAULT_MAX_SIMULATION_FAILURES {
+            assert!(manager.tripped_edges().is_empty());
+            assert!(manager.simulate(&cycle, U256::MAX).is_err());
+            assert_eq!(manager.failure_counts()[&first], failures);
+        }
+        let tripped = manager.tripped_edges();
+        assert_eq!(tripped.len(), 2);
+        assert!(tripped.iter().all(|&e| manager.graph[e].pool.id() == first));
+
+        // The A -> B leg moves to the 1% V3 pool
+        let pools = cycle_pools(&mut manager).unwrap();
+        assert!(!pools.contains(&first));
+        assert!(pools.contains(&addr(13).into_word()));
+
+        // New state from the chain resets the count
+        manager
+            .update_from_log(&sync_log(addr(10), 1000 * E18 + 1, 1000 * E18))
+            .unwrap();
+        assert!(manager.failure_counts().is_empty());
+        assert!(manager.tripped_edges().is_empty());
+        assert!(cycle_pools(&mut manager).unwrap().contains(&first));
+    }
+
+    #[test]
+    fn cycles_through_rebasing_tokens_can_be_left_out() {
+        // A <-> B (rebasing) 10% apart, A <-> C 5% apart
+        let mut manager = GraphManager::new();
+        let rebasing = TokenBuilder::new(addr(2), "stETH".to_string(), 18)
+            .is_rebasing(true)
+            .build();
+        manager.add_or_get_token(rebasing);
+        manager.add_or_get_token(token(addr(1)));
+        manager.add_or_get_token(token(addr(3)));
+        for (pool, other, reserve) in [(10, 2, 1000), (11, 2, 1100), (12, 3, 1000), (13, 3, 1050)] {
+            let pool = v2_pool(addr(pool), addr(1), addr(other), 1000 * E18, reserve * E18);
+            manager.add_pool(PoolVariant::V2(pool)).unwrap();
+        }
+        let a = manager.node_map[&addr(1)];
+        let b = manager.node_map[&addr(2)];
+        let touches_b = |manager: &GraphManager, cycle: &[EdgeIndex]| {
+            cycle.iter().any(|&edge| {
+                let (from, to) = manager.graph.edge_endpoints(edge).unwrap();
+                from == b || to == b
+            })
+        };
+
+        let cycle = manager.search_negative_cycle(a).unwrap();
+        assert!(touches_b(&manager, &cycle));
+        assert_eq!(manager.rebasing_edges().len(), 4);
+
+        manager.exclude_rebasing = true;
+        let cycle = manager.search_negative_cycle(a).unwrap();
+        assert!(!touches_b(&manager, &cycle));
+        // Nothing is found from the rebasing token itself
+        assert_eq!(manager.search_negative_cycle(b), None);
+    }
+
+    #[test]
+    fn the_opportunity_stream_yields_each_blocks_news_and_errors() {
+        let mut manager = graph_with([
+            PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), 1000 * E18, 1000 * E18)),
+            PoolVariant::V2(v2_pool(addr(11), addr(2), addr(3), 1000 * E18, 1000 * E18)),
+            PoolVariant::V2(v2_pool(addr(12), addr(3), addr(1), 1000 * E18, 1000 * E18)),
+        ]);
+        let bases = [manager.node_map[&addr(1)]];
+        let config = ScanConfig {
+            top_n: 5,
+            max_in: U256::from(1000 * E18),
+            gas_per_hop: 100_000,
+            dedup_window: 10,
+        };
+        let unknown_event = log(addr(10), vec![B256::ZERO], &[]);
+        let blocks = stream::iter([
+            (1, vec![sync_log(addr(12), 1000 * E18, 1100 * E18)]),
+            // The same cycle again is suppressed, but the bad log still comes through
+            (
+                2,
+                vec![unknown_event, sync_log(addr(11), 1000 * E18, 1001 * E18)],
+            ),
+        ]);
+        let items: Vec<Result<Opportunity>> =
+            block_on(manager.opportunity_stream(blocks, &bases, config).collect());
+
+        assert_eq!(items.len(), 2);
+        let found = items[0].as_ref().unwrap();
+        assert_eq!(found.block, 1);
+        assert!(found.profit > U256::ZERO);
+        let error = items[1].as_ref().unwrap_err();
+        assert!(format!("{error:#}").contains("block 2"), "{error:#}");
+        // The rest of block 2 was applied
+        let edge = manager.address_to_edge[&addr(11)][0];
+        assert_eq!(manager.graph[edge].pool.last_updated_block(), 2);
+    }
+
+    #[test]
+    fn the_opportunity_stream_skips_tripped_pools() {
+        let mut manager = graph_with([
+            PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), 1000 * E18, 1000 * E18)),
+            PoolVariant::V2(v2_pool(addr(11), addr(2), addr(3), 1000 * E18, 1000 * E18)),
+            PoolVariant::V2(v2_pool(addr(12), addr(3), addr(1), 1000 * E18, 1100 * E18)),
+        ]);
+        let bases = [manager.node_map[&addr(1)]];
+        let config = ScanConfig {
+            top_n: 5,
+            max_in: U256::from(1000 * E18),
+            gas_per_hop: 100_000,
+            dedup_window: 10,
+        };
+        let cycle = manager.search_negative_cycle(bases[0]).unwrap();
+        assert_eq!(manager.scan_top_n(&bases, 1, &config).len(), 1);
+
+        // The only profitable cycle goes through pool 10, which keeps failing
+        for _ in 0..DEFAULT_MAX_SIMULATION_FAILURES {
+            assert!(manager.simulate(&cycle, U256::MAX).is_err());
+        }
+        let blocks = stream::iter([(2, Vec::new())]);
+        let items: Vec<Result<Opportunity>> =
+            block_on(manager.opportunity_stream(blocks, &bases, config).collect());
+        assert!(items.is_empty());
+    }
+
+    #[test]
+    fn a_stale_registry_is_an_error_rather_than_a_panic() {
+        let mut manager = graph_with([PoolVariant::V2(v2_pool(
+            addr(10),
+            addr(1),
+            addr(2),
+            1000 * E18,
+            1000 * E18,
+        ))]);
+        // Removing an edge directly moves the last one into its slot
+        manager.graph.remove_edge(EdgeIndex::new(0));
+        let error = manager
+            .apply_logs(1, &[sync_log(addr(10), E18, E18)])
+            .unwrap_err();
+        assert!(error.to_string().contains("reindex_pools"), "{error}");
+
+        manager.reindex_pools();
+        assert_eq!(
+            manager
+                .apply_logs(2, &[sync_log(addr(10), E18, E18)])
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn merging_keeps_the_fresher_state_of_shared_pools() {
+        let pool = |address, token0, token1, reserve1, block| {
+            PoolVariant::V2(UniswapV2Pool {
+                last_updated_block: block,
+                ..v2_pool(
+                    addr(address),
+                    addr(token0),
+                    addr(token1),
+                    1000 * E18,
+                    reserve1 * E18,
+                )
+            })
+        };
+        let mut manager = graph_with([pool(10, 1, 2, 1000, 5), pool(11, 2, 3, 1000, 5)]);
+        let other = graph_with([
+            pool(10, 1, 2, 1200, 9),
+            pool(11, 2, 3, 1300, 1),
+            pool(12, 3, 4, 1000, 1),
+        ]);
+
+        assert_eq!(manager.merge(other), 1);
+        // Tokens 1..=3 are shared, token 4 is new
+        assert_eq!(manager.graph.node_count(), 4);
+        assert_eq!(manager.graph.edge_count(), 6);
+        let reserve1 = |manager: &GraphManager, address| {
+            let edges = &manager.address_to_edge[&addr(address)];
+            assert_eq!(edges.len(), 2);
+            match &manager.graph[edges[0]].pool {
+                PoolVariant::V2(pool) => pool.reserve1 / E18,
+                _ => unreachable!("only V2 pools"),
+            }
+        };
+        // Pool 10 is fresher in the other graph, pool 11 in this one
+        assert_eq!(reserve1(&manager, 10), 1200);
+        assert_eq!(reserve1(&manager, 11), 1000);
+        assert_eq!(reserve1(&manager, 12), 1000);
+        assert!(manager.validate().is_empty());
+    }
+
+    #[test]
+    fn validation_reports_edges_inconsistent_with_their_pools() {
+        let mut manager = graph_with([
+            PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), 1000 * E18, 1000 * E18)),
+            PoolVariant::V2(v2_pool(addr(11), addr(2), addr(3), 1000 * E18, 1000 * E18)),
+        ]);
+        assert!(manager.validate().is_empty());
+        let node = |manager: &GraphManager, token| manager.node_map[&addr(token)];
+
+        // token0 -> token1 flagged as selling token1
+        let (a, b, c) = (node(&manager, 1), node(&manager, 2), node(&manager, 3));
+        let pool = PoolVariant::V2(v2_pool(addr(12), addr(1), addr(2), E18, E18));
+        let flipped = manager
+            .graph
+            .add_edge(a, b, GraphEdge::new(pool.clone(), false));
+        // Joining a token the pool doesn't trade
+        let stray = manager.graph.add_edge(a, c, GraphEdge::new(pool, true));
+        // A pool token without a node
+        let orphan = PoolVariant::V2(v2_pool(addr(13), addr(1), addr(9), E18, E18));
+        let unknown = manager.graph.add_edge(a, b, GraphEdge::new(orphan, true));
+
+        let errors = manager.validate();
+        assert_eq!(
+            errors,
+            [
+                ValidationError::DirectionMismatch {
+                    edge: flipped,
+                    zero_for_one: false
+                },
+                ValidationError::EndpointMismatch { edge: stray },
+                ValidationError::UnknownToken {
+                    edge: unknown,
+                    token: addr(9)
+                },
+            ]
+        );
+        assert!(
+            errors[0].to_string().contains("zero_for_one is false"),
+            "{}",
+            errors[0]
+        );
+    }
+
+    #[test]
+    fn probing_quotes_every_edge_once() {
+        let mut broken = v2_pool(addr(12), addr(1), addr(3), 1000 * E18, 1000 * E18);
+        broken.fee_bps = 10_001;
+        let manager = graph_with([
+            PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), 1000 * E18, 2000 * E18)),
+            PoolVariant::V3(v3_pool(addr(11), addr(2), addr(3), PRICE_X96_ONE, E18)),
+            PoolVariant::V2(broken),
+        ]);
+        let amount_in = U256::from(E18 / 1000);
+
+        let quotes: Vec<_> = manager.probe_edges(amount_in).collect();
+        assert_eq!(quotes.len(), manager.graph.edge_count());
+        let edges: HashSet<EdgeIndex> = quotes.iter().map(|(edge, _)| *edge).collect();
+        assert_eq!(edges.len(), quotes.len());
+
+        for (edge, quote) in quotes {
+            let GraphEdge {
+                pool, zero_for_one, ..
+            } = &manager.graph[edge];
+            if pool.address() == addr(12) {
+                // A failing pool doesn't stop the others being probed
+                assert!(quote.is_err());
+            } else {
+                assert_eq!(
+                    quote.unwrap(),
+                    pool.get_amount_out(amount_in, *zero_for_one).unwrap()
+                );
+            }
+        }
+        // Nothing is recorded against the failing pool
+        assert!(manager.failure_counts().is_empty());
+    }
+
+    #[test]
+    fn replaying_mints_and_swaps_rebuilds_the_pools() {
+        let v4 = v4_pool(addr(3), addr(4), 3000, PRICE_X96_ONE, 0);
+        let pool_id = v4.key.id();
+        let pools = vec![
+            PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), 0, 0)),
+            PoolVariant::V3(v3_pool(addr(11), addr(2), addr(3), PRICE_X96_ONE, 0)),
+            PoolVariant::V4(v4),
+        ];
+        let initial: Vec<_> = pools
+            .into_iter()
+            .map(|pool| {
+                let (token0, token1) = pool.tokens();
+                (pool, token(token0), token(token1))
+            })
+            .collect();
+
+        let price_after = get_sqrt_ratio_at_tick(-100).unwrap();
+        let logs = [
+            sync_log(addr(10), 1000 * E18, 2000 * E18),
+            position_log(addr(11), -600, 600, E18, true),
+            position_log(addr(11), -120, 120, E18, true),
+            v3_swap_log(addr(11), price_after, 2 * E18, -100),
+            modify_liquidity_log(pool_id, -60, 60, E18 as i64),
+            v4_swap_log(pool_id, price_after, E18, -100, 3000),
+            // Untracked pools are skipped
+            sync_log(addr(99), 1, 1),
+        ];
+        let manager = GraphManager::from_log_replay(initial.clone(), &logs).unwrap();
+        let pool = |log: &Log| &manager.graph[manager.edges_for_log(log)[0]].pool;
+
+        let PoolVariant::V2(v2) = pool(&logs[0]) else {
+            panic!("not the V2 pool")
+        };
+        assert_eq!((v2.reserve0, v2.reserve1), (1000 * E18, 2000 * E18));
+        let PoolVariant::V3(v3) = pool(&logs[1]) else {
+            panic!("not the V3 pool")
+        };
+        assert_eq!(
+            (v3.sqrt_price_x96, v3.tick, v3.liquidity),
+            (price_after, -100, 2 * E18)
+        );
+        assert_eq!(
+            v3.tick_bitmap,
+            BTreeMap::from([
+                (-600, E18 as i128),
+                (-120, E18 as i128),
+                (120, -(E18 as i128)),
+                (600, -(E18 as i128)),
+            ])
+        );
+        let PoolVariant::V4(v4) = pool(&logs[4]) else {
+            panic!("not the V4 pool")
+        };
+        assert_eq!(
+            (v4.sqrt_price_x96, v4.tick, v4.liquidity),
+            (price_after, -100, E18)
+        );
+        assert_eq!(v4.tick_bitmap.len(), 2);
+        // The replayed weights are fresh
+        assert!(
+            manager
+                .graph
+                .edge_indices()
+                .all(|e| manager.graph[e].weight().is_finite())
+        );
+
+        // A log that doesn't decode stops the replay, naming it
+        let mut truncated = logs[1].clone();
+        truncated.data.data = Default::default();
+        let Err(err) = GraphManager::from_log_replay(initial, &[logs[0].clone(), truncated]) else {
+            panic!("a truncated Mint log replayed")
+        };
+        assert!(err.to_string().starts_with("replaying log 1"), "{err}");
+    }
+
+    #[test]
+    fn base_nodes_skip_absent_and_repeated_tokens() {
+        let manager = graph_with([
+            PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), 1000 * E18, 1000 * E18)),
+            PoolVariant::V2(v2_pool(addr(11), addr(2), addr(3), 1000 * E18, 1000 * E18)),
+        ]);
+        let node = |byte| manager.node_map[&addr(byte)];
+        assert_eq!(
+            manager.base_nodes(&[addr(3), addr(9), addr(1), addr(3)]),
+            [node(3), node(1)]
+        );
+        assert!(manager.base_nodes(&[addr(9)]).is_empty());
+
+        // Native ETH and WETH share a node once merged
+        let mut merged = GraphManager::new().with_native_weth_merged();
+        let weth = merged.add_or_get_token(Token::new(MAINNET_WETH, "WETH".into(), 18));
+        merged.add_or_get_token(Token::new(Address::ZERO, "ETH".into(), 18));
+        assert_eq!(merged.base_nodes(&[Address::ZERO, MAINNET_WETH]), [weth]);
+    }
 }
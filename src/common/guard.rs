@@ -0,0 +1,137 @@
+use crate::common::graph::{ArbGraph, spot_cost};
+use crate::common::pool::PoolVariant;
+use alloy_primitives::Address;
+use petgraph::graph::EdgeIndex;
+use std::collections::HashMap;
+
+/// Rejects cycles through pools whose spot price strays too far from a reference (an oracle or
+/// TWAP), which is what a flash-manipulated pool looks like to the cycle search.
+#[derive(Debug, Clone, Default)]
+pub struct PriceGuard {
+    /// Reference price of each token, per whole token, all in the same numeraire (e.g. USD).
+    /// Hops touching a token without a reference aren't checked.
+    pub reference_prices: HashMap<Address, f64>,
+    /// Largest accepted deviation of a hop's spot price from the reference, in percent
+    pub max_deviation_pct: f64,
+}
+
+impl PriceGuard {
+    pub fn new(reference_prices: HashMap<Address, f64>, max_deviation_pct: f64) -> Self {
+        Self {
+            reference_prices,
+            max_deviation_pct,
+        }
+    }
+
+    /// How far the hop's spot price is from the reference rate, in percent, or `None` if either
+    /// token has no (positive) reference price.
+    ///
+    /// The spot price is taken from the edge weight (reserves or `sqrt_price_x96`), normalized to
+    /// whole tokens, with the swap fee (and a V2 pair's transfer taxes) backed out.
+    pub fn hop_deviation(&self, graph: &ArbGraph, edge: EdgeIndex) -> Option<f64> {
+        let (from, to) = graph.edge_endpoints(edge)?;
+        let reference = |token: &Address| {
+            self.reference_prices
+                .get(token)
+                .copied()
+                .filter(|&price| price > 0.0)
+        };
+        let expected = reference(&graph[from].address)? / reference(&graph[to].address)?;
+
+        let pool = &graph[edge].pool;
+        let mut keep = 1.0 - pool.fee_pips() as f64 / 1e6;
+        if let PoolVariant::V2(pool) = pool {
+            keep *= (1.0 - pool.transfer_fee_bps_in as f64 / 1e4)
+                * (1.0 - pool.transfer_fee_bps_out as f64 / 1e4);
+        }
+        let spot = (-spot_cost(graph, edge)).exp() / keep;
+
+        Some((spot / expected - 1.0).abs() * 100.0)
+    }
+
+    /// Whether every hop of `cycle` is within `max_deviation_pct` of the reference (or unchecked)
+    pub fn accepts(&self, graph: &ArbGraph, cycle: &[EdgeIndex]) -> bool {
+        cycle.iter().all(|&edge| {
+            self.hop_deviation(graph, edge)
+                .is_none_or(|deviation| deviation <= self.max_deviation_pct)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::graph::GraphManager;
+    use crate::common::pool::LiquidityPool;
+    use crate::common::test_util::{addr, graph_with, v2_pool};
+
+    const E18: u128 = 1_000_000_000_000_000_000;
+
+    /// A -> B -> C -> A over balanced V2 pools, except that C -> A pays 10% over the odds
+    fn triangle() -> GraphManager {
+        graph_with([
+            PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), 1000 * E18, 1000 * E18)),
+            PoolVariant::V2(v2_pool(addr(11), addr(2), addr(3), 1000 * E18, 1000 * E18)),
+            PoolVariant::V2(v2_pool(addr(12), addr(3), addr(1), 1000 * E18, 1100 * E18)),
+        ])
+    }
+
+    fn guard(max_deviation_pct: f64) -> PriceGuard {
+        let prices = (1..=3).map(|token| (addr(token), 1.0)).collect();
+        PriceGuard::new(prices, max_deviation_pct)
+    }
+
+    #[test]
+    fn cycles_through_an_outlier_pool_are_rejected() {
+        let mut manager = triangle();
+        let a = manager.node_map[&addr(1)];
+        let cycle = manager.search_negative_cycle(a).unwrap();
+
+        let deviations: Vec<f64> = cycle
+            .iter()
+            .map(|&edge| guard(5.0).hop_deviation(&manager.graph, edge).unwrap())
+            .collect();
+        let outlier = cycle
+            .iter()
+            .position(|&edge| manager.graph[edge].pool.address() == addr(12))
+            .unwrap();
+        for (hop, deviation) in deviations.iter().enumerate() {
+            let expected = if hop == outlier { 10.0 } else { 0.0 };
+            assert!(
+                (deviation - expected).abs() < 1e-9,
+                "hop {hop}: {deviation}"
+            );
+        }
+
+        manager.price_guard = Some(guard(5.0));
+        assert_eq!(manager.search_negative_cycle(a), None);
+        manager.price_guard = Some(guard(20.0));
+        assert_eq!(manager.search_negative_cycle(a), Some(cycle));
+    }
+
+    #[test]
+    fn transfer_taxes_are_backed_out_of_the_spot_price() {
+        let mut pool = v2_pool(addr(10), addr(1), addr(2), 1000 * E18, 1000 * E18);
+        pool.transfer_fee_bps_in = 500;
+        pool.transfer_fee_bps_out = 300;
+        let manager = graph_with([PoolVariant::V2(pool)]);
+
+        for edge in manager.graph.edge_indices() {
+            let deviation = guard(1.0).hop_deviation(&manager.graph, edge).unwrap();
+            assert!(deviation < 1e-9, "{deviation}");
+        }
+    }
+
+    #[test]
+    fn hops_without_a_reference_are_unchecked() {
+        let manager = triangle();
+        let guard = PriceGuard::new(HashMap::from([(addr(1), 1.0)]), 0.0);
+        let edges: Vec<EdgeIndex> = manager.graph.edge_indices().collect();
+        assert!(
+            edges
+                .iter()
+                .all(|&edge| guard.hop_deviation(&manager.graph, edge).is_none())
+        );
+        assert!(guard.accepts(&manager.graph, &edges));
+    }
+}
@@ -0,0 +1,925 @@
+use crate::common::pool::{
+    PoolKey, PoolVariant, STABLE_FEE_DENOMINATOR, StableSwapPool, UniswapV2Pool, UniswapV3Pool,
+    UniswapV4Pool, V4_DYNAMIC_FEE_FLAG, WEIGHTED_ONE, WeightCache, WeightedPool,
+};
+use crate::common::swap_math::FEE_DENOMINATOR;
+use crate::common::token::Token;
+use alloy_primitives::{Address, B256, Bytes, I256, U256, address, b256, keccak256};
+use alloy_sol_types::{SolCall, sol};
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::path::Path;
+
+/// Multicall3 is deployed at the same address on every EVM chain
+pub const MULTICALL3_ADDRESS: Address = address!("cA11bde05977b3631167028862bE2a173976CA11");
+
+/// Uniswap's TickLens periphery contract (same address on mainnet and Polygon)
+pub const TICK_LENS_ADDRESS: Address = address!("bfd8137f7d1516D3ea5cA83523914859ec47F573");
+
+/// Pools per Multicall3 request, to keep calls under node gas/size limits
+pub const MULTICALL_CHUNK_SIZE: usize = 100;
+
+/// Uniswap V3's factory (same address on mainnet, Polygon and Arbitrum)
+pub const UNISWAP_V3_FACTORY: Address = address!("1F98431c8aD98523631AE4a59f267346ea31F984");
+
+/// keccak256 of the UniswapV3Pool creation code (PoolAddress.POOL_INIT_CODE_HASH)
+pub const V3_POOL_INIT_CODE_HASH: B256 =
+    b256!("e34f199b19b2b4f47f68442619d555527d244f78a3297ea89379f9f8cd7ff2b5");
+
+/// The fee tiers enabled on Uniswap V3 factories, in hundredths of a bip
+pub const V3_FEE_TIERS: [u32; 4] = [100, 500, 3000, 10000];
+
+sol! {
+    struct Call3 {
+        address target;
+        bool allowFailure;
+        bytes callData;
+    }
+
+    struct Result3 {
+        bool success;
+        bytes returnData;
+    }
+
+    function aggregate3(Call3[] calls) external payable returns (Result3[] returnData);
+
+    interface IUniswapV3Pool {
+        function slot0() external view returns (
+            uint160 sqrtPriceX96,
+            int24 tick,
+            uint16 observationIndex,
+            uint16 observationCardinality,
+            uint16 observationCardinalityNext,
+            uint8 feeProtocol,
+            bool unlocked
+        );
+        function liquidity() external view returns (uint128);
+        function tickSpacing() external view returns (int24);
+        function fee() external view returns (uint24);
+        function token0() external view returns (address);
+        function token1() external view returns (address);
+    }
+
+    struct PopulatedTick {
+        int24 tick;
+        int128 liquidityNet;
+        uint128 liquidityGross;
+    }
+
+    interface ITickLens {
+        function getPopulatedTicksInWord(address pool, int16 tickBitmapIndex)
+            external
+            view
+            returns (PopulatedTick[] populatedTicks);
+    }
+
+    interface IERC20Metadata {
+        function symbol() external view returns (string);
+        function decimals() external view returns (uint8);
+    }
+}
+
+/// The one RPC primitive the loaders need: a read-only `eth_call` against the latest block.
+/// Implemented by whatever transport the bot runs with (and by mocks); wrap it in
+/// `rpc::RetryProvider` to stay within the node's rate limits.
+pub trait CallProvider {
+    fn call(&self, to: Address, data: Bytes) -> impl Future<Output = Result<Bytes>> + Send;
+}
+
+/// Addresses of the pools `factory` deploys for the pair at each of `V3_FEE_TIERS`, derived with
+/// CREATE2 as in PoolAddress.computeAddress. The tokens may be passed in either order. The pools
+/// may not exist yet: probe them (e.g. with `load_v3_pools`) and drop the ones that fail.
+/// Only valid for factories deploying the canonical pool code (`V3_POOL_INIT_CODE_HASH`).
+pub fn compute_v3_pool_addresses(
+    factory: Address,
+    token0: Address,
+    token1: Address,
+) -> [Address; 4] {
+    let (token0, token1) = if token0 < token1 {
+        (token0, token1)
+    } else {
+        (token1, token0)
+    };
+    V3_FEE_TIERS.map(|fee| {
+        // salt = keccak256(abi.encode(token0, token1, fee))
+        let mut encoded = [0u8; 3 * 32];
+        encoded[12..32].copy_from_slice(token0.as_slice());
+        encoded[44..64].copy_from_slice(token1.as_slice());
+        encoded[64..96].copy_from_slice(&U256::from(fee).to_be_bytes::<32>());
+        factory.create2(keccak256(encoded), V3_POOL_INIT_CODE_HASH)
+    })
+}
+
+/// Executes `calls` through Multicall3 with `allowFailure` set, returning each call's
+/// return data (or `None` if that individual call reverted).
+pub async fn multicall<P: CallProvider>(
+    provider: &P,
+    calls: Vec<(Address, Vec<u8>)>,
+) -> Result<Vec<Option<Bytes>>> {
+    let expected = calls.len();
+    let calls = calls
+        .into_iter()
+        .map(|(target, data)| Call3 {
+            target,
+            allowFailure: true,
+            callData: data.into(),
+        })
+        .collect();
+
+    let raw = provider
+        .call(
+            MULTICALL3_ADDRESS,
+            aggregate3Call { calls }.abi_encode().into(),
+        )
+        .await?;
+    let results = aggregate3Call::abi_decode_returns(&raw)?;
+    ensure!(
+        results.len() == expected,
+        "multicall returned {} results for {expected} calls",
+        results.len()
+    );
+
+    Ok(results
+        .into_iter()
+        .map(|r| r.success.then_some(r.returnData))
+        .collect())
+}
+
+/// Fetches token0/token1/fee/slot0/liquidity/tickSpacing for every pool in `addresses`, batched
+/// through Multicall3 in chunks of `MULTICALL_CHUNK_SIZE`.
+///
+/// Transport errors fail the whole load; a pool whose calls revert or don't decode only fails
+/// its own entry. The output has one entry per input address, in order. The tick bitmap is left
+/// empty.
+pub async fn load_v3_pools<P: CallProvider>(
+    provider: &P,
+    addresses: &[Address],
+) -> Result<Vec<Result<UniswapV3Pool>>> {
+    const CALLS_PER_POOL: usize = 6;
+
+    let mut pools = Vec::with_capacity(addresses.len());
+    for chunk in addresses.chunks(MULTICALL_CHUNK_SIZE) {
+        let calls = chunk
+            .iter()
+            .flat_map(|&pool| {
+                [
+                    (pool, IUniswapV3Pool::token0Call {}.abi_encode()),
+                    (pool, IUniswapV3Pool::token1Call {}.abi_encode()),
+                    (pool, IUniswapV3Pool::feeCall {}.abi_encode()),
+                    (pool, IUniswapV3Pool::slot0Call {}.abi_encode()),
+                    (pool, IUniswapV3Pool::liquidityCall {}.abi_encode()),
+                    (pool, IUniswapV3Pool::tickSpacingCall {}.abi_encode()),
+                ]
+            })
+            .collect();
+
+        let results = multicall(provider, calls).await?;
+        for (&address, results) in chunk.iter().zip(results.chunks(CALLS_PER_POOL)) {
+            pools.push(decode_v3_pool(address, results));
+        }
+    }
+
+    Ok(pools)
+}
+
+/// Builds a pool from its six multicall results (same order as in `load_v3_pools`)
+fn decode_v3_pool(address: Address, results: &[Option<Bytes>]) -> Result<UniswapV3Pool> {
+    let data = |i: usize| -> Result<&Bytes> {
+        results[i]
+            .as_ref()
+            .ok_or_else(|| anyhow!("call {i} to pool {address} reverted"))
+    };
+
+    let token0 = IUniswapV3Pool::token0Call::abi_decode_returns(data(0)?)?;
+    let token1 = IUniswapV3Pool::token1Call::abi_decode_returns(data(1)?)?;
+    let fee = IUniswapV3Pool::feeCall::abi_decode_returns(data(2)?)?;
+    let slot0 = IUniswapV3Pool::slot0Call::abi_decode_returns(data(3)?)?;
+    let liquidity = IUniswapV3Pool::liquidityCall::abi_decode_returns(data(4)?)?;
+    let tick_spacing = IUniswapV3Pool::tickSpacingCall::abi_decode_returns(data(5)?)?;
+
+    let mut pool = UniswapV3Pool {
+        address,
+        token0,
+        token1,
+        fee: fee.to(),
+        liquidity,
+        sqrt_price_x96: slot0.sqrtPriceX96.to(),
+        tick: slot0.tick.as_i32(),
+        tick_spacing: tick_spacing.as_i32(),
+        tick_bitmap: BTreeMap::new(),
+        dynamic_fee: None,
+        last_updated_block: 0,
+        weight_cache: WeightCache::default(),
+    };
+    pool.refresh_weights();
+    Ok(pool)
+}
+
+/// Fills `pool.tick_bitmap` with the net liquidity of every initialized tick within
+/// `range_spacings` tick spacings of the current tick, read from the TickLens at `tick_lens`.
+///
+/// TickLens works per bitmap word (256 tick spacings), so the ticks of every word overlapping the
+/// range are loaded, `MULTICALL_CHUNK_SIZE` words per request. The previous bitmap is replaced
+/// only if every word loads. Swaps that move past the loaded range won't see the ticks beyond it.
+pub async fn load_tick_bitmap<P: CallProvider>(
+    provider: &P,
+    tick_lens: Address,
+    pool: &mut UniswapV3Pool,
+    range_spacings: i32,
+) -> Result<()> {
+    ensure!(
+        pool.tick_spacing > 0,
+        "pool {} has no tick spacing",
+        pool.address
+    );
+    ensure!(range_spacings >= 0, "negative tick range");
+
+    // Same word layout as TickBitmap.position: compressed tick >> 8, rounding towards -inf
+    let compressed = pool.tick.div_euclid(pool.tick_spacing);
+    let word = |compressed: i32| (compressed >> 8).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+    let words: Vec<i16> = (word(compressed.saturating_sub(range_spacings))
+        ..=word(compressed.saturating_add(range_spacings)))
+        .collect();
+
+    let mut tick_bitmap = BTreeMap::new();
+    for chunk in words.chunks(MULTICALL_CHUNK_SIZE) {
+        let calls = chunk
+            .iter()
+            .map(|&word| {
+                let call = ITickLens::getPopulatedTicksInWordCall {
+                    pool: pool.address,
+                    tickBitmapIndex: word,
+                };
+                (tick_lens, call.abi_encode())
+            })
+            .collect();
+
+        let results = multicall(provider, calls).await?;
+        for (&word, data) in chunk.iter().zip(results) {
+            let data = data.ok_or_else(|| {
+                anyhow!("TickLens call for word {word} of {} reverted", pool.address)
+            })?;
+            for tick in ITickLens::getPopulatedTicksInWordCall::abi_decode_returns(&data)? {
+                // Ticks whose net liquidity cancels out don't change anything when crossed
+                if tick.liquidityNet != 0 {
+                    tick_bitmap.insert(tick.tick.as_i32(), tick.liquidityNet);
+                }
+            }
+        }
+    }
+
+    pool.tick_bitmap = tick_bitmap;
+    Ok(())
+}
+
+/// Fills in the `symbol` and `decimals` of `tokens` from their `symbol()` and `decimals()`,
+/// batched through Multicall3 in chunks of `MULTICALL_CHUNK_SIZE` tokens.
+///
+/// Symbols returned as `bytes32` (MKR, SAI and other early tokens) are read up to the first
+/// NUL. A field whose call reverts or doesn't decode keeps its current value, so tokens without
+/// metadata keep their placeholders; only transport errors fail.
+pub async fn enrich_tokens<P: CallProvider>(provider: &P, tokens: &mut [Token]) -> Result<()> {
+    for chunk in tokens.chunks_mut(MULTICALL_CHUNK_SIZE) {
+        let calls = chunk
+            .iter()
+            .flat_map(|token| {
+                [
+                    (token.address, IERC20Metadata::symbolCall {}.abi_encode()),
+                    (token.address, IERC20Metadata::decimalsCall {}.abi_encode()),
+                ]
+            })
+            .collect();
+
+        let results = multicall(provider, calls).await?;
+        for (token, results) in chunk.iter_mut().zip(results.chunks(2)) {
+            if let Some(symbol) = results[0].as_ref().and_then(|data| decode_symbol(data)) {
+                token.symbol = symbol;
+            }
+            if let Some(decimals) = results[1]
+                .as_ref()
+                .and_then(|data| IERC20Metadata::decimalsCall::abi_decode_returns(data).ok())
+            {
+                token.decimals = decimals;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Decodes a `symbol()` return value, either an ABI string or a NUL-padded `bytes32`
+fn decode_symbol(data: &[u8]) -> Option<String> {
+    if let Ok(symbol) = IERC20Metadata::symbolCall::abi_decode_returns(data) {
+        return Some(symbol);
+    }
+    if data.len() != 32 {
+        return None;
+    }
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    let symbol = std::str::from_utf8(&data[..end]).ok()?;
+    (!symbol.is_empty()).then(|| symbol.to_string())
+}
+
+/// A token of a JSON pool descriptor
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TokenDescriptor {
+    address: Address,
+    symbol: String,
+    decimals: u8,
+}
+
+/// One entry of a JSON pool list (see `load_pools_from_json`). Amounts are strings (decimal or
+/// 0x-hex), since JSON numbers can't hold most reserves; fees are in each protocol's own units.
+#[derive(Deserialize)]
+#[serde(tag = "version", deny_unknown_fields)]
+enum PoolDescriptor {
+    V2 {
+        address: Address,
+        tokens: [TokenDescriptor; 2],
+        /// In bps
+        fee: u32,
+        reserve0: U256,
+        reserve1: U256,
+    },
+    V3 {
+        address: Address,
+        tokens: [TokenDescriptor; 2],
+        /// In hundredths of a bip
+        fee: u32,
+        tick_spacing: i32,
+        sqrt_price_x96: U256,
+        liquidity: U256,
+        tick: i32,
+        /// (tick, net liquidity) pairs
+        #[serde(default)]
+        ticks: Vec<(i32, I256)>,
+    },
+    V4 {
+        tokens: [TokenDescriptor; 2],
+        /// In hundredths of a bip, or `V4_DYNAMIC_FEE_FLAG`
+        fee: u32,
+        tick_spacing: i32,
+        #[serde(default)]
+        hooks: Address,
+        sqrt_price_x96: U256,
+        liquidity: U256,
+        tick: i32,
+        #[serde(default)]
+        ticks: Vec<(i32, I256)>,
+    },
+    Stable {
+        address: Address,
+        tokens: [TokenDescriptor; 2],
+        /// In `STABLE_FEE_DENOMINATOR` units
+        fee: u64,
+        amp: u64,
+        balance0: U256,
+        balance1: U256,
+    },
+    Weighted {
+        address: Address,
+        pool_id: B256,
+        tokens: [TokenDescriptor; 2],
+        /// In `WEIGHTED_ONE` units, like the weights
+        fee: u64,
+        weights: [u64; 2],
+        balance0: U256,
+        balance1: U256,
+    },
+}
+
+/// A validated pool descriptor. Validation runs inside deserialization, so serde_json reports
+/// a rejected entry with its line and column.
+#[derive(Deserialize)]
+#[serde(try_from = "PoolDescriptor")]
+struct LoadedPool(PoolVariant, Token, Token);
+
+impl TryFrom<PoolDescriptor> for LoadedPool {
+    type Error = anyhow::Error;
+
+    fn try_from(descriptor: PoolDescriptor) -> Result<Self> {
+        let to_u128 = |value: U256, name: &str| {
+            u128::try_from(value).map_err(|_| anyhow!("{name} {value} overflows u128"))
+        };
+        let (pool, [token0, token1]) = match descriptor {
+            PoolDescriptor::V2 {
+                address,
+                tokens,
+                fee,
+                reserve0,
+                reserve1,
+            } => {
+                ensure!(fee < 10000, "V2 fee {fee} bps must be below 10000");
+                let pool = UniswapV2Pool {
+                    address,
+                    token0: tokens[0].address,
+                    token1: tokens[1].address,
+                    reserve0: to_u128(reserve0, "reserve0")?,
+                    reserve1: to_u128(reserve1, "reserve1")?,
+                    fee_bps: fee,
+                    ..Default::default()
+                };
+                (PoolVariant::V2(pool), tokens)
+            }
+            PoolDescriptor::V3 {
+                address,
+                tokens,
+                fee,
+                tick_spacing,
+                sqrt_price_x96,
+                liquidity,
+                tick,
+                ticks,
+            } => {
+                ensure!(fee < FEE_DENOMINATOR, "V3 fee {fee} out of range");
+                let pool = UniswapV3Pool {
+                    address,
+                    token0: tokens[0].address,
+                    token1: tokens[1].address,
+                    fee,
+                    liquidity: to_u128(liquidity, "liquidity")?,
+                    sqrt_price_x96,
+                    tick,
+                    tick_spacing: positive_spacing(tick_spacing)?,
+                    tick_bitmap: tick_map(ticks)?,
+                    dynamic_fee: None,
+                    last_updated_block: 0,
+                    weight_cache: WeightCache::default(),
+                };
+                (PoolVariant::V3(pool), tokens)
+            }
+            PoolDescriptor::V4 {
+                tokens,
+                fee,
+                tick_spacing,
+                hooks,
+                sqrt_price_x96,
+                liquidity,
+                tick,
+                ticks,
+            } => {
+                ensure!(
+                    fee < FEE_DENOMINATOR || fee == V4_DYNAMIC_FEE_FLAG,
+                    "V4 fee {fee} out of range (nor the dynamic-fee flag {V4_DYNAMIC_FEE_FLAG:#x})"
+                );
+                let pool = UniswapV4Pool {
+                    key: PoolKey {
+                        currency0: tokens[0].address,
+                        currency1: tokens[1].address,
+                        fee,
+                        tick_spacing: positive_spacing(tick_spacing)?,
+                        hooks,
+                    },
+                    liquidity: to_u128(liquidity, "liquidity")?,
+                    sqrt_price_x96,
+                    tick,
+                    tick_bitmap: tick_map(ticks)?,
+                    hook_address: hooks,
+                    dynamic_fee: None,
+                    last_updated_block: 0,
+                    weight_cache: WeightCache::default(),
+                };
+                (PoolVariant::V4(pool), tokens)
+            }
+            PoolDescriptor::Stable {
+                address,
+                tokens,
+                fee,
+                amp,
+                balance0,
+                balance1,
+            } => {
+                ensure!(
+                    fee < STABLE_FEE_DENOMINATOR,
+                    "StableSwap fee {fee} out of range"
+                );
+                ensure!(amp > 0, "StableSwap amp must be positive");
+                let precision = |token: &TokenDescriptor| {
+                    let shift = 18u8.checked_sub(token.decimals).ok_or_else(|| {
+                        anyhow!("StableSwap token {} has over 18 decimals", token.address)
+                    })?;
+                    Ok::<_, anyhow::Error>(U256::from(10).pow(U256::from(shift)))
+                };
+                let pool = StableSwapPool {
+                    address,
+                    token0: tokens[0].address,
+                    token1: tokens[1].address,
+                    balance0,
+                    balance1,
+                    precision0: precision(&tokens[0])?,
+                    precision1: precision(&tokens[1])?,
+                    amp,
+                    fee,
+                    ..Default::default()
+                };
+                (PoolVariant::Stable(pool), tokens)
+            }
+            PoolDescriptor::Weighted {
+                address,
+                pool_id,
+                tokens,
+                fee,
+                weights,
+                balance0,
+                balance1,
+            } => {
+                ensure!(fee < WEIGHTED_ONE, "weighted pool fee {fee} out of range");
+                ensure!(
+                    weights[0] != 0 && weights[0].checked_add(weights[1]) == Some(WEIGHTED_ONE),
+                    "weights {weights:?} must be positive and sum to {WEIGHTED_ONE}"
+                );
+                let pool = WeightedPool {
+                    address,
+                    pool_id,
+                    token0: tokens[0].address,
+                    token1: tokens[1].address,
+                    balance0,
+                    balance1,
+                    weight0: weights[0],
+                    weight1: weights[1],
+                    swap_fee: fee,
+                    ..Default::default()
+                };
+                (PoolVariant::Weighted(pool), tokens)
+            }
+        };
+
+        // Pools sort their pair, so the order also tells the two tokens apart
+        ensure!(
+            token0.address < token1.address,
+            "tokens must be distinct and sorted ({} before {})",
+            token0.address,
+            token1.address
+        );
+        let token = |t: TokenDescriptor| Token::new(t.address, t.symbol, t.decimals);
+        Ok(LoadedPool(pool, token(token0), token(token1)))
+    }
+}
+
+fn positive_spacing(tick_spacing: i32) -> Result<i32> {
+    ensure!(
+        tick_spacing > 0,
+        "tick spacing {tick_spacing} must be positive"
+    );
+    Ok(tick_spacing)
+}
+
+fn tick_map(ticks: Vec<(i32, I256)>) -> Result<BTreeMap<i32, i128>> {
+    let mut map = BTreeMap::new();
+    for (tick, net) in ticks {
+        let net = i128::try_from(net).map_err(|_| anyhow!("net liquidity {net} overflows i128"))?;
+        if map.insert(tick, net).is_some() {
+            bail!("tick {tick} listed twice");
+        }
+    }
+    Ok(map)
+}
+
+/// Reads a JSON array of pool descriptors, e.g. exported from a subgraph, into pools and their
+/// two tokens (ready for `GraphManager::ingest`).
+///
+/// Each entry has a `version` (`V2`, `V3`, `V4`, `Stable` or `Weighted`), a sorted pair of
+/// `tokens` (`address`, `symbol`, `decimals`), a `fee` in the protocol's units and the pool's
+/// state (reserves, or price/liquidity/ticks, ...). Unknown versions or fields, missing or
+/// out-of-range values are rejected with the line and column of the offending entry.
+pub fn load_pools_from_json(path: &Path) -> Result<Vec<(PoolVariant, Token, Token)>> {
+    let json = std::fs::read_to_string(path)
+        .with_context(|| format!("reading pool list {}", path.display()))?;
+    let pools: Vec<LoadedPool> = serde_json::from_str(&json)
+        .with_context(|| format!("parsing pool list {}", path.display()))?;
+    Ok(pools
+        .into_iter()
+        .map(|LoadedPool(pool, token0, token1)| (pool, token0, token1))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::test_util::{addr, v3_pool};
+    use alloy_primitives::aliases::I24;
+    use alloy_primitives::{I256, U256};
+    use alloy_sol_types::SolValue;
+    use futures::executor::block_on;
+    use std::cell::Cell;
+
+    /// Answers each call of a Multicall3 batch with `respond(target, calldata)`, where `None`
+    /// makes that call revert
+    struct MockChain<F> {
+        respond: F,
+        requests: Cell<usize>,
+    }
+
+    impl<F: Fn(Address, &[u8]) -> Option<Vec<u8>>> MockChain<F> {
+        fn new(respond: F) -> Self {
+            Self {
+                respond,
+                requests: Cell::new(0),
+            }
+        }
+    }
+
+    impl<F: Fn(Address, &[u8]) -> Option<Vec<u8>>> CallProvider for MockChain<F> {
+        fn call(&self, to: Address, data: Bytes) -> impl Future<Output = Result<Bytes>> + Send {
+            assert_eq!(to, MULTICALL3_ADDRESS);
+            self.requests.set(self.requests.get() + 1);
+            let results: Vec<Result3> = aggregate3Call::abi_decode(&data)
+                .expect("an aggregate3 call")
+                .calls
+                .into_iter()
+                .map(|call| match (self.respond)(call.target, &call.callData) {
+                    Some(data) => Result3 {
+                        success: true,
+                        returnData: data.into(),
+                    },
+                    None => Result3 {
+                        success: false,
+                        returnData: Bytes::new(),
+                    },
+                })
+                .collect();
+            std::future::ready(Ok(results.abi_encode().into()))
+        }
+    }
+
+    const REVERTING_POOL: Address = Address::repeat_byte(0xee);
+
+    /// Canned V3 pool state: tick -5 on spacing 60, 777 liquidity
+    fn v3_pool_state(target: Address, calldata: &[u8]) -> Option<Vec<u8>> {
+        if target == REVERTING_POOL {
+            return None;
+        }
+        let selector: [u8; 4] = calldata[..4].try_into().unwrap();
+        Some(match selector {
+            IUniswapV3Pool::token0Call::SELECTOR => Address::repeat_byte(1).abi_encode(),
+            IUniswapV3Pool::token1Call::SELECTOR => Address::repeat_byte(2).abi_encode(),
+            IUniswapV3Pool::feeCall::SELECTOR => U256::from(3000).abi_encode(),
+            IUniswapV3Pool::slot0Call::SELECTOR => (
+                U256::from(1) << 96,
+                I256::try_from(-5).unwrap(),
+                U256::ZERO,
+                U256::ZERO,
+                U256::ZERO,
+                U256::ZERO,
+                true,
+            )
+                .abi_encode_params(),
+            IUniswapV3Pool::liquidityCall::SELECTOR => U256::from(777).abi_encode(),
+            IUniswapV3Pool::tickSpacingCall::SELECTOR => I256::try_from(60).unwrap().abi_encode(),
+            _ => return None,
+        })
+    }
+
+    #[test]
+    fn loads_v3_pools_in_chunks() {
+        let addresses: Vec<Address> = (0..150).map(Address::with_last_byte).collect();
+        let chain = MockChain::new(v3_pool_state);
+        let pools = block_on(load_v3_pools(&chain, &addresses)).unwrap();
+
+        assert_eq!(chain.requests.get(), 2);
+        assert_eq!(pools.len(), 150);
+        for (pool, &address) in pools.iter().zip(&addresses) {
+            let pool = pool.as_ref().unwrap();
+            assert_eq!(pool.address, address);
+            assert_eq!(
+                (pool.token0, pool.token1),
+                (Address::repeat_byte(1), Address::repeat_byte(2))
+            );
+            assert_eq!(
+                (pool.fee, pool.tick, pool.tick_spacing, pool.liquidity),
+                (3000, -5, 60, 777)
+            );
+            assert_eq!(pool.sqrt_price_x96, U256::from(1) << 96);
+        }
+    }
+
+    #[test]
+    fn a_reverting_pool_only_fails_its_own_entry() {
+        let addresses = [
+            Address::with_last_byte(1),
+            REVERTING_POOL,
+            Address::with_last_byte(2),
+        ];
+        let pools = block_on(load_v3_pools(&MockChain::new(v3_pool_state), &addresses)).unwrap();
+        assert!(pools[0].is_ok() && pools[2].is_ok());
+        assert!(pools[1].is_err());
+    }
+
+    const TICK_LENS: Address = Address::repeat_byte(0x7e);
+
+    /// Canned TickLens: ticks -120 and 60 (and a cancelled-out -60) in words -1 and 0
+    fn tick_lens_state(target: Address, calldata: &[u8]) -> Option<Vec<u8>> {
+        assert_eq!(target, TICK_LENS);
+        let call = ITickLens::getPopulatedTicksInWordCall::abi_decode(calldata).unwrap();
+        let populated = |tick: i32, net: i128| PopulatedTick {
+            tick: I24::try_from(tick).unwrap(),
+            liquidityNet: net,
+            liquidityGross: net.unsigned_abs(),
+        };
+        let ticks = match call.tickBitmapIndex {
+            -1 => vec![populated(-120, 500), populated(-60, 0)],
+            0 => vec![populated(60, -500)],
+            1 => return None,
+            _ => vec![],
+        };
+        Some(ITickLens::getPopulatedTicksInWordCall::abi_encode_returns(
+            &ticks,
+        ))
+    }
+
+    #[test]
+    fn loads_the_tick_bitmap_from_the_tick_lens() {
+        let chain = MockChain::new(tick_lens_state);
+        let mut pool = v3_pool(addr(1), addr(2), addr(3), U256::from(1) << 96, 1000);
+        block_on(load_tick_bitmap(&chain, TICK_LENS, &mut pool, 200)).unwrap();
+
+        // Tick 0 with ±200 spacings spans words -1 and 0
+        assert_eq!(chain.requests.get(), 1);
+        assert_eq!(pool.tick_bitmap, BTreeMap::from([(-120, 500), (60, -500)]));
+    }
+
+    /// Canned token metadata: addr(1) is USDC, addr(2) returns a `bytes32` symbol like MKR,
+    /// addr(3) has no metadata at all and addr(4) no `decimals()`
+    fn token_metadata(target: Address, calldata: &[u8]) -> Option<Vec<u8>> {
+        let selector: [u8; 4] = calldata[..4].try_into().unwrap();
+        let symbol = selector == IERC20Metadata::symbolCall::SELECTOR;
+        Some(match (target[19], symbol) {
+            (1, true) => "USDC".to_string().abi_encode(),
+            (1, false) => U256::from(6).abi_encode(),
+            (2, true) => {
+                let mut word = [0u8; 32];
+                word[..3].copy_from_slice(b"MKR");
+                word.to_vec()
+            }
+            (2, false) => U256::from(18).abi_encode(),
+            (4, true) => "ODD".to_string().abi_encode(),
+            _ => return None,
+        })
+    }
+
+    #[test]
+    fn enriches_tokens_with_string_and_bytes32_symbols() {
+        let mut tokens: Vec<Token> = (1..=4)
+            .map(|n| Token::new(Address::with_last_byte(n), "?".to_string(), 0))
+            .collect();
+        let chain = MockChain::new(token_metadata);
+        block_on(enrich_tokens(&chain, &mut tokens)).unwrap();
+
+        assert_eq!(chain.requests.get(), 1);
+        let metadata: Vec<(&str, u8)> = tokens
+            .iter()
+            .map(|token| (token.symbol.as_str(), token.decimals))
+            .collect();
+        // Calls that revert leave the placeholders in place
+        assert_eq!(metadata, [("USDC", 6), ("MKR", 18), ("?", 0), ("ODD", 0)]);
+    }
+
+    #[test]
+    fn a_reverting_tick_lens_word_keeps_the_previous_bitmap() {
+        let chain = MockChain::new(tick_lens_state);
+        let mut pool = v3_pool(addr(1), addr(2), addr(3), U256::from(1) << 96, 1000);
+        pool.tick_bitmap.insert(600, 1);
+        // ±300 spacings reaches word 1, which reverts
+        assert!(block_on(load_tick_bitmap(&chain, TICK_LENS, &mut pool, 300)).is_err());
+        assert_eq!(pool.tick_bitmap, BTreeMap::from([(600, 1)]));
+    }
+
+    /// Writes `json` to a file of its own under the temp dir
+    fn pool_list(name: &str, json: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("pools-{}-{name}.json", std::process::id()));
+        std::fs::write(&path, json).unwrap();
+        path
+    }
+
+    const TOKENS_AB: &str = r#"[
+        {"address": "0x1111111111111111111111111111111111111111", "symbol": "A", "decimals": 18},
+        {"address": "0x2222222222222222222222222222222222222222", "symbol": "B", "decimals": 6}
+    ]"#;
+    const TOKENS_BA: &str = r#"[
+        {"address": "0x2222222222222222222222222222222222222222", "symbol": "B", "decimals": 6},
+        {"address": "0x1111111111111111111111111111111111111111", "symbol": "A", "decimals": 18}
+    ]"#;
+    const TOKENS_BC: &str = r#"[
+        {"address": "0x2222222222222222222222222222222222222222", "symbol": "B", "decimals": 6},
+        {"address": "0x3333333333333333333333333333333333333333", "symbol": "C", "decimals": 18}
+    ]"#;
+
+    #[test]
+    fn ingests_a_json_pool_list_of_every_version() {
+        let json = format!(
+            r#"[
+            {{"version": "V2", "address": "0x00000000000000000000000000000000000000a1",
+              "tokens": {TOKENS_AB}, "fee": 30, "reserve0": "1000000000000000000000",
+              "reserve1": "0x77359400"}},
+            {{"version": "V3", "address": "0x00000000000000000000000000000000000000a2",
+              "tokens": {TOKENS_BC}, "fee": 500, "tick_spacing": 10,
+              "sqrt_price_x96": "79228162514264337593543950336", "liquidity": "1000000000000",
+              "tick": 0, "ticks": [[-10, "1000"], [10, "-1000"]]}},
+            {{"version": "V4", "tokens": {TOKENS_AB}, "fee": 8388608, "tick_spacing": 60,
+              "sqrt_price_x96": "79228162514264337593543950336", "liquidity": "5000",
+              "tick": 0}},
+            {{"version": "Stable", "address": "0x00000000000000000000000000000000000000a3",
+              "tokens": {TOKENS_BC}, "fee": 4000000, "amp": 200, "balance0": "1000000",
+              "balance1": "1000000000000000000"}},
+            {{"version": "Weighted", "address": "0x00000000000000000000000000000000000000a4",
+              "pool_id": "0x00000000000000000000000000000000000000a4000200000000000000000001",
+              "tokens": {TOKENS_AB}, "fee": 3000000000000000,
+              "weights": [800000000000000000, 200000000000000000],
+              "balance0": "4000", "balance1": "1000"}}
+        ]"#
+        );
+        let pools = load_pools_from_json(&pool_list("all", &json)).unwrap();
+        let versions: Vec<&str> = pools.iter().map(|(pool, _, _)| pool.version()).collect();
+        assert_eq!(versions, ["V2", "V3", "V4", "Stable", "Weighted"]);
+
+        let PoolVariant::V3(v3) = &pools[1].0 else {
+            panic!("not a V3 pool")
+        };
+        assert_eq!(v3.tick_bitmap, BTreeMap::from([(-10, 1000), (10, -1000)]));
+        let PoolVariant::Stable(stable) = &pools[3].0 else {
+            panic!("not a StableSwap pool")
+        };
+        assert_eq!(stable.precision0, U256::from(10u128.pow(12)));
+
+        let mut manager = crate::common::graph::GraphManager::new();
+        assert_eq!(manager.ingest(pools), 5);
+        assert_eq!(manager.graph.node_count(), 3);
+        assert_eq!(manager.graph.edge_count(), 10);
+        let b = &manager.graph[manager.node_map[&Address::repeat_byte(0x22)]];
+        assert_eq!((b.symbol.as_str(), b.decimals), ("B", 6));
+    }
+
+    #[test]
+    fn rejects_malformed_entries_with_their_position() {
+        let v4 = |fee: u32| {
+            format!(
+                r#"[
+                {{"version": "V4", "tokens": {TOKENS_AB}, "fee": {fee}, "tick_spacing": 60,
+                  "sqrt_price_x96": "1", "liquidity": "1", "tick": 0}}
+            ]"#
+            )
+        };
+        assert!(load_pools_from_json(&pool_list("v4-ok", &v4(999_999))).is_ok());
+
+        let cases = [
+            ("v4-fee", v4(FEE_DENOMINATOR), "V4 fee"),
+            ("v4-fee-flag", v4(V4_DYNAMIC_FEE_FLAG + 1), "V4 fee"),
+            (
+                "version",
+                format!(r#"[{{"version": "V5", "tokens": {TOKENS_AB}}}]"#),
+                "unknown variant",
+            ),
+            (
+                "unsorted",
+                format!(
+                    r#"[{{"version": "V2", "address": "0x00000000000000000000000000000000000000a1",
+                    "tokens": {TOKENS_BA}, "fee": 30, "reserve0": "1", "reserve1": "1"}}]"#
+                ),
+                "sorted",
+            ),
+        ];
+        for (name, json, message) in cases {
+            let error = load_pools_from_json(&pool_list(name, &json)).unwrap_err();
+            let error = format!("{error:#}");
+            assert!(error.contains(message), "{name}: {error}");
+            assert!(error.contains("line"), "{name}: {error}");
+        }
+    }
+
+    #[test]
+    fn v3_pool_addresses_follow_the_create2_derivation() {
+        let usdc = address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48");
+        let weth = address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+        let pools = compute_v3_pool_addresses(UNISWAP_V3_FACTORY, usdc, weth);
+
+        // keccak256(0xff ++ factory ++ keccak256(abi.encode(token0, token1, fee)) ++ init code
+        // hash)[12..], with the salt encoded by the ABI encoder rather than by hand
+        for (pool, fee) in pools.iter().zip(V3_FEE_TIERS) {
+            let salt = keccak256((usdc, weth, U256::from(fee)).abi_encode_params());
+            let mut preimage = vec![0xff];
+            preimage.extend_from_slice(UNISWAP_V3_FACTORY.as_slice());
+            preimage.extend_from_slice(salt.as_slice());
+            preimage.extend_from_slice(V3_POOL_INIT_CODE_HASH.as_slice());
+            assert_eq!(
+                *pool,
+                Address::from_slice(&keccak256(preimage)[12..]),
+                "fee {fee}"
+            );
+        }
+
+        // Either token order, one distinct pool per tier, per factory
+        assert_eq!(
+            compute_v3_pool_addresses(UNISWAP_V3_FACTORY, weth, usdc),
+            pools
+        );
+        let unique: std::collections::HashSet<_> = pools.iter().collect();
+        assert_eq!(unique.len(), V3_FEE_TIERS.len());
+        assert!(
+            compute_v3_pool_addresses(addr(9), usdc, weth)
+                .iter()
+                .all(|pool| !pools.contains(pool))
+        );
+    }
+}
@@ -0,0 +1,76 @@
+use alloy_primitives::U256;
+
+/// Converts `x` to the nearest `f64` (round half to even), for any `U256`.
+///
+/// The top 64 bits are rounded to a 53-bit mantissa, with the discarded low bits folded into a
+/// sticky bit so the rounding is exact, then scaled back up by a power of two. The relative error
+/// is therefore at most 2^-53 (~1.1e-16) across the whole range, and since U256::MAX is ~1.2e77
+/// the result is always finite.
+pub fn u256_to_f64(x: U256) -> f64 {
+    let bits = x.bit_len();
+    if bits <= 64 {
+        return x.to::<u64>() as f64;
+    }
+
+    let shift = bits - 64;
+    let top = (x >> shift).to::<u64>();
+    let dropped = x & ((U256::from(1) << shift) - U256::from(1));
+    let sticky = u64::from(dropped != U256::ZERO);
+    (top | sticky) as f64 * 2f64.powi(shift as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::uint;
+
+    /// |f64 - x| / x, measured exactly in U256 by converting the f64 back
+    fn relative_error(x: U256) -> f64 {
+        let approx = U256::from(u256_to_f64(x));
+        let diff = if approx > x { approx - x } else { x - approx };
+        u256_to_f64(diff) / u256_to_f64(x)
+    }
+
+    #[test]
+    fn small_values_are_exact() {
+        for x in [0u64, 1, 42, 1 << 53, u64::MAX >> 11] {
+            assert_eq!(u256_to_f64(U256::from(x)), x as f64);
+        }
+        assert_eq!(u256_to_f64(U256::from(u64::MAX)), u64::MAX as f64);
+    }
+
+    #[test]
+    fn mid_and_huge_values_stay_within_half_an_ulp() {
+        let values = [
+            U256::from(u64::MAX) + U256::from(1),
+            uint!(1_000_000_000_000_000_000_000_000_U256),
+            uint!(79228162514264337593543950336_U256) * uint!(12345678901234567_U256),
+            U256::MAX >> 1,
+            // 53 ones, then a 0 and ones: just under half an ulp, so it rounds down
+            U256::MAX ^ (U256::from(1) << 202),
+        ];
+        for x in values {
+            assert!(relative_error(x) <= 2f64.powi(-53), "{x}");
+        }
+        // Values within half an ulp of 2^256 round up to it
+        assert_eq!(u256_to_f64(U256::MAX), 2f64.powi(256));
+        assert!(u256_to_f64(U256::MAX).is_finite());
+    }
+
+    #[test]
+    fn rounds_half_to_even_using_every_dropped_bit() {
+        let base = U256::from(1) << 200;
+        let ulp = U256::from(1) << (200 - 52);
+        // Exactly half an ulp above an even mantissa rounds down...
+        assert_eq!(u256_to_f64(base + ulp / U256::from(2)), 2f64.powi(200));
+        // ...but any lower set bit (beyond the top 64) tips it up
+        let tipped = base + ulp / U256::from(2) + U256::from(1);
+        assert_eq!(u256_to_f64(tipped), 2f64.powi(200) * (1.0 + 2f64.powi(-52)));
+        // Half an ulp above an odd mantissa rounds up to even
+        let odd = base + ulp;
+        assert_eq!(
+            u256_to_f64(odd + ulp / U256::from(2)),
+            2f64.powi(200) * (1.0 + 2.0 * 2f64.powi(-52))
+        );
+    }
+}
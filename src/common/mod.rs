@@ -0,0 +1,25 @@
+pub mod calldata;
+pub mod chains;
+pub mod error;
+pub mod events;
+pub mod graph;
+pub mod guard;
+pub mod loader;
+pub mod math;
+pub mod peg;
+pub mod pool;
+pub mod profit;
+pub mod rpc;
+pub mod scan;
+pub mod shared;
+pub mod solver;
+pub mod spfa;
+pub mod swap_math;
+pub mod sync;
+#[cfg(test)]
+pub(crate) mod test_util;
+pub mod tick_bitmap;
+pub mod tickmath;
+pub mod token;
+pub mod triangle;
+pub mod verify;
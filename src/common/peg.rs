@@ -0,0 +1,253 @@
+use crate::common::graph::{ArbGraph, GraphEdge, best_out_edges};
+use crate::common::math::u256_to_f64;
+use crate::common::solver::simulate_cycle;
+use crate::common::spfa::layered_walk;
+use crate::common::token::Token;
+use alloy_primitives::{Address, U256};
+use anyhow::{Context, Result, ensure};
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use std::collections::{HashMap, HashSet};
+
+/// Tokens treated as the same value (e.g. USD stablecoins), each pegged to a common unit.
+///
+/// A path from one member to another is then a trade like any cycle: it's profitable if its
+/// output, valued at the peg ratio, is worth more than its input.
+#[derive(Debug, Clone, Default)]
+pub struct PegSet {
+    pegs: HashMap<Address, f64>,
+}
+
+impl PegSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `token`, one whole unit of which is worth `peg` of the common unit (1.0 for each
+    /// stablecoin of a USD set)
+    pub fn with_token(mut self, token: Address, peg: f64) -> Self {
+        self.pegs.insert(token, peg);
+        self
+    }
+
+    pub fn peg(&self, token: &Address) -> Option<f64> {
+        self.pegs.get(token).copied()
+    }
+
+    pub fn contains(&self, token: &Address) -> bool {
+        self.pegs.contains_key(token)
+    }
+
+    /// Raw units of `to` worth one raw unit of `from` at the pegs, or `None` unless both are
+    /// members
+    pub fn conversion_rate(&self, from: &Token, to: &Token) -> Option<f64> {
+        let decimals_shift = to.decimals as i32 - from.decimals as i32;
+        Some(self.peg(&from.address)? / self.peg(&to.address)? * 10f64.powi(decimals_shift))
+    }
+
+    /// Cost of swapping `from` back into `to` at the pegs, decimals-normalized like `edge_cost`
+    fn peg_cost(&self, from: &Token, to: &Token) -> Option<f64> {
+        Some(-(self.peg(&from.address)? / self.peg(&to.address)?).ln())
+    }
+}
+
+/// Finds the paths of at most `max_hops` edges from one member of `pegs` to another that make
+/// money when the output is valued at the peg, i.e. whose cost plus the swap back at the pegs
+/// is below `-min_profit_threshold` (see `GraphManager::min_profit_threshold`).
+///
+/// Runs `max_hops` rounds of Bellman-Ford from every member, like `find_negative_cycle_within`,
+/// and reports the cheapest simple path found for each (start, end) pair, cheapest first. Paths
+/// back to their own start are plain cycles and are left to the regular search.
+pub fn find_peg_paths(
+    graph: &ArbGraph,
+    pegs: &PegSet,
+    max_hops: usize,
+    min_profit_threshold: f64,
+) -> Vec<Vec<EdgeIndex>> {
+    let members: Vec<NodeIndex> = graph
+        .node_indices()
+        .filter(|&node| pegs.contains(&graph[node].address))
+        .collect();
+    let n = graph.node_count();
+
+    let mut found: Vec<(Vec<EdgeIndex>, f64)> = Vec::new();
+    for &start in &members {
+        let mut best: HashMap<NodeIndex, (Vec<EdgeIndex>, f64)> = HashMap::new();
+        let mut dist = vec![f64::INFINITY; n];
+        dist[start.index()] = 0.0;
+        // pred[k][v]: last edge of the cheapest k-edge walk from start to v
+        let mut pred: Vec<Vec<Option<EdgeIndex>>> = vec![vec![None; n]];
+
+        for _ in 0..max_hops {
+            let mut next = vec![f64::INFINITY; n];
+            let mut next_pred = vec![None; n];
+            for u in graph.node_indices() {
+                if dist[u.index()].is_infinite() {
+                    continue;
+                }
+                for (v, edge, weight) in best_out_edges(graph, u) {
+                    let candidate = dist[u.index()] + weight;
+                    if candidate < next[v.index()] {
+                        next[v.index()] = candidate;
+                        next_pred[v.index()] = Some(edge);
+                    }
+                }
+            }
+            dist = next;
+            pred.push(next_pred);
+
+            for &end in members.iter().filter(|&&end| end != start) {
+                let Some(peg_cost) = pegs.peg_cost(&graph[end], &graph[start]) else {
+                    continue;
+                };
+                let total = dist[end.index()] + peg_cost;
+                if total >= -min_profit_threshold
+                    || best.get(&end).is_some_and(|(_, b)| *b <= total)
+                {
+                    continue;
+                }
+                if let Some(path) = layered_walk(graph, &pred, end).filter(|p| is_simple(graph, p))
+                {
+                    best.insert(end, (path, total));
+                }
+            }
+        }
+        found.extend(best.into_values());
+    }
+
+    found.sort_by(|a, b| a.1.total_cmp(&b.1));
+    found.into_iter().map(|(path, _)| path).collect()
+}
+
+/// Profit of trading `amount_in` along `path`, in raw units of its first token, with the
+/// output valued at the pegs. Negative if the trade loses money.
+pub fn peg_profit(
+    graph: &ArbGraph,
+    pegs: &PegSet,
+    path: &[EdgeIndex],
+    amount_in: U256,
+) -> Result<f64> {
+    ensure!(!path.is_empty(), "empty path");
+    let (start, _) = graph.edge_endpoints(path[0]).context("edge not in graph")?;
+    let (_, end) = graph
+        .edge_endpoints(path[path.len() - 1])
+        .context("edge not in graph")?;
+    let rate = pegs
+        .conversion_rate(&graph[end], &graph[start])
+        .with_context(|| format!("{} or {} isn't pegged", graph[start], graph[end]))?;
+
+    let edges: Vec<GraphEdge> = path.iter().map(|&e| graph[e].clone()).collect();
+    let amount_out = simulate_cycle(&edges, amount_in)?;
+    Ok(u256_to_f64(amount_out) * rate - u256_to_f64(amount_in))
+}
+
+/// Whether `path` visits every token at most once
+fn is_simple(graph: &ArbGraph, path: &[EdgeIndex]) -> bool {
+    let mut seen = HashSet::new();
+    path.iter().all(|&edge| {
+        graph
+            .edge_endpoints(edge)
+            .is_some_and(|(from, _)| seen.insert(from))
+    }) && path
+        .last()
+        .and_then(|&edge| graph.edge_endpoints(edge))
+        .is_some_and(|(_, to)| !seen.contains(&to))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::graph::GraphManager;
+    use crate::common::pool::{LiquidityPool, PoolVariant};
+    use crate::common::test_util::{addr, graph_with, v2_pool};
+
+    const E18: u128 = 1_000_000_000_000_000_000;
+
+    /// USDC (1) buys 2% more USDT (2) directly; DAI (3) trades at par with both
+    fn stables() -> GraphManager {
+        let pool = |address, token0, token1, reserve1| {
+            PoolVariant::V2(v2_pool(
+                addr(address),
+                addr(token0),
+                addr(token1),
+                1_000_000 * E18,
+                reserve1 * E18,
+            ))
+        };
+        graph_with([
+            pool(10, 1, 2, 1_020_000),
+            pool(11, 1, 3, 1_000_000),
+            pool(12, 3, 2, 1_000_000),
+        ])
+    }
+
+    fn usd() -> PegSet {
+        PegSet::new()
+            .with_token(addr(1), 1.0)
+            .with_token(addr(2), 1.0)
+    }
+
+    #[test]
+    fn finds_a_usdc_to_usdt_path_at_par() {
+        let manager = stables();
+        let paths = find_peg_paths(&manager.graph, &usd(), 3, manager.min_profit_threshold);
+        assert_eq!(paths.len(), 1);
+        let path = &paths[0];
+        let pools: Vec<Address> = path
+            .iter()
+            .map(|&e| manager.graph[e].pool.address())
+            .collect();
+        assert_eq!(pools, [addr(10)]);
+        let (start, _) = manager.graph.edge_endpoints(path[0]).unwrap();
+        assert_eq!(manager.graph[start].address, addr(1));
+
+        let profit = peg_profit(&manager.graph, &usd(), path, U256::from(1000 * E18)).unwrap();
+        // About 2% less the 0.3% fee and a little price impact
+        assert!(
+            profit > 15.0 * E18 as f64 && profit < 17.0 * E18 as f64,
+            "{profit}"
+        );
+    }
+
+    #[test]
+    fn the_peg_ratio_decides_the_direction() {
+        let manager = stables();
+        // USDT at 0.97 makes 1.02 USDT worth less than the USDC paid, but USDT now buys USDC
+        // cheaply, best through DAI at par
+        let discounted = PegSet::new()
+            .with_token(addr(1), 1.0)
+            .with_token(addr(2), 0.97);
+        let paths = find_peg_paths(&manager.graph, &discounted, 3, manager.min_profit_threshold);
+        assert_eq!(paths.len(), 1);
+        let pools: Vec<Address> = paths[0]
+            .iter()
+            .map(|&e| manager.graph[e].pool.address())
+            .collect();
+        assert_eq!(pools, [addr(12), addr(11)]);
+        let profit = peg_profit(&manager.graph, &discounted, &paths[0], U256::from(E18)).unwrap();
+        assert!(profit > 0.0, "{profit}");
+        // Non-members have no peg
+        assert!(
+            find_peg_paths(
+                &manager.graph,
+                &PegSet::new().with_token(addr(1), 1.0),
+                3,
+                manager.min_profit_threshold,
+            )
+            .is_empty()
+        );
+    }
+
+    #[test]
+    fn conversion_rates_follow_the_decimals() {
+        let pegs = usd().with_token(addr(3), 0.5);
+        let usdc = Token::new(addr(1), "USDC".to_string(), 6);
+        let usdt = Token::new(addr(2), "USDT".to_string(), 6);
+        let half = Token::new(addr(3), "HALF".to_string(), 18);
+        assert_eq!(pegs.conversion_rate(&usdc, &usdt), Some(1.0));
+        // A raw unit of USDC is 1e-6 USD, or 2e-6 HALF: 2e12 raw units
+        let rate = pegs.conversion_rate(&usdc, &half).unwrap();
+        assert!((rate / 2e12 - 1.0).abs() < 1e-12, "{rate}");
+        let stranger = Token::new(addr(4), "X".to_string(), 18);
+        assert_eq!(pegs.conversion_rate(&usdc, &stranger), None);
+    }
+}
@@ -1,8 +1,27 @@
-use alloy_primitives::{Address, Log, U256};
-use anyhow::Result;
+use crate::common::error::{PoolError, PoolResult, pool_ensure};
+use crate::common::events::{
+    abi_i32, abi_word, decode_burn, decode_mint, decode_modify_liquidity, decode_sync,
+    decode_v3_swap, decode_v4_swap,
+};
+use crate::common::math::u256_to_f64;
+use crate::common::swap_math::{
+    FEE_DENOMINATOR, compute_swap_step, compute_swap_step_exact_output, mul_div,
+    mul_div_rounding_up,
+};
+use crate::common::tickmath::{
+    MAX_SQRT_RATIO, MAX_TICK, MIN_SQRT_RATIO, MIN_TICK, get_sqrt_ratio_at_tick, sqrt_price_to_price,
+};
+use alloy_primitives::{Address, B256, I256, Log, U256, b256, keccak256};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
-/// A unified behavior for any DEX pool (V2, V3, V4)
+pub use crate::common::events::{
+    V2_SYNC_EVENT, V3_BURN_EVENT, V3_MINT_EVENT, V3_SWAP_EVENT, V4_MODIFY_LIQUIDITY_EVENT,
+    V4_SWAP_EVENT,
+};
+
+/// A unified behavior for any DEX pool (V2, V3, V4, StableSwap, Balancer weighted)
 pub trait LiquidityPool {
     /// Returns the address of the pool contract (or the Hook address for V4)
     fn address(&self) -> Address;
@@ -10,46 +29,331 @@ pub trait LiquidityPool {
     /// Returns (token0, token1)
     fn tokens(&self) -> (Address, Address);
 
-    /// Calculates the 'cost' for the graph edge.
-    /// Usually -log(price * (1 - fee))
+    /// Calculates the 'cost' for the graph edge: `-ln(price)`, where `price` is the marginal
+    /// output per unit of input in this direction, in raw units and net of fees, i.e.
+    /// `-ln(get_marginal_price(zero_for_one))`. `f64::INFINITY` if the direction can't be traded.
+    ///
+    /// Every pool type follows this sign, so around a closed cycle the weights sum to
+    /// `-ln(product of rates)` (decimal shifts cancel): negative exactly when an infinitesimal
+    /// trade comes back with more than it started with, ~0 for a fee-free break-even loop, and
+    /// `-sum ln(1 - fee)` > 0 for a loop that is break-even before fees.
     fn get_log_weight(&self, zero_for_one: bool) -> f64;
 
     /// Simulates a swap to get exact output.
     /// Used by the Newton-Raphson solver to calculate f(x).
-    fn get_amount_out(&self, amount_in: U256, zero_for_one: bool) -> Result<U256>;
+    fn get_amount_out(&self, amount_in: U256, zero_for_one: bool) -> PoolResult<U256>;
+
+    /// Inverse of `get_amount_out`: the input needed to receive `amount_out`.
+    /// Fails if the pool can't provide that much.
+    fn get_amount_in(&self, amount_out: U256, zero_for_one: bool) -> PoolResult<U256>;
 
     /// Calculates the marginal price (derivative) at the current state.
     /// Used by Newton-Raphson to calculate f'(x).
     fn get_marginal_price(&self, zero_for_one: bool) -> f64;
 
     /// Updates the internal state (reserves, ticks, liquidity) from a blockchain Log.
-    fn update_from_log(&mut self, log: &Log) -> Result<()>;
+    fn update_from_log(&mut self, log: &Log) -> PoolResult<()>;
+}
+
+/// Log weights for both trade directions, recomputed only when the pool state changes so the
+/// hot SPFA loop never evaluates `ln`. NaN marks a direction as not computed yet.
+#[derive(Debug, Clone, Copy)]
+pub struct WeightCache {
+    zero_for_one: f64,
+    one_for_zero: f64,
+}
+
+impl Default for WeightCache {
+    fn default() -> Self {
+        Self {
+            zero_for_one: f64::NAN,
+            one_for_zero: f64::NAN,
+        }
+    }
+}
+
+impl WeightCache {
+    /// The cached weight for a direction, if it has been computed
+    pub fn get(&self, zero_for_one: bool) -> Option<f64> {
+        let weight = if zero_for_one {
+            self.zero_for_one
+        } else {
+            self.one_for_zero
+        };
+        (!weight.is_nan()).then_some(weight)
+    }
+
+    /// Builds a cache by evaluating `compute` for both directions
+    pub fn compute(compute: impl Fn(bool) -> f64) -> Self {
+        Self {
+            zero_for_one: compute(true),
+            one_for_zero: compute(false),
+        }
+    }
+}
+
+/// 1.0 in the Q64.96 fixed-point format used by `marginal_price_x96`: a price `p` is stored as
+/// `floor(p * 2^96)`, i.e. 96 fractional bits with the integer part in the bits above
+pub const PRICE_X96_ONE: U256 = U256::from_limbs([0, 1 << 32, 0, 0]);
+
+#[cfg(test)]
+thread_local! {
+    /// Calls to `log_weight_from_price_x96` on this thread, so tests can tell a cached weight
+    /// from a recomputed one
+    static LOG_WEIGHT_EVALUATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// Edge weight (-ln) of a fee-adjusted Q64.96 marginal price. V2 and V3/V4 weights all go
+/// through here, so pools quoting the same price get the same weight whatever their state is
+/// made of. A zero price (empty pool, or below 2^-96) can't be traded: infinite weight.
+///
+/// With 96 fractional bits, precision drops for raw prices far below 1 (to ~1e-5 relative at
+/// 1e-24). Prices are rounded down, so such edges only ever look slightly worse, never better.
+fn log_weight_from_price_x96(price_x96: U256) -> f64 {
+    #[cfg(test)]
+    LOG_WEIGHT_EVALUATIONS.with(|count| count.set(count.get() + 1));
+    if price_x96 == U256::ZERO {
+        return f64::INFINITY;
+    }
+    // Dividing by 2^96 is exact in f64, and taking ln of the price itself (rather than
+    // subtracting 96 ln 2) keeps near-1 prices precise
+    -(u256_to_f64(price_x96) / u256_to_f64(PRICE_X96_ONE)).ln()
 }
 
-#[derive(Debug, Clone)]
+/// ln(1 + i/32) for i in 0..=32, the mantissa table of `approx_ln`
+const LN_MANTISSA_TABLE: [f64; 33] = [
+    0.0,
+    0.030771658666753687,
+    0.06062462181643484,
+    0.08961215868968714,
+    0.11778303565638346,
+    0.1451820098444979,
+    0.17185025692665923,
+    0.19782574332991987,
+    0.22314355131420976,
+    0.24783616390458127,
+    0.27193371548364176,
+    0.2954642128938359,
+    0.3184537311185346,
+    0.3409265869705932,
+    0.3629054936893685,
+    0.38441169891033206,
+    0.4054651081081644,
+    0.4260843953109001,
+    0.44628710262841953,
+    0.46608972992459924,
+    0.4855078157817008,
+    0.5045560107523953,
+    0.5232481437645479,
+    0.5415972824327444,
+    0.5596157879354227,
+    0.5773153650348236,
+    0.5947071077466928,
+    0.6118015411059929,
+    0.6286086594223741,
+    0.6451379613735847,
+    0.661398482245365,
+    0.6773988235918061,
+    std::f64::consts::LN_2,
+];
+
+/// Cheap ln(x): the bit length gives the power of two, and the mantissa is interpolated
+/// linearly in `LN_MANTISSA_TABLE`. Off by less than 1.3e-4; -inf for 0.
+fn approx_ln(x: U256) -> f64 {
+    if x == U256::ZERO {
+        return f64::NEG_INFINITY;
+    }
+    // The top 24 bits after the leading one: 5 to index the table, 19 to interpolate
+    let exponent = x.bit_len() - 1;
+    let top = if exponent >= 24 {
+        (x >> (exponent - 24)).to::<u32>()
+    } else {
+        (x << (24 - exponent)).to::<u32>()
+    } & 0xff_ffff;
+    let index = (top >> 19) as usize;
+    let frac = (top & 0x7_ffff) as f64 / (1 << 19) as f64;
+
+    let low = LN_MANTISSA_TABLE[index];
+    let mantissa = low + (LN_MANTISSA_TABLE[index + 1] - low) * frac;
+    exponent as f64 * std::f64::consts::LN_2 + mantissa
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct UniswapV2Pool {
     pub address: Address,
     pub token0: Address,
     pub token1: Address,
     pub reserve0: u128, // Using u128 fits V2 u112 reserves
     pub reserve1: u128,
-    pub fee_bps: u32, // Usually 30 (0.3%)
+    pub fee_bps: u32, // 30 (0.3%) on Uniswap; forks often charge less (e.g. 25 or 20)
+
+    // Fee-on-transfer taxes, in bps: charged on the input as it's sent into the pool and on
+    // the output as it's sent to the trader. 0 for regular tokens.
+    pub transfer_fee_bps_in: u32,
+    pub transfer_fee_bps_out: u32,
+
+    // Block of the last log applied through GraphManager::apply_logs (0 if never updated)
+    #[serde(default)]
+    pub last_updated_block: u64,
+
+    // Refreshed by update_from_log / refresh_weights
+    #[serde(skip)]
+    pub weight_cache: WeightCache,
 }
 
-impl LiquidityPool for UniswapV2Pool {
-    fn get_amount_out(&self, amount_in: U256, zero_for_one: bool) -> Result<U256> {
-        // Standard x*y=k formula
-        let (r_in, r_out) = if zero_for_one {
+impl UniswapV2Pool {
+    /// An empty pool charging `fee_bps` (30 on Uniswap, less on many forks), to be completed
+    /// with struct update syntax: `UniswapV2Pool { reserve0, ..UniswapV2Pool::with_fee(25) }`.
+    /// Quotes, marginal prices and weights all use this fee.
+    pub fn with_fee(fee_bps: u32) -> Self {
+        Self {
+            fee_bps,
+            ..Default::default()
+        }
+    }
+
+    /// Returns (reserve_in, reserve_out) for the given trade direction
+    pub fn reserves(&self, zero_for_one: bool) -> (u128, u128) {
+        if zero_for_one {
             (self.reserve0, self.reserve1)
         } else {
             (self.reserve1, self.reserve0)
-        };
+        }
+    }
 
-        let amount_in_with_fee = amount_in * U256::from(10000 - self.fee_bps);
-        let numerator = amount_in_with_fee * U256::from(r_out);
-        let denominator = (U256::from(r_in) * U256::from(10000)) + amount_in_with_fee;
+    /// Whether the swap fee and transfer taxes leave anything to trade
+    fn fees_valid(&self) -> bool {
+        self.fee_bps < 10000
+            && self.transfer_fee_bps_in < 10000
+            && self.transfer_fee_bps_out < 10000
+    }
 
-        Ok(numerator / denominator)
+    /// Fraction of the value that survives the swap fee and both transfer taxes
+    fn fee_multiplier(&self) -> f64 {
+        let keep = |bps: u32| 1.0 - bps as f64 / 10000.0;
+        keep(self.fee_bps) * keep(self.transfer_fee_bps_in) * keep(self.transfer_fee_bps_out)
+    }
+
+    /// Applies a Sync log to the reserves without refreshing the cached weights.
+    /// Returns whether the pool state changed.
+    pub fn apply_log(&mut self, log: &Log) -> PoolResult<bool> {
+        if log.address != self.address {
+            return Err(PoolError::AddressMismatch {
+                pool: self.address,
+                log: log.address,
+            });
+        }
+        let topic = log.topics().first();
+        if topic != Some(&V2_SYNC_EVENT) {
+            return Err(PoolError::UnknownEvent(topic.copied()));
+        }
+        let sync = decode_sync(log).map_err(PoolError::MalformedLog)?;
+        self.reserve0 = sync.reserve0;
+        self.reserve1 = sync.reserve1;
+        Ok(true)
+    }
+
+    /// Recomputes the cached log weights; call after editing reserves directly
+    pub fn refresh_weights(&mut self) {
+        self.weight_cache =
+            WeightCache::compute(|zero_for_one| self.compute_log_weight(zero_for_one));
+    }
+
+    fn compute_log_weight(&self, zero_for_one: bool) -> f64 {
+        // Empty (or fully-fee'd) pools price at 0; the infinite cost prunes the edge
+        log_weight_from_price_x96(self.marginal_price_x96(zero_for_one))
+    }
+
+    /// Exact-integer counterpart of `get_marginal_price`, in Q64.96 (see `PRICE_X96_ONE`):
+    /// `(y/x)` scaled by the swap fee and both transfer taxes, rounded down. Unlike the `f64`
+    /// version it keeps full precision for 18-decimal reserves. 0 for empty or fully-fee'd pools.
+    pub fn marginal_price_x96(&self, zero_for_one: bool) -> U256 {
+        let (r_in, r_out) = self.reserves(zero_for_one);
+        if r_in == 0 || r_out == 0 || !self.fees_valid() {
+            return U256::ZERO;
+        }
+
+        let keep = |bps: u32| U256::from(10000 - bps);
+        let numerator = U256::from(r_out)
+            * keep(self.fee_bps)
+            * keep(self.transfer_fee_bps_in)
+            * keep(self.transfer_fee_bps_out);
+        let denominator = U256::from(r_in) * U256::from(10000u64.pow(3));
+
+        // At most r_out * 2^96 < 2^224, so this can't overflow
+        mul_div(numerator, PRICE_X96_ONE, denominator).unwrap_or_default()
+    }
+}
+
+impl LiquidityPool for UniswapV2Pool {
+    fn get_amount_out(&self, amount_in: U256, zero_for_one: bool) -> PoolResult<U256> {
+        pool_ensure!(
+            self.fees_valid(),
+            InvalidState,
+            "pool {} has invalid fees (fee_bps {}, transfer taxes {}/{})",
+            self.address,
+            self.fee_bps,
+            self.transfer_fee_bps_in,
+            self.transfer_fee_bps_out
+        );
+
+        // The pool only receives what's left after the input token's transfer tax
+        let amount_in = apply_bps_tax(amount_in, self.transfer_fee_bps_in);
+
+        // Standard x*y=k formula
+        let (r_in, r_out) = self.reserves(zero_for_one);
+
+        let amount_in_with_fee = amount_in
+            .checked_mul(U256::from(10000 - self.fee_bps))
+            .ok_or_else(|| {
+                PoolError::Overflow(format!("amount_in {amount_in} overflows with fee"))
+            })?;
+        // r_in * 10000 always fits (u128 * u16), only the sum can overflow
+        let denominator = (U256::from(r_in) * U256::from(10000))
+            .checked_add(amount_in_with_fee)
+            .ok_or_else(|| {
+                PoolError::Overflow(format!("amount_in {amount_in} overflows denominator"))
+            })?;
+
+        // 512-bit intermediate for amount_in_with_fee * r_out
+        let amount_out = mul_div(amount_in_with_fee, U256::from(r_out), denominator)?;
+
+        // ...and the trader only receives what's left after the output token's tax
+        Ok(apply_bps_tax(amount_out, self.transfer_fee_bps_out))
+    }
+
+    fn get_amount_in(&self, amount_out: U256, zero_for_one: bool) -> PoolResult<U256> {
+        pool_ensure!(
+            self.fees_valid(),
+            InvalidState,
+            "pool {} has invalid fees (fee_bps {}, transfer taxes {}/{})",
+            self.address,
+            self.fee_bps,
+            self.transfer_fee_bps_in,
+            self.transfer_fee_bps_out
+        );
+
+        // The pool has to send enough to cover the output token's tax
+        let pool_out = gross_of_bps_tax(amount_out, self.transfer_fee_bps_out)?;
+
+        let (r_in, r_out) = self.reserves(zero_for_one);
+        pool_ensure!(
+            pool_out < U256::from(r_out),
+            InsufficientLiquidity,
+            "pool {} can't pay out {pool_out} (reserve {r_out})",
+            self.address
+        );
+
+        // getAmountIn: r_in * out * 10000 / ((r_out - out) * (10000 - fee)) + 1
+        let numerator = U256::from(r_in)
+            .checked_mul(pool_out)
+            .and_then(|n| n.checked_mul(U256::from(10000)))
+            .ok_or_else(|| PoolError::Overflow(format!("amount_out {amount_out} overflows")))?;
+        let denominator = (U256::from(r_out) - pool_out) * U256::from(10000 - self.fee_bps);
+        let pool_in = numerator / denominator + U256::from(1);
+
+        // ...and the pool has to receive that much after the input token's tax
+        gross_of_bps_tax(pool_in, self.transfer_fee_bps_in)
     }
 
     // ... implement other methods
@@ -60,17 +364,58 @@ impl LiquidityPool for UniswapV2Pool {
         (self.token0, self.token1)
     }
     fn get_log_weight(&self, zero_for_one: bool) -> f64 {
-        todo!("-log(price)")
+        self.weight_cache
+            .get(zero_for_one)
+            .unwrap_or_else(|| self.compute_log_weight(zero_for_one))
     }
     fn get_marginal_price(&self, zero_for_one: bool) -> f64 {
-        todo!("y/x")
+        let (r_in, r_out) = self.reserves(zero_for_one);
+        if r_in == 0 || r_out == 0 || !self.fees_valid() {
+            return 0.0;
+        }
+
+        // d(out)/d(in) at zero input: (y/x) * (1 - fee), scaled by the transfer taxes
+        (r_out as f64 / r_in as f64) * self.fee_multiplier()
     }
-    fn update_from_log(&mut self, log: &Log) -> Result<()> {
-        todo!("Parse Sync event")
+    fn update_from_log(&mut self, log: &Log) -> PoolResult<()> {
+        if self.apply_log(log)? {
+            self.refresh_weights();
+        }
+        Ok(())
     }
 }
 
-#[derive(Debug, Clone)]
+/// An amount that still leaves at least `net` after a transfer tax of `bps` (the inverse of
+/// `apply_bps_tax`, rounded up)
+fn gross_of_bps_tax(net: U256, bps: u32) -> PoolResult<U256> {
+    if bps == 0 {
+        return Ok(net);
+    }
+    Ok(mul_div_rounding_up(
+        net,
+        U256::from(10000),
+        U256::from(10000 - bps),
+    )?)
+}
+
+/// `amount` minus a transfer tax of `bps`; the tax itself is rounded down, as token contracts do
+fn apply_bps_tax(amount: U256, bps: u32) -> U256 {
+    if bps == 0 {
+        return amount;
+    }
+    // bps / 10000 < 1, so the tax never overflows nor exceeds the amount
+    let tax = mul_div(amount, U256::from(bps), U256::from(10000)).unwrap_or_default();
+    amount - tax
+}
+
+/// keccak256("Fee(uint16)"), emitted by Algebra (QuickSwap V3) pools when the fee changes
+pub const ALGEBRA_FEE_EVENT: B256 =
+    b256!("598b9f043c813aa6be3426ca60d1c65d17256312890be5118dab55b0775ebe2a");
+
+/// A Uniswap V3 pool, or an Algebra pool (QuickSwap V3 on Polygon): the swap math and the
+/// Swap/Mint/Burn events are the same, but Algebra adjusts the fee on the fly and announces
+/// each change with a Fee event, tracked in `dynamic_fee`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UniswapV3Pool {
     pub address: Address,
     pub token0: Address,
@@ -84,16 +429,192 @@ pub struct UniswapV3Pool {
     // Minimal TickLens: Store simplified ticks locally for simulation
     // Map: TickIndex -> NetLiquidityChange
     pub tick_bitmap: BTreeMap<i32, i128>,
+
+    // Current fee of an Algebra pool, overriding `fee` (None for Uniswap pools)
+    #[serde(default)]
+    pub dynamic_fee: Option<u32>,
+
+    // Block of the last log applied through GraphManager::apply_logs (0 if never updated)
+    #[serde(default)]
+    pub last_updated_block: u64,
+
+    // Refreshed by update_from_log / refresh_weights
+    #[serde(skip)]
+    pub weight_cache: WeightCache,
+}
+
+impl UniswapV3Pool {
+    /// The fee actually charged on swaps, in hundredths of a bip: the last fee an Algebra pool
+    /// announced, or the static `fee`
+    pub fn resolve_fee(&self) -> u32 {
+        self.dynamic_fee.unwrap_or(self.fee)
+    }
+
+    /// Finds the next initialized tick in the swap direction, like TickBitmap's
+    /// nextInitializedTickWithinOneWord but without the one-word limit.
+    /// Searching down (zero_for_one) includes `tick` itself; searching up starts strictly above it.
+    /// Only multiples of `tick_spacing` are considered valid positions.
+    pub fn next_initialized_tick(&self, tick: i32, zero_for_one: bool) -> Option<i32> {
+        self.state().next_initialized_tick(tick, zero_for_one)
+    }
+
+    /// Net liquidity added when crossing `tick` left to right (0 if uninitialized)
+    pub fn net_liquidity_at(&self, tick: i32) -> i128 {
+        self.state().net_liquidity_at(tick)
+    }
+
+    /// Borrowed view of the swap state for the shared concentrated-liquidity math
+    fn state(&self) -> ConcentratedLiquidity<'_> {
+        ConcentratedLiquidity {
+            sqrt_price_x96: self.sqrt_price_x96,
+            tick: self.tick,
+            liquidity: self.liquidity,
+            tick_spacing: self.tick_spacing,
+            tick_bitmap: &self.tick_bitmap,
+        }
+    }
+
+    /// `get_amount_out` together with the swap fee the LPs keep out of `amount_in`, summed over
+    /// every tick range the swap walks through (each step's fee is rounded up, as on-chain)
+    pub fn get_amount_out_with_fee(
+        &self,
+        amount_in: U256,
+        zero_for_one: bool,
+    ) -> PoolResult<(U256, U256)> {
+        self.state()
+            .swap_exact_input(amount_in, zero_for_one, self.resolve_fee())
+    }
+
+    /// `get_amount_out`, halting where the price reaches `sqrt_price_limit` (as the pool's
+    /// `swap` does for price protection). `None` swaps the whole input like `get_amount_out`.
+    pub fn get_amount_out_limited(
+        &self,
+        amount_in: U256,
+        zero_for_one: bool,
+        sqrt_price_limit: Option<U256>,
+    ) -> PoolResult<LimitedSwap> {
+        self.state().swap_exact_input_limited(
+            amount_in,
+            zero_for_one,
+            self.resolve_fee(),
+            sqrt_price_limit,
+            None,
+        )
+    }
+
+    /// Applies a Swap/Mint/Burn (or Algebra Fee) log without refreshing the cached weights.
+    /// Returns whether the pool state changed (other events are ignored).
+    pub fn apply_log(&mut self, log: &Log) -> PoolResult<bool> {
+        if log.address != self.address {
+            return Err(PoolError::AddressMismatch {
+                pool: self.address,
+                log: log.address,
+            });
+        }
+
+        match log.topics().first() {
+            Some(&V3_SWAP_EVENT) => {
+                let swap = decode_v3_swap(log).map_err(PoolError::MalformedLog)?;
+                self.sqrt_price_x96 = swap.sqrt_price_x96;
+                self.liquidity = swap.liquidity;
+                self.tick = swap.tick;
+            }
+            // Fee(uint16 fee), in hundredths of a bip like V3 fees
+            Some(&ALGEBRA_FEE_EVENT) => {
+                let fee = abi_word(&log.data.data, 0).map_err(PoolError::MalformedLog)?;
+                let fee = U256::from_be_slice(fee);
+                self.dynamic_fee = Some(
+                    u32::try_from(fee)
+                        .map_err(|_| PoolError::Overflow(format!("fee {fee} overflows")))?,
+                );
+            }
+            Some(&V3_MINT_EVENT) => {
+                let mint = decode_mint(log).map_err(PoolError::MalformedLog)?;
+                self.apply_position_delta(mint.tick_lower, mint.tick_upper, mint.amount, true)?;
+            }
+            Some(&V3_BURN_EVENT) => {
+                let burn = decode_burn(log).map_err(PoolError::MalformedLog)?;
+                self.apply_position_delta(burn.tick_lower, burn.tick_upper, burn.amount, false)?;
+            }
+            // Collect, Flash, etc. don't touch the swap state
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Recomputes the cached log weights; call after editing the price or liquidity directly
+    pub fn refresh_weights(&mut self) {
+        self.weight_cache =
+            WeightCache::compute(|zero_for_one| self.compute_log_weight(zero_for_one));
+    }
+
+    fn compute_log_weight(&self, zero_for_one: bool) -> f64 {
+        self.state().log_weight(zero_for_one, self.resolve_fee())
+    }
+
+    /// Adds (Mint) or removes (Burn) `amount` of liquidity over [tick_lower, tick_upper)
+    fn apply_position_delta(
+        &mut self,
+        tick_lower: i32,
+        tick_upper: i32,
+        amount: u128,
+        add: bool,
+    ) -> PoolResult<()> {
+        let delta = i128::try_from(amount)
+            .map_err(|_| PoolError::Overflow("liquidity delta overflow".into()))?;
+        apply_liquidity_delta(
+            &mut self.tick_bitmap,
+            &mut self.liquidity,
+            self.tick,
+            tick_lower,
+            tick_upper,
+            if add { delta } else { -delta },
+        )
+    }
+}
+
+/// Adds `delta` of liquidity (negative to remove it) over [tick_lower, tick_upper) to a V3/V4
+/// pool's ticks, and to its active `liquidity` if the range covers the current `tick`
+fn apply_liquidity_delta(
+    tick_bitmap: &mut BTreeMap<i32, i128>,
+    liquidity: &mut u128,
+    tick: i32,
+    tick_lower: i32,
+    tick_upper: i32,
+    delta: i128,
+) -> PoolResult<()> {
+    pool_ensure!(tick_lower < tick_upper, InvalidInput, "invalid tick range");
+
+    // Net liquidity is added at the lower tick and removed at the upper one
+    for (boundary, net) in [(tick_lower, delta), (tick_upper, -delta)] {
+        let entry = tick_bitmap.entry(boundary).or_insert(0);
+        *entry = entry.checked_add(net).ok_or_else(|| {
+            PoolError::Overflow(format!("net liquidity overflow at tick {boundary}"))
+        })?;
+        if *entry == 0 {
+            tick_bitmap.remove(&boundary);
+        }
+    }
+
+    // Active liquidity only changes if the position covers the current tick
+    if tick_lower <= tick && tick < tick_upper {
+        *liquidity = liquidity
+            .checked_add_signed(delta)
+            .ok_or_else(|| PoolError::Overflow("active liquidity underflow".into()))?;
+    }
+    Ok(())
 }
 
 impl LiquidityPool for UniswapV3Pool {
-    fn get_amount_out(&self, amount_in: U256, zero_for_one: bool) -> Result<U256> {
-        // Must implement standard V3 SwapMath step-by-step
-        // 1. Calculate next initialized tick
-        // 2. Compute swap within current tick range
-        // 3. Cross tick if needed (update L)
-        // 4. Repeat until amount_in is exhausted
-        todo!("Implement V3 SwapMath")
+    fn get_amount_out(&self, amount_in: U256, zero_for_one: bool) -> PoolResult<U256> {
+        self.state()
+            .swap_exact_input(amount_in, zero_for_one, self.resolve_fee())
+            .map(|(amount_out, _)| amount_out)
+    }
+
+    fn get_amount_in(&self, amount_out: U256, zero_for_one: bool) -> PoolResult<U256> {
+        self.state()
+            .swap_exact_output(amount_out, zero_for_one, self.resolve_fee())
     }
 
     // ... implement other methods
@@ -104,18 +625,438 @@ impl LiquidityPool for UniswapV3Pool {
         (self.token0, self.token1)
     }
     fn get_log_weight(&self, zero_for_one: bool) -> f64 {
-        todo!()
+        self.weight_cache
+            .get(zero_for_one)
+            .unwrap_or_else(|| self.compute_log_weight(zero_for_one))
     }
     fn get_marginal_price(&self, zero_for_one: bool) -> f64 {
-        todo!()
+        self.state()
+            .marginal_price(zero_for_one, self.resolve_fee())
+    }
+    fn update_from_log(&mut self, log: &Log) -> PoolResult<()> {
+        if self.apply_log(log)? {
+            self.refresh_weights();
+        }
+        Ok(())
+    }
+}
+
+/// Swap state right after a tick crossing, and the input/output it took to get there
+#[derive(Debug, Clone, Copy)]
+struct SwapCheckpoint {
+    amount_in: U256,
+    amount_out: U256,
+    fee_amount: U256,
+    sqrt_price_x96: U256,
+    tick: i32,
+    liquidity: u128,
+}
+
+/// Memoized tick crossings for repeated exact-input swaps through one V3/V4 pool in one
+/// direction, e.g. across the evaluations of a single solve.
+///
+/// A swap that crosses a tick always spends the same input to get there, so a later swap with
+/// at least that much input resumes from the crossing instead of re-walking the ticks, and a
+/// smaller one resumes from an earlier crossing. Only valid while the pool state is unchanged:
+/// use a fresh cache per solve.
+#[derive(Debug, Clone, Default)]
+pub struct SwapCache {
+    zero_for_one: bool,
+    fee: u32,
+    checkpoints: Vec<SwapCheckpoint>,
+}
+
+/// Outcome of a V3/V4 swap that may stop before its input is used up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LimitedSwap {
+    pub amount_out: U256,
+    /// Input actually swapped (fees included); the rest is left with the trader
+    pub amount_in: U256,
+    /// Part of `amount_in` kept by the LPs
+    pub fee_amount: U256,
+}
+
+impl SwapCache {
+    /// Drops the checkpoints if they were recorded for another direction or fee
+    fn reset_unless(&mut self, zero_for_one: bool, fee: u32) {
+        if self.zero_for_one != zero_for_one || self.fee != fee {
+            *self = Self {
+                zero_for_one,
+                fee,
+                checkpoints: Vec::new(),
+            };
+        }
+    }
+}
+
+/// Borrowed view of concentrated-liquidity state, shared by the V3 and V4 swap math
+struct ConcentratedLiquidity<'a> {
+    sqrt_price_x96: U256,
+    tick: i32,
+    liquidity: u128,
+    tick_spacing: i32,
+    tick_bitmap: &'a BTreeMap<i32, i128>,
+}
+
+impl ConcentratedLiquidity<'_> {
+    /// d(out)/d(in) at amount_in -> 0: the spot price net of the LP fee
+    fn marginal_price(&self, zero_for_one: bool, fee_pips: u32) -> f64 {
+        // Nothing can be bought from an uninitialized or empty range
+        if self.sqrt_price_x96 == U256::ZERO || self.liquidity_at_price(zero_for_one) == 0 {
+            return 0.0;
+        }
+
+        // Raw-unit price of token0 in token1
+        let spot = sqrt_price_to_price(self.sqrt_price_x96, 0, 0);
+        let price = if zero_for_one { spot } else { 1.0 / spot };
+        let fee = fee_pips as f64 / FEE_DENOMINATOR as f64;
+        price * (1.0 - fee)
+    }
+
+    /// Fee-adjusted spot price in Q64.96 (see `PRICE_X96_ONE`), rounded down; 0 for an
+    /// uninitialized range or without liquidity at the current price in this direction (see
+    /// `liquidity_at_price`), e.g. once the price has left every position.
+    ///
+    /// Ticks span prices of 2^-128..2^128 while Q64.96 (in a U256) covers 2^-96..2^160, so a
+    /// price beyond that (only seen in pools pushed to the ends of the tick range) also comes
+    /// out as 0, i.e. untradeable.
+    fn marginal_price_x96(&self, zero_for_one: bool, fee_pips: u32) -> U256 {
+        let sqrt_price = self.sqrt_price_x96;
+        if sqrt_price == U256::ZERO
+            || self.liquidity_at_price(zero_for_one) == 0
+            || fee_pips >= FEE_DENOMINATOR
+        {
+            return U256::ZERO;
+        }
+
+        // Price of token0 in token1 is sqrtP^2 / 2^192, so in Q64.96 it's sqrtP^2 / 2^96 and its
+        // inverse 2^288 / sqrtP^2, each in two roundings down
+        let price = if zero_for_one {
+            mul_div(sqrt_price, sqrt_price, PRICE_X96_ONE)
+        } else {
+            mul_div(PRICE_X96_ONE * PRICE_X96_ONE, PRICE_X96_ONE, sqrt_price)
+                .map(|scaled| scaled / sqrt_price)
+        };
+        let Ok(price) = price else {
+            return U256::ZERO;
+        };
+        mul_div(
+            price,
+            U256::from(FEE_DENOMINATOR - fee_pips),
+            U256::from(FEE_DENOMINATOR),
+        )
+        .unwrap_or_default()
+    }
+
+    /// Liquidity the first step of a swap in this direction trades against: the active
+    /// liquidity, unless the price sits exactly on the initialized tick the swap crosses first,
+    /// which it then crosses before trading anything. Selling token0 that's `tick` itself;
+    /// buying it, `tick + 1`, where a swap down that stopped on a tick's price leaves the pool.
+    fn liquidity_at_price(&self, zero_for_one: bool) -> u128 {
+        let boundary = if zero_for_one {
+            self.tick
+        } else {
+            self.tick.saturating_add(1)
+        };
+        let on_initialized_tick = self.next_initialized_tick(self.tick, zero_for_one)
+            == Some(boundary)
+            && get_sqrt_ratio_at_tick(boundary).is_ok_and(|sqrt| sqrt == self.sqrt_price_x96);
+        if !on_initialized_tick {
+            return self.liquidity;
+        }
+        let net = self.net_liquidity_at(boundary);
+        let delta = if zero_for_one { -net } else { net };
+        self.liquidity.checked_add_signed(delta).unwrap_or(0)
+    }
+
+    /// No active liquidity and no initialized tick to cross in this direction: nothing can be
+    /// traded this way
+    fn is_drained(&self, zero_for_one: bool) -> bool {
+        self.liquidity == 0
+            && self
+                .next_initialized_tick(self.tick, zero_for_one)
+                .is_none()
+    }
+
+    /// -ln of the fee-adjusted spot price; an empty range can't be traded at all
+    fn log_weight(&self, zero_for_one: bool, fee_pips: u32) -> f64 {
+        log_weight_from_price_x96(self.marginal_price_x96(zero_for_one, fee_pips))
+    }
+
+    fn next_initialized_tick(&self, tick: i32, zero_for_one: bool) -> Option<i32> {
+        let spacing = self.tick_spacing.max(1);
+        let on_spacing = |t: &&i32| t.rem_euclid(spacing) == 0;
+
+        if zero_for_one {
+            self.tick_bitmap
+                .range(..=tick)
+                .map(|(t, _)| t)
+                .rev()
+                .find(on_spacing)
+                .copied()
+        } else {
+            self.tick_bitmap
+                .range(tick.saturating_add(1)..)
+                .map(|(t, _)| t)
+                .find(on_spacing)
+                .copied()
+        }
+    }
+
+    fn net_liquidity_at(&self, tick: i32) -> i128 {
+        self.tick_bitmap.get(&tick).copied().unwrap_or(0)
+    }
+
+    /// Standard SwapMath loop: swap within the current range, cross ticks and repeat.
+    /// Returns (amount_out, fee_amount), the fee being the part of the input the LPs keep.
+    fn swap_exact_input(
+        &self,
+        amount_in: U256,
+        zero_for_one: bool,
+        fee: u32,
+    ) -> PoolResult<(U256, U256)> {
+        self.swap_exact_input_cached(amount_in, zero_for_one, fee, None)
+    }
+
+    /// Input (fees included) needed to take `amount_out` out of the pool, walking the ticks
+    /// like `swap_exact_input`. Fails if the liquidity runs out first.
+    fn swap_exact_output(
+        &self,
+        amount_out: U256,
+        zero_for_one: bool,
+        fee: u32,
+    ) -> PoolResult<U256> {
+        pool_ensure!(
+            self.sqrt_price_x96 != U256::ZERO,
+            InvalidState,
+            "pool is not initialized"
+        );
+        pool_ensure!(
+            amount_out == U256::ZERO || !self.is_drained(zero_for_one),
+            InsufficientLiquidity,
+            "no liquidity for {amount_out} out"
+        );
+
+        let sqrt_price_limit = if zero_for_one {
+            MIN_SQRT_RATIO + U256::from(1)
+        } else {
+            MAX_SQRT_RATIO - U256::from(1)
+        };
+
+        let mut amount_remaining = amount_out;
+        let mut amount_in = U256::ZERO;
+        let mut sqrt_price = self.sqrt_price_x96;
+        let mut tick = self.tick;
+        let mut liquidity = self.liquidity;
+
+        while amount_remaining != U256::ZERO {
+            pool_ensure!(
+                sqrt_price != sqrt_price_limit,
+                InsufficientLiquidity,
+                "insufficient liquidity for {amount_out} out ({amount_remaining} short)"
+            );
+
+            let next_initialized = self.next_initialized_tick(tick, zero_for_one);
+            let tick_next = match next_initialized {
+                Some(t) => t.clamp(MIN_TICK, MAX_TICK),
+                None if zero_for_one => MIN_TICK,
+                None => MAX_TICK,
+            };
+
+            let sqrt_price_next = get_sqrt_ratio_at_tick(tick_next)?;
+            let sqrt_price_target = if zero_for_one {
+                sqrt_price_next.max(sqrt_price_limit)
+            } else {
+                sqrt_price_next.min(sqrt_price_limit)
+            };
+
+            let step = compute_swap_step_exact_output(
+                sqrt_price,
+                sqrt_price_target,
+                liquidity,
+                amount_remaining,
+                fee,
+            )?;
+            sqrt_price = step.sqrt_price_next_x96;
+            amount_remaining -= step.amount_out;
+            amount_in += step.amount_in + step.fee_amount;
+
+            if sqrt_price != sqrt_price_next {
+                // Stopped inside the range: output filled (or price limit reached)
+                continue;
+            }
+            if next_initialized.is_some() {
+                let net = self.net_liquidity_at(tick_next);
+                let delta = if zero_for_one { -net } else { net };
+                liquidity = liquidity.checked_add_signed(delta).ok_or_else(|| {
+                    PoolError::Overflow(format!("liquidity underflow crossing tick {tick_next}"))
+                })?;
+            }
+            tick = if zero_for_one {
+                tick_next - 1
+            } else {
+                tick_next
+            };
+        }
+
+        Ok(amount_in)
     }
-    fn update_from_log(&mut self, log: &Log) -> Result<()> {
-        todo!("Parse Swap/Mint/Burn")
+
+    /// `swap_exact_input`, resuming from the furthest tick crossing in `cache` that the input
+    /// reaches and recording any new crossings
+    fn swap_exact_input_cached(
+        &self,
+        amount_in: U256,
+        zero_for_one: bool,
+        fee: u32,
+        cache: Option<&mut SwapCache>,
+    ) -> PoolResult<(U256, U256)> {
+        self.swap_exact_input_limited(amount_in, zero_for_one, fee, None, cache)
+            .map(|swap| (swap.amount_out, swap.fee_amount))
+    }
+
+    /// The swap loop behind `swap_exact_input(_cached)`, stopping early once the price reaches
+    /// `sqrt_price_limit` (validated like the pool's `swap`). The cache must only be passed
+    /// without a limit, since its checkpoints assume the swap runs to the end of the range.
+    fn swap_exact_input_limited(
+        &self,
+        amount_in: U256,
+        zero_for_one: bool,
+        fee: u32,
+        sqrt_price_limit: Option<U256>,
+        mut cache: Option<&mut SwapCache>,
+    ) -> PoolResult<LimitedSwap> {
+        pool_ensure!(
+            self.sqrt_price_x96 != U256::ZERO,
+            InvalidState,
+            "pool is not initialized"
+        );
+        debug_assert!(cache.is_none() || sqrt_price_limit.is_none());
+
+        let sqrt_price_limit = match sqrt_price_limit {
+            Some(limit) if zero_for_one => {
+                pool_ensure!(
+                    limit < self.sqrt_price_x96 && limit > MIN_SQRT_RATIO,
+                    InvalidInput,
+                    "sqrt price limit {limit} not between MIN_SQRT_RATIO and the current price"
+                );
+                limit
+            }
+            Some(limit) => {
+                pool_ensure!(
+                    limit > self.sqrt_price_x96 && limit < MAX_SQRT_RATIO,
+                    InvalidInput,
+                    "sqrt price limit {limit} not between the current price and MAX_SQRT_RATIO"
+                );
+                limit
+            }
+            // Swap until the input is exhausted or the price hits the end of the tick range
+            None if zero_for_one => MIN_SQRT_RATIO + U256::from(1),
+            None => MAX_SQRT_RATIO - U256::from(1),
+        };
+
+        // An empty range with nothing to cross into (e.g. a freshly created pool) fills nothing
+        if self.is_drained(zero_for_one) {
+            return Ok(LimitedSwap::default());
+        }
+
+        let mut amount_remaining = amount_in;
+        let mut amount_out = U256::ZERO;
+        let mut fee_amount = U256::ZERO;
+        let mut sqrt_price = self.sqrt_price_x96;
+        let mut tick = self.tick;
+        let mut liquidity = self.liquidity;
+
+        // Crossings are deterministic for any input that reaches them, so resume from the
+        // last checkpoint at or below amount_in
+        let mut crossings = 0;
+        if let Some(cache) = cache.as_deref_mut() {
+            cache.reset_unless(zero_for_one, fee);
+            crossings = cache
+                .checkpoints
+                .partition_point(|c| c.amount_in <= amount_in);
+            if let Some(c) = crossings.checked_sub(1).map(|i| &cache.checkpoints[i]) {
+                amount_remaining = amount_in - c.amount_in;
+                amount_out = c.amount_out;
+                fee_amount = c.fee_amount;
+                sqrt_price = c.sqrt_price_x96;
+                tick = c.tick;
+                liquidity = c.liquidity;
+            }
+        }
+
+        while amount_remaining != U256::ZERO && sqrt_price != sqrt_price_limit {
+            // 1. Calculate next initialized tick
+            let next_initialized = self.next_initialized_tick(tick, zero_for_one);
+            let tick_next = match next_initialized {
+                Some(t) => t.clamp(MIN_TICK, MAX_TICK),
+                None if zero_for_one => MIN_TICK,
+                None => MAX_TICK,
+            };
+
+            let sqrt_price_next = get_sqrt_ratio_at_tick(tick_next)?;
+            let sqrt_price_target = if zero_for_one {
+                sqrt_price_next.max(sqrt_price_limit)
+            } else {
+                sqrt_price_next.min(sqrt_price_limit)
+            };
+
+            // 2. Compute swap within current tick range
+            let step = compute_swap_step(
+                sqrt_price,
+                sqrt_price_target,
+                liquidity,
+                amount_remaining,
+                fee,
+            )?;
+            sqrt_price = step.sqrt_price_next_x96;
+            amount_remaining -= step.amount_in + step.fee_amount;
+            amount_out += step.amount_out;
+            fee_amount += step.fee_amount;
+
+            // 3. Cross tick if needed (update L)
+            if sqrt_price != sqrt_price_next {
+                // Stopped inside the range: input exhausted (or price limit reached)
+                break;
+            }
+            if next_initialized.is_some() {
+                let net = self.net_liquidity_at(tick_next);
+                let delta = if zero_for_one { -net } else { net };
+                liquidity = liquidity.checked_add_signed(delta).ok_or_else(|| {
+                    PoolError::Overflow(format!("liquidity underflow crossing tick {tick_next}"))
+                })?;
+            }
+            tick = if zero_for_one {
+                tick_next - 1
+            } else {
+                tick_next
+            };
+
+            crossings += 1;
+            if let Some(cache) = cache.as_deref_mut()
+                && crossings > cache.checkpoints.len()
+            {
+                cache.checkpoints.push(SwapCheckpoint {
+                    amount_in: amount_in - amount_remaining,
+                    amount_out,
+                    fee_amount,
+                    sqrt_price_x96: sqrt_price,
+                    tick,
+                    liquidity,
+                });
+            }
+        }
+
+        Ok(LimitedSwap {
+            amount_out,
+            amount_in: amount_in - amount_remaining,
+            fee_amount,
+        })
     }
 }
 
 /// V4 is unique because all pools live in one contract (the PoolManager). A pool is defined by a PoolKey.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PoolKey {
     pub currency0: Address,
     pub currency1: Address,
@@ -124,26 +1065,190 @@ pub struct PoolKey {
     pub hooks: Address,
 }
 
-#[derive(Debug, Clone)]
+impl PoolKey {
+    /// A key for the pair in V4's canonical order (`currency0 < currency1`), whichever way the
+    /// currencies are passed. Native ETH is `Address::ZERO`, so it always ends up as `currency0`.
+    pub fn new(
+        currency_a: Address,
+        currency_b: Address,
+        fee: u32,
+        tick_spacing: i32,
+        hooks: Address,
+    ) -> Self {
+        let (currency0, currency1) = if currency_a < currency_b {
+            (currency_a, currency_b)
+        } else {
+            (currency_b, currency_a)
+        };
+        Self {
+            currency0,
+            currency1,
+            fee,
+            tick_spacing,
+            hooks,
+        }
+    }
+
+    /// Whether one side of the pair is native ETH rather than an ERC-20
+    pub fn has_native(&self) -> bool {
+        self.currency0 == Address::ZERO
+    }
+
+    /// The swap direction selling `currency_in`, `None` if it isn't one of the pair's currencies
+    pub fn zero_for_one(&self, currency_in: Address) -> Option<bool> {
+        if currency_in == self.currency0 {
+            Some(true)
+        } else if currency_in == self.currency1 {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// The pool id the PoolManager derives from this key: keccak256(abi.encode(key))
+    pub fn id(&self) -> B256 {
+        let mut encoded = [0u8; 5 * 32];
+        encoded[12..32].copy_from_slice(self.currency0.as_slice());
+        encoded[44..64].copy_from_slice(self.currency1.as_slice());
+        encoded[64..96].copy_from_slice(&U256::from(self.fee).to_be_bytes::<32>());
+        // int24 is sign-extended to a full word
+        encoded[96..128].copy_from_slice(
+            &I256::try_from(self.tick_spacing)
+                .expect("fits")
+                .to_be_bytes::<32>(),
+        );
+        encoded[140..160].copy_from_slice(self.hooks.as_slice());
+        keccak256(encoded)
+    }
+}
+
+/// `PoolKey::fee` value marking a pool whose fee is set by its hook (LPFeeLibrary.DYNAMIC_FEE_FLAG)
+pub const V4_DYNAMIC_FEE_FLAG: u32 = 0x800000;
+
+/// Fee assumed for a dynamic-fee pool until its hook's current fee is known
+pub const V4_DEFAULT_DYNAMIC_FEE: u32 = 3000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UniswapV4Pool {
     pub key: PoolKey, // Identity of the pool
     pub liquidity: u128,
     pub sqrt_price_x96: U256,
     pub tick: i32,
 
+    // Same layout as V3: TickIndex -> NetLiquidityChange
+    pub tick_bitmap: BTreeMap<i32, i128>,
+
     // V4 might use dynamic fees via hooks, requiring logic here
     pub hook_address: Address,
+
+    // Last known fee for dynamic-fee pools (None until observed)
+    pub dynamic_fee: Option<u32>,
+
+    // Block of the last log applied through GraphManager::apply_logs (0 if never updated)
+    #[serde(default)]
+    pub last_updated_block: u64,
+
+    // Refreshed by update_from_log / refresh_weights
+    #[serde(skip)]
+    pub weight_cache: WeightCache,
+}
+
+impl UniswapV4Pool {
+    /// The LP fee actually charged on swaps, in hundredths of a bip.
+    /// Dynamic-fee pools carry a flag in `key.fee` rather than a fee, so we use the cached
+    /// dynamic fee or, failing that, `V4_DEFAULT_DYNAMIC_FEE`.
+    pub fn resolve_fee(&self) -> u32 {
+        if self.key.fee == V4_DYNAMIC_FEE_FLAG {
+            self.dynamic_fee.unwrap_or(V4_DEFAULT_DYNAMIC_FEE)
+        } else {
+            self.key.fee
+        }
+    }
+
+    /// Applies a PoolManager Swap/ModifyLiquidity log without refreshing the cached weights.
+    /// Returns whether the pool state changed (other events are ignored). The PoolManager emits
+    /// the logs of every pool, so they are matched on the pool id (topic 1), not the address.
+    pub fn apply_log(&mut self, log: &Log) -> PoolResult<bool> {
+        match log.topics().first() {
+            Some(&V4_SWAP_EVENT) => {
+                let swap = decode_v4_swap(log).map_err(PoolError::MalformedLog)?;
+                self.check_pool_id(swap.pool_id)?;
+                self.sqrt_price_x96 = swap.sqrt_price_x96;
+                self.liquidity = swap.liquidity;
+                self.tick = swap.tick;
+                // The event reports the fee the hook charged, i.e. the current dynamic fee
+                if self.key.fee == V4_DYNAMIC_FEE_FLAG {
+                    self.dynamic_fee = Some(swap.fee);
+                }
+            }
+            Some(&V4_MODIFY_LIQUIDITY_EVENT) => {
+                let modify = decode_modify_liquidity(log).map_err(PoolError::MalformedLog)?;
+                self.check_pool_id(modify.pool_id)?;
+                let delta = i128::try_from(modify.liquidity_delta)
+                    .map_err(|_| PoolError::Overflow("liquidity delta overflow".into()))?;
+                apply_liquidity_delta(
+                    &mut self.tick_bitmap,
+                    &mut self.liquidity,
+                    self.tick,
+                    modify.tick_lower,
+                    modify.tick_upper,
+                    delta,
+                )?;
+            }
+            // Initialize, Donate, etc. don't touch the swap state
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn check_pool_id(&self, log_pool_id: B256) -> PoolResult<()> {
+        let pool = self.key.id();
+        if log_pool_id != pool {
+            return Err(PoolError::PoolIdMismatch {
+                pool,
+                log: log_pool_id,
+            });
+        }
+        Ok(())
+    }
+
+    /// Recomputes the cached log weights; call after editing the price or liquidity directly
+    pub fn refresh_weights(&mut self) {
+        self.weight_cache =
+            WeightCache::compute(|zero_for_one| self.compute_log_weight(zero_for_one));
+    }
+
+    fn compute_log_weight(&self, zero_for_one: bool) -> f64 {
+        self.state().log_weight(zero_for_one, self.resolve_fee())
+    }
+
+    fn state(&self) -> ConcentratedLiquidity<'_> {
+        ConcentratedLiquidity {
+            sqrt_price_x96: self.sqrt_price_x96,
+            tick: self.tick,
+            liquidity: self.liquidity,
+            tick_spacing: self.key.tick_spacing,
+            tick_bitmap: &self.tick_bitmap,
+        }
+    }
 }
 
 impl LiquidityPool for UniswapV4Pool {
     // V4 Math is nearly identical to V3, but Fee logic might differ
-    fn get_amount_out(&self, amount_in: U256, zero_for_one: bool) -> Result<U256> {
-        // Implement V4 SwapMath (check Hooks for dynamic fees)
-        todo!()
+    fn get_amount_out(&self, amount_in: U256, zero_for_one: bool) -> PoolResult<U256> {
+        self.state()
+            .swap_exact_input(amount_in, zero_for_one, self.resolve_fee())
+            .map(|(amount_out, _)| amount_out)
+    }
+
+    fn get_amount_in(&self, amount_out: U256, zero_for_one: bool) -> PoolResult<U256> {
+        self.state()
+            .swap_exact_output(amount_out, zero_for_one, self.resolve_fee())
     }
 
     fn address(&self) -> Address {
-        // In V4, address is the PoolManager, but we might track the Hook address
+        // In V4, address is the PoolManager, but we might track the Hook address.
+        // Many pools share a hook, so use `PoolVariant::id` to tell pools apart.
         self.key.hooks
     }
     // ... implement other methods
@@ -151,23 +1256,807 @@ impl LiquidityPool for UniswapV4Pool {
         (self.key.currency0, self.key.currency1)
     }
     fn get_log_weight(&self, zero_for_one: bool) -> f64 {
-        todo!()
+        self.weight_cache
+            .get(zero_for_one)
+            .unwrap_or_else(|| self.compute_log_weight(zero_for_one))
     }
     fn get_marginal_price(&self, zero_for_one: bool) -> f64 {
-        todo!()
+        self.state()
+            .marginal_price(zero_for_one, self.resolve_fee())
     }
-    fn update_from_log(&mut self, log: &Log) -> Result<()> {
-        todo!()
+    fn update_from_log(&mut self, log: &Log) -> PoolResult<()> {
+        if self.apply_log(log)? {
+            self.refresh_weights();
+        }
+        Ok(())
+    }
+}
+
+/// keccak256("TokenExchange(address,int128,uint256,int128,uint256)")
+pub const STABLE_TOKEN_EXCHANGE_EVENT: B256 =
+    b256!("8b3e96f2b889fa771c53c981b40daf005f63f637f1869f707052d15a3dd97140");
+
+/// StableSwap fees are expressed in 1e10ths (4_000_000 = 0.04%), as in Curve pools
+pub const STABLE_FEE_DENOMINATOR: u64 = 10_000_000_000;
+
+/// Newton rounds for D and y before giving up, as in Curve's contracts
+const STABLE_MAX_ITERATIONS: usize = 255;
+
+/// A 2-coin Curve StableSwap pool (the classic, non-A_PRECISION math)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StableSwapPool {
+    pub address: Address,
+    pub token0: Address,
+    pub token1: Address,
+    pub balance0: U256, // Raw token units
+    pub balance1: U256,
+
+    // 10^(18 - decimals) for each coin; Curve's PRECISION_MUL, normalizing balances to 18 decimals
+    pub precision0: U256,
+    pub precision1: U256,
+
+    pub amp: u64, // The pool's `A()`
+    pub fee: u64, // In STABLE_FEE_DENOMINATOR units
+
+    // Block of the last log applied through GraphManager::apply_logs (0 if never updated)
+    #[serde(default)]
+    pub last_updated_block: u64,
+
+    // Refreshed by update_from_log / refresh_weights
+    #[serde(skip)]
+    pub weight_cache: WeightCache,
+}
+
+impl StableSwapPool {
+    /// Balances normalized to 18 decimals, as (in, out) for the given direction
+    fn xp(&self, zero_for_one: bool) -> PoolResult<(U256, U256)> {
+        let normalize = |balance: U256, precision: U256| {
+            balance
+                .checked_mul(precision)
+                .ok_or_else(|| PoolError::Overflow("balance overflow".into()))
+        };
+        let x0 = normalize(self.balance0, self.precision0)?;
+        let x1 = normalize(self.balance1, self.precision1)?;
+        Ok(if zero_for_one { (x0, x1) } else { (x1, x0) })
+    }
+
+    /// A * n for n = 2 coins, as used throughout the Curve math
+    fn ann(&self) -> U256 {
+        U256::from(self.amp) * U256::from(2)
+    }
+
+    /// A = 0 has no invariant to solve for (Ann - 1 underflows, D / Ann divides by zero)
+    fn ensure_amp(&self) -> PoolResult<()> {
+        pool_ensure!(
+            self.amp != 0,
+            InvalidState,
+            "StableSwap pool {} has A = 0",
+            self.address
+        );
+        Ok(())
+    }
+
+    /// The invariant D for normalized balances, by Newton's method like Curve's get_D
+    fn get_d(&self, x: U256, y: U256) -> PoolResult<U256> {
+        self.ensure_amp()?;
+        let sum = x + y;
+        if sum == U256::ZERO {
+            return Ok(U256::ZERO);
+        }
+        pool_ensure!(
+            x != U256::ZERO && y != U256::ZERO,
+            InsufficientLiquidity,
+            "StableSwap pool {} has an empty side",
+            self.address
+        );
+        let ann = self.ann();
+        let two = U256::from(2);
+
+        let mut d = sum;
+        for _ in 0..STABLE_MAX_ITERATIONS {
+            // D_P = D^(n+1) / (n^n * x * y)
+            let d_p = mul_div(mul_div(d, d, x * two)?, d, y * two)?;
+            let d_prev = d;
+            let numerator = ann * sum + d_p * two;
+            let denominator = (ann - U256::from(1)) * d + U256::from(3) * d_p;
+            d = mul_div(numerator, d, denominator)?;
+            if d.abs_diff(d_prev) <= U256::from(1) {
+                return Ok(d);
+            }
+        }
+        Err(PoolError::NoConvergence(format!(
+            "StableSwap D did not converge for pool {}",
+            self.address
+        )))
+    }
+
+    /// The other normalized balance that keeps the invariant at `d` when one side is `x`,
+    /// like Curve's get_y
+    fn get_y(&self, x: U256, d: U256) -> PoolResult<U256> {
+        self.ensure_amp()?;
+        pool_ensure!(
+            x != U256::ZERO,
+            InsufficientLiquidity,
+            "StableSwap input balance is zero"
+        );
+        let ann = self.ann();
+        let two = U256::from(2);
+
+        // c = D^(n+1) / (n^n * x * Ann), b = x + D / Ann
+        let c = mul_div(mul_div(d, d, x * two)?, d, ann * two)?;
+        let b = x + d / ann;
+
+        let mut y = d;
+        for _ in 0..STABLE_MAX_ITERATIONS {
+            let y_prev = y;
+            let denominator = (y * two + b)
+                .checked_sub(d)
+                .ok_or_else(|| PoolError::Overflow("StableSwap y underflow".into()))?;
+            y = (y * y + c) / denominator;
+            if y.abs_diff(y_prev) <= U256::from(1) {
+                return Ok(y);
+            }
+        }
+        Err(PoolError::NoConvergence(format!(
+            "StableSwap y did not converge for pool {}",
+            self.address
+        )))
+    }
+
+    /// Applies a TokenExchange log to the balances without refreshing the cached weights.
+    /// The admin's share of the fee isn't visible in the event, so the output balance drifts
+    /// up by that amount until the pool is reloaded.
+    pub fn apply_log(&mut self, log: &Log) -> PoolResult<bool> {
+        if log.address != self.address {
+            return Err(PoolError::AddressMismatch {
+                pool: self.address,
+                log: log.address,
+            });
+        }
+        if log.topics().first() != Some(&STABLE_TOKEN_EXCHANGE_EVENT) {
+            return Ok(false);
+        }
+
+        // TokenExchange(address indexed buyer, int128 sold_id, uint256 tokens_sold,
+        //               int128 bought_id, uint256 tokens_bought)
+        let word = |index| abi_word(&log.data.data, index).map_err(PoolError::MalformedLog);
+        let sold_id = abi_i32(word(0)?);
+        let tokens_sold = U256::from_be_slice(word(1)?);
+        let bought_id = abi_i32(word(2)?);
+        let tokens_bought = U256::from_be_slice(word(3)?);
+
+        let (sold, bought) = match (sold_id, bought_id) {
+            (0, 1) => (&mut self.balance0, &mut self.balance1),
+            (1, 0) => (&mut self.balance1, &mut self.balance0),
+            _ => {
+                return Err(PoolError::MalformedLog(anyhow!(
+                    "invalid coin ids {sold_id} -> {bought_id}"
+                )));
+            }
+        };
+        *sold = sold
+            .checked_add(tokens_sold)
+            .ok_or_else(|| PoolError::Overflow("balance overflow".into()))?;
+        *bought = bought
+            .checked_sub(tokens_bought)
+            .ok_or_else(|| PoolError::Overflow("balance underflow".into()))?;
+        Ok(true)
+    }
+
+    /// Recomputes the cached log weights; call after editing the balances directly
+    pub fn refresh_weights(&mut self) {
+        self.weight_cache =
+            WeightCache::compute(|zero_for_one| self.compute_log_weight(zero_for_one));
+    }
+
+    fn compute_log_weight(&self, zero_for_one: bool) -> f64 {
+        let marginal = self.get_marginal_price(zero_for_one);
+        if marginal <= 0.0 {
+            return f64::INFINITY;
+        }
+        -marginal.ln()
+    }
+}
+
+impl LiquidityPool for StableSwapPool {
+    fn get_amount_out(&self, amount_in: U256, zero_for_one: bool) -> PoolResult<U256> {
+        pool_ensure!(
+            self.fee < STABLE_FEE_DENOMINATOR,
+            InvalidState,
+            "pool {} has invalid fee {}",
+            self.address,
+            self.fee
+        );
+        let (precision_in, precision_out) = if zero_for_one {
+            (self.precision0, self.precision1)
+        } else {
+            (self.precision1, self.precision0)
+        };
+        pool_ensure!(
+            precision_out != U256::ZERO,
+            InvalidState,
+            "pool {} has no precision set",
+            self.address
+        );
+
+        let (x_in, x_out) = self.xp(zero_for_one)?;
+        let d = self.get_d(x_in, x_out)?;
+        let dx = amount_in
+            .checked_mul(precision_in)
+            .ok_or_else(|| PoolError::Overflow(format!("amount_in {amount_in} overflows")))?;
+        let x = x_in
+            .checked_add(dx)
+            .ok_or_else(|| PoolError::Overflow(format!("amount_in {amount_in} overflows")))?;
+        let y = self.get_y(x, d)?;
+
+        // Curve keeps 1 wei back for rounding, then takes the fee from the output
+        let dy = x_out.saturating_sub(y).saturating_sub(U256::from(1));
+        let fee = mul_div(dy, U256::from(self.fee), U256::from(STABLE_FEE_DENOMINATOR))?;
+        Ok((dy - fee) / precision_out)
+    }
+
+    fn get_amount_in(&self, amount_out: U256, zero_for_one: bool) -> PoolResult<U256> {
+        pool_ensure!(
+            self.fee < STABLE_FEE_DENOMINATOR,
+            InvalidState,
+            "pool {} has invalid fee {}",
+            self.address,
+            self.fee
+        );
+        let (precision_in, precision_out) = if zero_for_one {
+            (self.precision0, self.precision1)
+        } else {
+            (self.precision1, self.precision0)
+        };
+        pool_ensure!(
+            precision_in != U256::ZERO,
+            InvalidState,
+            "pool {} has no precision set",
+            self.address
+        );
+
+        let (x_in, x_out) = self.xp(zero_for_one)?;
+        let d = self.get_d(x_in, x_out)?;
+
+        // Undo get_amount_out's steps in reverse: the fee, then the 1 wei kept back
+        let dy = amount_out
+            .checked_mul(precision_out)
+            .ok_or_else(|| PoolError::Overflow(format!("amount_out {amount_out} overflows")))?;
+        let fee_complement = U256::from(STABLE_FEE_DENOMINATOR - self.fee);
+        let dy = mul_div_rounding_up(dy, U256::from(STABLE_FEE_DENOMINATOR), fee_complement)?
+            + U256::from(1);
+        pool_ensure!(
+            dy < x_out,
+            InsufficientLiquidity,
+            "pool {} can't pay out {amount_out}",
+            self.address
+        );
+
+        // The curve is symmetric in the two balances, so get_y also solves for the input side
+        let x = self.get_y(x_out - dy, d)?;
+        let dx = x.saturating_sub(x_in);
+        // Round up, plus 1 for the Newton iterations' own rounding
+        Ok(dx.div_ceil(precision_in) + U256::from(1))
+    }
+
+    fn address(&self) -> Address {
+        self.address
+    }
+    fn tokens(&self) -> (Address, Address) {
+        (self.token0, self.token1)
+    }
+    fn get_log_weight(&self, zero_for_one: bool) -> f64 {
+        self.weight_cache
+            .get(zero_for_one)
+            .unwrap_or_else(|| self.compute_log_weight(zero_for_one))
+    }
+    fn get_marginal_price(&self, zero_for_one: bool) -> f64 {
+        let Ok((x_in, x_out)) = self.xp(zero_for_one) else {
+            return 0.0;
+        };
+        let Ok(d) = self.get_d(x_in, x_out) else {
+            return 0.0;
+        };
+        if d == U256::ZERO {
+            return 0.0;
+        }
+
+        // Implicit derivative of Ann * (x + y) + D = Ann * D + D^3 / (4xy):
+        // -dy/dx = (Ann + D^3 / (4x^2 y)) / (Ann + D^3 / (4x y^2))
+        let (x, y, d) = (u256_to_f64(x_in), u256_to_f64(x_out), u256_to_f64(d));
+        let ann = u256_to_f64(self.ann());
+        let d3 = d * d * d;
+        let normalized = (ann + d3 / (4.0 * x * x * y)) / (ann + d3 / (4.0 * x * y * y));
+
+        // Back to raw units of the two tokens, net of the fee
+        let (precision_in, precision_out) = if zero_for_one {
+            (self.precision0, self.precision1)
+        } else {
+            (self.precision1, self.precision0)
+        };
+        let fee = self.fee as f64 / STABLE_FEE_DENOMINATOR as f64;
+        normalized * u256_to_f64(precision_in) / u256_to_f64(precision_out) * (1.0 - fee)
+    }
+    fn update_from_log(&mut self, log: &Log) -> PoolResult<()> {
+        if self.apply_log(log)? {
+            self.refresh_weights();
+        }
+        Ok(())
+    }
+}
+
+/// keccak256("Swap(bytes32,address,address,uint256,uint256)"), emitted by the Balancer Vault
+pub const BALANCER_SWAP_EVENT: B256 =
+    b256!("2170c741c41531aec20e7c107c24eecfdd15e69c9bb0a8dd37b1840b9e0b207b");
+
+/// Balancer weights and swap fees are 18-decimal fixed point (1e18 = 100%)
+pub const WEIGHTED_ONE: u64 = 1_000_000_000_000_000_000;
+
+/// Balancer caps a swap's input (and output) at 30% of the corresponding balance
+const WEIGHTED_MAX_RATIO: f64 = 0.3;
+
+/// Relative slack applied against the trader to `f64` weighted-pool quotes, well above their
+/// rounding error, so they never promise more than the Vault pays
+const WEIGHTED_QUOTE_MARGIN: f64 = 1e-12;
+
+/// A 2-token Balancer weighted pool (constant mean `b0^w0 * b1^w1`), like the 80/20 pools on
+/// Polygon.
+///
+/// The power in the invariant is evaluated in `f64` (via `ln_1p`/`exp_m1`, which keeps small
+/// trades precise) rather than with Balancer's fixed-point LogExpMath, so quotes agree with
+/// the Vault to about 1e-15 relative rather than to the wei; `WEIGHTED_QUOTE_MARGIN` keeps them
+/// on the conservative side.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WeightedPool {
+    pub address: Address,
+    /// Vault pool id; Vault Swap logs carry it in `topics[1]`
+    pub pool_id: B256,
+    pub token0: Address,
+    pub token1: Address,
+    pub balance0: U256, // Raw token units
+    pub balance1: U256,
+
+    // Normalized weights in WEIGHTED_ONE units, summing to WEIGHTED_ONE
+    pub weight0: u64,
+    pub weight1: u64,
+    pub swap_fee: u64, // In WEIGHTED_ONE units
+
+    // Block of the last log applied through GraphManager::apply_logs (0 if never updated)
+    #[serde(default)]
+    pub last_updated_block: u64,
+
+    // Refreshed by update_from_log / refresh_weights
+    #[serde(skip)]
+    pub weight_cache: WeightCache,
+}
+
+impl WeightedPool {
+    /// (balance_in, weight_in, balance_out, weight_out) for the given direction
+    fn sides(&self, zero_for_one: bool) -> (U256, u64, U256, u64) {
+        if zero_for_one {
+            (self.balance0, self.weight0, self.balance1, self.weight1)
+        } else {
+            (self.balance1, self.weight1, self.balance0, self.weight0)
+        }
+    }
+
+    /// Whether the weights and fee describe a tradable pool
+    fn params_valid(&self) -> bool {
+        self.weight0 != 0 && self.weight1 != 0 && self.swap_fee < WEIGHTED_ONE
+    }
+
+    /// Fraction of the input left after the swap fee
+    fn fee_complement(&self) -> f64 {
+        1.0 - self.swap_fee as f64 / WEIGHTED_ONE as f64
+    }
+
+    /// Applies a Vault Swap log without refreshing the cached weights.
+    /// Joins and exits (PoolBalanceChanged) aren't tracked; reload the pool after them.
+    pub fn apply_log(&mut self, log: &Log) -> PoolResult<bool> {
+        let topics = log.topics();
+        if topics.first() != Some(&BALANCER_SWAP_EVENT) {
+            return Ok(false);
+        }
+        // Swap(bytes32 indexed poolId, address indexed tokenIn, address indexed tokenOut,
+        //      uint256 amountIn, uint256 amountOut)
+        if topics.len() != 4 {
+            return Err(PoolError::MalformedLog(anyhow!(
+                "malformed Balancer Swap topics"
+            )));
+        }
+        if topics[1] != self.pool_id {
+            return Err(PoolError::PoolIdMismatch {
+                pool: self.pool_id,
+                log: topics[1],
+            });
+        }
+
+        let word = |index| abi_word(&log.data.data, index).map_err(PoolError::MalformedLog);
+        let amount_in = U256::from_be_slice(word(0)?);
+        let amount_out = U256::from_be_slice(word(1)?);
+        let token_in = Address::from_word(topics[2]);
+        let token_out = Address::from_word(topics[3]);
+
+        // The whole input, fee included, stays in the pool
+        let (balance_in, balance_out) = if (token_in, token_out) == (self.token0, self.token1) {
+            (&mut self.balance0, &mut self.balance1)
+        } else if (token_in, token_out) == (self.token1, self.token0) {
+            (&mut self.balance1, &mut self.balance0)
+        } else {
+            return Err(PoolError::MalformedLog(anyhow!(
+                "swap {token_in} -> {token_out} isn't this pool's pair"
+            )));
+        };
+        *balance_in = balance_in
+            .checked_add(amount_in)
+            .ok_or_else(|| PoolError::Overflow("balance overflow".into()))?;
+        *balance_out = balance_out
+            .checked_sub(amount_out)
+            .ok_or_else(|| PoolError::Overflow("balance underflow".into()))?;
+        Ok(true)
+    }
+
+    /// Recomputes the cached log weights; call after editing the balances directly
+    pub fn refresh_weights(&mut self) {
+        self.weight_cache =
+            WeightCache::compute(|zero_for_one| self.compute_log_weight(zero_for_one));
+    }
+
+    fn compute_log_weight(&self, zero_for_one: bool) -> f64 {
+        let marginal = self.get_marginal_price(zero_for_one);
+        if marginal <= 0.0 {
+            return f64::INFINITY;
+        }
+        -marginal.ln()
+    }
+}
+
+impl LiquidityPool for WeightedPool {
+    fn get_amount_out(&self, amount_in: U256, zero_for_one: bool) -> PoolResult<U256> {
+        pool_ensure!(
+            self.params_valid(),
+            InvalidState,
+            "pool {} has invalid weights or fee",
+            self.pool_id
+        );
+        let (balance_in, weight_in, balance_out, weight_out) = self.sides(zero_for_one);
+        pool_ensure!(
+            balance_in != U256::ZERO && balance_out != U256::ZERO,
+            InsufficientLiquidity,
+            "pool {} has an empty side",
+            self.pool_id
+        );
+        pool_ensure!(
+            u256_to_f64(amount_in) <= u256_to_f64(balance_in) * WEIGHTED_MAX_RATIO,
+            InsufficientLiquidity,
+            "amount_in {amount_in} exceeds the pool's max in ratio"
+        );
+
+        // The fee is taken from the input, rounded up
+        let fee = mul_div_rounding_up(
+            amount_in,
+            U256::from(self.swap_fee),
+            U256::from(WEIGHTED_ONE),
+        )?;
+        let amount_in = u256_to_f64(amount_in - fee);
+
+        // out = b_out * (1 - (b_in / (b_in + in))^(w_in / w_out))
+        let exponent = weight_in as f64 / weight_out as f64;
+        let ratio = -(-exponent * (amount_in / u256_to_f64(balance_in)).ln_1p()).exp_m1();
+        let amount_out = u256_to_f64(balance_out) * ratio * (1.0 - WEIGHTED_QUOTE_MARGIN);
+        let amount_out = U256::saturating_from(amount_out.floor());
+        Ok(amount_out.min(balance_out))
+    }
+
+    fn get_amount_in(&self, amount_out: U256, zero_for_one: bool) -> PoolResult<U256> {
+        pool_ensure!(
+            self.params_valid(),
+            InvalidState,
+            "pool {} has invalid weights or fee",
+            self.pool_id
+        );
+        let (balance_in, weight_in, balance_out, weight_out) = self.sides(zero_for_one);
+        pool_ensure!(
+            u256_to_f64(amount_out) <= u256_to_f64(balance_out) * WEIGHTED_MAX_RATIO,
+            InsufficientLiquidity,
+            "amount_out {amount_out} exceeds the pool's max out ratio"
+        );
+
+        // in = b_in * ((b_out / (b_out - out))^(w_out / w_in) - 1) / (1 - fee)
+        let exponent = weight_out as f64 / weight_in as f64;
+        let ratio =
+            (-exponent * (-u256_to_f64(amount_out) / u256_to_f64(balance_out)).ln_1p()).exp_m1();
+        let amount_in =
+            u256_to_f64(balance_in) * ratio / self.fee_complement() * (1.0 + WEIGHTED_QUOTE_MARGIN);
+        Ok(U256::saturating_from(amount_in.ceil()))
+    }
+
+    fn address(&self) -> Address {
+        self.address
+    }
+    fn tokens(&self) -> (Address, Address) {
+        (self.token0, self.token1)
+    }
+    fn get_log_weight(&self, zero_for_one: bool) -> f64 {
+        self.weight_cache
+            .get(zero_for_one)
+            .unwrap_or_else(|| self.compute_log_weight(zero_for_one))
+    }
+    fn get_marginal_price(&self, zero_for_one: bool) -> f64 {
+        let (balance_in, weight_in, balance_out, weight_out) = self.sides(zero_for_one);
+        if !self.params_valid() || balance_in == U256::ZERO || balance_out == U256::ZERO {
+            return 0.0;
+        }
+
+        // d(out)/d(in) at zero input: (b_out / w_out) / (b_in / w_in), net of the fee
+        let spot = (u256_to_f64(balance_out) * weight_in as f64)
+            / (u256_to_f64(balance_in) * weight_out as f64);
+        spot * self.fee_complement()
+    }
+    fn update_from_log(&mut self, log: &Log) -> PoolResult<()> {
+        if self.apply_log(log)? {
+            self.refresh_weights();
+        }
+        Ok(())
     }
 }
 
 /// This is the most critical part for performance. Instead of using Box<dyn LiquidityPool>, use an enum.
 /// This allows the compiler to inline the functions, making your graph traversal significantly faster.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PoolVariant {
     V2(UniswapV2Pool),
     V3(UniswapV3Pool),
     V4(UniswapV4Pool),
+    Stable(StableSwapPool),
+    Weighted(WeightedPool),
+}
+
+impl PoolVariant {
+    /// Unique identity of the pool: the contract address (left-padded) for V2/V3/Stable, and the
+    /// singleton's pool id for V4 and Balancer (see `singleton_id`)
+    pub fn id(&self) -> B256 {
+        self.singleton_id()
+            .unwrap_or_else(|| self.address().into_word())
+    }
+
+    /// For pools living in a singleton (V4's PoolManager, Balancer's Vault), the singleton's
+    /// pool id, which its logs carry in `topics[1]`. `None` for standalone pool contracts.
+    pub fn singleton_id(&self) -> Option<B256> {
+        match self {
+            PoolVariant::V4(p) => Some(p.key.id()),
+            PoolVariant::Weighted(p) => Some(p.pool_id),
+            _ => None,
+        }
+    }
+
+    /// Short protocol label ("V2", "V3", "V4", "Stable", "Weighted") for logs and exports
+    pub fn version(&self) -> &'static str {
+        match self {
+            PoolVariant::V2(_) => "V2",
+            PoolVariant::V3(_) => "V3",
+            PoolVariant::V4(_) => "V4",
+            PoolVariant::Stable(_) => "Stable",
+            PoolVariant::Weighted(_) => "Weighted",
+        }
+    }
+
+    /// Applies a log without refreshing the cached weights, so a batch of logs can share a
+    /// single `refresh_weights`. Returns whether the pool state changed.
+    pub fn apply_log(&mut self, log: &Log) -> PoolResult<bool> {
+        match self {
+            PoolVariant::V2(p) => p.apply_log(log),
+            PoolVariant::V3(p) => p.apply_log(log),
+            PoolVariant::V4(p) => p.apply_log(log),
+            PoolVariant::Stable(p) => p.apply_log(log),
+            PoolVariant::Weighted(p) => p.apply_log(log),
+        }
+    }
+
+    /// `get_amount_out` reusing (and extending) the tick crossings memoized in `cache`.
+    /// V2, StableSwap and weighted pools have no ticks to walk and ignore it.
+    pub fn get_amount_out_cached(
+        &self,
+        amount_in: U256,
+        zero_for_one: bool,
+        cache: &mut SwapCache,
+    ) -> PoolResult<U256> {
+        match self {
+            PoolVariant::V2(p) => p.get_amount_out(amount_in, zero_for_one),
+            PoolVariant::V3(p) => p
+                .state()
+                .swap_exact_input_cached(amount_in, zero_for_one, p.resolve_fee(), Some(cache))
+                .map(|(amount_out, _)| amount_out),
+            PoolVariant::V4(p) => p
+                .state()
+                .swap_exact_input_cached(amount_in, zero_for_one, p.resolve_fee(), Some(cache))
+                .map(|(amount_out, _)| amount_out),
+            PoolVariant::Stable(p) => p.get_amount_out(amount_in, zero_for_one),
+            PoolVariant::Weighted(p) => p.get_amount_out(amount_in, zero_for_one),
+        }
+    }
+
+    /// Block of the last log applied to this pool (see `GraphManager::apply_logs`)
+    pub fn last_updated_block(&self) -> u64 {
+        match self {
+            PoolVariant::V2(p) => p.last_updated_block,
+            PoolVariant::V3(p) => p.last_updated_block,
+            PoolVariant::V4(p) => p.last_updated_block,
+            PoolVariant::Stable(p) => p.last_updated_block,
+            PoolVariant::Weighted(p) => p.last_updated_block,
+        }
+    }
+
+    pub fn set_last_updated_block(&mut self, block: u64) {
+        match self {
+            PoolVariant::V2(p) => p.last_updated_block = block,
+            PoolVariant::V3(p) => p.last_updated_block = block,
+            PoolVariant::V4(p) => p.last_updated_block = block,
+            PoolVariant::Stable(p) => p.last_updated_block = block,
+            PoolVariant::Weighted(p) => p.last_updated_block = block,
+        }
+    }
+
+    /// Recomputes the pool's cached log weights
+    pub fn refresh_weights(&mut self) {
+        match self {
+            PoolVariant::V2(p) => p.refresh_weights(),
+            PoolVariant::V3(p) => p.refresh_weights(),
+            PoolVariant::V4(p) => p.refresh_weights(),
+            PoolVariant::Stable(p) => p.refresh_weights(),
+            PoolVariant::Weighted(p) => p.refresh_weights(),
+        }
+    }
+
+    /// Approximation of `get_log_weight` for a coarse first scan of a huge graph: the logs are
+    /// taken from bit lengths and a small table (`approx_ln`) instead of `f64::ln`, which puts
+    /// it within about 5e-4 of the exact weight (0.05% on the rate). Candidate cycles must be
+    /// re-evaluated with the exact weights. StableSwap has no closed form and returns the exact
+    /// (cached) weight, and neither do weighted pools' non-integer powers.
+    pub fn get_log_weight_fast(&self, zero_for_one: bool) -> f64 {
+        let concentrated = |sqrt_price_x96: U256, liquidity: u128, fee: u32| {
+            if sqrt_price_x96 == U256::ZERO || liquidity == 0 || fee >= FEE_DENOMINATOR {
+                return f64::INFINITY;
+            }
+            // ln(price of token0 in token1) = 2 * (ln(sqrtPriceX96) - 96 ln 2)
+            let ln_price = 2.0 * (approx_ln(sqrt_price_x96) - 96.0 * std::f64::consts::LN_2);
+            let ln_price = if zero_for_one { ln_price } else { -ln_price };
+            let ln_keep = approx_ln(U256::from(FEE_DENOMINATOR - fee))
+                - approx_ln(U256::from(FEE_DENOMINATOR));
+            -(ln_price + ln_keep)
+        };
+
+        match self {
+            PoolVariant::V2(p) => {
+                let (r_in, r_out) = p.reserves(zero_for_one);
+                if r_in == 0 || r_out == 0 || !p.fees_valid() {
+                    return f64::INFINITY;
+                }
+                // -ln(r_out * keep / (r_in * 10000^3)), keep being the product of what the
+                // swap fee and both taxes leave (out of 10000 each)
+                let keep = [p.fee_bps, p.transfer_fee_bps_in, p.transfer_fee_bps_out]
+                    .iter()
+                    .fold(U256::from(1), |keep, &bps| keep * U256::from(10000 - bps));
+                approx_ln(U256::from(r_in) * U256::from(10000u64.pow(3)))
+                    - approx_ln(U256::from(r_out) * keep)
+            }
+            PoolVariant::V3(p) => concentrated(p.sqrt_price_x96, p.liquidity, p.resolve_fee()),
+            PoolVariant::V4(p) => concentrated(p.sqrt_price_x96, p.liquidity, p.resolve_fee()),
+            PoolVariant::Stable(p) => p.get_log_weight(zero_for_one),
+            PoolVariant::Weighted(p) => p.get_log_weight(zero_for_one),
+        }
+    }
+
+    /// Compact one-line summary of trading this pool in the given direction, for cycle logs,
+    /// e.g. `V3 0xC02a..6Cc2->0xA0b8..eB48 fee=3000 w=-0.000400 L=1000000`. Tokens are shown as
+    /// shortened addresses; see `describe_with_symbols` to name them.
+    pub fn describe(&self, zero_for_one: bool) -> String {
+        let (token0, token1) = self.tokens();
+        let (token_in, token_out) = if zero_for_one {
+            (token0, token1)
+        } else {
+            (token1, token0)
+        };
+        self.describe_with_symbols(
+            zero_for_one,
+            &short_address(token_in),
+            &short_address(token_out),
+        )
+    }
+
+    /// `describe` with the given token labels. The last field is the pool's depth: reserves
+    /// (in/out) for V2, active liquidity for V3/V4, amplification and balances for StableSwap,
+    /// weights (in/out, in WEIGHTED_ONE units) and balances for weighted pools.
+    pub fn describe_with_symbols(
+        &self,
+        zero_for_one: bool,
+        symbol_in: &str,
+        symbol_out: &str,
+    ) -> String {
+        let in_out = |a, b| if zero_for_one { (a, b) } else { (b, a) };
+        let depth = match self {
+            PoolVariant::V2(p) => {
+                let (r_in, r_out) = p.reserves(zero_for_one);
+                format!("R={r_in}/{r_out}")
+            }
+            PoolVariant::V3(p) => format!("L={}", p.liquidity),
+            PoolVariant::V4(p) => format!("L={}", p.liquidity),
+            PoolVariant::Stable(p) => {
+                let (b_in, b_out) = in_out(p.balance0, p.balance1);
+                format!("A={} B={b_in}/{b_out}", p.amp)
+            }
+            PoolVariant::Weighted(p) => {
+                let (b_in, w_in, b_out, w_out) = p.sides(zero_for_one);
+                format!("W={w_in}/{w_out} B={b_in}/{b_out}")
+            }
+        };
+        format!(
+            "{} {symbol_in}->{symbol_out} fee={} w={:.6} {depth}",
+            self.version(),
+            self.fee_pips(),
+            self.get_log_weight(zero_for_one)
+        )
+    }
+
+    /// Marginal price in Q64.96 (see `PRICE_X96_ONE`). Computed in integers for V2 and V3/V4
+    /// (the representation their weights come from); the other versions convert their `f64`
+    /// marginal price.
+    pub fn marginal_price_x96(&self, zero_for_one: bool) -> U256 {
+        match self {
+            PoolVariant::V2(p) => p.marginal_price_x96(zero_for_one),
+            PoolVariant::V3(p) => p.state().marginal_price_x96(zero_for_one, p.resolve_fee()),
+            PoolVariant::V4(p) => p.state().marginal_price_x96(zero_for_one, p.resolve_fee()),
+            _ => {
+                let price = self.get_marginal_price(zero_for_one);
+                U256::saturating_from(price.max(0.0) * u256_to_f64(PRICE_X96_ONE))
+            }
+        }
+    }
+
+    /// Rough depth of the pool in liquidity units (sqrt(x * y)), comparable across versions:
+    /// active liquidity for V3/V4, the geometric mean of the reserves or balances otherwise
+    pub fn depth(&self) -> f64 {
+        let geometric_mean = |a: f64, b: f64| (a * b).sqrt();
+        match self {
+            PoolVariant::V2(p) => geometric_mean(p.reserve0 as f64, p.reserve1 as f64),
+            PoolVariant::V3(p) => p.liquidity as f64,
+            PoolVariant::V4(p) => p.liquidity as f64,
+            PoolVariant::Stable(p) => {
+                geometric_mean(u256_to_f64(p.balance0), u256_to_f64(p.balance1))
+            }
+            PoolVariant::Weighted(p) => {
+                geometric_mean(u256_to_f64(p.balance0), u256_to_f64(p.balance1))
+            }
+        }
+    }
+
+    /// What the pool holds of the token it pays out when trading in this direction, which no
+    /// swap can take in full. `None` for V3/V4: their reserves are virtual, and a swap that runs
+    /// out of liquidity stops at the end of the tick range instead.
+    pub fn output_reserve(&self, zero_for_one: bool) -> Option<U256> {
+        let pick = |balance0: U256, balance1: U256| {
+            if zero_for_one { balance1 } else { balance0 }
+        };
+        match self {
+            PoolVariant::V2(p) => Some(pick(U256::from(p.reserve0), U256::from(p.reserve1))),
+            PoolVariant::V3(_) | PoolVariant::V4(_) => None,
+            PoolVariant::Stable(p) => Some(pick(p.balance0, p.balance1)),
+            PoolVariant::Weighted(p) => Some(pick(p.balance0, p.balance1)),
+        }
+    }
+
+    /// The swap fee normalized to hundredths of a bip (3000 = 0.3%) across versions
+    pub fn fee_pips(&self) -> u32 {
+        match self {
+            PoolVariant::V2(p) => p.fee_bps * 100,
+            PoolVariant::V3(p) => p.resolve_fee(),
+            PoolVariant::V4(p) => p.resolve_fee(),
+            // 1e10ths -> 1e6ths
+            PoolVariant::Stable(p) => (p.fee / 10_000) as u32,
+            // 1e18ths -> 1e6ths
+            PoolVariant::Weighted(p) => (p.swap_fee / 1_000_000_000_000) as u32,
+        }
+    }
 }
 
 // Delegate Trait implementation to the enum variants
@@ -177,14 +2066,28 @@ impl LiquidityPool for PoolVariant {
             PoolVariant::V2(p) => p.address(),
             PoolVariant::V3(p) => p.address(),
             PoolVariant::V4(p) => p.address(),
+            PoolVariant::Stable(p) => p.address(),
+            PoolVariant::Weighted(p) => p.address(),
         }
     }
 
-    fn get_amount_out(&self, amount_in: U256, zero_for_one: bool) -> Result<U256> {
+    fn get_amount_out(&self, amount_in: U256, zero_for_one: bool) -> PoolResult<U256> {
         match self {
             PoolVariant::V2(p) => p.get_amount_out(amount_in, zero_for_one),
             PoolVariant::V3(p) => p.get_amount_out(amount_in, zero_for_one),
             PoolVariant::V4(p) => p.get_amount_out(amount_in, zero_for_one),
+            PoolVariant::Stable(p) => p.get_amount_out(amount_in, zero_for_one),
+            PoolVariant::Weighted(p) => p.get_amount_out(amount_in, zero_for_one),
+        }
+    }
+
+    fn get_amount_in(&self, amount_out: U256, zero_for_one: bool) -> PoolResult<U256> {
+        match self {
+            PoolVariant::V2(p) => p.get_amount_in(amount_out, zero_for_one),
+            PoolVariant::V3(p) => p.get_amount_in(amount_out, zero_for_one),
+            PoolVariant::V4(p) => p.get_amount_in(amount_out, zero_for_one),
+            PoolVariant::Stable(p) => p.get_amount_in(amount_out, zero_for_one),
+            PoolVariant::Weighted(p) => p.get_amount_in(amount_out, zero_for_one),
         }
     }
 
@@ -193,6 +2096,8 @@ impl LiquidityPool for PoolVariant {
             PoolVariant::V2(p) => p.get_log_weight(zero_for_one),
             PoolVariant::V3(p) => p.get_log_weight(zero_for_one),
             PoolVariant::V4(p) => p.get_log_weight(zero_for_one),
+            PoolVariant::Stable(p) => p.get_log_weight(zero_for_one),
+            PoolVariant::Weighted(p) => p.get_log_weight(zero_for_one),
         }
     }
 
@@ -201,14 +2106,18 @@ impl LiquidityPool for PoolVariant {
             PoolVariant::V2(p) => p.get_marginal_price(zero_for_one),
             PoolVariant::V3(p) => p.get_marginal_price(zero_for_one),
             PoolVariant::V4(p) => p.get_marginal_price(zero_for_one),
+            PoolVariant::Stable(p) => p.get_marginal_price(zero_for_one),
+            PoolVariant::Weighted(p) => p.get_marginal_price(zero_for_one),
         }
     }
 
-    fn update_from_log(&mut self, log: &Log) -> Result<()> {
+    fn update_from_log(&mut self, log: &Log) -> PoolResult<()> {
         match self {
             PoolVariant::V2(p) => p.update_from_log(log),
             PoolVariant::V3(p) => p.update_from_log(log),
             PoolVariant::V4(p) => p.update_from_log(log),
+            PoolVariant::Stable(p) => p.update_from_log(log),
+            PoolVariant::Weighted(p) => p.update_from_log(log),
         }
     }
 
@@ -217,6 +2126,17 @@ impl LiquidityPool for PoolVariant {
             PoolVariant::V2(p) => p.tokens(),
             PoolVariant::V3(p) => p.tokens(),
             PoolVariant::V4(p) => p.tokens(),
+            PoolVariant::Stable(p) => p.tokens(),
+            PoolVariant::Weighted(p) => p.tokens(),
         }
     }
 }
+
+/// `0xC02a..6Cc2`: the checksummed address cut down to its first and last 4 hex digits
+fn short_address(address: Address) -> String {
+    let full = address.to_string();
+    format!("{}..{}", &full[..6], &full[full.len() - 4..])
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,1373 @@
+use super::*;
+use crate::common::swap_math::get_amount1_delta;
+use crate::common::test_util::{
+    addr, log, modify_liquidity_log, position_log, sync_log, v2_pool, v3_pool, v3_swap_log,
+    v4_pool, v4_swap_log,
+};
+use alloy_primitives::address;
+use proptest::prelude::*;
+
+const E18: u128 = 1_000_000_000_000_000_000;
+const E24: u128 = 1_000_000_000_000_000_000_000_000;
+
+fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+    assert!(
+        (actual - expected).abs() <= tolerance,
+        "{actual} is not within {tolerance} of {expected}"
+    );
+}
+
+#[test]
+fn v2_balanced_pool_weighs_the_fee() {
+    let pool = v2_pool(addr(1), addr(2), addr(3), E24, E24);
+    for zero_for_one in [true, false] {
+        assert_close(pool.get_log_weight(zero_for_one), -(0.997f64).ln(), 1e-12);
+    }
+}
+
+#[test]
+fn v2_weight_follows_the_direction() {
+    // 1 token0 buys 3000 token1
+    let pool = v2_pool(addr(1), addr(2), addr(3), E24, 3000 * E24);
+    assert_close(pool.get_log_weight(true), -(3000.0 * 0.997f64).ln(), 1e-12);
+    assert_close(pool.get_log_weight(false), -(0.997f64 / 3000.0).ln(), 1e-12);
+}
+
+#[test]
+fn v2_empty_pool_is_untradeable() {
+    for (reserve0, reserve1) in [(0, E24), (E24, 0), (0, 0)] {
+        let pool = v2_pool(addr(1), addr(2), addr(3), reserve0, reserve1);
+        assert_eq!(pool.get_log_weight(true), f64::INFINITY);
+        assert_eq!(pool.get_log_weight(false), f64::INFINITY);
+    }
+}
+
+#[test]
+fn v3_swap_within_one_range_matches_swap_math() {
+    // SwapMath.spec "exact amount in that is fully spent in one for zero", as a whole pool
+    let mut pool = v3_pool(addr(1), addr(2), addr(3), PRICE_X96_ONE, 2 * E18);
+    pool.fee = 600;
+    let (out, fee) = pool
+        .get_amount_out_with_fee(U256::from(E18), false)
+        .unwrap();
+    assert_eq!(out, U256::from(666399946655997866u128));
+    assert_eq!(fee, U256::from(600000000000000u128));
+}
+
+#[test]
+fn v3_swap_crosses_out_of_the_last_position() {
+    // A single position over [-60, 60): selling far more token0 than it holds drains it down
+    // to tick -60, and nothing is left below
+    let mut pool = v3_pool(addr(1), addr(2), addr(3), PRICE_X96_ONE, E18);
+    pool.tick_bitmap = BTreeMap::from([(-60, E18 as i128), (60, -(E18 as i128))]);
+
+    let out = pool.get_amount_out(U256::from(100 * E18), true).unwrap();
+    let lower = get_sqrt_ratio_at_tick(-60).unwrap();
+    let expected = get_amount1_delta(lower, PRICE_X96_ONE, E18, false).unwrap();
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn v3_marginal_price_matches_small_trades() {
+    // Price 10 (token1 per token0) over a wide, deep range
+    let sqrt_price = "250541448375047931186413801569".parse().unwrap();
+    let mut pool = v3_pool(addr(1), addr(2), addr(3), sqrt_price, E24);
+    pool.fee = 500;
+    pool.tick_bitmap = BTreeMap::from([(-600_000, E24 as i128), (600_000, -(E24 as i128))]);
+
+    let amount_in = 1e9;
+    for zero_for_one in [true, false] {
+        let out = pool
+            .get_amount_out(U256::from(amount_in as u64), zero_for_one)
+            .unwrap();
+        let marginal = pool.get_marginal_price(zero_for_one);
+        assert_close(u256_to_f64(out) / amount_in / marginal, 1.0, 1e-6);
+    }
+    assert_close(pool.get_marginal_price(true), 10.0 * 0.9995, 1e-9);
+}
+
+#[test]
+fn v2_sync_log_sets_the_reserves() {
+    let mut pool = v2_pool(addr(1), addr(2), addr(3), 1, 1);
+    pool.update_from_log(&sync_log(addr(1), 5 * E18, 7 * E18))
+        .unwrap();
+    assert_eq!((pool.reserve0, pool.reserve1), (5 * E18, 7 * E18));
+    // ...and the cached weights follow
+    assert_close(pool.get_log_weight(true), -(1.4 * 0.997f64).ln(), 1e-12);
+}
+
+#[test]
+fn v2_rejects_foreign_logs() {
+    let mut pool = v2_pool(addr(1), addr(2), addr(3), 1, 1);
+
+    let mut other_event = sync_log(addr(1), 5, 7);
+    other_event.data.set_topics_unchecked(vec![B256::ZERO]);
+    assert!(matches!(
+        pool.update_from_log(&other_event),
+        Err(PoolError::UnknownEvent(Some(B256::ZERO)))
+    ));
+
+    let other_pool = sync_log(addr(9), 5, 7);
+    assert!(matches!(
+        pool.update_from_log(&other_pool),
+        Err(PoolError::AddressMismatch { .. })
+    ));
+    assert_eq!((pool.reserve0, pool.reserve1), (1, 1));
+}
+
+#[test]
+fn v3_mint_around_the_price_adds_active_liquidity() {
+    let mut pool = v3_pool(addr(1), addr(2), addr(3), PRICE_X96_ONE, 0);
+    assert_eq!(pool.get_log_weight(true), f64::INFINITY);
+
+    pool.update_from_log(&position_log(addr(1), -60, 60, E18, true))
+        .unwrap();
+    assert_eq!(pool.liquidity, E18);
+    assert_eq!(pool.net_liquidity_at(-60), E18 as i128);
+    assert_eq!(pool.net_liquidity_at(60), -(E18 as i128));
+    assert!(pool.get_log_weight(true).is_finite());
+
+    // A position entirely above the price only registers its ticks
+    pool.update_from_log(&position_log(addr(1), 120, 240, E18, true))
+        .unwrap();
+    assert_eq!(pool.liquidity, E18);
+    assert_eq!(pool.net_liquidity_at(120), E18 as i128);
+
+    // Burning everything clears the ticks again
+    pool.update_from_log(&position_log(addr(1), -60, 60, E18, false))
+        .unwrap();
+    pool.update_from_log(&position_log(addr(1), 120, 240, E18, false))
+        .unwrap();
+    assert_eq!(pool.liquidity, 0);
+    assert!(pool.tick_bitmap.is_empty());
+}
+
+#[test]
+fn zero_liquidity_pools_fill_nothing_in_either_direction() {
+    let v3 = v3_pool(addr(1), addr(2), addr(3), PRICE_X96_ONE, 0);
+    let v4 = v4_pool(addr(2), addr(3), 3000, PRICE_X96_ONE, 0);
+    let amount = U256::from(E18);
+    for zero_for_one in [true, false] {
+        assert_eq!(v3.get_amount_out(amount, zero_for_one).unwrap(), U256::ZERO);
+        assert_eq!(v4.get_amount_out(amount, zero_for_one).unwrap(), U256::ZERO);
+        assert_eq!(
+            v3.get_amount_out_with_fee(amount, zero_for_one).unwrap(),
+            (U256::ZERO, U256::ZERO)
+        );
+        assert_eq!(v3.get_log_weight(zero_for_one), f64::INFINITY);
+        assert_eq!(v4.get_log_weight(zero_for_one), f64::INFINITY);
+        assert!(v3.get_amount_in(U256::from(1), zero_for_one).is_err());
+        assert!(v4.get_amount_in(U256::from(1), zero_for_one).is_err());
+    }
+}
+
+#[test]
+fn zero_active_liquidity_still_crosses_into_a_position() {
+    // Empty at the price, with a position above it: only buying token0 can reach it
+    let mut pool = v3_pool(addr(1), addr(2), addr(3), PRICE_X96_ONE, 0);
+    pool.apply_position_delta(120, 240, E18, true).unwrap();
+    pool.refresh_weights();
+
+    assert_eq!(
+        pool.get_amount_out(U256::from(E18), true).unwrap(),
+        U256::ZERO
+    );
+    assert!(pool.get_amount_out(U256::from(E18 / 1000), false).unwrap() > U256::ZERO);
+}
+
+#[test]
+fn v3_weights_need_liquidity_at_the_price_in_that_direction() {
+    // A position above the price: token1 buys into it across the empty range, token0 has
+    // nothing to buy
+    let mut below = v3_pool(addr(1), addr(2), addr(3), PRICE_X96_ONE, 0);
+    below.apply_position_delta(120, 240, E18, true).unwrap();
+    below.refresh_weights();
+    assert_eq!(below.get_log_weight(true), f64::INFINITY);
+
+    // Priced at the position's lower tick, that tick's liquidity is active going up but
+    // crossed before trading going down
+    let mut on_lower = UniswapV3Pool {
+        sqrt_price_x96: get_sqrt_ratio_at_tick(120).unwrap(),
+        tick: 120,
+        liquidity: E18,
+        ..below.clone()
+    };
+    on_lower.refresh_weights();
+    assert!(on_lower.get_log_weight(false).is_finite());
+    assert_eq!(on_lower.get_log_weight(true), f64::INFINITY);
+    assert_eq!(
+        on_lower.get_amount_out(U256::from(E18), true).unwrap(),
+        U256::ZERO
+    );
+}
+
+#[test]
+fn v3_stopped_on_a_tick_from_above_still_trades_upwards() {
+    // A swap down that ends exactly on the lower tick of the only position leaves tick 119
+    // and the price of 120, with that tick already crossed
+    let at_lower = get_sqrt_ratio_at_tick(120).unwrap();
+    let mut crossed = v3_pool(addr(1), addr(2), addr(3), at_lower, 0);
+    crossed.tick = 119;
+    crossed.apply_position_delta(120, 240, E18, true).unwrap();
+    crossed.refresh_weights();
+    assert_eq!(crossed.liquidity, 0);
+    // The same state as the pool would report it had the price arrived from below
+    let mut arrived = UniswapV3Pool {
+        tick: 120,
+        liquidity: E18,
+        ..crossed.clone()
+    };
+    arrived.refresh_weights();
+
+    for zero_for_one in [true, false] {
+        assert_eq!(
+            crossed.get_log_weight(zero_for_one),
+            arrived.get_log_weight(zero_for_one)
+        );
+        assert_eq!(
+            crossed.get_marginal_price(zero_for_one),
+            arrived.get_marginal_price(zero_for_one)
+        );
+        let amount = U256::from(E18 / 1000);
+        assert_eq!(
+            crossed.get_amount_out(amount, zero_for_one).unwrap(),
+            arrived.get_amount_out(amount, zero_for_one).unwrap()
+        );
+    }
+    assert!(crossed.get_log_weight(false).is_finite());
+    assert_eq!(crossed.get_log_weight(true), f64::INFINITY);
+}
+
+#[test]
+fn v3_swap_log_moves_the_price_across_a_tick() {
+    let mut pool = v3_pool(addr(1), addr(2), addr(3), PRICE_X96_ONE, E18);
+    pool.tick_bitmap = BTreeMap::from([(-60, E18 as i128), (60, -(E18 as i128))]);
+
+    // The swap pushed the price below tick -60, where only a second position is active
+    let sqrt_price = get_sqrt_ratio_at_tick(-61).unwrap();
+    pool.update_from_log(&v3_swap_log(addr(1), sqrt_price, 5, -61))
+        .unwrap();
+    assert_eq!(
+        (pool.sqrt_price_x96, pool.liquidity, pool.tick),
+        (sqrt_price, 5, -61)
+    );
+    assert_close(
+        pool.get_log_weight(true),
+        -(1.0001f64.powi(-61) * 0.997).ln(),
+        1e-9,
+    );
+}
+
+#[test]
+fn v3_ignores_unrelated_events() {
+    let mut pool = v3_pool(addr(1), addr(2), addr(3), PRICE_X96_ONE, E18);
+    let collect = log(addr(1), vec![B256::repeat_byte(0xc0)], &[]);
+    assert!(!pool.apply_log(&collect).unwrap());
+    assert!(pool.update_from_log(&collect).is_ok());
+    assert!(
+        pool.update_from_log(&v3_swap_log(addr(9), PRICE_X96_ONE, 1, 0))
+            .is_err()
+    );
+}
+
+#[test]
+fn v3_next_initialized_tick_on_a_sparse_bitmap() {
+    let mut pool = v3_pool(addr(1), addr(2), addr(3), PRICE_X96_ONE, 0);
+    // 30 is off the 60-tick grid and must never be returned
+    pool.tick_bitmap = [-120, 0, 30, 60, 180]
+        .into_iter()
+        .map(|tick| (tick, 1))
+        .collect();
+
+    assert_eq!(pool.next_initialized_tick(59, true), Some(0));
+    assert_eq!(pool.next_initialized_tick(0, true), Some(0));
+    assert_eq!(pool.next_initialized_tick(-1, true), Some(-120));
+    assert_eq!(pool.next_initialized_tick(-121, true), None);
+    assert_eq!(pool.next_initialized_tick(0, false), Some(60));
+    assert_eq!(pool.next_initialized_tick(60, false), Some(180));
+    assert_eq!(pool.next_initialized_tick(180, false), None);
+
+    assert_eq!(pool.net_liquidity_at(60), 1);
+    assert_eq!(pool.net_liquidity_at(120), 0);
+}
+
+#[test]
+fn v4_dynamic_fee_pool_charges_the_override() {
+    let mut dynamic = v4_pool(
+        addr(2),
+        addr(3),
+        V4_DYNAMIC_FEE_FLAG,
+        PRICE_X96_ONE,
+        100 * E18,
+    );
+    assert_eq!(dynamic.resolve_fee(), V4_DEFAULT_DYNAMIC_FEE);
+
+    dynamic.dynamic_fee = Some(500);
+    assert_eq!(dynamic.resolve_fee(), 500);
+    let fixed = v4_pool(addr(2), addr(3), 500, PRICE_X96_ONE, 100 * E18);
+    let amount_in = U256::from(E18 / 1000);
+    for zero_for_one in [true, false] {
+        assert_eq!(
+            dynamic.get_amount_out(amount_in, zero_for_one).unwrap(),
+            fixed.get_amount_out(amount_in, zero_for_one).unwrap()
+        );
+    }
+}
+
+#[test]
+fn v2_amount_out_rejects_overflowing_inputs() {
+    let pool = v2_pool(addr(1), addr(2), addr(3), u128::MAX, u128::MAX);
+    assert!(
+        pool.get_amount_out(U256::MAX / U256::from(2), true)
+            .is_err()
+    );
+
+    // amount_in * 9970 * reserve_out needs more than 256 bits, but the quotient fits
+    let amount_out = pool
+        .get_amount_out(U256::MAX / U256::from(1u128 << 64), true)
+        .unwrap();
+    assert!(amount_out < U256::from(u128::MAX));
+}
+
+#[test]
+fn v2_rejects_a_fee_of_one_hundred_percent() {
+    let pool = UniswapV2Pool {
+        fee_bps: 10_000,
+        ..v2_pool(addr(1), addr(2), addr(3), E18, E18)
+    };
+    assert!(pool.get_amount_out(U256::from(1), true).is_err());
+}
+
+#[test]
+fn v2_amount_out_matches_the_constant_product() {
+    let pool = v2_pool(addr(1), addr(2), addr(3), 1000, 1000);
+    // 100 * 9970 * 1000 / (1000 * 10000 + 100 * 9970) = 90.66.. rounded down
+    assert_eq!(
+        pool.get_amount_out(U256::from(100), true).unwrap(),
+        U256::from(90)
+    );
+}
+
+#[test]
+fn v2_fork_fees_are_honored_throughout() {
+    let amount_in = U256::from(E18);
+    let outputs: Vec<U256> = [20, 25, 30]
+        .into_iter()
+        .map(|fee_bps| {
+            let pool = UniswapV2Pool {
+                address: addr(1),
+                token0: addr(2),
+                token1: addr(3),
+                reserve0: E24,
+                reserve1: 2 * E24,
+                ..UniswapV2Pool::with_fee(fee_bps)
+            };
+            let keep = 1.0 - fee_bps as f64 / 10000.0;
+            assert_close(pool.get_marginal_price(true), 2.0 * keep, 1e-12);
+            assert_close(pool.get_marginal_price(false), 0.5 * keep, 1e-12);
+            assert_close(pool.get_log_weight(true), -(2.0 * keep).ln(), 1e-12);
+            assert_close(pool.get_log_weight(false), -(0.5 * keep).ln(), 1e-12);
+
+            // amount_in * (10000 - fee) * r_out / (r_in * 10000 + amount_in * (10000 - fee))
+            let with_fee = amount_in * U256::from(10000 - fee_bps);
+            let expected =
+                with_fee * U256::from(2 * E24) / (U256::from(E24) * U256::from(10000) + with_fee);
+            let amount_out = pool.get_amount_out(amount_in, true).unwrap();
+            assert_eq!(amount_out, expected, "fee_bps {fee_bps}");
+            amount_out
+        })
+        .collect();
+    // A lower fee always pays out more for the same input
+    assert!(
+        outputs[0] > outputs[1] && outputs[1] > outputs[2],
+        "{outputs:?}"
+    );
+}
+
+#[test]
+fn v2_marginal_price_is_the_rate_of_tiny_trades_both_ways() {
+    let pool = UniswapV2Pool {
+        transfer_fee_bps_in: 100,
+        transfer_fee_bps_out: 50,
+        ..v2_pool(addr(1), addr(2), addr(3), 2 * E24, 3000 * E24)
+    };
+    for zero_for_one in [true, false] {
+        let marginal = pool.get_marginal_price(zero_for_one);
+        // A trade a millionth of the input reserve moves the price by about that much
+        let (r_in, _) = pool.reserves(zero_for_one);
+        let amount_in = r_in / 1_000_000;
+        let amount_out = pool
+            .get_amount_out(U256::from(amount_in), zero_for_one)
+            .unwrap();
+        let rate = u256_to_f64(amount_out) / amount_in as f64;
+        assert_close(rate / marginal, 1.0, 2e-6);
+        assert_close(pool.get_log_weight(zero_for_one), -marginal.ln(), 1e-9);
+    }
+    let expected = 1500.0 * 0.997 * 0.99 * 0.995;
+    assert_close(pool.get_marginal_price(true) / expected, 1.0, 1e-12);
+
+    let drained = v2_pool(addr(1), addr(2), addr(3), 0, E24);
+    assert_eq!(drained.get_marginal_price(false), 0.0);
+    let fully_fee_d = UniswapV2Pool {
+        fee_bps: 10_000,
+        ..v2_pool(addr(1), addr(2), addr(3), E24, E24)
+    };
+    assert_eq!(fully_fee_d.get_marginal_price(true), 0.0);
+}
+
+fn log_weight_evaluations() -> usize {
+    LOG_WEIGHT_EVALUATIONS.with(|count| count.get())
+}
+
+#[test]
+fn cached_weights_are_served_without_recomputing() {
+    let mut v2 = v2_pool(addr(1), addr(2), addr(3), E24, 2 * E24);
+    let mut v3 = v3_pool(addr(4), addr(2), addr(3), PRICE_X96_ONE, E18);
+
+    let before = log_weight_evaluations();
+    v2.update_from_log(&sync_log(addr(1), E24, 3 * E24))
+        .unwrap();
+    v3.update_from_log(&v3_swap_log(
+        addr(4),
+        PRICE_X96_ONE * U256::from(2),
+        E18,
+        13_863,
+    ))
+    .unwrap();
+    // One evaluation per direction per pool, at update time
+    assert_eq!(log_weight_evaluations() - before, 4);
+
+    let before = log_weight_evaluations();
+    for _ in 0..1000 {
+        for zero_for_one in [true, false] {
+            v2.get_log_weight(zero_for_one);
+            v3.get_log_weight(zero_for_one);
+        }
+    }
+    assert_eq!(log_weight_evaluations(), before);
+    assert_close(v2.get_log_weight(true), -(3.0 * 0.997f64).ln(), 1e-12);
+}
+
+#[test]
+fn v4_swap_log_moves_the_price_and_reports_the_dynamic_fee() {
+    let mut pool = v4_pool(addr(2), addr(3), V4_DYNAMIC_FEE_FLAG, PRICE_X96_ONE, E18);
+    let sqrt_price = get_sqrt_ratio_at_tick(-61).unwrap();
+    pool.update_from_log(&v4_swap_log(pool.key.id(), sqrt_price, 5, -61, 500))
+        .unwrap();
+
+    assert_eq!(
+        (pool.sqrt_price_x96, pool.liquidity, pool.tick),
+        (sqrt_price, 5, -61)
+    );
+    assert_eq!(pool.resolve_fee(), 500);
+    assert_close(
+        pool.get_log_weight(true),
+        -(1.0001f64.powi(-61) * 0.9995).ln(),
+        1e-9,
+    );
+}
+
+#[test]
+fn v4_modify_liquidity_log_updates_the_ticks() {
+    let mut pool = v4_pool(addr(2), addr(3), 3000, PRICE_X96_ONE, E18);
+    let id = pool.key.id();
+
+    // Around the price: active, and crossed at both ends
+    pool.update_from_log(&modify_liquidity_log(id, -60, 60, E18 as i64))
+        .unwrap();
+    assert_eq!(pool.liquidity, 2 * E18);
+    assert_eq!(
+        pool.tick_bitmap,
+        BTreeMap::from([(-60, E18 as i128), (60, -(E18 as i128))])
+    );
+
+    // Above the price: only the ticks change
+    pool.update_from_log(&modify_liquidity_log(id, 120, 240, 7))
+        .unwrap();
+    assert_eq!(pool.liquidity, 2 * E18);
+    assert_eq!(pool.tick_bitmap[&120], 7);
+
+    // Negative deltas remove liquidity, clearing ticks that net to zero
+    pool.update_from_log(&modify_liquidity_log(id, -60, 60, -(E18 as i64)))
+        .unwrap();
+    pool.update_from_log(&modify_liquidity_log(id, 120, 240, -7))
+        .unwrap();
+    assert_eq!(pool.liquidity, E18);
+    assert!(pool.tick_bitmap.is_empty());
+}
+
+#[test]
+fn v4_only_applies_its_own_pool_id() {
+    let mut pool = v4_pool(addr(2), addr(3), 3000, PRICE_X96_ONE, E18);
+    let other = v4_pool(addr(2), addr(3), 500, PRICE_X96_ONE, E18).key.id();
+    assert!(matches!(
+        pool.apply_log(&v4_swap_log(other, PRICE_X96_ONE, 1, 0, 500)),
+        Err(PoolError::PoolIdMismatch { .. })
+    ));
+    assert!(matches!(
+        pool.apply_log(&modify_liquidity_log(other, -60, 60, 1)),
+        Err(PoolError::PoolIdMismatch { .. })
+    ));
+    assert_eq!(pool.liquidity, E18);
+
+    let donate = log(addr(1), vec![B256::repeat_byte(0xd0), pool.key.id()], &[]);
+    assert!(!pool.apply_log(&donate).unwrap());
+}
+
+#[test]
+fn v2_transfer_taxes_shrink_the_output() {
+    let plain = v2_pool(addr(1), addr(2), addr(3), E24, E24);
+    let taxed_in = UniswapV2Pool {
+        transfer_fee_bps_in: 500,
+        ..plain.clone()
+    };
+    let taxed_out = UniswapV2Pool {
+        transfer_fee_bps_out: 500,
+        ..plain.clone()
+    };
+    let amount_in = U256::from(E18);
+
+    // 5% of the input never reaches the pool
+    let expected = plain
+        .get_amount_out(U256::from(E18 / 20 * 19), true)
+        .unwrap();
+    assert_eq!(taxed_in.get_amount_out(amount_in, true).unwrap(), expected);
+
+    // 5% of the output never reaches the trader
+    let out = plain.get_amount_out(amount_in, true).unwrap();
+    assert_eq!(
+        taxed_out.get_amount_out(amount_in, true).unwrap(),
+        out - out * U256::from(500) / U256::from(10000)
+    );
+}
+
+#[test]
+fn v2_transfer_taxes_raise_the_weight() {
+    let plain = v2_pool(addr(1), addr(2), addr(3), E24, E24);
+    let taxed = UniswapV2Pool {
+        transfer_fee_bps_in: 500,
+        transfer_fee_bps_out: 100,
+        ..plain.clone()
+    };
+    for zero_for_one in [true, false] {
+        assert_close(
+            taxed.get_log_weight(zero_for_one) - plain.get_log_weight(zero_for_one),
+            -(0.95f64 * 0.99).ln(),
+            1e-12,
+        );
+        assert_close(
+            taxed.get_marginal_price(zero_for_one),
+            0.997 * 0.95 * 0.99,
+            1e-12,
+        );
+    }
+}
+
+#[test]
+fn v4_pool_id_matches_the_pool_manager() {
+    // The ETH/USDC 0.05% pool on Ethereum mainnet
+    let key = PoolKey {
+        currency0: Address::ZERO,
+        currency1: address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"),
+        fee: 500,
+        tick_spacing: 10,
+        hooks: Address::ZERO,
+    };
+    assert_eq!(
+        key.id(),
+        b256!("21c67e77068de97969ba93d4aab21826d33ca12bb9f565d8496e8fda8a82ca27")
+    );
+
+    // Every field is part of the identity
+    let others = [
+        PoolKey { fee: 3000, ..key },
+        PoolKey {
+            tick_spacing: 60,
+            ..key
+        },
+        PoolKey {
+            hooks: addr(1),
+            ..key
+        },
+    ];
+    for other in others {
+        assert_ne!(other.id(), key.id());
+    }
+}
+
+#[test]
+fn v4_native_eth_pool_swaps_both_ways() {
+    // ETH/USDC at ~3000 USDC per ETH: 3e-9 raw USDC per raw ETH, 0.05% fee
+    let usdc = address!("3c499c542cEF5E3811e1192ce70d8cC03d5c3359");
+    let sqrt_price = get_sqrt_ratio_at_tick(-196_257).unwrap();
+    let liquidity = 10u128.pow(15);
+    let pool = UniswapV4Pool {
+        key: PoolKey::new(usdc, Address::ZERO, 500, 10, Address::ZERO),
+        ..v4_pool(Address::ZERO, usdc, 500, sqrt_price, liquidity)
+    };
+    assert!(pool.key.has_native());
+    assert_eq!(pool.tokens(), (Address::ZERO, usdc));
+    assert_eq!(pool.key.zero_for_one(Address::ZERO), Some(true));
+    assert_eq!(pool.key.zero_for_one(usdc), Some(false));
+    assert_eq!(pool.key.zero_for_one(addr(9)), None);
+
+    // Closed form within the range: L / sqrtP' = L / sqrtP + dx, sqrtP' = sqrtP + dy / L
+    let sqrt_p = u256_to_f64(sqrt_price) / 2f64.powi(96);
+    let l = liquidity as f64;
+    let after_fee = 1.0 - 500.0 / 1e6;
+
+    // 0.1 ETH in
+    let eth_in = 1e17;
+    let sqrt_after = l / (l / sqrt_p + eth_in * after_fee);
+    let usdc_out = u256_to_f64(
+        pool.get_amount_out(U256::from(eth_in as u128), true)
+            .unwrap(),
+    );
+    // To within a raw unit or so of USDC
+    assert_close(usdc_out / (l * (sqrt_p - sqrt_after)), 1.0, 1e-8);
+    // About 300 USDC, less the fee and ~0.5% of price impact
+    assert_close(usdc_out / 1e6, 300.0 * 0.9995, 3.0);
+
+    // 300 USDC in
+    let usdc_in = 3e8;
+    let sqrt_after = sqrt_p + usdc_in * after_fee / l;
+    let eth_out = u256_to_f64(
+        pool.get_amount_out(U256::from(usdc_in as u128), false)
+            .unwrap(),
+    );
+    assert_close(eth_out / (l * (1.0 / sqrt_p - 1.0 / sqrt_after)), 1.0, 1e-8);
+    assert_close(eth_out / 1e18, 0.1 * 0.9995, 1e-3);
+}
+
+#[test]
+fn v4_static_fee_pool_prices_like_the_equivalent_v3_pool() {
+    let sqrt_price = get_sqrt_ratio_at_tick(1234).unwrap();
+    let mut v4 = v4_pool(addr(2), addr(3), 500, sqrt_price, 10 * E18);
+    let mut v3 = UniswapV3Pool {
+        fee: 500,
+        ..v3_pool(addr(1), addr(2), addr(3), sqrt_price, 10 * E18)
+    };
+    let ticks = BTreeMap::from([(1200, E18 as i128), (1260, -(E18 as i128))]);
+    v4.tick_bitmap = ticks.clone();
+    v3.tick_bitmap = ticks;
+
+    for zero_for_one in [true, false] {
+        assert_eq!(
+            v4.get_log_weight(zero_for_one),
+            v3.get_log_weight(zero_for_one)
+        );
+        assert_eq!(
+            v4.get_marginal_price(zero_for_one),
+            v3.get_marginal_price(zero_for_one)
+        );
+        assert_close(
+            v4.get_marginal_price(zero_for_one),
+            (-v4.get_log_weight(zero_for_one)).exp(),
+            1e-12,
+        );
+    }
+}
+
+#[test]
+fn v4_dynamic_fee_pool_prices_at_the_resolved_fee() {
+    let mut dynamic = v4_pool(addr(2), addr(3), V4_DYNAMIC_FEE_FLAG, PRICE_X96_ONE, E18);
+    dynamic.dynamic_fee = Some(500);
+    let fixed = v4_pool(addr(2), addr(3), 500, PRICE_X96_ONE, E18);
+    for zero_for_one in [true, false] {
+        assert_eq!(
+            dynamic.get_log_weight(zero_for_one),
+            fixed.get_log_weight(zero_for_one)
+        );
+        assert_close(
+            dynamic.get_log_weight(zero_for_one),
+            -(0.9995f64).ln(),
+            1e-12,
+        );
+    }
+}
+
+/// A V3 pool at price 1 with a ladder of positions every 60 ticks in [-1200, 1200)
+fn laddered_v3_pool() -> UniswapV3Pool {
+    let mut pool = v3_pool(addr(1), addr(2), addr(3), PRICE_X96_ONE, 0);
+    for lower in (-1200..1200).step_by(60) {
+        pool.apply_position_delta(lower, lower + 60, E18, true)
+            .unwrap();
+    }
+    pool.refresh_weights();
+    pool
+}
+
+#[test]
+fn cached_swaps_match_uncached_ones() {
+    let pool = PoolVariant::V3(laddered_v3_pool());
+    let mut cache = SwapCache::default();
+    // Growing, shrinking and direction-switching inputs, as the solver produces them
+    let inputs = [
+        (E18 / 10, true),
+        (E18, true),
+        (3 * E18, true),
+        (E18 / 2, true),
+        (2 * E18, true),
+        (E18, false),
+        (3 * E18, false),
+        (E18 / 3, true),
+    ];
+    for (amount_in, zero_for_one) in inputs {
+        let amount_in = U256::from(amount_in);
+        assert_eq!(
+            pool.get_amount_out_cached(amount_in, zero_for_one, &mut cache)
+                .unwrap(),
+            pool.get_amount_out(amount_in, zero_for_one).unwrap()
+        );
+    }
+}
+
+/// A USDC (6 decimals) / DAI (18 decimals) Curve pool, A = 200 and a 0.04% fee, holding 1M
+/// USDC against 1.2M DAI
+fn usdc_dai_stable_pool() -> StableSwapPool {
+    StableSwapPool {
+        address: addr(1),
+        token0: addr(2),
+        token1: addr(3),
+        balance0: U256::from(1_000_000 * 10u128.pow(6)),
+        balance1: U256::from(1_200_000 * E18),
+        precision0: U256::from(10u128.pow(12)),
+        precision1: U256::from(1),
+        amp: 200,
+        fee: 4_000_000,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn stable_swap_matches_curve_get_dy() {
+    // Reference outputs from a line-by-line port of Curve's get_D / get_y / get_dy
+    let pool = usdc_dai_stable_pool();
+    let cases: [(u128, bool, u128); 3] = [
+        (1000 * 10u128.pow(6), true, 1_000_514_875_879_821_819_941),
+        (
+            500_000 * 10u128.pow(6),
+            true,
+            499_015_201_738_357_983_874_774,
+        ),
+        (777 * E18, false, 775_972_442),
+    ];
+    for (amount_in, zero_for_one, expected) in cases {
+        assert_eq!(
+            pool.get_amount_out(U256::from(amount_in), zero_for_one)
+                .unwrap(),
+            U256::from(expected)
+        );
+    }
+}
+
+#[test]
+fn stable_marginal_price_matches_small_trades() {
+    let pool = usdc_dai_stable_pool();
+    for (zero_for_one, unit) in [(true, 10u128.pow(6)), (false, E18)] {
+        let out = pool.get_amount_out(U256::from(unit), zero_for_one).unwrap();
+        let rate = u256_to_f64(out) / unit as f64;
+        assert_close(pool.get_marginal_price(zero_for_one) / rate, 1.0, 1e-5);
+    }
+}
+
+#[test]
+fn stable_pools_without_amplification_are_invalid() {
+    let pool = StableSwapPool {
+        amp: 0,
+        ..usdc_dai_stable_pool()
+    };
+    for zero_for_one in [true, false] {
+        assert!(matches!(
+            pool.get_amount_out(U256::from(E18), zero_for_one),
+            Err(PoolError::InvalidState(_))
+        ));
+        assert_eq!(pool.get_marginal_price(zero_for_one), 0.0);
+    }
+}
+
+#[test]
+fn v2_fixed_point_marginal_price_agrees_with_f64() {
+    let reserve0 = 1_234_567_891_234_567_891_234_567;
+    let reserve1 = 7_654_321_987_654_321_987_654_321;
+    let pool = v2_pool(addr(1), addr(2), addr(3), reserve0, reserve1);
+
+    // floor(reserve1 * 0.997 * 2^96 / reserve0), computed independently
+    let price_x96 = pool.marginal_price_x96(true);
+    assert_eq!(price_x96, U256::from(489741031714863202884765545227u128));
+
+    for zero_for_one in [true, false] {
+        let fixed = u256_to_f64(pool.marginal_price_x96(zero_for_one)) / u256_to_f64(PRICE_X96_ONE);
+        let float = pool.get_marginal_price(zero_for_one);
+        assert_close(fixed / float, 1.0, 1e-15);
+    }
+}
+
+#[test]
+fn v2_fixed_point_marginal_price_resolves_single_wei() {
+    // 1e24 + 1 rounds to 1e24 in f64, but not in Q64.96
+    let even = v2_pool(addr(1), addr(2), addr(3), E24, E24);
+    let skewed = v2_pool(addr(1), addr(2), addr(3), E24, E24 + 1);
+    assert_eq!(
+        even.get_marginal_price(true),
+        skewed.get_marginal_price(true)
+    );
+    assert!(skewed.marginal_price_x96(true) > even.marginal_price_x96(true));
+    assert!(skewed.marginal_price_x96(false) < even.marginal_price_x96(false));
+}
+
+#[test]
+fn describes_v2_hops_with_reserves() {
+    let pool = PoolVariant::V2(v2_pool(addr(1), addr(2), addr(3), E24, 2 * E24));
+    assert_eq!(
+        pool.describe(true),
+        format!(
+            "V2 0x0202..0202->0x0303..0303 fee=3000 w=-0.690143 R={E24}/{}",
+            2 * E24
+        )
+    );
+    assert_eq!(
+        pool.describe_with_symbols(false, "WETH", "USDC"),
+        format!("V2 WETH->USDC fee=3000 w=0.696152 R={}/{E24}", 2 * E24)
+    );
+}
+
+#[test]
+fn describes_each_variant_with_its_depth() {
+    let weighted = WeightedPool {
+        address: addr(1),
+        token0: addr(2),
+        token1: addr(3),
+        balance0: U256::from(4 * E18),
+        balance1: U256::from(E18),
+        weight0: WEIGHTED_ONE / 5 * 4,
+        weight1: WEIGHTED_ONE / 5,
+        swap_fee: WEIGHTED_ONE / 1000,
+        ..Default::default()
+    };
+    let cases = [
+        (
+            PoolVariant::V3(v3_pool(addr(1), addr(2), addr(3), PRICE_X96_ONE, 777)),
+            "V3 A->B fee=3000",
+            "L=777".to_string(),
+        ),
+        (
+            PoolVariant::V4(v4_pool(addr(2), addr(3), 500, PRICE_X96_ONE, 888)),
+            "V4 A->B fee=500",
+            "L=888".to_string(),
+        ),
+        (
+            PoolVariant::Stable(usdc_dai_stable_pool()),
+            "Stable A->B fee=400",
+            format!("A=200 B={}/{}", 1_000_000 * 10u128.pow(6), 1_200_000 * E18),
+        ),
+        (
+            PoolVariant::Weighted(weighted),
+            "Weighted A->B fee=1000",
+            format!(
+                "W={}/{} B={}/{E18}",
+                WEIGHTED_ONE / 5 * 4,
+                WEIGHTED_ONE / 5,
+                4 * E18
+            ),
+        ),
+    ];
+    for (pool, prefix, depth) in cases {
+        let weight = format!("w={:.6}", pool.get_log_weight(true));
+        assert_eq!(
+            pool.describe_with_symbols(true, "A", "B"),
+            format!("{prefix} {weight} {depth}")
+        );
+    }
+}
+
+#[test]
+fn algebra_fee_log_changes_the_output() {
+    assert_eq!(
+        alloy_primitives::keccak256("Fee(uint16)"),
+        ALGEBRA_FEE_EVENT
+    );
+
+    let mut pool = v3_pool(addr(1), addr(2), addr(3), PRICE_X96_ONE, E24);
+    pool.refresh_weights();
+    let amount_in = U256::from(E18);
+    let at_static_fee = pool.get_amount_out(amount_in, true).unwrap();
+    let static_weight = pool.get_log_weight(true);
+
+    // QuickSwap lowers the fee to 0.01% ...
+    pool.update_from_log(&log(addr(1), vec![ALGEBRA_FEE_EVENT], &[U256::from(100)]))
+        .unwrap();
+    assert_eq!((pool.dynamic_fee, pool.resolve_fee()), (Some(100), 100));
+    let at_low_fee = pool.get_amount_out(amount_in, true).unwrap();
+    assert!(at_low_fee > at_static_fee);
+    assert!(pool.get_log_weight(true) < static_weight);
+
+    // ... then raises it to 1%
+    pool.update_from_log(&log(
+        addr(1),
+        vec![ALGEBRA_FEE_EVENT],
+        &[U256::from(10_000)],
+    ))
+    .unwrap();
+    assert!(pool.get_amount_out(amount_in, true).unwrap() < at_static_fee);
+    assert!(pool.get_log_weight(true) > static_weight);
+}
+
+/// A V2 pair with arbitrary uint112 reserves, a swap fee up to 1% and transfer taxes up to 10%
+fn any_v2_pool() -> impl Strategy<Value = UniswapV2Pool> {
+    let reserve = 1u128..(1 << 112);
+    (
+        reserve.clone(),
+        reserve,
+        0u32..=100,
+        0u32..=1000,
+        0u32..=1000,
+    )
+        .prop_map(|(reserve0, reserve1, fee_bps, tax_in, tax_out)| {
+            let mut pool = v2_pool(addr(1), addr(2), addr(3), reserve0, reserve1);
+            pool.fee_bps = fee_bps;
+            pool.transfer_fee_bps_in = tax_in;
+            pool.transfer_fee_bps_out = tax_out;
+            pool.refresh_weights();
+            pool
+        })
+}
+
+proptest! {
+    #[test]
+    fn v2_output_is_monotonic_in_the_input(
+        pool in any_v2_pool(),
+        zero_for_one: bool,
+        a in 0u128..(1 << 120),
+        b in 0u128..(1 << 120),
+    ) {
+        let (small, large) = (a.min(b), a.max(b));
+        let out_small = pool.get_amount_out(U256::from(small), zero_for_one).unwrap();
+        let out_large = pool.get_amount_out(U256::from(large), zero_for_one).unwrap();
+        prop_assert!(out_small <= out_large, "{small} -> {out_small}, {large} -> {out_large}");
+    }
+
+    #[test]
+    fn v2_output_stays_below_the_reserve(
+        pool in any_v2_pool(),
+        zero_for_one: bool,
+        amount_in in 0u128..(1 << 120),
+    ) {
+        let (_, reserve_out) = pool.reserves(zero_for_one);
+        let out = pool.get_amount_out(U256::from(amount_in), zero_for_one).unwrap();
+        prop_assert!(out < U256::from(reserve_out), "{amount_in} -> {out} of {reserve_out}");
+    }
+
+    #[test]
+    fn v2_execution_price_never_beats_the_marginal_price(
+        pool in any_v2_pool(),
+        zero_for_one: bool,
+        amount_in in 1u128..(1 << 120),
+    ) {
+        let out = pool.get_amount_out(U256::from(amount_in), zero_for_one).unwrap();
+        let execution_price = u256_to_f64(out) / amount_in as f64;
+        let marginal_price = pool.get_marginal_price(zero_for_one);
+        prop_assert!(
+            execution_price <= marginal_price * (1.0 + 1e-12),
+            "{execution_price} above {marginal_price}"
+        );
+    }
+
+    #[test]
+    fn v2_weight_is_minus_ln_of_the_marginal_price(pool in any_v2_pool(), zero_for_one: bool) {
+        let weight = pool.get_log_weight(zero_for_one);
+        let expected = -pool.get_marginal_price(zero_for_one).ln();
+        prop_assert!((weight - expected).abs() < 1e-9, "{weight} vs {expected}");
+    }
+}
+
+#[test]
+fn v2_amount_in_round_trips_the_amount_out() {
+    let plain = v2_pool(addr(1), addr(2), addr(3), E24, 3 * E24);
+    let mut taxed = plain.clone();
+    taxed.transfer_fee_bps_in = 200;
+    taxed.transfer_fee_bps_out = 500;
+
+    for pool in [plain, taxed] {
+        for zero_for_one in [true, false] {
+            for amount_in in [1000, E18, 1000 * E18, E24 / 2] {
+                let amount_in = U256::from(amount_in);
+                let out = pool.get_amount_out(amount_in, zero_for_one).unwrap();
+                let back = pool.get_amount_in(out, zero_for_one).unwrap();
+                // Enough for the same output, within what the rounding of `out` is worth
+                assert!(pool.get_amount_out(back, zero_for_one).unwrap() >= out);
+                let diff = if back > amount_in {
+                    back - amount_in
+                } else {
+                    amount_in - back
+                };
+                assert!(diff <= U256::from(8), "{back} vs {amount_in}");
+            }
+        }
+    }
+}
+
+#[test]
+fn v2_amount_in_rejects_outputs_beyond_the_reserve() {
+    let pool = v2_pool(addr(1), addr(2), addr(3), E24, 3 * E24);
+    assert!(pool.get_amount_in(U256::from(3 * E24 - 1), true).is_ok());
+    assert!(matches!(
+        pool.get_amount_in(U256::from(3 * E24), true),
+        Err(PoolError::InsufficientLiquidity(_))
+    ));
+}
+
+#[test]
+fn v2_math_errors_carry_their_kind() {
+    let pool = v2_pool(addr(1), addr(2), addr(3), E24, 3 * E24);
+    assert!(matches!(
+        pool.get_amount_out(U256::MAX, true),
+        Err(PoolError::Overflow(_))
+    ));
+    let mut bad_fee = pool.clone();
+    bad_fee.fee_bps = 10_000;
+    assert!(matches!(
+        bad_fee.get_amount_out(U256::from(E18), true),
+        Err(PoolError::InvalidState(_))
+    ));
+    assert!(matches!(
+        bad_fee.get_amount_in(U256::from(E18), true),
+        Err(PoolError::InvalidState(_))
+    ));
+
+    // Into anyhow with the same message
+    let err = pool.get_amount_in(U256::from(3 * E24), true).unwrap_err();
+    let message = err.to_string();
+    assert_eq!(anyhow::Error::from(err).to_string(), message);
+    assert!(message.contains("can't pay out"), "{message}");
+}
+
+#[test]
+fn v3_amount_in_round_trips_across_ticks() {
+    let pool = nested_v3_pool();
+    for zero_for_one in [true, false] {
+        // The larger inputs cross one and then several of the initialized ticks
+        for amount_in in [E18 / 1000, E18 / 10, E18 / 4] {
+            let amount_in = U256::from(amount_in);
+            let out = pool.get_amount_out(amount_in, zero_for_one).unwrap();
+            let back = pool.get_amount_in(out, zero_for_one).unwrap();
+            assert!(pool.get_amount_out(back, zero_for_one).unwrap() >= out);
+            let diff = if back > amount_in {
+                back - amount_in
+            } else {
+                amount_in - back
+            };
+            assert!(diff <= U256::from(100), "{back} vs {amount_in}");
+        }
+    }
+    // The widest position ends 1200 ticks out, where the liquidity runs dry
+    assert!(pool.get_amount_in(U256::from(1000 * E18), true).is_err());
+}
+
+#[test]
+fn approx_ln_stays_within_its_error_bound() {
+    assert_eq!(approx_ln(U256::ZERO), f64::NEG_INFINITY);
+    assert_eq!(approx_ln(U256::from(1)), 0.0);
+    // Every bit length, at and between the table's knots
+    for exponent in 0usize..255 {
+        for offset in [0u64, 1, 3, 7, 100, 12345, 999_999] {
+            let x =
+                (U256::from(1) << exponent) + (U256::from(offset) << exponent.saturating_sub(20));
+            let exact = u256_to_f64(x).ln();
+            assert!((approx_ln(x) - exact).abs() < 1.3e-4, "ln({x})");
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn v2_fast_weight_is_close_to_the_exact_one(pool in any_v2_pool(), zero_for_one: bool) {
+        let fast = PoolVariant::V2(pool.clone()).get_log_weight_fast(zero_for_one);
+        let exact = pool.get_log_weight(zero_for_one);
+        prop_assert!((fast - exact).abs() < 5e-4, "{fast} vs {exact}");
+    }
+
+    #[test]
+    fn v3_fast_weight_is_close_to_the_exact_one(
+        // Further out, the exact weight's Q64.96 price itself runs out of precision
+        tick in -400_000i32..400_000,
+        liquidity in 1u128..u128::MAX,
+        fee in 0u32..100_000,
+        zero_for_one: bool,
+    ) {
+        let sqrt_price = get_sqrt_ratio_at_tick(tick).unwrap();
+        let mut pool = UniswapV3Pool {
+            fee,
+            ..v3_pool(addr(1), addr(2), addr(3), sqrt_price, liquidity)
+        };
+        pool.refresh_weights();
+        let fast = PoolVariant::V3(pool.clone()).get_log_weight_fast(zero_for_one);
+        let exact = pool.get_log_weight(zero_for_one);
+        prop_assert!((fast - exact).abs() < 5e-4, "{fast} vs {exact}");
+    }
+}
+
+/// An 80/20 BAL/WETH pool holding 4M BAL against 1000 WETH, charging 0.5%
+fn bal_weth_80_20_pool() -> WeightedPool {
+    let mut pool = WeightedPool {
+        address: addr(1),
+        token0: addr(2),
+        token1: addr(3),
+        balance0: U256::from(4_000_000 * E18),
+        balance1: U256::from(1000 * E18),
+        weight0: WEIGHTED_ONE / 5 * 4,
+        weight1: WEIGHTED_ONE / 5,
+        swap_fee: WEIGHTED_ONE / 200,
+        ..Default::default()
+    };
+    pool.refresh_weights();
+    pool
+}
+
+#[test]
+fn weighted_pool_matches_the_balancer_out_given_in() {
+    // calcOutGivenIn evaluated with 60-digit decimals (fee rounded up from the input)
+    let pool = bal_weth_80_20_pool();
+    let cases: [(u128, bool, u128); 3] = [
+        (E18, true, 994_999_381_234_682),
+        (10_000 * E18, true, 9_888_429_938_663_853_991),
+        (10 * E18, false, 9_888_581_489_655_716_070_294),
+    ];
+    for (amount_in, zero_for_one, expected) in cases {
+        let out = pool
+            .get_amount_out(U256::from(amount_in), zero_for_one)
+            .unwrap();
+        // Never above the Vault's output, and only short of it by the quote margin
+        assert!(out <= U256::from(expected), "{out} > {expected}");
+        assert_close(u256_to_f64(out) / expected as f64, 1.0, 1e-11);
+    }
+}
+
+#[test]
+fn weighted_marginal_price_is_the_weighted_balance_ratio() {
+    // (b_out / w_out) / (b_in / w_in) * (1 - fee)
+    let pool = bal_weth_80_20_pool();
+    assert_close(pool.get_marginal_price(true) / 9.95e-4, 1.0, 1e-12);
+    assert_close(pool.get_marginal_price(false) / (0.995 / 1e-3), 1.0, 1e-12);
+    for zero_for_one in [true, false] {
+        let weight = pool.get_log_weight(zero_for_one);
+        assert_close(weight, -pool.get_marginal_price(zero_for_one).ln(), 1e-12);
+    }
+    assert!(
+        PoolVariant::Weighted(pool)
+            .get_amount_out(U256::from(E18), true)
+            .is_ok()
+    );
+}
+
+/// A V3 pool at price 1 with 20 nested positions of `E18` each, [-60w, 60w) for w in 1..=20,
+/// so its liquidity drops at every multiple of 60 ticks
+fn nested_v3_pool() -> UniswapV3Pool {
+    let mut pool = v3_pool(addr(1), addr(2), addr(3), PRICE_X96_ONE, 0);
+    for width in 1..=20 {
+        pool.apply_position_delta(-60 * width, 60 * width, E18, true)
+            .unwrap();
+    }
+    pool.refresh_weights();
+    pool
+}
+
+#[test]
+fn a_sqrt_price_limit_stops_the_swap_early() {
+    let pool = nested_v3_pool();
+    let amount_in = U256::from(E18 / 4);
+    for zero_for_one in [true, false] {
+        let full = pool.get_amount_out(amount_in, zero_for_one).unwrap();
+        let unlimited = pool
+            .get_amount_out_limited(amount_in, zero_for_one, None)
+            .unwrap();
+        assert_eq!(
+            (unlimited.amount_out, unlimited.amount_in),
+            (full, amount_in)
+        );
+
+        let limit_tick = if zero_for_one { -120 } else { 120 };
+        let limit = get_sqrt_ratio_at_tick(limit_tick).unwrap();
+        let partial = pool
+            .get_amount_out_limited(amount_in, zero_for_one, Some(limit))
+            .unwrap();
+        assert!(partial.amount_in < amount_in && partial.amount_out < full);
+        // Swapping just what was consumed lands on the limit for the same output
+        let replay = pool
+            .get_amount_out(partial.amount_in, zero_for_one)
+            .unwrap();
+        assert!(
+            replay.abs_diff(partial.amount_out) <= U256::from(1),
+            "{replay}"
+        );
+
+        // A limit the input can't reach changes nothing
+        let far_tick = if zero_for_one { -1200 } else { 1200 };
+        let far = get_sqrt_ratio_at_tick(far_tick).unwrap();
+        assert_eq!(
+            pool.get_amount_out_limited(amount_in, zero_for_one, Some(far))
+                .unwrap(),
+            unlimited
+        );
+        // A limit behind the price is rejected
+        let behind = get_sqrt_ratio_at_tick(-limit_tick).unwrap();
+        assert!(
+            pool.get_amount_out_limited(amount_in, zero_for_one, Some(behind))
+                .is_err()
+        );
+    }
+}
+
+#[test]
+fn multi_tick_swap_fee_matches_the_fee_tier() {
+    // About 40% of what the positions hold
+    let amount_in = U256::from(E18 / 4);
+    for fee in [500, 3000, 10_000] {
+        let pool = UniswapV3Pool {
+            fee,
+            ..nested_v3_pool()
+        };
+        for zero_for_one in [true, false] {
+            // Three ranges out the swap still has input left, so it crosses at least 3 ticks
+            let third_tick = if zero_for_one { -180 } else { 180 };
+            let limit = get_sqrt_ratio_at_tick(third_tick).unwrap();
+            let partial = pool
+                .get_amount_out_limited(amount_in, zero_for_one, Some(limit))
+                .unwrap();
+            assert!(partial.amount_in < amount_in);
+
+            let (out, fee_amount) = pool
+                .get_amount_out_with_fee(amount_in, zero_for_one)
+                .unwrap();
+            assert_eq!(out, pool.get_amount_out(amount_in, zero_for_one).unwrap());
+
+            // Each step's fee rounds up by at most a wei
+            let expected = amount_in * U256::from(fee) / U256::from(FEE_DENOMINATOR);
+            assert!(fee_amount >= expected, "{fee_amount} < {expected}");
+            assert!(
+                fee_amount - expected <= U256::from(40),
+                "{fee_amount} vs {expected}"
+            );
+        }
+    }
+}
+
+#[test]
+fn a_fee_free_pool_keeps_no_fee() {
+    let pool = UniswapV3Pool {
+        fee: 0,
+        ..nested_v3_pool()
+    };
+    let (out, fee_amount) = pool
+        .get_amount_out_with_fee(U256::from(E18 / 100), true)
+        .unwrap();
+    assert_eq!(fee_amount, U256::ZERO);
+    let charged = nested_v3_pool()
+        .get_amount_out(U256::from(E18 / 100), true)
+        .unwrap();
+    assert!(out > charged);
+}
+
+#[test]
+fn v2_and_v3_at_the_same_price_weigh_the_same() {
+    // Price 4 (token1 per token0) and a 0.3% fee in both
+    let mut v2 = v2_pool(addr(1), addr(2), addr(3), E24, 4 * E24);
+    v2.refresh_weights();
+    let mut v3 = v3_pool(
+        addr(4),
+        addr(2),
+        addr(3),
+        PRICE_X96_ONE * U256::from(2),
+        E24,
+    );
+    v3.refresh_weights();
+
+    for zero_for_one in [true, false] {
+        let v2_price = PoolVariant::V2(v2.clone()).marginal_price_x96(zero_for_one);
+        let v3_price = PoolVariant::V3(v3.clone()).marginal_price_x96(zero_for_one);
+        let gap = if v2_price > v3_price {
+            v2_price - v3_price
+        } else {
+            v3_price - v2_price
+        };
+        assert!(gap <= U256::from(1), "{v2_price} vs {v3_price}");
+
+        let expected = if zero_for_one {
+            -(4.0 * 0.997f64).ln()
+        } else {
+            -(0.997f64 / 4.0).ln()
+        };
+        assert_close(v2.get_log_weight(zero_for_one), expected, 1e-12);
+        assert_close(v3.get_log_weight(zero_for_one), expected, 1e-12);
+        assert_close(
+            v2.get_log_weight(zero_for_one),
+            v3.get_log_weight(zero_for_one),
+            1e-15,
+        );
+    }
+}
+
+/// A -> B through V2 at 2 B per A, B -> C through V3 at par and C -> A through V2 at `back`
+/// A per C, every pool charging `fee_bps`
+fn v2_v3_v2_loop(back: u128, fee_bps: u32) -> [(PoolVariant, bool); 3] {
+    let v2 = |address, token0, token1, reserve1| UniswapV2Pool {
+        fee_bps,
+        ..v2_pool(
+            addr(address),
+            addr(token0),
+            addr(token1),
+            1000 * E24,
+            reserve1 * E24,
+        )
+    };
+    let v3 = UniswapV3Pool {
+        fee: fee_bps * 100,
+        ..v3_pool(addr(11), addr(2), addr(3), PRICE_X96_ONE, 1000 * E24)
+    };
+    [
+        (PoolVariant::V2(v2(10, 1, 2, 2000)), true),
+        (PoolVariant::V3(v3), true),
+        (PoolVariant::V2(v2(12, 1, 3, back)), false),
+    ]
+}
+
+fn loop_weight(hops: &[(PoolVariant, bool)]) -> f64 {
+    hops.iter()
+        .map(|(pool, zero_for_one)| pool.get_log_weight(*zero_for_one))
+        .sum()
+}
+
+#[test]
+fn a_profitable_cross_version_loop_weighs_negative() {
+    // 2 B, 2 C, then 1.1 A back: C -> A pays 10% over par, well above the three fees
+    let hops = v2_v3_v2_loop(2000 * 1000 / 1100, 30);
+    let total = loop_weight(&hops);
+    assert!(total < 0.0, "{total}");
+    let rate: f64 = hops
+        .iter()
+        .map(|(pool, zero_for_one)| pool.get_marginal_price(*zero_for_one))
+        .product();
+    assert_close(total, -rate.ln(), 1e-9);
+
+    // ...and a small trade around it does come back with more
+    let amount_in = U256::from(E18);
+    let amount_out = hops.iter().fold(amount_in, |amount, (pool, zero_for_one)| {
+        pool.get_amount_out(amount, *zero_for_one).unwrap()
+    });
+    assert!(amount_out > amount_in);
+}
+
+#[test]
+fn a_break_even_cross_version_loop_weighs_nothing_but_its_fees() {
+    // 2 B, 2 C, then 1 A back
+    let free = loop_weight(&v2_v3_v2_loop(2000, 0));
+    assert_close(free, 0.0, 1e-12);
+    let fee_d = loop_weight(&v2_v3_v2_loop(2000, 30));
+    assert_close(fee_d, -3.0 * 0.997f64.ln(), 1e-12);
+    // Reversed, the same loop is just as break-even
+    let reversed: f64 = v2_v3_v2_loop(2000, 0)
+        .iter()
+        .map(|(pool, zero_for_one)| pool.get_log_weight(!zero_for_one))
+        .sum();
+    assert_close(reversed, 0.0, 1e-12);
+}
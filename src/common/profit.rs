@@ -0,0 +1,219 @@
+use crate::common::graph::GraphEdge;
+use crate::common::math::u256_to_f64;
+use crate::common::pool::PoolVariant;
+use crate::common::solver::simulate_cycle;
+use crate::common::token::Token;
+use alloy_primitives::{Address, U256};
+use std::collections::HashMap;
+
+/// Extra gas a V3 hop costs over a V2 hop (tick math, possible tick crossings)
+pub const V3_HOP_GAS_OVERHEAD: u64 = 40_000;
+/// Extra gas a V4 hop costs over a V2 hop (cheaper than V3 thanks to the singleton)
+pub const V4_HOP_GAS_OVERHEAD: u64 = 20_000;
+/// Extra gas a StableSwap hop costs over a V2 hop (Newton iterations for D and y)
+pub const STABLE_HOP_GAS_OVERHEAD: u64 = 60_000;
+/// Extra gas a Balancer weighted hop costs over a V2 hop (Vault accounting, LogExpMath pow)
+pub const WEIGHTED_HOP_GAS_OVERHEAD: u64 = 50_000;
+
+/// Estimated gas for executing one hop through `pool`, where `gas_per_hop` is the cost of a
+/// plain V2 swap
+pub fn hop_gas(pool: &PoolVariant, gas_per_hop: u64) -> u64 {
+    let overhead = match pool {
+        PoolVariant::V2(_) => 0,
+        PoolVariant::V3(_) => V3_HOP_GAS_OVERHEAD,
+        PoolVariant::V4(_) => V4_HOP_GAS_OVERHEAD,
+        PoolVariant::Stable(_) => STABLE_HOP_GAS_OVERHEAD,
+        PoolVariant::Weighted(_) => WEIGHTED_HOP_GAS_OVERHEAD,
+    };
+    gas_per_hop + overhead
+}
+
+/// Total estimated gas for executing the whole cycle
+pub fn cycle_gas(cycle: &[GraphEdge], gas_per_hop: u64) -> u64 {
+    cycle
+        .iter()
+        .map(|edge| hop_gas(&edge.pool, gas_per_hop))
+        .sum()
+}
+
+/// Profit of trading `amount_in` around `cycle`, after gas, in raw units of the input token.
+///
+/// `eth_price` is the value of one ETH (1e18 wei) in raw units of the input token, e.g. 1e18
+/// for a WETH cycle or 3000e6 for a USDC cycle with ETH at $3000.
+///
+/// The result is negative when the cycle loses money (gross or after gas), and
+/// `f64::NEG_INFINITY` if the route can't be simulated, so callers can simply filter on `> 0`.
+pub fn net_profit(
+    cycle: &[GraphEdge],
+    amount_in: U256,
+    gas_price_wei: U256,
+    gas_per_hop: u64,
+    eth_price: f64,
+) -> f64 {
+    let Ok(amount_out) = simulate_cycle(cycle, amount_in) else {
+        return f64::NEG_INFINITY;
+    };
+    let gross = u256_to_f64(amount_out) - u256_to_f64(amount_in);
+
+    let gas_cost_wei = u256_to_f64(gas_price_wei) * cycle_gas(cycle, gas_per_hop) as f64;
+    let gas_cost = gas_cost_wei / 1e18 * eth_price;
+
+    gross - gas_cost
+}
+
+/// Cheap upper bound on the return of trading around `cycle`, as a fraction of the input
+/// (0.01 = 1%), for ranking cycles before running the solver: `exp(-sum of log weights) - 1`,
+/// the rate of return of an infinitesimal trade at the current spot prices.
+///
+/// Every pool's output is concave in its input, so no real size does better: this is a bound,
+/// not the realizable profit, and it ignores gas. The decimal shifts of the edge weights cancel
+/// around a closed cycle. Negative for unprofitable cycles, -1 if a hop can't be traded.
+pub fn profit_upper_bound(cycle: &[GraphEdge]) -> f64 {
+    let total_weight: f64 = cycle.iter().map(GraphEdge::weight).sum();
+    (-total_weight).exp_m1()
+}
+
+/// Value of `amount` raw units of `token` in USD, given the price of one whole token
+pub fn profit_in_usd(token: &Token, amount: U256, token_price_usd: f64) -> f64 {
+    u256_to_f64(amount) / 10f64.powi(token.decimals as i32) * token_price_usd
+}
+
+/// Ranks cycles whose profits are denominated in different base tokens: each entry is a
+/// cycle's base token and profit (raw units), `prices_usd` the USD price of a whole token.
+///
+/// Returns `(index into profits, USD profit)`, most profitable first. Cycles whose base token
+/// has no price can't be compared and are left out.
+pub fn rank_by_usd_profit(
+    profits: &[(Token, U256)],
+    prices_usd: &HashMap<Address, f64>,
+) -> Vec<(usize, f64)> {
+    let mut ranked: Vec<(usize, f64)> = profits
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (token, profit))| {
+            let price = prices_usd.get(&token.address)?;
+            Some((i, profit_in_usd(token, *profit, *price)))
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::test_util::{addr, v2_pool, v3_pool};
+
+    const E18: u128 = 1_000_000_000_000_000_000;
+
+    /// WETH -> X -> WETH through two V2 pools 10% apart
+    fn mispriced_cycle() -> Vec<GraphEdge> {
+        vec![
+            GraphEdge::new(
+                PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), 1000 * E18, 2000 * E18)),
+                true,
+            ),
+            GraphEdge::new(
+                PoolVariant::V2(v2_pool(addr(11), addr(1), addr(2), 1100 * E18, 2000 * E18)),
+                false,
+            ),
+        ]
+    }
+
+    #[test]
+    fn gas_turns_a_gross_profit_into_a_loss() {
+        let cycle = mispriced_cycle();
+        let amount_in = U256::from(E18);
+        let gross = net_profit(&cycle, amount_in, U256::ZERO, 100_000, 1e18);
+        assert!(gross > 0.0);
+
+        // 200k gas at 1000 gwei is 0.2 ETH, more than the ~0.09 ETH gross profit
+        let gas_price = U256::from(1_000_000_000_000u64);
+        let net = net_profit(&cycle, amount_in, gas_price, 100_000, 1e18);
+        assert!(net < 0.0);
+        assert!((gross - net - 0.2e18).abs() < 1e3);
+    }
+
+    #[test]
+    fn gas_cost_is_converted_into_the_input_token() {
+        let cycle = mispriced_cycle();
+        let amount_in = U256::from(E18);
+        let gross = net_profit(&cycle, amount_in, U256::ZERO, 100_000, 1e18);
+        // Same cycle valued in a token worth half an ETH: the gas costs twice as many units
+        let net = net_profit(&cycle, amount_in, U256::from(1u64), 100_000, 2e18);
+        assert!((gross - net - 400_000.0).abs() < 64.0);
+    }
+
+    #[test]
+    fn v3_hops_cost_more_gas_than_v2() {
+        let v2 = PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), E18, E18));
+        let v3 = PoolVariant::V3(v3_pool(
+            addr(11),
+            addr(1),
+            addr(2),
+            U256::from(1) << 96,
+            E18,
+        ));
+        assert_eq!(hop_gas(&v2, 100_000), 100_000);
+        assert_eq!(hop_gas(&v3, 100_000), 100_000 + V3_HOP_GAS_OVERHEAD);
+    }
+
+    #[test]
+    fn usd_profit_accounts_for_decimals() {
+        let usdc = Token::new(addr(1), "USDC".to_string(), 6);
+        let weth = Token::new(addr(2), "WETH".to_string(), 18);
+        assert_eq!(profit_in_usd(&usdc, U256::from(150_000_000), 1.0), 150.0);
+        assert_eq!(profit_in_usd(&weth, U256::from(E18 / 10), 3000.0), 300.0);
+    }
+
+    #[test]
+    fn ranks_cycles_across_base_tokens() {
+        let usdc = Token::new(addr(1), "USDC".to_string(), 6);
+        let weth = Token::new(addr(2), "WETH".to_string(), 18);
+        let unpriced = Token::new(addr(3), "X".to_string(), 18);
+        // 150 USDC, 0.1 WETH ($300) and a profit that can't be priced
+        let profits = [
+            (usdc, U256::from(150_000_000)),
+            (weth, U256::from(E18 / 10)),
+            (unpriced, U256::from(1000 * E18)),
+        ];
+        let prices = HashMap::from([(addr(1), 1.0), (addr(2), 3000.0)]);
+        assert_eq!(
+            rank_by_usd_profit(&profits, &prices),
+            [(1, 300.0), (0, 150.0)]
+        );
+    }
+
+    #[test]
+    fn more_negative_cycles_get_higher_bounds() {
+        // X -> Y at 2, then back through a pool holding `back` X against 2000 Y
+        let cycle = |back: u128| {
+            vec![
+                GraphEdge::new(
+                    PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), 1000 * E18, 2000 * E18)),
+                    true,
+                ),
+                GraphEdge::new(
+                    PoolVariant::V2(v2_pool(addr(11), addr(1), addr(2), back * E18, 2000 * E18)),
+                    false,
+                ),
+            ]
+        };
+        let total_weight = |cycle: &[GraphEdge]| cycle.iter().map(GraphEdge::weight).sum::<f64>();
+
+        let cycles: Vec<_> = [900, 1050, 1100, 1200].map(cycle).into();
+        for pair in cycles.windows(2) {
+            assert!(total_weight(&pair[1]) < total_weight(&pair[0]));
+            assert!(profit_upper_bound(&pair[1]) > profit_upper_bound(&pair[0]));
+        }
+        assert!(profit_upper_bound(&cycles[0]) < 0.0);
+
+        // No real size beats it, even a tiny one
+        for cycle in &cycles[1..] {
+            let amount_in = U256::from(E18 / 1000);
+            let out = simulate_cycle(cycle, amount_in).unwrap();
+            let realized = u256_to_f64(out) / u256_to_f64(amount_in) - 1.0;
+            assert!(realized <= profit_upper_bound(cycle), "{realized}");
+        }
+    }
+}
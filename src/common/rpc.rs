@@ -0,0 +1,327 @@
+use crate::common::loader::CallProvider;
+use alloy_primitives::{Address, Bytes};
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::{Future, poll_fn};
+use std::sync::{Mutex, PoisonError};
+use std::task::{Poll, Waker};
+use std::time::Duration;
+
+/// Tuning knobs for `RetryProvider`
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    /// Calls allowed in flight at once (at least 1)
+    pub max_concurrency: usize,
+
+    /// Retries after the first attempt before the last error is returned
+    pub max_retries: u32,
+
+    /// Delay before the first retry, doubled for each further one...
+    pub initial_backoff: Duration,
+
+    /// ...up to this cap
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrency: 8,
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Delay before retry number `retry` (0 for the first retry)
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 1u32.checked_shl(retry).unwrap_or(u32::MAX);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// Marks a provider error as deterministic (e.g. a reverted call), so `RetryProvider` returns
+/// it right away instead of retrying
+#[derive(Debug)]
+pub struct Permanent(pub String);
+
+impl fmt::Display for Permanent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Permanent {}
+
+/// Whether retrying could fix `error`: anything but ABI decode errors and errors marked
+/// `Permanent`, which would fail the same way again
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    !error
+        .chain()
+        .any(|cause| cause.is::<alloy_sol_types::Error>() || cause.is::<Permanent>())
+}
+
+/// A `CallProvider` that limits the calls in flight and retries transient failures (rate
+/// limits, timeouts, dropped connections) with exponential backoff. Wraps the RPC transport
+/// handed to the loaders, e.g. `load_v3_pools(&RetryProvider::new(rpc, config, sleep), ..)`.
+///
+/// There is no async runtime in this crate, so the backoff delay is awaited through `sleep`
+/// (e.g. `tokio::time::sleep`). A call waiting for its retry doesn't hold a concurrency slot.
+pub struct RetryProvider<P, S> {
+    inner: P,
+    config: RetryConfig,
+    sleep: S,
+    slots: Mutex<Slots>,
+}
+
+/// Free concurrency slots and the calls waiting for one
+struct Slots {
+    free: usize,
+    waiters: VecDeque<Waker>,
+}
+
+/// A taken concurrency slot, given back on drop
+struct Slot<'a> {
+    slots: &'a Mutex<Slots>,
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        let mut slots = self.slots.lock().unwrap_or_else(PoisonError::into_inner);
+        slots.free += 1;
+        // Wake everyone: a waiter whose call was dropped would otherwise swallow the wakeup
+        for waker in slots.waiters.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+impl<P, S> RetryProvider<P, S> {
+    pub fn new(inner: P, config: RetryConfig, sleep: S) -> Self {
+        let free = config.max_concurrency.max(1);
+        Self {
+            inner,
+            config,
+            sleep,
+            slots: Mutex::new(Slots {
+                free,
+                waiters: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// The wrapped provider
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    pub fn config(&self) -> &RetryConfig {
+        &self.config
+    }
+
+    /// Waits for a free concurrency slot
+    async fn acquire(&self) -> Slot<'_> {
+        poll_fn(|cx| {
+            let mut slots = self.slots.lock().unwrap_or_else(PoisonError::into_inner);
+            if slots.free > 0 {
+                slots.free -= 1;
+                Poll::Ready(())
+            } else {
+                slots.waiters.push_back(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+        .await;
+        Slot { slots: &self.slots }
+    }
+}
+
+impl<P, S, F> CallProvider for RetryProvider<P, S>
+where
+    P: CallProvider + Sync,
+    S: Fn(Duration) -> F + Sync,
+    F: Future<Output = ()> + Send,
+{
+    async fn call(&self, to: Address, data: Bytes) -> Result<Bytes> {
+        let mut retry = 0;
+        loop {
+            let result = {
+                let _slot = self.acquire().await;
+                self.inner.call(to, data.clone()).await
+            };
+            match result {
+                Ok(output) => return Ok(output),
+                Err(e) if retry < self.config.max_retries && is_transient(&e) => {
+                    (self.sleep)(self.config.backoff(retry)).await;
+                    retry += 1;
+                }
+                Err(e) if retry > 0 => {
+                    return Err(
+                        e.context(format!("call to {to} failed after {} attempts", retry + 1))
+                    );
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use futures::executor::block_on;
+    use std::future::Ready;
+
+    /// Fails its first `failures` calls with `error()`, then echoes the calldata
+    struct Flaky<E> {
+        failures: u32,
+        error: E,
+        calls: Mutex<u32>,
+    }
+
+    impl<E: Fn() -> anyhow::Error + Sync> CallProvider for Flaky<E> {
+        fn call(&self, _to: Address, data: Bytes) -> impl Future<Output = Result<Bytes>> + Send {
+            let mut calls = self.calls.lock().unwrap();
+            *calls += 1;
+            let result = if *calls <= self.failures {
+                Err((self.error)())
+            } else {
+                Ok(data)
+            };
+            std::future::ready(result)
+        }
+    }
+
+    fn flaky<E>(failures: u32, error: E) -> Flaky<E> {
+        Flaky {
+            failures,
+            error,
+            calls: Mutex::new(0),
+        }
+    }
+
+    fn config(max_retries: u32) -> RetryConfig {
+        RetryConfig {
+            max_retries,
+            ..Default::default()
+        }
+    }
+
+    /// Records the requested delays instead of sleeping
+    fn recorder(delays: &Mutex<Vec<Duration>>) -> impl Fn(Duration) -> Ready<()> + Sync + '_ {
+        move |delay| {
+            delays.lock().unwrap().push(delay);
+            std::future::ready(())
+        }
+    }
+
+    #[test]
+    fn retries_transient_errors_until_success() {
+        let delays = Mutex::new(Vec::new());
+        let provider = RetryProvider::new(
+            flaky(2, || anyhow!("429 Too Many Requests")),
+            config(5),
+            recorder(&delays),
+        );
+        let data = Bytes::from_static(b"quote");
+        let output = block_on(provider.call(Address::ZERO, data.clone())).unwrap();
+        assert_eq!(output, data);
+        assert_eq!(*provider.inner().calls.lock().unwrap(), 3);
+        assert_eq!(
+            *delays.lock().unwrap(),
+            [Duration::from_millis(200), Duration::from_millis(400)]
+        );
+    }
+
+    #[test]
+    fn gives_up_after_the_last_retry() {
+        let delays = Mutex::new(Vec::new());
+        let provider = RetryProvider::new(
+            flaky(10, || anyhow!("timeout")),
+            config(2),
+            recorder(&delays),
+        );
+        let err = block_on(provider.call(Address::ZERO, Bytes::new())).unwrap_err();
+        assert_eq!(*provider.inner().calls.lock().unwrap(), 3);
+        assert!(err.to_string().contains("failed after 3 attempts"), "{err}");
+        assert_eq!(err.root_cause().to_string(), "timeout");
+    }
+
+    #[test]
+    fn deterministic_errors_are_not_retried() {
+        let delays = Mutex::new(Vec::new());
+        let reverted = RetryProvider::new(
+            flaky(1, || Permanent("execution reverted".into()).into()),
+            config(5),
+            recorder(&delays),
+        );
+        assert!(block_on(reverted.call(Address::ZERO, Bytes::new())).is_err());
+        assert_eq!(*reverted.inner().calls.lock().unwrap(), 1);
+
+        let undecodable = RetryProvider::new(
+            flaky(1, || alloy_sol_types::Error::Overrun.into()),
+            config(5),
+            recorder(&delays),
+        );
+        assert!(block_on(undecodable.call(Address::ZERO, Bytes::new())).is_err());
+        assert_eq!(*undecodable.inner().calls.lock().unwrap(), 1);
+        assert!(delays.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let config = RetryConfig {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+            ..Default::default()
+        };
+        let delays: Vec<u64> = (0..5)
+            .map(|retry| config.backoff(retry).as_secs())
+            .collect();
+        assert_eq!(delays, [1, 2, 4, 5, 5]);
+        assert_eq!(config.backoff(100), Duration::from_secs(5));
+    }
+
+    /// Each call stays pending for one poll, tracking how many are in flight at once
+    #[derive(Default)]
+    struct Slow {
+        in_flight: Mutex<(usize, usize)>,
+    }
+
+    impl CallProvider for Slow {
+        fn call(&self, _to: Address, data: Bytes) -> impl Future<Output = Result<Bytes>> + Send {
+            let mut started = false;
+            poll_fn(move |cx| {
+                let mut in_flight = self.in_flight.lock().unwrap();
+                if !started {
+                    started = true;
+                    in_flight.0 += 1;
+                    in_flight.1 = in_flight.1.max(in_flight.0);
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                in_flight.0 -= 1;
+                Poll::Ready(Ok(data.clone()))
+            })
+        }
+    }
+
+    #[test]
+    fn limits_the_calls_in_flight() {
+        let config = RetryConfig {
+            max_concurrency: 2,
+            ..Default::default()
+        };
+        let provider = RetryProvider::new(Slow::default(), config, |_| std::future::ready(()));
+        let calls = (0..6u8).map(|i| provider.call(Address::ZERO, Bytes::from(vec![i])));
+        let outputs = block_on(futures::future::join_all(calls));
+        assert!(outputs.iter().all(Result::is_ok));
+        assert_eq!(*provider.inner().in_flight.lock().unwrap(), (0, 2));
+    }
+}
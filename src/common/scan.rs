@@ -0,0 +1,546 @@
+use crate::common::graph::{ArbGraph, GraphEdge};
+use crate::common::math::u256_to_f64;
+use crate::common::pool::LiquidityPool;
+use crate::common::profit::{cycle_gas, net_profit};
+use crate::common::solver::{SolveConfig, optimal_amount_in, simulate_cycle};
+use crate::common::spfa::{canonicalize_cycle, find_all_negative_cycles};
+use alloy_primitives::{Address, U256};
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+/// Default `OpportunityCache::min_profit_change`: re-report a known cycle once its profit
+/// moves by 10%
+pub const DEFAULT_MIN_PROFIT_CHANGE: f64 = 0.1;
+
+/// Settings of `GraphManager::opportunity_stream`
+#[derive(Debug, Clone)]
+pub struct ScanConfig {
+    /// Opportunities sized per block (see `scan_top_n`)
+    pub top_n: usize,
+    /// Largest input the solver may choose
+    pub max_in: U256,
+    /// Gas of a plain V2 hop, for `Opportunity::gas_estimate`
+    pub gas_per_hop: u64,
+    /// Blocks a reported cycle stays suppressed (see `OpportunityCache`)
+    pub dedup_window: u64,
+}
+
+/// A detected cycle sized by the solver.
+///
+/// Serializes to JSON for external consumers (dashboards, executors), with addresses and
+/// amounts as hex strings. The edge indices only mean something in the graph the cycle was
+/// found in, so they are left out and come back empty when deserialized.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Opportunity {
+    /// Edges of the cycle, starting at one of the scanned bases
+    #[serde(skip)]
+    pub cycle: Vec<EdgeIndex>,
+    /// Token each hop sells, starting at the base (the cycle returns to it after the last hop)
+    pub cycle_tokens: Vec<Address>,
+    /// Pool of each hop (the Hook address for V4)
+    pub pools: Vec<Address>,
+    /// Most profitable input, at most the scan's `max_in`
+    pub amount_in: U256,
+    /// Output of the cycle at `amount_in`
+    pub expected_out: U256,
+    /// Output minus input at `amount_in`, before gas
+    pub profit: U256,
+    /// Estimated gas for executing the cycle, see `cycle_gas`
+    pub gas_estimate: u64,
+    /// Block whose state the cycle was found in
+    pub block: u64,
+}
+
+/// Finds the negative cycles through `bases` and returns the `n` most profitable, by gross
+/// profit, highest first, stamped with the `block` the graph reflects and a gas estimate at
+/// `gas_per_hop` per plain V2 hop.
+///
+/// Only the cycles the search reports are sized, so the solver runs once per candidate rather
+/// than per possible route. Candidates the solver can't size, or that don't make money once
+/// sized, are left out (see `scan_filtered` for why each one was).
+pub fn scan_top_n(
+    graph: &ArbGraph,
+    bases: &[NodeIndex],
+    n: usize,
+    max_in: U256,
+    block: u64,
+    gas_per_hop: u64,
+) -> Vec<Opportunity> {
+    let config = ScanConfig {
+        top_n: n,
+        max_in,
+        gas_per_hop,
+        dedup_window: 0,
+    };
+    scan_filtered(graph, bases, block, &config, &ScanFilter::default(), None)
+}
+
+/// Why the scanner dropped a candidate cycle
+#[derive(Debug, Clone, PartialEq)]
+pub enum RejectReason {
+    /// Sized, but its gross profit is below `ScanFilter::min_profit` (or zero)
+    BelowThreshold { profit: U256 },
+    /// Profitable before gas, but not once gas is paid (see `ScanFilter::gas_price`)
+    GasUnprofitable { profit: U256, gas_cost: f64 },
+    /// A pool on the cycle hasn't been updated within the `ScanFilter::freshness` window
+    StalePool {
+        edge: EdgeIndex,
+        last_updated_block: u64,
+    },
+    /// The input had to be capped at what a hop can execute (see `SolveResult::clamped`), and
+    /// nothing profitable was left below the cap
+    LiquidityClamped,
+    /// The solver or the final simulation failed on the cycle
+    SanityCheckFailed(String),
+}
+
+/// Extra checks of `scan_filtered`, all off by default
+#[derive(Debug, Clone, Default)]
+pub struct ScanFilter {
+    /// Smallest gross profit worth reporting, in raw units of the cycle's base
+    pub min_profit: U256,
+    /// Gas price in wei: cycles whose gas costs more than their profit are rejected, for bases
+    /// in `eth_prices`
+    pub gas_price: Option<U256>,
+    /// Value of one ETH in raw units of each base (see `profit::net_profit`)
+    pub eth_prices: HashMap<Address, f64>,
+    /// `(current block, max lag)`: cycles through pools not updated since `current - max lag`
+    /// are rejected
+    pub freshness: Option<(u64, u64)>,
+}
+
+/// `scan_top_n` with the checks of `filter`, also telling why each other candidate was left
+/// out: pass `rejected` to collect every dropped cycle with its `RejectReason`, in search
+/// order. Candidates that pass but don't make the top `config.top_n` aren't rejections.
+pub fn scan_filtered(
+    graph: &ArbGraph,
+    bases: &[NodeIndex],
+    block: u64,
+    config: &ScanConfig,
+    filter: &ScanFilter,
+    rejected: Option<&mut Vec<(Vec<EdgeIndex>, RejectReason)>>,
+) -> Vec<Opportunity> {
+    if config.top_n == 0 {
+        return Vec::new();
+    }
+    rank_cycles(
+        graph,
+        find_all_negative_cycles(graph, bases),
+        block,
+        config,
+        filter,
+        rejected,
+    )
+}
+
+/// The sizing and ranking of `scan_filtered`, over candidate `cycles` found elsewhere
+pub(crate) fn rank_cycles(
+    graph: &ArbGraph,
+    cycles: Vec<Vec<EdgeIndex>>,
+    block: u64,
+    config: &ScanConfig,
+    filter: &ScanFilter,
+    mut rejected: Option<&mut Vec<(Vec<EdgeIndex>, RejectReason)>>,
+) -> Vec<Opportunity> {
+    let mut opportunities = Vec::new();
+    for cycle in cycles {
+        match size_candidate(graph, &cycle, block, config, filter) {
+            Ok(opportunity) => opportunities.push(opportunity),
+            Err(reason) => {
+                if let Some(rejected) = rejected.as_deref_mut() {
+                    rejected.push((cycle, reason));
+                }
+            }
+        }
+    }
+
+    opportunities.sort_by_key(|o| Reverse(o.profit));
+    opportunities.truncate(config.top_n);
+    opportunities
+}
+
+/// Sizes `cycle` and runs the checks of `filter` on it: staleness first (no point sizing a
+/// stale cycle), then the sizing itself, the profit and gas
+fn size_candidate(
+    graph: &ArbGraph,
+    cycle: &[EdgeIndex],
+    block: u64,
+    config: &ScanConfig,
+    filter: &ScanFilter,
+) -> Result<Opportunity, RejectReason> {
+    if let Some((current_block, max_lag)) = filter.freshness {
+        let oldest_fresh = current_block.saturating_sub(max_lag);
+        if let Some(&edge) = cycle
+            .iter()
+            .find(|&&e| graph[e].pool.last_updated_block() < oldest_fresh)
+        {
+            return Err(RejectReason::StalePool {
+                edge,
+                last_updated_block: graph[edge].pool.last_updated_block(),
+            });
+        }
+    }
+
+    let failed = |e: anyhow::Error| RejectReason::SanityCheckFailed(format!("{e:#}"));
+    let edges: Vec<GraphEdge> = cycle.iter().map(|&e| graph[e].clone()).collect();
+    let solved =
+        optimal_amount_in(&edges, config.max_in, &SolveConfig::default()).map_err(failed)?;
+    let amount_in = solved.amount;
+    let expected_out = simulate_cycle(&edges, amount_in).map_err(failed)?;
+    let profit = expected_out.saturating_sub(amount_in);
+    if profit == U256::ZERO && solved.clamped {
+        return Err(RejectReason::LiquidityClamped);
+    }
+    if profit == U256::ZERO || profit < filter.min_profit {
+        return Err(RejectReason::BelowThreshold { profit });
+    }
+
+    let endpoints = |e: EdgeIndex| {
+        graph
+            .edge_endpoints(e)
+            .ok_or_else(|| RejectReason::SanityCheckFailed(format!("edge {e:?} not in graph")))
+    };
+    let cycle_tokens = cycle
+        .iter()
+        .map(|&e| endpoints(e).map(|(from, _)| graph[from].address))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if let Some(gas_price) = filter.gas_price
+        && let Some(&eth_price) = filter.eth_prices.get(&cycle_tokens[0])
+    {
+        let net = net_profit(&edges, amount_in, gas_price, config.gas_per_hop, eth_price);
+        if net <= 0.0 {
+            return Err(RejectReason::GasUnprofitable {
+                profit,
+                gas_cost: u256_to_f64(profit) - net,
+            });
+        }
+    }
+
+    Ok(Opportunity {
+        cycle: cycle.to_vec(),
+        cycle_tokens,
+        pools: edges.iter().map(|edge| edge.pool.address()).collect(),
+        amount_in,
+        expected_out,
+        profit,
+        gas_estimate: cycle_gas(&edges, config.gas_per_hop),
+        block,
+    })
+}
+
+/// Suppresses opportunities that were already reported recently, so scanning every block
+/// doesn't report the same trade over and over.
+///
+/// Cycles are keyed by `canonicalize_cycle`, so a rotation of a reported cycle counts as the
+/// same trade. A cycle is suppressed for `window` blocks after it was last reported, unless its
+/// gross profit has moved by at least `min_profit_change` (relative) since then.
+#[derive(Debug, Clone)]
+pub struct OpportunityCache {
+    pub window: u64,
+    pub min_profit_change: f64,
+
+    // Canonical cycle -> (block, gross profit) when last reported
+    reported: HashMap<Vec<EdgeIndex>, (u64, U256)>,
+    hits: u64,
+    misses: u64,
+}
+
+impl OpportunityCache {
+    pub fn new(window: u64) -> Self {
+        Self {
+            window,
+            min_profit_change: DEFAULT_MIN_PROFIT_CHANGE,
+            reported: HashMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Whether `opportunity`, found at `block`, should be reported. Reporting it restarts its
+    /// window; a suppressed opportunity counts as a hit, anything else as a miss.
+    pub fn should_report(
+        &mut self,
+        graph: &ArbGraph,
+        opportunity: &Opportunity,
+        block: u64,
+    ) -> bool {
+        let key = canonicalize_cycle(&opportunity.cycle, graph);
+        let profit = opportunity.profit;
+
+        if let Some(&(reported_at, reported_profit)) = self.reported.get(&key)
+            && block.saturating_sub(reported_at) < self.window
+            && !self.changed_materially(reported_profit, profit)
+        {
+            self.hits += 1;
+            return false;
+        }
+
+        self.misses += 1;
+        self.reported.insert(key, (block, profit));
+        true
+    }
+
+    /// Keeps the opportunities of `block` that `should_report` lets through, in order
+    pub fn filter(
+        &mut self,
+        graph: &ArbGraph,
+        opportunities: Vec<Opportunity>,
+        block: u64,
+    ) -> Vec<Opportunity> {
+        opportunities
+            .into_iter()
+            .filter(|opportunity| self.should_report(graph, opportunity, block))
+            .collect()
+    }
+
+    /// Forgets cycles whose window has passed by `block`, to bound memory
+    pub fn prune(&mut self, block: u64) {
+        let window = self.window;
+        self.reported
+            .retain(|_, (reported_at, _)| block.saturating_sub(*reported_at) < window);
+    }
+
+    /// Opportunities suppressed as duplicates
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Opportunities let through
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Cycles currently remembered
+    pub fn len(&self) -> usize {
+        self.reported.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reported.is_empty()
+    }
+
+    fn changed_materially(&self, before: U256, now: U256) -> bool {
+        let (before, now) = (u256_to_f64(before), u256_to_f64(now));
+        now != before && (now - before).abs() >= before * self.min_profit_change
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::graph::GraphManager;
+    use crate::common::pool::PoolVariant;
+    use crate::common::test_util::{addr, graph_with, v2_pool};
+
+    const E18: u128 = 1_000_000_000_000_000_000;
+
+    /// Token A against tokens 2..=5, each through a balanced V2 pool and one paying
+    /// 5%, 10%, 15% and 20% more of the other token
+    fn star() -> GraphManager {
+        graph_with((0..4u8).flat_map(|k| {
+            let other = addr(2 + k);
+            let premium = 50 * (k as u128 + 1);
+            [
+                PoolVariant::V2(v2_pool(
+                    addr(10 + 2 * k),
+                    addr(1),
+                    other,
+                    1000 * E18,
+                    1000 * E18,
+                )),
+                PoolVariant::V2(v2_pool(
+                    addr(11 + 2 * k),
+                    addr(1),
+                    other,
+                    1000 * E18,
+                    (1000 + premium) * E18,
+                )),
+            ]
+        }))
+    }
+
+    fn scan(manager: &GraphManager, n: usize) -> Vec<Opportunity> {
+        let a = manager.node_map[&addr(1)];
+        scan_top_n(&manager.graph, &[a], n, U256::from(1000 * E18), 7, 100_000)
+    }
+
+    #[test]
+    fn returns_the_top_n_most_profitable_first() {
+        let manager = star();
+        let all = scan(&manager, 10);
+        assert_eq!(all.len(), 4);
+        assert!(all.windows(2).all(|pair| pair[0].profit >= pair[1].profit));
+
+        let top = scan(&manager, 3);
+        assert_eq!(top, all[..3]);
+        // The 20% premium pool pays best
+        assert_eq!(top[0].pools, [addr(17), addr(16)]);
+        assert!(scan(&manager, 0).is_empty());
+    }
+
+    #[test]
+    fn opportunities_are_sized_and_stamped() {
+        let manager = star();
+        for opportunity in scan(&manager, 10) {
+            assert_eq!(opportunity.cycle_tokens[0], addr(1));
+            assert_eq!(
+                opportunity.profit,
+                opportunity.expected_out - opportunity.amount_in
+            );
+            assert!(opportunity.amount_in > U256::ZERO);
+            assert!(opportunity.amount_in <= U256::from(1000 * E18));
+            assert_eq!((opportunity.block, opportunity.gas_estimate), (7, 200_000));
+        }
+    }
+
+    #[test]
+    fn a_repeat_within_the_window_is_suppressed() {
+        let manager = star();
+        let best = scan(&manager, 1).remove(0);
+        let mut cache = OpportunityCache::new(3);
+
+        assert!(cache.should_report(&manager.graph, &best, 10));
+        assert!(!cache.should_report(&manager.graph, &best, 11));
+        // The same cycle entered from its other hop is the same trade
+        let mut rotated = best.clone();
+        rotated.cycle.rotate_left(1);
+        assert!(!cache.should_report(&manager.graph, &rotated, 12));
+        assert_eq!((cache.hits(), cache.misses()), (2, 1));
+
+        // Once the window has passed it's news again
+        assert!(cache.should_report(&manager.graph, &best, 15));
+        assert_eq!((cache.hits(), cache.misses()), (2, 2));
+    }
+
+    #[test]
+    fn a_material_profit_change_is_reported_again() {
+        let manager = star();
+        let best = scan(&manager, 1).remove(0);
+        let mut cache = OpportunityCache::new(100);
+        assert!(cache.should_report(&manager.graph, &best, 1));
+
+        // 5% up is within the default 10%, 20% up isn't
+        let mut moved = best.clone();
+        moved.profit = best.profit * U256::from(105) / U256::from(100);
+        assert!(!cache.should_report(&manager.graph, &moved, 2));
+        moved.profit = best.profit * U256::from(120) / U256::from(100);
+        assert!(cache.should_report(&manager.graph, &moved, 3));
+    }
+
+    #[test]
+    fn filtering_a_block_and_pruning() {
+        let manager = star();
+        let all = scan(&manager, 10);
+        let mut cache = OpportunityCache::new(2);
+
+        assert_eq!(cache.filter(&manager.graph, all.clone(), 1), all);
+        assert!(cache.filter(&manager.graph, all.clone(), 2).is_empty());
+        assert_eq!(cache.len(), all.len());
+
+        cache.prune(2);
+        assert_eq!(cache.len(), all.len());
+        cache.prune(3);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn opportunities_round_trip_through_json_with_hex_fields() {
+        let manager = star();
+        let best = scan(&manager, 1).remove(0);
+
+        let json = serde_json::to_value(&best).unwrap();
+        assert!(json.get("cycle").is_none());
+        assert_eq!(json["cycle_tokens"][0], addr(1).to_string());
+        assert_eq!(json["pools"][0], addr(17).to_string());
+        assert_eq!(json["amount_in"], format!("{:#x}", best.amount_in));
+        assert_eq!(json["profit"], format!("{:#x}", best.profit));
+        assert_eq!(
+            (json["gas_estimate"].as_u64(), json["block"].as_u64()),
+            (Some(200_000), Some(7))
+        );
+
+        // Everything but the graph-local edge indices comes back
+        let back: Opportunity = serde_json::from_value(json).unwrap();
+        assert!(back.cycle.is_empty());
+        assert_eq!(
+            back,
+            Opportunity {
+                cycle: Vec::new(),
+                ..best
+            }
+        );
+    }
+
+    type RejectedScan = (Vec<Opportunity>, Vec<(Vec<EdgeIndex>, RejectReason)>);
+
+    /// `scan` through `scan_filtered`, with the rejected candidates
+    fn scan_with(manager: &GraphManager, filter: &ScanFilter) -> RejectedScan {
+        let a = manager.node_map[&addr(1)];
+        let config = ScanConfig {
+            top_n: 10,
+            max_in: U256::from(1000 * E18),
+            gas_per_hop: 100_000,
+            dedup_window: 0,
+        };
+        let mut rejected = Vec::new();
+        let found = scan_filtered(
+            &manager.graph,
+            &[a],
+            7,
+            &config,
+            filter,
+            Some(&mut rejected),
+        );
+        (found, rejected)
+    }
+
+    #[test]
+    fn gas_unprofitable_cycles_are_rejected_with_their_gas_cost() {
+        let manager = star();
+        // 200k gas at 2500 gwei costs 0.5 of the base: more than the 5% premium cycle makes
+        // (about 0.23), less than the 10% one (about 1)
+        let filter = ScanFilter {
+            gas_price: Some(U256::from(2_500_000_000_000u64)),
+            eth_prices: HashMap::from([(addr(1), 1e18)]),
+            ..ScanFilter::default()
+        };
+        let (found, rejected) = scan_with(&manager, &filter);
+        assert_eq!(found.len() + rejected.len(), 4);
+        assert_eq!(rejected.len(), 1, "{rejected:?}");
+
+        let (cycle, reason) = &rejected[0];
+        let RejectReason::GasUnprofitable { profit, gas_cost } = reason else {
+            panic!("unexpected reason {reason:?}");
+        };
+        assert!(*gas_cost > u256_to_f64(*profit));
+        assert!((gas_cost / 5e17 - 1.0).abs() < 1e-9, "{gas_cost}");
+        // It's the cycle through the smallest premium
+        let pools: Vec<Address> = cycle
+            .iter()
+            .map(|&e| manager.graph[e].pool.address())
+            .collect();
+        assert!(pools.contains(&addr(11)), "{pools:?}");
+    }
+
+    #[test]
+    fn stale_cycles_are_rejected_before_sizing() {
+        let manager = star();
+        let filter = ScanFilter {
+            freshness: Some((100, 10)),
+            ..ScanFilter::default()
+        };
+        let (found, rejected) = scan_with(&manager, &filter);
+        assert!(found.is_empty());
+        assert_eq!(rejected.len(), 4);
+        assert!(rejected.iter().all(|(_, reason)| matches!(
+            reason,
+            RejectReason::StalePool {
+                last_updated_block: 0,
+                ..
+            }
+        )));
+    }
+}
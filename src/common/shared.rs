@@ -0,0 +1,167 @@
+use crate::common::graph::{GraphEdge, GraphManager};
+use alloy_primitives::Log;
+use anyhow::Result;
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use std::collections::HashSet;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// A `GraphManager` shared between one writer (the syncer) and any number of readers (the
+/// scanners). Cloning is cheap and yields another handle to the same graph.
+///
+/// Scans hold the read lock only for the search itself and return owned copies of the cycle
+/// edges, so the simulation and solving that follow don't block the writer.
+#[derive(Clone, Default)]
+pub struct SharedGraph {
+    inner: Arc<RwLock<GraphManager>>,
+}
+
+impl SharedGraph {
+    pub fn new(mut manager: GraphManager) -> Self {
+        // Edges may have been added to the graph directly, bypassing the registry
+        manager.reindex_pools();
+        Self {
+            inner: Arc::new(RwLock::new(manager)),
+        }
+    }
+
+    /// Shared access to the graph. A writer that panicked mid-batch leaves at worst some stale
+    /// pools, which the next logs overwrite, so a poisoned lock is simply taken over.
+    pub fn read(&self) -> RwLockReadGuard<'_, GraphManager> {
+        self.inner.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Exclusive access to the graph (see `read` about poisoning)
+    pub fn write(&self) -> RwLockWriteGuard<'_, GraphManager> {
+        self.inner.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Searches for a negative cycle from `source` (see `GraphManager::search_negative_cycle`)
+    /// and returns copies of its edges, ready for `optimal_amount_in`
+    pub fn scan(&self, source: NodeIndex) -> Option<Vec<GraphEdge>> {
+        let manager = self.read();
+        let cycle = manager.search_negative_cycle(source)?;
+        Some(owned_edges(&manager, &cycle))
+    }
+
+    /// Finds the distinct negative cycles through any of `bases` (see
+    /// `GraphManager::search_all_negative_cycles`), as owned edges
+    pub fn scan_all(&self, bases: &[NodeIndex]) -> Vec<Vec<GraphEdge>> {
+        let manager = self.read();
+        manager
+            .search_all_negative_cycles(bases)
+            .iter()
+            .map(|cycle| owned_edges(&manager, cycle))
+            .collect()
+    }
+
+    /// Applies the logs of `block` under the write lock (see `GraphManager::apply_logs`)
+    pub fn apply_logs(&self, block: u64, logs: &[Log]) -> Result<HashSet<EdgeIndex>> {
+        self.write().apply_logs(block, logs)
+    }
+}
+
+fn owned_edges(manager: &GraphManager, cycle: &[EdgeIndex]) -> Vec<GraphEdge> {
+    cycle
+        .iter()
+        .map(|&edge| manager.graph[edge].clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::pool::{LiquidityPool, PoolVariant};
+    use crate::common::test_util::{addr, graph_with, sync_log, v2_pool};
+    use alloy_primitives::Address;
+    use std::thread;
+
+    const E18: u128 = 1_000_000_000_000_000_000;
+    const BLOCKS: u64 = 200;
+
+    /// Reserves of the pool at `address`: (reserve0, reserve1)
+    fn reserves(manager: &GraphManager, address: Address) -> (u128, u128) {
+        let edge = manager.address_to_edge[&address][0];
+        match &manager.graph[edge].pool {
+            PoolVariant::V2(pool) => (pool.reserve0, pool.reserve1),
+            _ => unreachable!("only V2 pools"),
+        }
+    }
+
+    #[test]
+    fn readers_see_whole_batches_while_the_writer_syncs() {
+        // A -> B -> C -> A, with C -> A toggled between balanced and 10% mispriced
+        let shared = SharedGraph::new(graph_with([
+            PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), 1000 * E18, 1000 * E18)),
+            PoolVariant::V2(v2_pool(addr(11), addr(2), addr(3), 1000 * E18, 1000 * E18)),
+            PoolVariant::V2(v2_pool(addr(12), addr(3), addr(1), 1000 * E18, 1000 * E18)),
+        ]));
+        let a = shared.read().node_map[&addr(1)];
+
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..BLOCKS {
+                        {
+                            // Each batch moves pools 10 and 11 together
+                            let manager = shared.read();
+                            assert_eq!(reserves(&manager, addr(10)), reserves(&manager, addr(11)));
+                        }
+                        if let Some(cycle) = shared.scan(a) {
+                            // Only found while the last pool is mispriced, on that state
+                            let last = cycle.iter().find(|edge| edge.pool.address() == addr(12));
+                            match &last.expect("the cycle closes through pool 12").pool {
+                                PoolVariant::V2(pool) => assert_eq!(pool.reserve1, 1100 * E18),
+                                _ => unreachable!("only V2 pools"),
+                            }
+                        }
+                    }
+                });
+            }
+            scope.spawn(|| {
+                for block in 1..=BLOCKS {
+                    let reserve = 1000 * E18 + block as u128;
+                    let last = if block % 2 == 0 { 1100 } else { 1000 } * E18;
+                    let logs = [
+                        sync_log(addr(10), reserve, reserve),
+                        sync_log(addr(11), reserve, reserve),
+                        sync_log(addr(12), 1000 * E18, last),
+                    ];
+                    assert_eq!(shared.apply_logs(block, &logs).unwrap().len(), 6);
+                }
+            });
+        });
+
+        let manager = shared.read();
+        assert_eq!(reserves(&manager, addr(10)).0, 1000 * E18 + BLOCKS as u128);
+        assert!(manager.search_negative_cycle(a).is_some());
+    }
+
+    #[test]
+    fn a_failed_batch_leaves_the_graph_usable() {
+        let shared = SharedGraph::new(graph_with([PoolVariant::V2(v2_pool(
+            addr(10),
+            addr(1),
+            addr(2),
+            1000 * E18,
+            1000 * E18,
+        ))]));
+        // An edge removed behind the registry's back
+        shared.write().graph.remove_edge(EdgeIndex::new(0));
+        assert!(
+            shared
+                .apply_logs(1, &[sync_log(addr(10), E18, E18)])
+                .is_err()
+        );
+
+        // The error didn't poison the lock
+        shared.write().reindex_pools();
+        assert_eq!(
+            shared
+                .apply_logs(2, &[sync_log(addr(10), E18, E18)])
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(reserves(&shared.read(), addr(10)), (E18, E18));
+    }
+}
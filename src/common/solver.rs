@@ -0,0 +1,917 @@
+use crate::common::graph::GraphEdge;
+use crate::common::math::u256_to_f64;
+use crate::common::pool::{LiquidityPool, PRICE_X96_ONE, PoolVariant, SwapCache, UniswapV2Pool};
+use crate::common::swap_math::mul_div;
+use alloy_primitives::ruint::UintTryFrom;
+use alloy_primitives::{U256, U512};
+use anyhow::{Result, ensure};
+use std::cell::RefCell;
+
+/// Default cap on Newton-Raphson iterations
+const MAX_ITERATIONS: u32 = 64;
+
+/// Stop once a step moves the input by less than this many wei...
+const ABS_TOLERANCE: f64 = 1.0;
+/// ...or by less than this fraction of the current input (the default `SolveConfig::tolerance`)
+const REL_TOLERANCE: f64 = 1e-9;
+
+/// Slices `optimal_split` hands out one at a time
+pub const SPLIT_CHUNKS: u64 = 100;
+
+/// Tuning knobs for `optimal_amount_in`
+#[derive(Debug, Clone, Copy)]
+pub struct SolveConfig {
+    /// Newton/bisection iterations before giving up
+    pub max_iter: u32,
+    /// Converged once a step moves the input by less than this fraction of it (or 1 wei)
+    pub tolerance: f64,
+    /// Where to start the search; defaults to `adaptive_initial_guess` (or half of `max_in`)
+    pub initial_guess: Option<U256>,
+    /// Without an `initial_guess`, start from `adaptive_initial_guess` rather than half of
+    /// `max_in`
+    pub adaptive_guess: bool,
+    /// Memoize V3/V4 tick crossings across the solver's evaluations (same results, fewer
+    /// tick walks)
+    pub memoize: bool,
+    /// Size cycles of two plain V2 hops with `optimal_two_hop_v2` instead of iterating
+    pub closed_form: bool,
+}
+
+impl Default for SolveConfig {
+    fn default() -> Self {
+        Self {
+            max_iter: MAX_ITERATIONS,
+            tolerance: REL_TOLERANCE,
+            initial_guess: None,
+            adaptive_guess: true,
+            memoize: true,
+            closed_form: true,
+        }
+    }
+}
+
+/// Outcome of `optimal_amount_in`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SolveResult {
+    /// Most profitable input found, or 0 if the cycle doesn't make money
+    pub amount: U256,
+    /// Newton/bisection iterations performed
+    pub iterations: u32,
+    /// False if `max_iter` ran out before the step size dropped below the tolerance
+    pub converged: bool,
+    /// f'(x) - 1 at the returned amount; 0 at the exact optimum
+    pub final_residual: f64,
+    /// True if some hop couldn't execute `max_in`, so the search was capped at
+    /// `max_executable_in` instead
+    pub clamped: bool,
+}
+
+/// Closed-form estimate of the optimal input, for starting the solver near the optimum.
+///
+/// Models the cycle as one constant-product pool at the cycle's spot rate `P` (the product of
+/// the marginal prices) and as deep as its shallowest hop: with `L` that hop's virtual input
+/// reserve (`depth / sqrt(price)`) in units of the input token, the optimum of
+/// `P * L * x / (L + x) - x` is `L * (sqrt(P) - 1)`. Exact for a single V2 hop without fees,
+/// close for V2 cycles dominated by one pool, and only a starting point otherwise.
+///
+/// `None` if the cycle doesn't make money at spot prices or a hop is empty.
+pub fn adaptive_initial_guess(cycle: &[GraphEdge]) -> Option<U256> {
+    let mut rate = 1.0; // Units of the current hop's input token per unit of the cycle's input
+    let mut shallowest = f64::INFINITY;
+    for edge in cycle {
+        let price = edge.pool.get_marginal_price(edge.zero_for_one);
+        let depth = edge.pool.depth();
+        if !(price > 0.0 && depth > 0.0) {
+            return None;
+        }
+        shallowest = shallowest.min(depth / price.sqrt() / rate);
+        rate *= price;
+    }
+
+    let guess = shallowest * (rate.sqrt() - 1.0);
+    (guess.is_finite() && guess >= 1.0).then(|| U256::saturating_from(guess))
+}
+
+/// Exact optimal input for the two-pool cycle `pool_a` then `pool_b`, in the given directions.
+///
+/// A chain of two constant-product pools acts as a single one, so the profit
+/// `f(x) - x` peaks where `f'(x) = 1`, at
+/// `x* = (sqrt(ga * gb * r1 * r2 * r3 * r4) - r1 * r3) / (ga * r3 + ga * gb * r2)`.
+/// Here `(r1, r2)` and `(r3, r4)` are each pool's (in, out) reserves, and `ga`, `gb` are the
+/// fractions the fees leave. It is evaluated exactly in 512 bits and rounded down, so it
+/// only differs from the integer optimum by the pools' own rounding.
+///
+/// Some(0) if the cycle doesn't make money. None if a pool charges transfer taxes (the
+/// closed form doesn't cover them), a fee is 100% or more, or the reserves are too large
+/// for the 512-bit intermediate (impossible for uint112 reserves).
+pub fn optimal_two_hop_v2(
+    pool_a: &UniswapV2Pool,
+    a_zero_for_one: bool,
+    pool_b: &UniswapV2Pool,
+    b_zero_for_one: bool,
+) -> Option<U256> {
+    let untaxed = |p: &UniswapV2Pool| p.transfer_fee_bps_in == 0 && p.transfer_fee_bps_out == 0;
+    if !untaxed(pool_a) || !untaxed(pool_b) || pool_a.fee_bps >= 10000 || pool_b.fee_bps >= 10000 {
+        return None;
+    }
+
+    let (r1, r2) = pool_a.reserves(a_zero_for_one);
+    let (r3, r4) = pool_b.reserves(b_zero_for_one);
+    let [r1, r2, r3, r4] = [r1, r2, r3, r4].map(U512::from);
+    // The fees as fractions ga = a / d, gb = b / d, cleared from the formula's denominators
+    let a = U512::from(10000 - pool_a.fee_bps);
+    let b = U512::from(10000 - pool_b.fee_bps);
+    let d = U512::from(10000u32);
+
+    let product = a
+        .checked_mul(b)?
+        .checked_mul(r1)?
+        .checked_mul(r2)?
+        .checked_mul(r3)?
+        .checked_mul(r4)?;
+    let gain = d * product.root(2);
+    let cost = d * d * r1 * r3;
+    if gain <= cost {
+        return Some(U256::ZERO);
+    }
+    let denominator = a * d * r3 + a * b * r2;
+    if denominator == U512::ZERO {
+        return Some(U256::ZERO);
+    }
+    U256::uint_try_from((gain - cost) / denominator).ok()
+}
+
+/// `optimal_two_hop_v2` for a cycle of exactly two V2 hops, `None` for anything else
+fn two_hop_v2_optimum(cycle: &[GraphEdge]) -> Option<U256> {
+    let [first, second] = cycle else {
+        return None;
+    };
+    match (&first.pool, &second.pool) {
+        (PoolVariant::V2(a), PoolVariant::V2(b)) => {
+            optimal_two_hop_v2(a, first.zero_for_one, b, second.zero_for_one)
+        }
+        _ => None,
+    }
+}
+
+/// Simulates the whole cycle: each hop's output is the next hop's input.
+/// This is f(x) for the solver.
+pub fn simulate_cycle(cycle: &[GraphEdge], amount_in: U256) -> Result<U256> {
+    Ok(cycle.iter().try_fold(amount_in, |amount, edge| {
+        edge.pool.get_amount_out(amount, edge.zero_for_one)
+    })?)
+}
+
+/// The amount held after each hop of the route, starting with `amount_in` (hops + 1 entries).
+/// Fails on the first hop whose `get_amount_out` fails.
+pub fn quote_route(cycle: &[GraphEdge], amount_in: U256) -> Result<Vec<U256>> {
+    let mut amounts = Vec::with_capacity(cycle.len() + 1);
+    amounts.push(amount_in);
+    for edge in cycle {
+        let amount = *amounts.last().expect("starts non-empty");
+        amounts.push(edge.pool.get_amount_out(amount, edge.zero_for_one)?);
+    }
+    Ok(amounts)
+}
+
+/// Outcome of `quote_route_with_min_out`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckedQuote {
+    /// The amount held after each hop, as from `quote_route`
+    pub amounts: Vec<U256>,
+    /// First hop whose output is below its minimum, where an on-chain router would revert
+    pub failed_hop: Option<usize>,
+}
+
+/// `quote_route` with a minimum output per hop (`min_out[i]` for hop `i`, e.g. the quote less
+/// a slippage tolerance), reporting the first hop that falls short of it. The whole route is
+/// still quoted, so `amounts` also shows how far the later hops would have fallen.
+pub fn quote_route_with_min_out(
+    cycle: &[GraphEdge],
+    amount_in: U256,
+    min_out: &[U256],
+) -> Result<CheckedQuote> {
+    ensure!(
+        min_out.len() == cycle.len(),
+        "{} minimum outputs for {} hops",
+        min_out.len(),
+        cycle.len()
+    );
+    let amounts = quote_route(cycle, amount_in)?;
+    let failed_hop = amounts[1..]
+        .iter()
+        .zip(min_out)
+        .position(|(amount_out, min)| amount_out < min);
+    Ok(CheckedQuote {
+        amounts,
+        failed_hop,
+    })
+}
+
+/// How much worse than the spot price a trade executes, in percent
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceImpact {
+    /// Impact of each hop, in route order
+    pub hops: Vec<f64>,
+    /// Impact of the route as a whole (compounded over the hops)
+    pub route: f64,
+}
+
+/// Price impact of trading `amount_in` along `cycle`: the realized rate (out / in) compared to
+/// the marginal price, which already includes the pool fee, so a dust-sized trade shows ~0%.
+/// A hop with no spot price (an empty pool) shows 100%.
+pub fn price_impact(cycle: &[GraphEdge], amount_in: U256) -> Result<PriceImpact> {
+    let amounts = quote_route(cycle, amount_in)?;
+    let impact = |amount_in: U256, amount_out: U256, marginal: f64| -> f64 {
+        if amount_in == U256::ZERO {
+            return 0.0;
+        }
+        if marginal <= 0.0 {
+            return 100.0;
+        }
+        let execution = u256_to_f64(amount_out) / u256_to_f64(amount_in);
+        (1.0 - execution / marginal) * 100.0
+    };
+
+    let marginals: Vec<f64> = cycle
+        .iter()
+        .map(|edge| edge.pool.get_marginal_price(edge.zero_for_one))
+        .collect();
+    let hops = amounts
+        .windows(2)
+        .zip(&marginals)
+        .map(|(pair, &marginal)| impact(pair[0], pair[1], marginal))
+        .collect();
+    let route = impact(
+        amount_in,
+        *amounts.last().expect("quote_route is non-empty"),
+        marginals.iter().product(),
+    );
+
+    Ok(PriceImpact { hops, route })
+}
+
+/// Whether every hop can execute its share of `amount_in`: the quote succeeds, and no hop
+/// would pay out all of the pool's output reserve (see `PoolVariant::output_reserve`)
+pub fn route_executable(cycle: &[GraphEdge], amount_in: U256) -> bool {
+    let Ok(amounts) = quote_route(cycle, amount_in) else {
+        return false;
+    };
+    cycle.iter().zip(&amounts[1..]).all(|(edge, &out)| {
+        edge.pool
+            .output_reserve(edge.zero_for_one)
+            .is_none_or(|reserve| out < reserve)
+    })
+}
+
+/// The largest input up to `max_in` that the route can execute, found by bisection (a hop that
+/// can execute an amount can execute any smaller one)
+pub fn max_executable_in(cycle: &[GraphEdge], max_in: U256) -> U256 {
+    if route_executable(cycle, max_in) {
+        return max_in;
+    }
+    // lo is executable (0 trivially), hi isn't
+    let (mut lo, mut hi) = (U256::ZERO, max_in);
+    while hi - lo > U256::from(1) {
+        let mid = lo + (hi - lo) / U256::from(2);
+        if route_executable(cycle, mid) {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
+
+/// `simulate_cycle` through one `SwapCache` per hop (`caches` must have one entry per hop)
+pub fn simulate_cycle_cached(
+    cycle: &[GraphEdge],
+    amount_in: U256,
+    caches: &mut [SwapCache],
+) -> Result<U256> {
+    ensure!(
+        caches.len() == cycle.len(),
+        "one swap cache per hop required"
+    );
+    Ok(cycle
+        .iter()
+        .zip(caches)
+        .try_fold(amount_in, |amount, (edge, cache)| {
+            edge.pool
+                .get_amount_out_cached(amount, edge.zero_for_one, cache)
+        })?)
+}
+
+/// Finds the input that maximizes `f(x) - x` around the cycle, clamped to `max_in`.
+///
+/// If a hop can't absorb `max_in` (it would drain the pool, or the quote fails), `max_in` is
+/// first lowered to `max_executable_in` and the result is flagged `clamped`.
+///
+/// The product of the marginal prices (in Q64.96) is f'(0): if it's not above 1 the cycle loses
+/// money at any size and we return 0. A cycle of two V2 pools is sized by `optimal_two_hop_v2`
+/// in one step. Otherwise we solve f'(x) = 1 with Newton-Raphson (derivatives by finite
+/// differences of `get_amount_out`), falling back to bisection whenever the Newton step leaves
+/// the bracket or the derivative vanishes.
+///
+/// Running out of iterations isn't an error: the most profitable amount seen is returned with
+/// `converged = false`.
+pub fn optimal_amount_in(
+    cycle: &[GraphEdge],
+    max_in: U256,
+    config: &SolveConfig,
+) -> Result<SolveResult> {
+    let unprofitable = SolveResult {
+        amount: U256::ZERO,
+        iterations: 0,
+        converged: true,
+        final_residual: 0.0,
+        clamped: false,
+    };
+    if cycle.is_empty() || max_in == U256::ZERO {
+        return Ok(unprofitable);
+    }
+
+    let executable = max_executable_in(cycle, max_in);
+    let clamped = executable < max_in;
+    let max_in = executable;
+    let unprofitable = SolveResult {
+        clamped,
+        ..unprofitable
+    };
+    if max_in == U256::ZERO {
+        return Ok(unprofitable);
+    }
+
+    // Multiplied in Q64.96 so that near-break-even V2 cycles aren't misjudged by f64 rounding
+    let spot_rate = cycle.iter().fold(PRICE_X96_ONE, |rate, edge| {
+        let price = edge.pool.marginal_price_x96(edge.zero_for_one);
+        mul_div(rate, price, PRICE_X96_ONE).unwrap_or(U256::MAX)
+    });
+    if spot_rate <= PRICE_X96_ONE {
+        return Ok(SolveResult {
+            final_residual: u256_to_f64(spot_rate) / u256_to_f64(PRICE_X96_ONE) - 1.0,
+            ..unprofitable
+        });
+    }
+
+    let caches = RefCell::new(vec![SwapCache::default(); cycle.len()]);
+    let simulate = |amount: U256| -> Result<U256> {
+        if config.memoize {
+            simulate_cycle_cached(cycle, amount, &mut caches.borrow_mut())
+        } else {
+            simulate_cycle(cycle, amount)
+        }
+    };
+    // f(x) in floating point, for derivative estimates
+    let output_f64 = |x: f64| -> Result<f64> {
+        let out = simulate(U256::saturating_from(x.max(0.0)))?;
+        Ok(u256_to_f64(out))
+    };
+
+    let max_in_f64 = u256_to_f64(max_in);
+
+    // g(x) = f'(x) - 1 and g'(x) = f''(x) from central differences, plus the profit f(x) - x.
+    // Past a clamped max_in the route can't execute, so the differences are taken backwards.
+    let evaluate = |x: f64| -> Result<(f64, f64, f64)> {
+        let h = (x * 1e-4).clamp(1.0, x.max(1.0));
+        let mid = output_f64(x)?;
+        if clamped && x + h > max_in_f64 {
+            let back1 = output_f64(x - h)?;
+            let back2 = output_f64(x - 2.0 * h)?;
+            let gradient = (3.0 * mid - 4.0 * back1 + back2) / (2.0 * h) - 1.0;
+            let curvature = (mid - 2.0 * back1 + back2) / (h * h);
+            return Ok((gradient, curvature, mid - x));
+        }
+        let lo = output_f64(x - h)?;
+        let hi = output_f64(x + h)?;
+        let gradient = (hi - lo) / (2.0 * h) - 1.0;
+        let curvature = (hi - 2.0 * mid + lo) / (h * h);
+        Ok((gradient, curvature, mid - x))
+    };
+
+    // Two V2 hops have a closed-form optimum, no need to iterate
+    let closed_form = config
+        .closed_form
+        .then(|| two_hop_v2_optimum(cycle))
+        .flatten();
+    if let Some(optimum) = closed_form {
+        let amount = optimum.min(max_in);
+        let (final_residual, _, _) = evaluate(u256_to_f64(amount))?;
+        if amount == U256::ZERO || simulate(amount)? <= amount {
+            return Ok(SolveResult {
+                final_residual,
+                ..unprofitable
+            });
+        }
+        return Ok(SolveResult {
+            amount,
+            iterations: 0,
+            converged: true,
+            final_residual,
+            clamped,
+        });
+    }
+
+    let mut iterations = 0;
+    let mut converged = true;
+
+    // Still profitable at the cap: the optimum lies beyond max_in
+    let (gradient_at_max, _, _) = evaluate(max_in_f64)?;
+    let x = if gradient_at_max > 0.0 {
+        max_in_f64
+    } else {
+        // f is concave, so g is decreasing and the root is bracketed by [lo, hi]
+        let (mut lo, mut hi) = (0.0, max_in_f64);
+        let guess = config.initial_guess.or_else(|| {
+            config
+                .adaptive_guess
+                .then(|| adaptive_initial_guess(cycle))
+                .flatten()
+                .filter(|&guess| guess < max_in)
+        });
+        let mut x = guess.map_or(max_in_f64 / 2.0, |guess| u256_to_f64(guess.min(max_in)));
+        let mut best = (f64::NEG_INFINITY, x);
+        converged = false;
+
+        while iterations < config.max_iter {
+            iterations += 1;
+            let (gradient, curvature, profit) = evaluate(x)?;
+            if profit > best.0 {
+                best = (profit, x);
+            }
+            if gradient > 0.0 {
+                lo = x;
+            } else {
+                hi = x;
+            }
+
+            let newton = if curvature < 0.0 && curvature.is_finite() {
+                x - gradient / curvature
+            } else {
+                f64::NAN
+            };
+            let next = if newton > lo && newton < hi {
+                newton
+            } else {
+                (lo + hi) / 2.0
+            };
+
+            let step = (next - x).abs();
+            x = next;
+            if step < ABS_TOLERANCE.max(x * config.tolerance) {
+                converged = true;
+                break;
+            }
+        }
+
+        if converged { x } else { best.1 }
+    };
+
+    // At the cap, return it exactly rather than its f64 rounding (which may not execute)
+    let amount = if x >= max_in_f64 {
+        max_in
+    } else {
+        U256::saturating_from(x).min(max_in)
+    };
+    let (final_residual, _, _) = evaluate(u256_to_f64(amount))?;
+
+    // Only report sizes that actually make money after integer rounding
+    if simulate(amount)? <= amount {
+        return Ok(SolveResult {
+            iterations,
+            converged,
+            final_residual,
+            ..unprofitable
+        });
+    }
+    Ok(SolveResult {
+        amount,
+        iterations,
+        converged,
+        final_residual,
+        clamped,
+    })
+}
+
+/// Splits `amount_in` across parallel pools trading the same pair in the same direction so
+/// the total output is maximized, returning each pool's share (in the order of `pools`).
+///
+/// Water-filling in `SPLIT_CHUNKS` slices: each slice goes to the pool whose output grows the
+/// most from it, i.e. the best marginal price at its current allocation. Outputs are concave in
+/// the input, so the marginal prices end up equal to within one slice. A pool that can't quote
+/// a slice (e.g. it would drain it) gets no more; if none can, the shares sum to less than
+/// `amount_in`.
+pub fn optimal_split(pools: &[&PoolVariant], zero_for_one: bool, amount_in: U256) -> Vec<U256> {
+    let mut shares = vec![U256::ZERO; pools.len()];
+    let chunks = U256::from(SPLIT_CHUNKS).min(amount_in);
+    if chunks == U256::ZERO {
+        return shares;
+    }
+    let chunk = amount_in / chunks;
+    // The rounding remainder rides along with the first slice
+    let mut next_chunk = chunk + amount_in % chunks;
+
+    // Output of each pool at its current share, so every slice needs one quote per pool
+    let mut outputs = vec![U256::ZERO; pools.len()];
+    let mut exhausted = vec![false; pools.len()];
+    for _ in 0..chunks.to::<u64>() {
+        let mut best: Option<(usize, U256, U256)> = None;
+        for (i, pool) in pools.iter().enumerate() {
+            if exhausted[i] {
+                continue;
+            }
+            let Ok(out) = pool.get_amount_out(shares[i] + next_chunk, zero_for_one) else {
+                exhausted[i] = true;
+                continue;
+            };
+            let gain = out.saturating_sub(outputs[i]);
+            if best.is_none_or(|(_, best_gain, _)| gain > best_gain) {
+                best = Some((i, gain, out));
+            }
+        }
+
+        let Some((i, _, out)) = best else {
+            break;
+        };
+        shares[i] += next_chunk;
+        outputs[i] = out;
+        next_chunk = chunk;
+    }
+    shares
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::pool::{PoolVariant, UniswapV2Pool};
+    use crate::common::pool::{WEIGHTED_ONE, WeightedPool};
+    use crate::common::test_util::{addr, v2_pool, v3_pool};
+    use std::collections::BTreeMap;
+
+    const E18: u128 = 1_000_000_000_000_000_000;
+
+    fn v2_edge(reserve0: u128, reserve1: u128, zero_for_one: bool) -> GraphEdge {
+        let pool = v2_pool(addr(10), addr(1), addr(2), reserve0 * E18, reserve1 * E18);
+        GraphEdge::new(PoolVariant::V2(pool), zero_for_one)
+    }
+
+    fn profit(cycle: &[GraphEdge], amount_in: f64) -> f64 {
+        let out = simulate_cycle(cycle, U256::saturating_from(amount_in)).unwrap();
+        u256_to_f64(out) - amount_in
+    }
+
+    /// A -> B at 2 B per A, then B -> A at 0.55 A per B: 10% apart
+    fn two_pool_cycle() -> Vec<GraphEdge> {
+        vec![v2_edge(1000, 2000, true), v2_edge(1100, 2000, false)]
+    }
+
+    #[test]
+    fn two_pool_cycle_matches_the_closed_form() {
+        let cycle = two_pool_cycle();
+        let result =
+            optimal_amount_in(&cycle, U256::from(1000 * E18), &SolveConfig::default()).unwrap();
+
+        let g = 0.997f64;
+        let (r1, r2, r3, r4) = (1000e18, 2000e18, 2000e18, 1100e18);
+        let expected = ((g * g * r1 * r2 * r3 * r4).sqrt() - r1 * r3) / (g * r3 + g * g * r2);
+        assert!(result.converged && !result.clamped);
+        assert!((u256_to_f64(result.amount) / expected - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn the_closed_form_matches_the_iterative_solver() {
+        let iterative = SolveConfig {
+            closed_form: false,
+            tolerance: 1e-12,
+            ..SolveConfig::default()
+        };
+        let fork = |fee_bps, reserve0, reserve1, zero_for_one| {
+            let pool = UniswapV2Pool {
+                fee_bps,
+                ..v2_pool(addr(11), addr(1), addr(2), reserve0 * E18, reserve1 * E18)
+            };
+            GraphEdge::new(PoolVariant::V2(pool), zero_for_one)
+        };
+        let cycles = [
+            two_pool_cycle(),
+            vec![v2_edge(1000, 2000, true), fork(25, 1050, 2000, false)],
+            vec![v2_edge(5, 10, true), fork(20, 300, 500, false)],
+        ];
+        for cycle in cycles {
+            let max_in = U256::from(1000 * E18);
+            let closed = optimal_amount_in(&cycle, max_in, &SolveConfig::default()).unwrap();
+            let newton = optimal_amount_in(&cycle, max_in, &iterative).unwrap();
+            assert_eq!(closed.iterations, 0);
+            assert!(newton.converged && newton.iterations > 0);
+
+            let (x, y) = (u256_to_f64(closed.amount), u256_to_f64(newton.amount));
+            assert!((x / y - 1.0).abs() < 1e-6, "{x} vs {y}");
+            // Neither beats the other by more than the pools' rounding
+            assert!((profit(&cycle, x) - profit(&cycle, y)).abs() <= 2.0);
+        }
+    }
+
+    #[test]
+    fn clamps_to_max_in() {
+        let cycle = two_pool_cycle();
+        let result = optimal_amount_in(&cycle, U256::from(E18), &SolveConfig::default()).unwrap();
+        assert_eq!(result.amount, U256::from(E18));
+    }
+
+    #[test]
+    fn a_shallow_middle_hop_clamps_the_input() {
+        // A -> B at 2, B -> C through a 10:10 weighted pool, C -> A at 1: twice the money, but
+        // the Vault takes at most 30% of a balance (3 B) per swap
+        let mut weighted = WeightedPool {
+            address: addr(11),
+            token0: addr(2),
+            token1: addr(3),
+            balance0: U256::from(10 * E18),
+            balance1: U256::from(10 * E18),
+            weight0: WEIGHTED_ONE / 2,
+            weight1: WEIGHTED_ONE / 2,
+            swap_fee: WEIGHTED_ONE / 1000 * 3,
+            ..Default::default()
+        };
+        weighted.refresh_weights();
+        let cycle = vec![
+            v2_edge(1000, 2000, true),
+            GraphEdge::new(PoolVariant::Weighted(weighted), true),
+            GraphEdge::new(
+                PoolVariant::V2(v2_pool(addr(12), addr(3), addr(1), 1000 * E18, 1000 * E18)),
+                true,
+            ),
+        ];
+        let max_in = U256::from(1000 * E18);
+        assert!(!route_executable(&cycle, max_in));
+
+        // ~1.5 A buys the 3 B the middle hop can take
+        let executable = max_executable_in(&cycle, max_in);
+        assert!(executable > U256::from(E18) && executable < U256::from(2 * E18));
+        assert!(route_executable(&cycle, executable));
+        assert!(!route_executable(&cycle, executable + U256::from(1)));
+
+        let result = optimal_amount_in(&cycle, max_in, &SolveConfig::default()).unwrap();
+        assert!(result.clamped);
+        assert!(result.amount > U256::ZERO && result.amount <= executable);
+        assert!(simulate_cycle(&cycle, result.amount).unwrap() > result.amount);
+
+        // Sized below the limit, the same cycle isn't clamped
+        let small = optimal_amount_in(&cycle, U256::from(E18), &SolveConfig::default()).unwrap();
+        assert!(!small.clamped);
+    }
+
+    #[test]
+    fn unprofitable_cycle_sizes_to_zero() {
+        // The same pools the other way round buy high and sell low
+        let cycle = vec![v2_edge(1100, 2000, true), v2_edge(1000, 2000, false)];
+        let result =
+            optimal_amount_in(&cycle, U256::from(1000 * E18), &SolveConfig::default()).unwrap();
+        assert_eq!(result.amount, U256::ZERO);
+        assert!(result.final_residual < 0.0);
+    }
+
+    /// A -> B -> C -> A, about 10% mispriced overall
+    fn three_hop_cycle() -> Vec<GraphEdge> {
+        vec![
+            v2_edge(1000, 2000, true),
+            GraphEdge::new(
+                PoolVariant::V2(v2_pool(addr(11), addr(2), addr(3), 2000 * E18, 3000 * E18)),
+                true,
+            ),
+            GraphEdge::new(
+                PoolVariant::V2(v2_pool(addr(12), addr(3), addr(1), 3000 * E18, 1100 * E18)),
+                true,
+            ),
+        ]
+    }
+
+    #[test]
+    fn newton_finds_the_optimum_of_a_three_hop_cycle() {
+        let cycle = three_hop_cycle();
+        let result =
+            optimal_amount_in(&cycle, U256::from(1000 * E18), &SolveConfig::default()).unwrap();
+        assert!(result.converged && (1..20).contains(&result.iterations));
+        assert!(result.final_residual.abs() < 1e-6);
+
+        let x = u256_to_f64(result.amount);
+        assert!(profit(&cycle, x) > 0.0);
+        assert!(profit(&cycle, x) >= profit(&cycle, x * 0.99));
+        assert!(profit(&cycle, x) >= profit(&cycle, x * 1.01));
+    }
+
+    #[test]
+    fn the_adaptive_guess_saves_iterations() {
+        // Two V2 hops are solved in closed form, so start from three
+        let mut shallow_first = three_hop_cycle();
+        shallow_first[0] = GraphEdge::new(
+            PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), 100 * E18, 200 * E18)),
+            true,
+        );
+        for cycle in [three_hop_cycle(), shallow_first] {
+            let max_in = U256::from(1000 * E18);
+            let adaptive = optimal_amount_in(&cycle, max_in, &SolveConfig::default()).unwrap();
+            let config = SolveConfig {
+                adaptive_guess: false,
+                ..SolveConfig::default()
+            };
+            let halfway = optimal_amount_in(&cycle, max_in, &config).unwrap();
+
+            assert!(adaptive.converged && halfway.converged);
+            assert!(
+                adaptive.iterations < halfway.iterations,
+                "{} vs {} iterations",
+                adaptive.iterations,
+                halfway.iterations
+            );
+            let (a, b) = (u256_to_f64(adaptive.amount), u256_to_f64(halfway.amount));
+            assert!((a / b - 1.0).abs() < 1e-9, "{a} vs {b}");
+        }
+        // Nothing to start from when spot prices don't make money
+        let losing = [v2_edge(1000, 2000, true), v2_edge(1000, 2000, false)];
+        assert_eq!(adaptive_initial_guess(&losing), None);
+    }
+
+    #[test]
+    fn quote_route_reports_every_hop() {
+        let amounts = quote_route(&three_hop_cycle(), U256::from(E18)).unwrap();
+        // amount * 9970 * reserve_out / (reserve_in * 10000 + amount * 9970) at each hop
+        let expected: [u128; 4] = [
+            E18,
+            1_992_013_962_079_806_432,
+            2_976_101_555_019_043_088,
+            1_086_888_529_606_073_612,
+        ];
+        assert_eq!(amounts, expected.map(U256::from));
+    }
+
+    #[test]
+    fn a_violated_middle_hop_minimum_is_reported() {
+        let cycle = three_hop_cycle();
+        let amount_in = U256::from(E18);
+        let quoted = quote_route(&cycle, amount_in).unwrap();
+
+        let exact = quote_route_with_min_out(&cycle, amount_in, &quoted[1..]).unwrap();
+        assert_eq!(exact.failed_hop, None);
+        assert_eq!(exact.amounts, quoted);
+
+        // One wei more than the middle hop delivers, and the last hop's minimum also missed
+        let mut min_out = quoted[1..].to_vec();
+        min_out[1] += U256::from(1);
+        min_out[2] += U256::from(1);
+        let checked = quote_route_with_min_out(&cycle, amount_in, &min_out).unwrap();
+        assert_eq!(checked.failed_hop, Some(1));
+        assert_eq!(checked.amounts, quoted);
+
+        assert!(quote_route_with_min_out(&cycle, amount_in, &min_out[..2]).is_err());
+    }
+
+    #[test]
+    fn quote_route_stops_at_the_failing_hop() {
+        let mut cycle = three_hop_cycle();
+        cycle[1] = GraphEdge::new(
+            PoolVariant::V2(UniswapV2Pool {
+                fee_bps: 10_000,
+                ..v2_pool(addr(11), addr(2), addr(3), E18, E18)
+            }),
+            true,
+        );
+        assert!(quote_route(&cycle, U256::from(E18)).is_err());
+    }
+
+    #[test]
+    fn a_near_empty_cycle_stops_at_the_iteration_cap() {
+        // Reserves of a few thousand wei: every integer step is a large jump in the output
+        let pool = |address, token0, token1, reserve0, reserve1| {
+            PoolVariant::V2(v2_pool(
+                addr(address),
+                addr(token0),
+                addr(token1),
+                reserve0,
+                reserve1,
+            ))
+        };
+        let cycle = vec![
+            GraphEdge::new(pool(10, 1, 2, 1000, 2100), true),
+            GraphEdge::new(pool(11, 2, 3, 1000, 1000), true),
+            GraphEdge::new(pool(12, 1, 3, 1000, 2000), false),
+        ];
+        let config = SolveConfig {
+            max_iter: 3,
+            tolerance: 1e-12,
+            initial_guess: None,
+            adaptive_guess: false,
+            memoize: true,
+            closed_form: true,
+        };
+        let max_in = U256::from(1_000_000_000u64);
+        let result = optimal_amount_in(&cycle, max_in, &config).unwrap();
+
+        assert!(!result.converged);
+        assert_eq!(result.iterations, 3);
+        // The best amount seen is returned rather than an error. Rounding eats the 5% spot
+        // profit of wei-sized reserves, so here that's the break-even 0.
+        assert!(result.amount <= max_in);
+        assert!(simulate_cycle(&cycle, result.amount).unwrap() >= result.amount);
+    }
+
+    #[test]
+    fn price_impact_grows_with_size_on_v2() {
+        let shallow = vec![v2_edge(1, 2, true)];
+        // Half the reserve moves the price a lot, a millionth of it barely at all
+        let big = price_impact(&shallow, U256::from(E18 / 2)).unwrap();
+        let small = price_impact(&shallow, U256::from(E18 / 1_000_000)).unwrap();
+        assert!(big.route > 30.0, "{big:?}");
+        assert!(small.route.abs() < 1e-3, "{small:?}");
+        assert_eq!(big.hops, [big.route]);
+    }
+
+    #[test]
+    fn price_impact_compounds_over_v3_hops() {
+        let mut pool = v3_pool(addr(10), addr(1), addr(2), PRICE_X96_ONE, E18);
+        pool.tick_bitmap = BTreeMap::from([(-600, E18 as i128), (600, -(E18 as i128))]);
+        let route = vec![
+            GraphEdge::new(PoolVariant::V3(pool.clone()), true),
+            GraphEdge::new(PoolVariant::V3(pool), false),
+        ];
+
+        let small = price_impact(&route, U256::from(E18 / 1_000_000)).unwrap();
+        assert!(small.route.abs() < 1e-3, "{small:?}");
+        let big = price_impact(&route, U256::from(E18 / 100)).unwrap();
+        assert!(big.hops.iter().all(|&hop| hop > 0.5), "{big:?}");
+        let compounded = 100.0
+            * (1.0
+                - big
+                    .hops
+                    .iter()
+                    .map(|hop| 1.0 - hop / 100.0)
+                    .product::<f64>());
+        assert!((big.route - compounded).abs() < 1e-9, "{big:?}");
+    }
+
+    #[test]
+    fn memoized_and_plain_solves_agree() {
+        // A V3 pool whose liquidity thins out every 60 ticks, against a 5% richer V2 pool
+        let mut v3 = v3_pool(addr(10), addr(1), addr(2), PRICE_X96_ONE, 0);
+        for (i, lower) in (-1200..1200).step_by(60).enumerate() {
+            let net = (1000 - 40 * i.abs_diff(20)) as i128 * E18 as i128;
+            *v3.tick_bitmap.entry(lower).or_default() += net;
+            *v3.tick_bitmap.entry(lower + 60).or_default() -= net;
+            if lower == 0 {
+                v3.liquidity = net as u128;
+            }
+        }
+        let cycle = vec![
+            GraphEdge::new(PoolVariant::V3(v3), true),
+            v2_edge(1050, 1000, false),
+        ];
+
+        let solve = |memoize| {
+            let config = SolveConfig {
+                memoize,
+                ..SolveConfig::default()
+            };
+            optimal_amount_in(&cycle, U256::from(1000 * E18), &config).unwrap()
+        };
+        let (memoized, plain) = (solve(true), solve(false));
+        assert!(memoized.amount > U256::ZERO);
+        assert_eq!(memoized.amount, plain.amount);
+        assert_eq!(memoized.iterations, plain.iterations);
+    }
+
+    #[test]
+    fn split_favors_the_deeper_pool_and_beats_either_alone() {
+        // Same price, three times the depth
+        let shallow = PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), 1000 * E18, 1000 * E18));
+        let deep = PoolVariant::V2(v2_pool(addr(11), addr(1), addr(2), 3000 * E18, 3000 * E18));
+        let amount_in = U256::from(300 * E18);
+
+        let shares = optimal_split(&[&shallow, &deep], true, amount_in);
+        assert_eq!(shares[0] + shares[1], amount_in);
+        assert!(shares[1] > shares[0]);
+        // Equal marginal prices on x*y=k pools at the same price means shares in proportion to
+        // depth: 75 and 225, to within a slice
+        let slice = amount_in / U256::from(SPLIT_CHUNKS);
+        assert!(
+            shares[0].abs_diff(U256::from(75 * E18)) <= slice,
+            "{shares:?}"
+        );
+
+        let out = |pool: &PoolVariant, amount| pool.get_amount_out(amount, true).unwrap();
+        let split = out(&shallow, shares[0]) + out(&deep, shares[1]);
+        assert!(split > out(&deep, amount_in));
+        assert!(split > out(&shallow, amount_in));
+    }
+
+    #[test]
+    fn split_of_nothing_or_across_no_pools() {
+        let pool = PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), 1000 * E18, 1000 * E18));
+        assert_eq!(optimal_split(&[&pool], true, U256::ZERO), [U256::ZERO]);
+        assert!(optimal_split(&[], true, U256::from(E18)).is_empty());
+        // Fewer wei than slices: one wei per slice
+        assert_eq!(
+            optimal_split(&[&pool], true, U256::from(7)),
+            [U256::from(7)]
+        );
+    }
+}
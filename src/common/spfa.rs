@@ -0,0 +1,657 @@
+use crate::common::graph::{ArbGraph, best_out_edges_excluding, edge_cost};
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use std::collections::{HashSet, VecDeque};
+
+/// Default `min_profit_threshold`: a cycle must cost less than this much below zero (in log
+/// space) to be reported. Rounding in the f64 weights adds up to ~1e-15 per hop, so this is far
+/// above the noise of any realistic cycle, yet only a 1e-7 % return.
+pub const DEFAULT_MIN_PROFIT_THRESHOLD: f64 = 1e-9;
+
+/// Runs SPFA (queue-based Bellman-Ford) from `source` and returns the first negative cycle found.
+/// The cycle is returned as an ordered list of edges that starts and ends at the same token,
+/// rotated to start at `source` when the source lies on the cycle.
+///
+/// Cycles costing `-DEFAULT_MIN_PROFIT_THRESHOLD` or more are float noise around break-even and
+/// aren't reported.
+pub fn find_negative_cycle(graph: &ArbGraph, source: NodeIndex) -> Option<Vec<EdgeIndex>> {
+    find_negative_cycle_excluding(
+        graph,
+        source,
+        &HashSet::new(),
+        None,
+        DEFAULT_MIN_PROFIT_THRESHOLD,
+    )
+}
+
+/// `find_negative_cycle` (or, given `max_hops`, `find_negative_cycle_within`) as if the
+/// `excluded` edges weren't in the graph, reporting only cycles costing less than
+/// `-min_profit_threshold`.
+///
+/// Unbounded, SPFA runs on weights raised by `min_profit_threshold / n` (n tokens), so cycles
+/// near break-even aren't negative and can't stop the search before a profitable one: every
+/// cycle clearing the threshold stays negative, while one within `threshold * hops / n` of
+/// break-even doesn't. A cycle in between can still be found first, and isn't reported.
+pub fn find_negative_cycle_excluding(
+    graph: &ArbGraph,
+    source: NodeIndex,
+    excluded: &HashSet<EdgeIndex>,
+    max_hops: Option<usize>,
+    min_profit_threshold: f64,
+) -> Option<Vec<EdgeIndex>> {
+    let mut cycle = match max_hops {
+        Some(max_hops) => bounded_search(graph, source, max_hops, excluded, min_profit_threshold)?,
+        None => spfa(graph, &[source], excluded, min_profit_threshold)
+            .filter(|cycle| cycle_cost(graph, cycle) < -min_profit_threshold)?,
+    };
+    rotate_to(graph, &mut cycle, &[source]);
+    Some(cycle)
+}
+
+/// Like `find_negative_cycle`, but only considers cycles of at most `max_hops` edges.
+///
+/// Instead of running to convergence, Bellman-Ford is stopped after `max_hops` rounds: round `k`
+/// relaxes the cheapest `k`-edge walks from `source`, so the work is bounded by
+/// `max_hops * edges` however large the graph is. The first round that returns to `source` at a
+/// negative cost yields the cheapest simple cycle of that walk.
+pub fn find_negative_cycle_within(
+    graph: &ArbGraph,
+    source: NodeIndex,
+    max_hops: usize,
+) -> Option<Vec<EdgeIndex>> {
+    find_negative_cycle_excluding(
+        graph,
+        source,
+        &HashSet::new(),
+        Some(max_hops),
+        DEFAULT_MIN_PROFIT_THRESHOLD,
+    )
+}
+
+/// Total cost of `cycle`, as compared against the threshold
+fn cycle_cost(graph: &ArbGraph, cycle: &[EdgeIndex]) -> f64 {
+    cycle.iter().map(|&e| edge_cost(graph, e)).sum()
+}
+
+/// The `max_hops` rounds of Bellman-Ford behind `find_negative_cycle_within`
+fn bounded_search(
+    graph: &ArbGraph,
+    source: NodeIndex,
+    max_hops: usize,
+    excluded: &HashSet<EdgeIndex>,
+    min_profit_threshold: f64,
+) -> Option<Vec<EdgeIndex>> {
+    let n = graph.node_count();
+    if source.index() >= n {
+        return None;
+    }
+
+    let mut dist = vec![f64::INFINITY; n];
+    dist[source.index()] = 0.0;
+    // pred[k][v]: last edge of the cheapest k-edge walk from source to v
+    let mut pred: Vec<Vec<Option<EdgeIndex>>> = vec![vec![None; n]];
+
+    for _ in 0..max_hops {
+        let mut next = vec![f64::INFINITY; n];
+        let mut next_pred = vec![None; n];
+
+        for u in graph.node_indices() {
+            if dist[u.index()].is_infinite() {
+                continue;
+            }
+            for (v, edge, weight) in best_out_edges_excluding(graph, u, excluded) {
+                let candidate = dist[u.index()] + weight;
+                if candidate < next[v.index()] {
+                    next[v.index()] = candidate;
+                    next_pred[v.index()] = Some(edge);
+                }
+            }
+        }
+
+        dist = next;
+        pred.push(next_pred);
+        // The walk may split into cycles that are each too shallow; keep going if so
+        if dist[source.index()] < -min_profit_threshold {
+            let walk = layered_walk(graph, &pred, source)?;
+            if let Some(cycle) = cheapest_simple_cycle(graph, &walk, min_profit_threshold) {
+                return Some(cycle);
+            }
+        }
+    }
+
+    None
+}
+
+/// Finds distinct negative cycles through any of `bases` with a single SPFA seeded from all of
+/// them at once.
+///
+/// After each detection one edge of the cycle is excluded and the search is rerun, so cycles
+/// that overlap (but don't share that edge) are still found. Each cycle is reported once,
+/// rotated to start at the first of `bases` it passes through. Like `find_negative_cycle`, cycles
+/// within `DEFAULT_MIN_PROFIT_THRESHOLD` of break-even are skipped.
+pub fn find_all_negative_cycles(graph: &ArbGraph, bases: &[NodeIndex]) -> Vec<Vec<EdgeIndex>> {
+    find_all_negative_cycles_excluding(graph, bases, &HashSet::new(), DEFAULT_MIN_PROFIT_THRESHOLD)
+}
+
+/// `find_all_negative_cycles` as if the `excluded` edges weren't in the graph, reporting only
+/// cycles costing less than `-min_profit_threshold` (searched for like in
+/// `find_negative_cycle_excluding`)
+pub fn find_all_negative_cycles_excluding(
+    graph: &ArbGraph,
+    bases: &[NodeIndex],
+    excluded: &HashSet<EdgeIndex>,
+    min_profit_threshold: f64,
+) -> Vec<Vec<EdgeIndex>> {
+    let bases: Vec<NodeIndex> = bases
+        .iter()
+        .copied()
+        .filter(|base| base.index() < graph.node_count())
+        .collect();
+
+    let mut excluded = excluded.clone();
+    let mut cycles = Vec::new();
+
+    // Every round excludes one more edge, so this terminates
+    while let Some(mut cycle) = spfa(graph, &bases, &excluded, min_profit_threshold) {
+        // Excluding one of its edges also guarantees a cycle (or a rotation of it) is never
+        // reported twice
+        let &lowest = cycle.iter().min().expect("cycles are non-empty");
+        excluded.insert(lowest);
+
+        if cycle_cost(graph, &cycle) < -min_profit_threshold && rotate_to(graph, &mut cycle, &bases)
+        {
+            cycles.push(cycle);
+        }
+    }
+
+    cycles
+}
+
+/// Rotates a cycle to start with the edge leaving its lowest token address, so every rotation
+/// of the same cycle maps to the same `Vec` (e.g. for deduplication in a `HashSet`).
+///
+/// If that token is visited more than once, the lexicographically smallest rotation wins.
+/// Traversing the same pools in the other direction uses the opposite edges and is a different
+/// trade, so it's deliberately left distinct.
+pub fn canonicalize_cycle(edges: &[EdgeIndex], graph: &ArbGraph) -> Vec<EdgeIndex> {
+    let source = |edge: EdgeIndex| {
+        graph
+            .edge_endpoints(edge)
+            .map(|(from, _)| graph[from].address)
+    };
+    let Some(lowest) = edges.iter().filter_map(|&e| source(e)).min() else {
+        return edges.to_vec();
+    };
+
+    (0..edges.len())
+        .filter(|&i| source(edges[i]) == Some(lowest))
+        .map(|i| {
+            let mut rotation = edges.to_vec();
+            rotation.rotate_left(i);
+            rotation
+        })
+        .min()
+        .unwrap_or_else(|| edges.to_vec())
+}
+
+/// Multi-source SPFA: every source starts at distance 0. Returns the first negative cycle
+/// reachable from the sources, ignoring `excluded` edges, with every weight raised by
+/// `min_profit_threshold / n` (see `find_negative_cycle_excluding`).
+fn spfa(
+    graph: &ArbGraph,
+    sources: &[NodeIndex],
+    excluded: &HashSet<EdgeIndex>,
+    min_profit_threshold: f64,
+) -> Option<Vec<EdgeIndex>> {
+    let n = graph.node_count();
+    let offset = min_profit_threshold / n.max(1) as f64;
+
+    let mut dist = vec![f64::INFINITY; n];
+    let mut pred: Vec<Option<EdgeIndex>> = vec![None; n];
+    // Number of edges on the current shortest path; reaching n means we're looping
+    let mut path_len = vec![0usize; n];
+    let mut in_queue = vec![false; n];
+    let mut queue = VecDeque::new();
+
+    for &source in sources {
+        if source.index() >= n || in_queue[source.index()] {
+            continue;
+        }
+        dist[source.index()] = 0.0;
+        queue.push_back(source);
+        in_queue[source.index()] = true;
+    }
+
+    while let Some(u) = queue.pop_front() {
+        in_queue[u.index()] = false;
+
+        // Only the cheapest of several parallel pools is worth relaxing
+        for (v, edge, weight) in best_out_edges_excluding(graph, u, excluded) {
+            let candidate = dist[u.index()] + weight + offset;
+            if candidate < dist[v.index()] {
+                dist[v.index()] = candidate;
+                pred[v.index()] = Some(edge);
+                path_len[v.index()] = path_len[u.index()] + 1;
+
+                if path_len[v.index()] >= n {
+                    return extract_cycle(graph, &pred, v);
+                }
+                if !in_queue[v.index()] {
+                    queue.push_back(v);
+                    in_queue[v.index()] = true;
+                }
+            }
+        }
+    }
+
+    None
+}
+
+/// Walks the predecessor chain back from `start` until it closes a loop
+fn extract_cycle(
+    graph: &ArbGraph,
+    pred: &[Option<EdgeIndex>],
+    start: NodeIndex,
+) -> Option<Vec<EdgeIndex>> {
+    let prev = |node: NodeIndex| -> Option<(EdgeIndex, NodeIndex)> {
+        let edge = pred[node.index()]?;
+        let (from, _) = graph.edge_endpoints(edge)?;
+        Some((edge, from))
+    };
+
+    // Stepping back n times guarantees we're standing on the cycle itself
+    let mut on_cycle = start;
+    for _ in 0..graph.node_count() {
+        on_cycle = prev(on_cycle)?.1;
+    }
+
+    let mut cycle = Vec::new();
+    let mut node = on_cycle;
+    loop {
+        let (edge, from) = prev(node)?;
+        cycle.push(edge);
+        node = from;
+        if node == on_cycle {
+            break;
+        }
+    }
+    cycle.reverse();
+
+    Some(cycle)
+}
+
+/// Rebuilds the walk ending at `end` after `pred.len() - 1` rounds of the bounded search
+pub(crate) fn layered_walk(
+    graph: &ArbGraph,
+    pred: &[Vec<Option<EdgeIndex>>],
+    end: NodeIndex,
+) -> Option<Vec<EdgeIndex>> {
+    let mut walk = Vec::with_capacity(pred.len());
+    let mut node = end;
+    for layer in pred[1..].iter().rev() {
+        let edge = layer[node.index()]?;
+        walk.push(edge);
+        node = graph.edge_endpoints(edge)?.0;
+    }
+    walk.reverse();
+    Some(walk)
+}
+
+/// Splits a closed walk that may revisit tokens into its simple cycles and returns the
+/// cheapest one, if it costs less than `-min_profit_threshold`. A negative walk always contains
+/// at least one negative cycle, and none of them is longer than the walk.
+fn cheapest_simple_cycle(
+    graph: &ArbGraph,
+    walk: &[EdgeIndex],
+    min_profit_threshold: f64,
+) -> Option<Vec<EdgeIndex>> {
+    let mut best: Option<(Vec<EdgeIndex>, f64)> = None;
+    // Edges of the current path, and the token each of them leaves from
+    let mut path: Vec<(NodeIndex, EdgeIndex)> = Vec::new();
+    for &edge in walk {
+        let (from, to) = graph.edge_endpoints(edge)?;
+        path.push((from, edge));
+
+        // Arriving at a token already on the path closes a simple cycle; cut it out
+        if let Some(start) = path.iter().position(|&(node, _)| node == to) {
+            let cycle: Vec<EdgeIndex> = path.drain(start..).map(|(_, e)| e).collect();
+            let total = cycle_cost(graph, &cycle);
+            if best.as_ref().is_none_or(|(_, b)| total < *b) {
+                best = Some((cycle, total));
+            }
+        }
+    }
+
+    best.filter(|(_, total)| *total < -min_profit_threshold)
+        .map(|(cycle, _)| cycle)
+}
+
+/// Rotates `cycle` to start at the first of `preferred` it passes through.
+/// Returns false (leaving the cycle as is) if it passes through none of them.
+fn rotate_to(graph: &ArbGraph, cycle: &mut [EdgeIndex], preferred: &[NodeIndex]) -> bool {
+    let start_of = |edge: EdgeIndex| graph.edge_endpoints(edge).map(|(from, _)| from);
+    let Some(pos) = preferred
+        .iter()
+        .find_map(|&node| cycle.iter().position(|&e| start_of(e) == Some(node)))
+    else {
+        return false;
+    };
+    cycle.rotate_left(pos);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::graph::GraphManager;
+    use crate::common::pool::{LiquidityPool, PoolVariant, UniswapV2Pool};
+    use crate::common::test_util::{addr, graph_with, token, v2_pool};
+    use alloy_primitives::Address;
+
+    const E18: u128 = 1_000_000_000_000_000_000;
+
+    /// A -> B -> C -> A over three balanced V2 pools, except that C -> A pays `last_rate`
+    fn triangle(last_rate: u128) -> GraphManager {
+        graph_with([
+            PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), 1000 * E18, 1000 * E18)),
+            PoolVariant::V2(v2_pool(addr(11), addr(2), addr(3), 1000 * E18, 1000 * E18)),
+            PoolVariant::V2(v2_pool(
+                addr(12),
+                addr(3),
+                addr(1),
+                1000 * E18,
+                last_rate * E18,
+            )),
+        ])
+    }
+
+    #[test]
+    fn finds_the_mispriced_triangle() {
+        let manager = triangle(1100);
+        let a = manager.node_map[&addr(1)];
+        let cycle = find_negative_cycle(&manager.graph, a).unwrap();
+
+        assert_eq!(cycle.len(), 3);
+        assert!(cycle_cost(&manager.graph, &cycle) < 0.0);
+        // Executable as is: from A back to A, each hop starting where the last one ended
+        let endpoints: Vec<_> = cycle
+            .iter()
+            .map(|&e| manager.graph.edge_endpoints(e).unwrap())
+            .collect();
+        assert_eq!(endpoints[0].0, a);
+        assert_eq!(endpoints[2].1, a);
+        assert!(endpoints.windows(2).all(|w| w[0].1 == w[1].0));
+    }
+
+    #[test]
+    fn consistent_prices_have_no_cycle() {
+        // Balanced pools only lose the fees around any loop
+        let manager = triangle(1000);
+        let a = manager.node_map[&addr(1)];
+        assert_eq!(find_negative_cycle(&manager.graph, a), None);
+    }
+
+    #[test]
+    fn routes_through_the_cheaper_parallel_pool() {
+        let mut manager = triangle(1100);
+        let cheap = UniswapV2Pool {
+            fee_bps: 5,
+            ..v2_pool(addr(13), addr(1), addr(2), 1000 * E18, 1000 * E18)
+        };
+        manager.add_pool(PoolVariant::V2(cheap)).unwrap();
+        let a = manager.node_map[&addr(1)];
+
+        let cycle = find_negative_cycle(&manager.graph, a).unwrap();
+        let pools: Vec<_> = cycle
+            .iter()
+            .map(|&e| manager.graph[e].pool.address())
+            .collect();
+        assert_eq!(pools, [addr(13), addr(11), addr(12)]);
+    }
+
+    #[test]
+    fn multi_source_search_reports_each_cycle_once() {
+        // A -> B -> C -> A and D -> C -> B -> D are both 10% mispriced and share tokens B and C
+        let pool = |address, token0, token1, reserve1| {
+            PoolVariant::V2(v2_pool(
+                addr(address),
+                addr(token0),
+                addr(token1),
+                1000 * E18,
+                reserve1 * E18,
+            ))
+        };
+        let manager = graph_with([
+            pool(10, 1, 2, 1000),
+            pool(11, 2, 3, 1000),
+            pool(12, 3, 1, 1100),
+            pool(13, 2, 4, 1000),
+            pool(14, 4, 3, 1100),
+        ]);
+        let (a, d) = (manager.node_map[&addr(1)], manager.node_map[&addr(4)]);
+        let cycles = find_all_negative_cycles(&manager.graph, &[a, d]);
+
+        let pools: Vec<Vec<u8>> = cycles
+            .iter()
+            .map(|cycle| {
+                cycle
+                    .iter()
+                    .map(|&e| manager.graph[e].pool.address()[0])
+                    .collect()
+            })
+            .collect();
+        assert!(pools.contains(&vec![10, 11, 12]));
+        assert!(pools.contains(&vec![14, 11, 13]));
+
+        let mut canonical: Vec<_> = cycles
+            .iter()
+            .map(|cycle| canonicalize_cycle(cycle, &manager.graph))
+            .collect();
+        canonical.sort();
+        canonical.dedup();
+        assert_eq!(canonical.len(), cycles.len());
+        for cycle in &cycles {
+            assert!(cycle_cost(&manager.graph, cycle) < 0.0);
+            let start = manager.graph.edge_endpoints(cycle[0]).unwrap().0;
+            assert!(start == a || start == d);
+        }
+    }
+
+    /// The edges of A -> B -> C -> A (in that order) and of its reverse A -> C -> B -> A
+    fn both_directions(manager: &GraphManager) -> ([EdgeIndex; 3], [EdgeIndex; 3]) {
+        let node = |byte| manager.node_map[&addr(byte)];
+        let edge = |from, to| manager.graph.find_edge(node(from), node(to)).unwrap();
+        (
+            [edge(1, 2), edge(2, 3), edge(3, 1)],
+            [edge(1, 3), edge(3, 2), edge(2, 1)],
+        )
+    }
+
+    #[test]
+    fn rotations_canonicalize_identically() {
+        let manager = triangle(1100);
+        let ([ab, bc, ca], _) = both_directions(&manager);
+
+        let canonical = canonicalize_cycle(&[ab, bc, ca], &manager.graph);
+        assert_eq!(canonical, [ab, bc, ca]);
+        for rotation in [[bc, ca, ab], [ca, ab, bc]] {
+            assert_eq!(canonicalize_cycle(&rotation, &manager.graph), canonical);
+        }
+    }
+
+    #[test]
+    fn the_reverse_direction_stays_distinct() {
+        let manager = triangle(1100);
+        let (forward, [ac, cb, ba]) = both_directions(&manager);
+        let reverse = canonicalize_cycle(&[cb, ba, ac], &manager.graph);
+        assert_eq!(reverse, [ac, cb, ba]);
+        assert_ne!(reverse, canonicalize_cycle(&forward, &manager.graph));
+    }
+
+    #[test]
+    fn a_repeated_token_picks_the_smallest_rotation() {
+        // A -> B -> A -> C -> A passes A twice
+        let manager = triangle(1100);
+        let ([ab, _, ca], [ac, _, ba]) = both_directions(&manager);
+        // Of the two rotations starting at A, the one leading with the lower edge index
+        assert!(ab < ac);
+        assert_eq!(
+            canonicalize_cycle(&[ac, ca, ab, ba], &manager.graph),
+            [ab, ba, ac, ca]
+        );
+    }
+
+    /// A -> B -> C -> D -> E -> A over five balanced V2 pools, except that E -> A pays 1100,
+    /// plus (given `with_triangle`) A -> F -> G -> A paying 1050 on its last hop
+    fn ring(with_triangle: bool) -> GraphManager {
+        let balanced = |pool, token0, token1| {
+            PoolVariant::V2(v2_pool(
+                addr(pool),
+                addr(token0),
+                addr(token1),
+                1000 * E18,
+                1000 * E18,
+            ))
+        };
+        let mut pools = vec![
+            balanced(20, 1, 2),
+            balanced(21, 2, 3),
+            balanced(22, 3, 4),
+            balanced(23, 4, 5),
+            PoolVariant::V2(v2_pool(addr(24), addr(5), addr(1), 1000 * E18, 1100 * E18)),
+        ];
+        if with_triangle {
+            pools.extend([
+                balanced(25, 1, 6),
+                balanced(26, 6, 7),
+                PoolVariant::V2(v2_pool(addr(27), addr(7), addr(1), 1000 * E18, 1050 * E18)),
+            ]);
+        }
+        graph_with(pools)
+    }
+
+    #[test]
+    fn cycles_longer_than_max_hops_are_never_returned() {
+        let manager = ring(false);
+        let a = manager.node_map[&addr(1)];
+
+        assert_eq!(find_negative_cycle(&manager.graph, a).unwrap().len(), 5);
+        for max_hops in 0..5 {
+            assert_eq!(
+                find_negative_cycle_within(&manager.graph, a, max_hops),
+                None
+            );
+        }
+        let cycle = find_negative_cycle_within(&manager.graph, a, 5).unwrap();
+        assert_eq!(cycle.len(), 5);
+        assert!(cycle_cost(&manager.graph, &cycle) < 0.0);
+    }
+
+    #[test]
+    fn shorter_cycles_are_still_found_under_the_cap() {
+        let manager = ring(true);
+        let a = manager.node_map[&addr(1)];
+
+        for max_hops in 3..=6 {
+            let cycle = find_negative_cycle_within(&manager.graph, a, max_hops).unwrap();
+            assert!(cycle.len() <= max_hops);
+            assert!(cycle_cost(&manager.graph, &cycle) < 0.0);
+            assert_eq!(manager.graph.edge_endpoints(cycle[0]).unwrap().0, a);
+        }
+        let within_four = find_negative_cycle_within(&manager.graph, a, 4).unwrap();
+        let pools: Vec<Address> = within_four
+            .iter()
+            .map(|&e| manager.graph[e].pool.address())
+            .collect();
+        assert_eq!(pools, [addr(25), addr(26), addr(27)]);
+    }
+
+    /// A -> B -> C -> A over fee-free V2 pools whose rates multiply to `1 + 1e-12`
+    fn break_even_triangle() -> GraphManager {
+        let fee_free = |pool, token0, token1, reserve0, reserve1| {
+            let mut pool = v2_pool(addr(pool), addr(token0), addr(token1), reserve0, reserve1);
+            pool.fee_bps = 0;
+            pool.refresh_weights();
+            PoolVariant::V2(pool)
+        };
+        graph_with([
+            fee_free(10, 1, 2, 1000 * E18, 2000 * E18),
+            fee_free(11, 2, 3, 1000 * E18, 3000 * E18),
+            fee_free(12, 3, 1, 6000 * E18, 1000 * E18 + 1_000_000_000),
+        ])
+    }
+
+    #[test]
+    fn break_even_cycles_are_not_reported() {
+        let manager = break_even_triangle();
+        let a = manager.node_map[&addr(1)];
+        let none = HashSet::new();
+
+        assert_eq!(find_negative_cycle(&manager.graph, a), None);
+        assert_eq!(find_negative_cycle_within(&manager.graph, a, 3), None);
+        // Without the threshold the 1e-12 "profit" counts as a cycle, bounded or not
+        for max_hops in [None, Some(3)] {
+            let cycle = find_negative_cycle_excluding(&manager.graph, a, &none, max_hops, 0.0);
+            assert_eq!(cycle.map(|cycle| cycle.len()), Some(3));
+            let cycle = find_negative_cycle_excluding(&manager.graph, a, &none, max_hops, 1e-13);
+            assert!(cycle.is_some());
+        }
+
+        let mut manager = manager;
+        assert_eq!(manager.find_negative_cycle(a), None);
+        manager.min_profit_threshold = 0.0;
+        assert!(manager.find_negative_cycle(a).is_some());
+    }
+
+    #[test]
+    fn a_cycle_under_the_threshold_does_not_hide_one_sharing_its_edge() {
+        // Against a 3% threshold, A -> B -> A gains ~0.4% and A -> B -> C -> D -> A ~3.7%,
+        // both buying B in pool 10
+        let pool = |address, token0, token1, reserve1| {
+            PoolVariant::V2(v2_pool(
+                addr(address),
+                addr(token0),
+                addr(token1),
+                1000 * E18,
+                reserve1 * E18,
+            ))
+        };
+        let manager = graph_with([
+            pool(10, 1, 2, 1000),
+            pool(11, 2, 1, 1010),
+            pool(12, 2, 3, 1000),
+            pool(13, 3, 4, 1000),
+            pool(14, 4, 1, 1050),
+        ]);
+        let a = manager.node_map[&addr(1)];
+
+        let cycle =
+            find_negative_cycle_excluding(&manager.graph, a, &HashSet::new(), None, 0.03).unwrap();
+        let pools: Vec<Address> = cycle
+            .iter()
+            .map(|&e| manager.graph[e].pool.address())
+            .collect();
+        assert_eq!(pools, [addr(10), addr(12), addr(13), addr(14)]);
+        assert!(cycle_cost(&manager.graph, &cycle) < -0.03);
+    }
+
+    #[test]
+    fn a_shallow_cycle_does_not_hide_a_real_one() {
+        let mut manager = break_even_triangle();
+        // A -> D -> A pays 10% through two more pools
+        for pool in [
+            v2_pool(addr(13), addr(1), addr(4), 1000 * E18, 1000 * E18),
+            v2_pool(addr(14), addr(1), addr(4), 1000 * E18, 1100 * E18),
+        ] {
+            manager.add_or_get_token(token(addr(4)));
+            manager.add_pool(PoolVariant::V2(pool)).unwrap();
+        }
+        let a = manager.node_map[&addr(1)];
+        for cycle in [
+            find_negative_cycle(&manager.graph, a).unwrap(),
+            find_negative_cycle_within(&manager.graph, a, 3).unwrap(),
+        ] {
+            assert_eq!(cycle.len(), 2);
+            assert!(cycle_cost(&manager.graph, &cycle) < -0.05);
+        }
+    }
+}
@@ -0,0 +1,505 @@
+use alloy_primitives::ruint::UintTryFrom;
+use alloy_primitives::{U256, U512};
+use anyhow::{Result, anyhow, ensure};
+
+/// Fees are expressed in hundredths of a bip (3000 = 0.3%)
+pub const FEE_DENOMINATOR: u32 = 1_000_000;
+
+const Q96_RESOLUTION: usize = 96;
+
+// --- FullMath ---
+
+/// floor(a * b / denominator) with a full 512-bit intermediate, like Uniswap's FullMath.mulDiv
+pub fn mul_div(a: U256, b: U256, denominator: U256) -> Result<U256> {
+    ensure!(denominator != U256::ZERO, "mul_div by zero");
+    let product: U512 = a.widening_mul(b);
+    let result = product / U512::from(denominator);
+    U256::uint_try_from(result).map_err(|_| anyhow!("mul_div overflow"))
+}
+
+/// ceil(a * b / denominator) with a full 512-bit intermediate
+pub fn mul_div_rounding_up(a: U256, b: U256, denominator: U256) -> Result<U256> {
+    let result = mul_div(a, b, denominator)?;
+    let product: U512 = a.widening_mul(b);
+    if product % U512::from(denominator) == U512::ZERO {
+        return Ok(result);
+    }
+    result
+        .checked_add(U256::from(1))
+        .ok_or_else(|| anyhow!("mul_div_rounding_up overflow"))
+}
+
+/// ceil(a / b)
+fn div_rounding_up(a: U256, b: U256) -> Result<U256> {
+    ensure!(b != U256::ZERO, "division by zero");
+    Ok(a.div_ceil(b))
+}
+
+// --- SqrtPriceMath ---
+
+/// Next sqrt price after adding/removing `amount` of token0, rounded up
+pub fn get_next_sqrt_price_from_amount0_rounding_up(
+    sqrt_price_x96: U256,
+    liquidity: u128,
+    amount: U256,
+    add: bool,
+) -> Result<U256> {
+    if amount == U256::ZERO {
+        return Ok(sqrt_price_x96);
+    }
+    let numerator1 = U256::from(liquidity) << Q96_RESOLUTION;
+
+    if add {
+        // Fast path when amount * sqrtP doesn't overflow
+        if let Some(product) = amount.checked_mul(sqrt_price_x96)
+            && let Some(denominator) = numerator1.checked_add(product)
+        {
+            return mul_div_rounding_up(numerator1, sqrt_price_x96, denominator);
+        }
+        div_rounding_up(numerator1, (numerator1 / sqrt_price_x96) + amount)
+    } else {
+        let product = amount
+            .checked_mul(sqrt_price_x96)
+            .ok_or_else(|| anyhow!("amount0 overflow"))?;
+        ensure!(numerator1 > product, "insufficient liquidity for amount0");
+        mul_div_rounding_up(numerator1, sqrt_price_x96, numerator1 - product)
+    }
+}
+
+/// Next sqrt price after adding/removing `amount` of token1, rounded down
+pub fn get_next_sqrt_price_from_amount1_rounding_down(
+    sqrt_price_x96: U256,
+    liquidity: u128,
+    amount: U256,
+    add: bool,
+) -> Result<U256> {
+    let liquidity = U256::from(liquidity);
+    let fits_u160 = amount < (U256::from(1) << 160);
+
+    if add {
+        let quotient = if fits_u160 {
+            (amount << Q96_RESOLUTION) / liquidity
+        } else {
+            mul_div(amount, U256::from(1) << Q96_RESOLUTION, liquidity)?
+        };
+        sqrt_price_x96
+            .checked_add(quotient)
+            .ok_or_else(|| anyhow!("sqrt price overflow"))
+    } else {
+        let quotient = if fits_u160 {
+            div_rounding_up(amount << Q96_RESOLUTION, liquidity)?
+        } else {
+            mul_div_rounding_up(amount, U256::from(1) << Q96_RESOLUTION, liquidity)?
+        };
+        ensure!(
+            sqrt_price_x96 > quotient,
+            "insufficient liquidity for amount1"
+        );
+        Ok(sqrt_price_x96 - quotient)
+    }
+}
+
+/// Next sqrt price given an input amount of token0 (zero_for_one) or token1
+pub fn get_next_sqrt_price_from_input(
+    sqrt_price_x96: U256,
+    liquidity: u128,
+    amount_in: U256,
+    zero_for_one: bool,
+) -> Result<U256> {
+    ensure!(sqrt_price_x96 != U256::ZERO, "sqrt price is zero");
+    ensure!(liquidity != 0, "liquidity is zero");
+
+    if zero_for_one {
+        get_next_sqrt_price_from_amount0_rounding_up(sqrt_price_x96, liquidity, amount_in, true)
+    } else {
+        get_next_sqrt_price_from_amount1_rounding_down(sqrt_price_x96, liquidity, amount_in, true)
+    }
+}
+
+/// Next sqrt price given an output amount of token1 (zero_for_one) or token0
+pub fn get_next_sqrt_price_from_output(
+    sqrt_price_x96: U256,
+    liquidity: u128,
+    amount_out: U256,
+    zero_for_one: bool,
+) -> Result<U256> {
+    ensure!(sqrt_price_x96 != U256::ZERO, "sqrt price is zero");
+    ensure!(liquidity != 0, "liquidity is zero");
+
+    if zero_for_one {
+        get_next_sqrt_price_from_amount1_rounding_down(sqrt_price_x96, liquidity, amount_out, false)
+    } else {
+        get_next_sqrt_price_from_amount0_rounding_up(sqrt_price_x96, liquidity, amount_out, false)
+    }
+}
+
+/// Amount of token0 between two sqrt prices: L * (sqrtB - sqrtA) / (sqrtA * sqrtB)
+pub fn get_amount0_delta(
+    mut sqrt_a: U256,
+    mut sqrt_b: U256,
+    liquidity: u128,
+    round_up: bool,
+) -> Result<U256> {
+    if sqrt_a > sqrt_b {
+        std::mem::swap(&mut sqrt_a, &mut sqrt_b);
+    }
+    ensure!(sqrt_a != U256::ZERO, "sqrt price is zero");
+
+    let numerator1 = U256::from(liquidity) << Q96_RESOLUTION;
+    let numerator2 = sqrt_b - sqrt_a;
+
+    if round_up {
+        div_rounding_up(mul_div_rounding_up(numerator1, numerator2, sqrt_b)?, sqrt_a)
+    } else {
+        Ok(mul_div(numerator1, numerator2, sqrt_b)? / sqrt_a)
+    }
+}
+
+/// Amount of token1 between two sqrt prices: L * (sqrtB - sqrtA)
+pub fn get_amount1_delta(
+    mut sqrt_a: U256,
+    mut sqrt_b: U256,
+    liquidity: u128,
+    round_up: bool,
+) -> Result<U256> {
+    if sqrt_a > sqrt_b {
+        std::mem::swap(&mut sqrt_a, &mut sqrt_b);
+    }
+    let q96 = U256::from(1) << Q96_RESOLUTION;
+
+    if round_up {
+        mul_div_rounding_up(U256::from(liquidity), sqrt_b - sqrt_a, q96)
+    } else {
+        mul_div(U256::from(liquidity), sqrt_b - sqrt_a, q96)
+    }
+}
+
+// --- SwapMath ---
+
+/// The result of swapping within a single initialized-tick range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwapStep {
+    pub sqrt_price_next_x96: U256,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub fee_amount: U256,
+}
+
+/// Computes an exact-input swap step towards `sqrt_price_target_x96`, like SwapMath.computeSwapStep
+pub fn compute_swap_step(
+    sqrt_price_current_x96: U256,
+    sqrt_price_target_x96: U256,
+    liquidity: u128,
+    amount_remaining: U256,
+    fee_pips: u32,
+) -> Result<SwapStep> {
+    ensure!(fee_pips < FEE_DENOMINATOR, "fee {fee_pips} out of range");
+
+    let zero_for_one = sqrt_price_current_x96 >= sqrt_price_target_x96;
+    let fee_complement = U256::from(FEE_DENOMINATOR - fee_pips);
+    let denominator = U256::from(FEE_DENOMINATOR);
+
+    let amount_remaining_less_fee = mul_div(amount_remaining, fee_complement, denominator)?;
+
+    // Input needed to reach the target price
+    let amount_in_to_target = if zero_for_one {
+        get_amount0_delta(
+            sqrt_price_target_x96,
+            sqrt_price_current_x96,
+            liquidity,
+            true,
+        )?
+    } else {
+        get_amount1_delta(
+            sqrt_price_current_x96,
+            sqrt_price_target_x96,
+            liquidity,
+            true,
+        )?
+    };
+
+    let sqrt_price_next_x96 = if amount_remaining_less_fee >= amount_in_to_target {
+        sqrt_price_target_x96
+    } else {
+        get_next_sqrt_price_from_input(
+            sqrt_price_current_x96,
+            liquidity,
+            amount_remaining_less_fee,
+            zero_for_one,
+        )?
+    };
+
+    let reached_target = sqrt_price_next_x96 == sqrt_price_target_x96;
+
+    let (amount_in, amount_out) = if zero_for_one {
+        let amount_in = if reached_target {
+            amount_in_to_target
+        } else {
+            get_amount0_delta(sqrt_price_next_x96, sqrt_price_current_x96, liquidity, true)?
+        };
+        let amount_out = get_amount1_delta(
+            sqrt_price_next_x96,
+            sqrt_price_current_x96,
+            liquidity,
+            false,
+        )?;
+        (amount_in, amount_out)
+    } else {
+        let amount_in = if reached_target {
+            amount_in_to_target
+        } else {
+            get_amount1_delta(sqrt_price_current_x96, sqrt_price_next_x96, liquidity, true)?
+        };
+        let amount_out = get_amount0_delta(
+            sqrt_price_current_x96,
+            sqrt_price_next_x96,
+            liquidity,
+            false,
+        )?;
+        (amount_in, amount_out)
+    };
+
+    // If we stopped short of the target, the whole remainder was consumed and the rest is fee
+    let fee_amount = if !reached_target {
+        amount_remaining - amount_in
+    } else {
+        mul_div_rounding_up(amount_in, U256::from(fee_pips), fee_complement)?
+    };
+
+    Ok(SwapStep {
+        sqrt_price_next_x96,
+        amount_in,
+        amount_out,
+        fee_amount,
+    })
+}
+
+/// Computes an exact-output swap step towards `sqrt_price_target_x96`: the exactOut branch of
+/// SwapMath.computeSwapStep. `amount_remaining` is the output still wanted.
+pub fn compute_swap_step_exact_output(
+    sqrt_price_current_x96: U256,
+    sqrt_price_target_x96: U256,
+    liquidity: u128,
+    amount_remaining: U256,
+    fee_pips: u32,
+) -> Result<SwapStep> {
+    ensure!(fee_pips < FEE_DENOMINATOR, "fee {fee_pips} out of range");
+
+    let zero_for_one = sqrt_price_current_x96 >= sqrt_price_target_x96;
+
+    // Output available before reaching the target price
+    let amount_out_to_target = if zero_for_one {
+        get_amount1_delta(
+            sqrt_price_target_x96,
+            sqrt_price_current_x96,
+            liquidity,
+            false,
+        )?
+    } else {
+        get_amount0_delta(
+            sqrt_price_current_x96,
+            sqrt_price_target_x96,
+            liquidity,
+            false,
+        )?
+    };
+
+    let sqrt_price_next_x96 = if amount_remaining >= amount_out_to_target {
+        sqrt_price_target_x96
+    } else {
+        get_next_sqrt_price_from_output(
+            sqrt_price_current_x96,
+            liquidity,
+            amount_remaining,
+            zero_for_one,
+        )?
+    };
+
+    let reached_target = sqrt_price_next_x96 == sqrt_price_target_x96;
+
+    let (amount_in, amount_out) = if zero_for_one {
+        let amount_in =
+            get_amount0_delta(sqrt_price_next_x96, sqrt_price_current_x96, liquidity, true)?;
+        let amount_out = if reached_target {
+            amount_out_to_target
+        } else {
+            get_amount1_delta(
+                sqrt_price_next_x96,
+                sqrt_price_current_x96,
+                liquidity,
+                false,
+            )?
+        };
+        (amount_in, amount_out)
+    } else {
+        let amount_in =
+            get_amount1_delta(sqrt_price_current_x96, sqrt_price_next_x96, liquidity, true)?;
+        let amount_out = if reached_target {
+            amount_out_to_target
+        } else {
+            get_amount0_delta(
+                sqrt_price_current_x96,
+                sqrt_price_next_x96,
+                liquidity,
+                false,
+            )?
+        };
+        (amount_in, amount_out)
+    };
+
+    // Never hand out more than was asked for
+    let amount_out = amount_out.min(amount_remaining);
+    let fee_amount = mul_div_rounding_up(
+        amount_in,
+        U256::from(fee_pips),
+        U256::from(FEE_DENOMINATOR - fee_pips),
+    )?;
+
+    Ok(SwapStep {
+        sqrt_price_next_x96,
+        amount_in,
+        amount_out,
+        fee_amount,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Vectors from Uniswap v3-core's SwapMath.spec.ts
+    const PRICE_1_1: U256 = U256::from_limbs([0, 1 << 32, 0, 0]);
+
+    fn price(sqrt_price_x96: &str) -> U256 {
+        sqrt_price_x96.parse().unwrap()
+    }
+
+    #[test]
+    fn exact_in_capped_at_price_target() {
+        // encodePriceSqrt(101, 100), one for zero
+        let target = price("79623317895830914510639640423");
+        let step = compute_swap_step(
+            PRICE_1_1,
+            target,
+            2 * 10u128.pow(18),
+            U256::from(10u128.pow(18)),
+            600,
+        )
+        .unwrap();
+        assert_eq!(step.sqrt_price_next_x96, target);
+        assert_eq!(step.amount_in, U256::from(9975124224178055u128));
+        assert_eq!(step.fee_amount, U256::from(5988667735148u128));
+        assert_eq!(step.amount_out, U256::from(9925619580021728u128));
+    }
+
+    #[test]
+    fn exact_in_fully_spent() {
+        // encodePriceSqrt(1000, 100), one for zero
+        let target = price("250541448375047931186413801569");
+        let step = compute_swap_step(
+            PRICE_1_1,
+            target,
+            2 * 10u128.pow(18),
+            U256::from(10u128.pow(18)),
+            600,
+        )
+        .unwrap();
+        assert!(step.sqrt_price_next_x96 < target);
+        assert_eq!(step.amount_in, U256::from(999400000000000000u128));
+        assert_eq!(step.fee_amount, U256::from(600000000000000u128));
+        assert_eq!(step.amount_out, U256::from(666399946655997866u128));
+    }
+
+    #[test]
+    fn entire_input_taken_as_fee() {
+        let current = U256::from(2413);
+        let step = compute_swap_step(
+            current,
+            price("79887613182836312"),
+            1985041575832132834610021537970,
+            U256::from(10),
+            1872,
+        )
+        .unwrap();
+        assert_eq!(step.sqrt_price_next_x96, current);
+        assert_eq!(step.amount_in, U256::ZERO);
+        assert_eq!(step.fee_amount, U256::from(10));
+        assert_eq!(step.amount_out, U256::ZERO);
+    }
+
+    #[test]
+    fn rejects_fee_of_one_hundred_percent() {
+        assert!(
+            compute_swap_step(PRICE_1_1, U256::from(1), 1, U256::from(1), FEE_DENOMINATOR).is_err()
+        );
+    }
+
+    // Vectors from Uniswap v3-core's FullMath.spec.ts
+    const Q128: U256 = U256::from_limbs([0, 0, 1, 0]);
+
+    fn q128(numerator: u64, denominator: u64) -> U256 {
+        Q128 * U256::from(numerator) / U256::from(denominator)
+    }
+
+    #[test]
+    fn mul_div_matches_full_math() {
+        for mul_div in [mul_div, mul_div_rounding_up] {
+            assert!(mul_div(Q128, U256::from(5), U256::ZERO).is_err());
+            assert!(mul_div(Q128, Q128, U256::ZERO).is_err());
+            assert!(mul_div(Q128, Q128, U256::from(1)).is_err());
+            assert!(mul_div(U256::MAX, U256::MAX, U256::MAX - U256::from(1)).is_err());
+            assert_eq!(mul_div(U256::MAX, U256::MAX, U256::MAX).unwrap(), U256::MAX);
+        }
+
+        // Without phantom overflow, then with a 512-bit product
+        assert_eq!(
+            mul_div(Q128, q128(50, 100), q128(150, 100)).unwrap(),
+            Q128 / U256::from(3)
+        );
+        assert_eq!(
+            mul_div(Q128, q128(35, 1), q128(8, 1)).unwrap(),
+            q128(4375, 1000)
+        );
+        assert_eq!(
+            mul_div(Q128, q128(1000, 1), q128(3000, 1)).unwrap(),
+            Q128 / U256::from(3)
+        );
+    }
+
+    #[test]
+    fn mul_div_rounding_up_matches_full_math() {
+        assert_eq!(
+            mul_div_rounding_up(Q128, q128(50, 100), q128(150, 100)).unwrap(),
+            Q128 / U256::from(3) + U256::from(1)
+        );
+        // Exact quotients aren't rounded
+        assert_eq!(
+            mul_div_rounding_up(Q128, q128(35, 1), q128(8, 1)).unwrap(),
+            q128(4375, 1000)
+        );
+        assert_eq!(
+            mul_div_rounding_up(Q128, q128(1000, 1), q128(3000, 1)).unwrap(),
+            Q128 / U256::from(3) + U256::from(1)
+        );
+
+        // The floor fits in 256 bits but rounding it up doesn't
+        let overflowing = [
+            (
+                "535006138814359",
+                "432862656469423142931042426214547535783388063929571229938474969",
+                "2",
+            ),
+            (
+                "115792089237316195423570985008687907853269984659341747863450311749907997002549",
+                "115792089237316195423570985008687907853269984659341747863450311749907997002550",
+                "115792089237316195423570985008687907853269984653042931687443039491902864365164",
+            ),
+        ];
+        for (a, b, denominator) in overflowing {
+            let parse = |n: &str| n.parse::<U256>().unwrap();
+            let (a, b, denominator) = (parse(a), parse(b), parse(denominator));
+            assert_eq!(mul_div(a, b, denominator).unwrap(), U256::MAX);
+            assert!(mul_div_rounding_up(a, b, denominator).is_err());
+        }
+    }
+}
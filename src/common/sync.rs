@@ -0,0 +1,172 @@
+use crate::common::graph::GraphManager;
+use alloy_primitives::Log;
+use anyhow::Result;
+use futures::{Stream, StreamExt};
+use petgraph::graph::EdgeIndex;
+use std::collections::HashSet;
+
+/// Routes on-chain logs to the pools in a `GraphManager` and keeps the edge weights fresh.
+///
+/// Each pool is stored once per trading direction (one clone per edge), so every log is applied
+/// to all edges carrying the emitting pool, as found through the manager's pool registry.
+/// Weights are refreshed once per block rather than once per log.
+pub struct PoolSyncer {
+    pub manager: GraphManager,
+
+    /// Last block whose logs were applied
+    pub last_block: Option<u64>,
+}
+
+impl PoolSyncer {
+    pub fn new(mut manager: GraphManager) -> Self {
+        // Edges may have been added to the graph directly, bypassing the registry
+        manager.reindex_pools();
+        Self {
+            manager,
+            last_block: None,
+        }
+    }
+
+    /// Applies all logs of `block` in order, then refreshes the weights of every touched edge.
+    ///
+    /// Returns the edges whose weights changed (see `GraphManager::apply_logs`).
+    ///
+    /// Logs from untracked pools are ignored. A log that fails to apply doesn't stop the
+    /// batch; the first such error is returned once the rest of the block has been applied.
+    pub fn apply_block(&mut self, block: u64, logs: &[Log]) -> Result<HashSet<EdgeIndex>> {
+        let result = self.manager.apply_logs(block, logs);
+        self.last_block = Some(block);
+        result
+    }
+
+    /// Consumes a stream of `(block number, log)` pairs, as delivered by a log subscription,
+    /// applying them one block at a time. Logs are expected in block order; a block is flushed
+    /// as soon as a log from a different block arrives, and the last one when the stream ends.
+    pub async fn run<S>(&mut self, mut logs: S) -> Result<()>
+    where
+        S: Stream<Item = (u64, Log)> + Unpin,
+    {
+        let mut pending: Option<(u64, Vec<Log>)> = None;
+
+        while let Some((block, log)) = logs.next().await {
+            match &mut pending {
+                Some((current, batch)) if *current == block => batch.push(log),
+                _ => {
+                    if let Some((current, batch)) = pending.replace((block, vec![log])) {
+                        self.apply_block(current, &batch)?;
+                    }
+                }
+            }
+        }
+
+        if let Some((current, batch)) = pending {
+            self.apply_block(current, &batch)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::pool::{PRICE_X96_ONE, PoolVariant};
+    use crate::common::test_util::{
+        addr, graph_with, modify_liquidity_log, sync_log, v2_pool, v4_pool, v4_swap_log,
+    };
+    use alloy_primitives::U256;
+    use futures::executor::block_on;
+    use futures::stream;
+
+    const E18: u128 = 1_000_000_000_000_000_000;
+
+    #[test]
+    fn logs_from_a_stream_update_the_weights_per_block() {
+        let v4 = v4_pool(addr(2), addr(3), 3000, PRICE_X96_ONE, E18);
+        let pool_id = v4.key.id();
+        let mut syncer = PoolSyncer::new(graph_with([
+            PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), 1000 * E18, 1000 * E18)),
+            PoolVariant::V4(v4),
+        ]));
+        let weight = |syncer: &PoolSyncer, from: u8, to: u8| {
+            let manager = &syncer.manager;
+            let edge = manager
+                .best_edge(manager.node_map[&addr(from)], manager.node_map[&addr(to)])
+                .unwrap();
+            manager.graph[edge].weight()
+        };
+        let v2_before = weight(&syncer, 1, 2);
+        let v4_before = weight(&syncer, 2, 3);
+
+        let logs = vec![
+            (7, sync_log(addr(10), 1000 * E18, 1500 * E18)),
+            (7, modify_liquidity_log(pool_id, -600, 600, E18 as i64)),
+            (
+                8,
+                v4_swap_log(
+                    pool_id,
+                    PRICE_X96_ONE * U256::from(2),
+                    2 * E18,
+                    13_863,
+                    3000,
+                ),
+            ),
+            // Untracked pools are ignored
+            (8, sync_log(addr(99), 1, 1)),
+        ];
+        block_on(syncer.run(stream::iter(logs))).unwrap();
+
+        assert_eq!(syncer.last_block, Some(8));
+        assert!((weight(&syncer, 1, 2) - (v2_before - 1.5f64.ln())).abs() < 1e-9);
+        // Both V4 edges follow the price from 1 up to 4
+        assert!((weight(&syncer, 2, 3) - (v4_before - 4f64.ln())).abs() < 1e-6);
+        assert!((weight(&syncer, 3, 2) + weight(&syncer, 2, 3) + 2.0 * 0.997f64.ln()).abs() < 1e-6);
+        let v4_log = v4_swap_log(pool_id, PRICE_X96_ONE, 0, 0, 0);
+        for &edge in syncer.manager.edges_for_log(&v4_log) {
+            let PoolVariant::V4(pool) = &syncer.manager.graph[edge].pool else {
+                panic!("not the V4 pool");
+            };
+            assert_eq!(pool.tick_bitmap.len(), 2);
+            assert_eq!(pool.last_updated_block, 8);
+        }
+    }
+
+    #[test]
+    fn only_the_updated_pools_edges_are_flagged() {
+        let mut syncer = PoolSyncer::new(graph_with([
+            PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), 1000 * E18, 1000 * E18)),
+            PoolVariant::V2(v2_pool(addr(11), addr(2), addr(3), 1000 * E18, 1000 * E18)),
+            PoolVariant::V2(v2_pool(addr(12), addr(1), addr(3), 1000 * E18, 1000 * E18)),
+        ]));
+        let weights = |syncer: &PoolSyncer| {
+            let graph = &syncer.manager.graph;
+            graph
+                .edge_indices()
+                .map(|e| graph[e].weight())
+                .collect::<Vec<_>>()
+        };
+        let before = weights(&syncer);
+
+        let log = sync_log(addr(11), 1000 * E18, 2000 * E18);
+        let changed = syncer.apply_block(5, std::slice::from_ref(&log)).unwrap();
+        let expected: HashSet<EdgeIndex> =
+            syncer.manager.edges_for_log(&log).iter().copied().collect();
+        assert_eq!(expected.len(), 2);
+        assert_eq!(changed, expected);
+
+        let after = weights(&syncer);
+        for (i, edge) in syncer.manager.graph.edge_indices().enumerate() {
+            assert_eq!(after[i] != before[i], changed.contains(&edge), "edge {i}");
+        }
+
+        // Through update_from_log too, and nothing for an untracked pool
+        let log = sync_log(addr(10), 1000 * E18, 3000 * E18);
+        let changed = syncer.manager.update_from_log(&log).unwrap();
+        assert_eq!(changed, syncer.manager.edges_for_log(&log));
+        assert!(
+            syncer
+                .apply_block(6, &[sync_log(addr(99), 1, 1)])
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use crate::common::calldata::V4_POOL_MANAGER;
+use crate::common::events::{
+    V2_SYNC_EVENT, V3_BURN_EVENT, V3_MINT_EVENT, V3_SWAP_EVENT, V4_MODIFY_LIQUIDITY_EVENT,
+    V4_SWAP_EVENT,
+};
+use crate::common::graph::GraphManager;
+use crate::common::pool::{
+    LiquidityPool, PoolKey, PoolVariant, UniswapV2Pool, UniswapV3Pool, UniswapV4Pool, WeightCache,
+};
+use crate::common::tickmath::get_tick_at_sqrt_ratio;
+use crate::common::token::Token;
+use alloy_primitives::{Address, B256, Bytes, I256, Log, U256};
+use std::collections::BTreeMap;
+
+/// A distinct, recognizable address for fixtures: `0x0101..01`, `0x0202..02`, ...
+pub fn addr(byte: u8) -> Address {
+    Address::repeat_byte(byte)
+}
+
+/// A V2 pool at the Uniswap fee (30 bps) with the given reserves
+pub fn v2_pool(
+    address: Address,
+    token0: Address,
+    token1: Address,
+    reserve0: u128,
+    reserve1: u128,
+) -> UniswapV2Pool {
+    UniswapV2Pool {
+        address,
+        token0,
+        token1,
+        reserve0,
+        reserve1,
+        ..UniswapV2Pool::with_fee(30)
+    }
+}
+
+/// A V3 pool at the 0.3% tier (tick spacing 60) priced at `sqrt_price_x96`, with `liquidity`
+/// active and no initialized ticks
+pub fn v3_pool(
+    address: Address,
+    token0: Address,
+    token1: Address,
+    sqrt_price_x96: U256,
+    liquidity: u128,
+) -> UniswapV3Pool {
+    UniswapV3Pool {
+        address,
+        token0,
+        token1,
+        fee: 3000,
+        liquidity,
+        sqrt_price_x96,
+        tick: get_tick_at_sqrt_ratio(sqrt_price_x96).expect("price in range"),
+        tick_spacing: 60,
+        tick_bitmap: BTreeMap::new(),
+        dynamic_fee: None,
+        last_updated_block: 0,
+        weight_cache: WeightCache::default(),
+    }
+}
+
+/// A hookless V4 pool on tick spacing 60 charging `fee` (or carrying the dynamic-fee flag),
+/// priced at `sqrt_price_x96` with `liquidity` active and no initialized ticks
+pub fn v4_pool(
+    currency0: Address,
+    currency1: Address,
+    fee: u32,
+    sqrt_price_x96: U256,
+    liquidity: u128,
+) -> UniswapV4Pool {
+    UniswapV4Pool {
+        key: PoolKey {
+            currency0,
+            currency1,
+            fee,
+            tick_spacing: 60,
+            hooks: Address::ZERO,
+        },
+        liquidity,
+        sqrt_price_x96,
+        tick: get_tick_at_sqrt_ratio(sqrt_price_x96).expect("price in range"),
+        tick_bitmap: BTreeMap::new(),
+        hook_address: Address::ZERO,
+        dynamic_fee: None,
+        last_updated_block: 0,
+        weight_cache: WeightCache::default(),
+    }
+}
+
+/// A log emitted by `address`, with `words` ABI-encoded as its data
+pub fn log(address: Address, topics: Vec<B256>, words: &[U256]) -> Log {
+    let data: Vec<u8> = words
+        .iter()
+        .flat_map(|word| word.to_be_bytes::<32>())
+        .collect();
+    Log::new_unchecked(address, topics, Bytes::from(data))
+}
+
+/// A V2 pair's Sync log
+pub fn sync_log(address: Address, reserve0: u128, reserve1: u128) -> Log {
+    log(
+        address,
+        vec![V2_SYNC_EVENT],
+        &[U256::from(reserve0), U256::from(reserve1)],
+    )
+}
+
+/// A signed integer (int24, int256, ...) as a sign-extended ABI word
+pub fn int_word(value: i64) -> U256 {
+    I256::try_from(value).expect("fits").into_raw()
+}
+
+/// A V3 pool's Swap log leaving the pool at the given state (amounts left at 0)
+pub fn v3_swap_log(address: Address, sqrt_price_x96: U256, liquidity: u128, tick: i32) -> Log {
+    log(
+        address,
+        vec![V3_SWAP_EVENT, B256::ZERO, B256::ZERO],
+        &[
+            U256::ZERO,
+            U256::ZERO,
+            sqrt_price_x96,
+            U256::from(liquidity),
+            int_word(tick.into()),
+        ],
+    )
+}
+
+/// A V3 pool's Mint (or, with `mint` unset, Burn) log for `amount` of liquidity over
+/// [tick_lower, tick_upper)
+pub fn position_log(
+    address: Address,
+    tick_lower: i32,
+    tick_upper: i32,
+    amount: u128,
+    mint: bool,
+) -> Log {
+    let topics = vec![
+        if mint { V3_MINT_EVENT } else { V3_BURN_EVENT },
+        B256::ZERO,
+        int_word(tick_lower.into()).into(),
+        int_word(tick_upper.into()).into(),
+    ];
+    let amount = U256::from(amount);
+    // Mint also carries the sender ahead of the amount
+    let data: &[U256] = if mint {
+        &[U256::ZERO, amount, U256::ZERO, U256::ZERO]
+    } else {
+        &[amount, U256::ZERO, U256::ZERO]
+    };
+    log(address, topics, data)
+}
+
+/// An 18-decimal token named after its address byte, e.g. `T1` for `addr(1)`
+pub fn token(address: Address) -> Token {
+    Token::new(address, format!("T{}", address[0]), 18)
+}
+
+/// A graph holding `pools` (both directions each) over 18-decimal tokens (see `token`)
+pub fn graph_with(pools: impl IntoIterator<Item = PoolVariant>) -> GraphManager {
+    let mut manager = GraphManager::new();
+    for pool in pools {
+        let (token0, token1) = pool.tokens();
+        manager.add_or_get_token(token(token0));
+        manager.add_or_get_token(token(token1));
+        manager.add_pool(pool).expect("pool is added");
+    }
+    manager
+}
+
+/// The V4 PoolManager's Swap log leaving pool `pool_id` at the given state, having charged
+/// `fee` (amounts left at 0)
+pub fn v4_swap_log(
+    pool_id: B256,
+    sqrt_price_x96: U256,
+    liquidity: u128,
+    tick: i32,
+    fee: u32,
+) -> Log {
+    log(
+        V4_POOL_MANAGER,
+        vec![V4_SWAP_EVENT, pool_id, B256::ZERO],
+        &[
+            U256::ZERO,
+            U256::ZERO,
+            sqrt_price_x96,
+            U256::from(liquidity),
+            int_word(tick.into()),
+            U256::from(fee),
+        ],
+    )
+}
+
+/// The V4 PoolManager's ModifyLiquidity log adding `delta` of liquidity (removing, if negative)
+/// to pool `pool_id` over [tick_lower, tick_upper)
+pub fn modify_liquidity_log(pool_id: B256, tick_lower: i32, tick_upper: i32, delta: i64) -> Log {
+    log(
+        V4_POOL_MANAGER,
+        vec![V4_MODIFY_LIQUIDITY_EVENT, pool_id, B256::ZERO],
+        &[
+            int_word(tick_lower.into()),
+            int_word(tick_upper.into()),
+            int_word(delta),
+            U256::ZERO,
+        ],
+    )
+}
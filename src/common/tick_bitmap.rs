@@ -0,0 +1,291 @@
+use crate::common::tickmath::{MAX_TICK, MIN_TICK};
+use alloy_primitives::U256;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Compact tick storage using Uniswap's TickBitmap layout: one bit per tick spacing, packed in
+/// 256-bit words keyed by `compressed >> 8` (`compressed = tick / tick_spacing`, rounded down),
+/// with the net liquidity of the initialized ticks kept alongside.
+///
+/// Holds the same information as a pool's `tick_bitmap` (net liquidity by tick), and answers
+/// `next_initialized_tick` like the pool does, by scanning words instead of walking a tree.
+/// Ticks that aren't a multiple of the spacing can't be initialized and are dropped.
+///
+/// Serialized as the spacing and the net liquidity by tick; the words are rebuilt on load.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "TickList", into = "TickList")]
+pub struct TickBitmap {
+    // At least 1; the word math divides by it
+    tick_spacing: i32,
+    words: HashMap<i16, U256>,
+    liquidity_net: HashMap<i32, i128>,
+}
+
+impl Default for TickBitmap {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl TickBitmap {
+    /// An empty bitmap; spacings below 1 are taken as 1
+    pub fn new(tick_spacing: i32) -> Self {
+        Self {
+            tick_spacing: tick_spacing.max(1),
+            words: HashMap::new(),
+            liquidity_net: HashMap::new(),
+        }
+    }
+
+    pub fn tick_spacing(&self) -> i32 {
+        self.tick_spacing
+    }
+
+    /// Builds the bitmap from a pool's `tick_bitmap` (TickIndex -> NetLiquidityChange)
+    pub fn from_ticks(ticks: &BTreeMap<i32, i128>, tick_spacing: i32) -> Self {
+        let mut bitmap = Self::new(tick_spacing);
+        for (&tick, &net) in ticks {
+            bitmap
+                .update(tick, net)
+                .expect("each tick is added to zero once");
+        }
+        bitmap
+    }
+
+    /// Back to the `tick_bitmap` representation
+    pub fn to_ticks(&self) -> BTreeMap<i32, i128> {
+        self.liquidity_net.iter().map(|(&t, &n)| (t, n)).collect()
+    }
+
+    /// Number of initialized ticks
+    pub fn len(&self) -> usize {
+        self.liquidity_net.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.liquidity_net.is_empty()
+    }
+
+    /// Adds `delta` to the net liquidity at `tick`, flipping its bit when it becomes (or stops
+    /// being) initialized. Ignored for ticks off the spacing; None (and unchanged) if the net
+    /// liquidity would overflow.
+    pub fn update(&mut self, tick: i32, delta: i128) -> Option<()> {
+        if delta == 0 || tick.rem_euclid(self.tick_spacing) != 0 {
+            return Some(());
+        }
+        let current = self.net_liquidity_at(tick);
+        let updated = current.checked_add(delta)?;
+        let was_initialized = current != 0;
+        let is_initialized = updated != 0;
+        if is_initialized {
+            self.liquidity_net.insert(tick, updated);
+        } else {
+            self.liquidity_net.remove(&tick);
+        }
+
+        if was_initialized != is_initialized {
+            let (word, bit) = self.position(tick.div_euclid(self.tick_spacing));
+            let entry = self.words.entry(word).or_default();
+            *entry ^= U256::from(1) << bit;
+            if *entry == U256::ZERO {
+                self.words.remove(&word);
+            }
+        }
+        Some(())
+    }
+
+    /// Net liquidity added when crossing `tick` left to right (0 if uninitialized)
+    pub fn net_liquidity_at(&self, tick: i32) -> i128 {
+        self.liquidity_net.get(&tick).copied().unwrap_or(0)
+    }
+
+    /// Same contract as `UniswapV3Pool::next_initialized_tick`: searching down (zero_for_one)
+    /// includes `tick` itself, searching up starts strictly above it, and there's no one-word
+    /// limit. Scans at most the words between `tick` and the end of the tick range.
+    pub fn next_initialized_tick(&self, tick: i32, zero_for_one: bool) -> Option<i32> {
+        let spacing = self.tick_spacing;
+        let (min_word, _) = self.position(MIN_TICK.div_euclid(spacing));
+        let (max_word, _) = self.position(MAX_TICK.div_euclid(spacing));
+
+        if zero_for_one {
+            let (mut word, bit) = self.position(tick.div_euclid(spacing));
+            // Bits at or below `bit`
+            let mut mask = U256::MAX >> (255 - bit);
+            while word >= min_word {
+                let bits = self.word(word) & mask;
+                if bits != U256::ZERO {
+                    let msb = 255 - bits.leading_zeros() as i32;
+                    return Some(self.tick_at(word, msb));
+                }
+                word = word.checked_sub(1)?;
+                mask = U256::MAX;
+            }
+        } else {
+            let (mut word, bit) = self.position(tick.div_euclid(spacing) + 1);
+            // Bits at or above `bit`
+            let mut mask = U256::MAX << bit;
+            while word <= max_word {
+                let bits = self.word(word) & mask;
+                if bits != U256::ZERO {
+                    let lsb = bits.trailing_zeros() as i32;
+                    return Some(self.tick_at(word, lsb));
+                }
+                word = word.checked_add(1)?;
+                mask = U256::MAX;
+            }
+        }
+        None
+    }
+
+    fn word(&self, word: i16) -> U256 {
+        self.words.get(&word).copied().unwrap_or_default()
+    }
+
+    /// (word, bit) of a compressed tick, as in TickBitmap.position
+    fn position(&self, compressed: i32) -> (i16, usize) {
+        ((compressed >> 8) as i16, (compressed & 0xff) as usize)
+    }
+
+    fn tick_at(&self, word: i16, bit: i32) -> i32 {
+        ((word as i32) * 256 + bit) * self.tick_spacing
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct TickList {
+    tick_spacing: i32,
+    ticks: BTreeMap<i32, i128>,
+}
+
+impl From<TickList> for TickBitmap {
+    fn from(list: TickList) -> Self {
+        Self::from_ticks(&list.ticks, list.tick_spacing)
+    }
+}
+
+impl From<TickBitmap> for TickList {
+    fn from(bitmap: TickBitmap) -> Self {
+        Self {
+            tick_spacing: bitmap.tick_spacing,
+            ticks: bitmap.to_ticks(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::pool::{PRICE_X96_ONE, UniswapV3Pool};
+    use crate::common::test_util::{addr, v3_pool};
+    use proptest::prelude::*;
+
+    /// The pools' own walk over their `tick_bitmap`
+    fn btree_next_initialized_tick(
+        ticks: &BTreeMap<i32, i128>,
+        tick_spacing: i32,
+        tick: i32,
+        zero_for_one: bool,
+    ) -> Option<i32> {
+        let pool = UniswapV3Pool {
+            tick_spacing,
+            tick_bitmap: ticks.clone(),
+            ..v3_pool(addr(1), addr(2), addr(3), PRICE_X96_ONE, 0)
+        };
+        pool.next_initialized_tick(tick, zero_for_one)
+    }
+
+    proptest! {
+        #[test]
+        fn next_tick_matches_the_btree_walk(
+            tick_spacing in prop::sample::select(vec![1, 10, 60, 200]),
+            ticks in prop::collection::btree_map(MIN_TICK..=MAX_TICK, 1i128..1000, 0..40),
+            queries in prop::collection::vec(MIN_TICK..=MAX_TICK, 20),
+        ) {
+            // Half of the ticks snapped onto the spacing, the rest mostly off it
+            let ticks: BTreeMap<i32, i128> = ticks
+                .into_iter()
+                .enumerate()
+                .map(|(i, (t, n))| {
+                    let snap = if i % 2 == 0 { t.rem_euclid(tick_spacing) } else { 0 };
+                    (t - snap, n)
+                })
+                .collect();
+            let bitmap = TickBitmap::from_ticks(&ticks, tick_spacing);
+            let queries = queries.into_iter().chain(ticks.keys().flat_map(|&t| [t - 1, t, t + 1]));
+            for tick in queries {
+                for zero_for_one in [true, false] {
+                    prop_assert_eq!(
+                        bitmap.next_initialized_tick(tick, zero_for_one),
+                        btree_next_initialized_tick(&ticks, tick_spacing, tick, zero_for_one),
+                        "tick {} zero_for_one {}", tick, zero_for_one
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn the_range_ends_are_found_from_anywhere() {
+        let ticks = BTreeMap::from([(MIN_TICK, 5), (MAX_TICK, -5)]);
+        let bitmap = TickBitmap::from_ticks(&ticks, 1);
+        assert_eq!(
+            bitmap.next_initialized_tick(MAX_TICK - 1, true),
+            Some(MIN_TICK)
+        );
+        assert_eq!(
+            bitmap.next_initialized_tick(MIN_TICK, false),
+            Some(MAX_TICK)
+        );
+        assert_eq!(bitmap.next_initialized_tick(MIN_TICK - 1, true), None);
+        assert_eq!(bitmap.next_initialized_tick(MAX_TICK, false), None);
+    }
+
+    #[test]
+    fn updates_flip_bits_and_reject_overflow() {
+        let mut bitmap = TickBitmap::new(60);
+        bitmap.update(120, 7).unwrap();
+        bitmap.update(-60, i128::MAX).unwrap();
+        // Off the spacing: ignored
+        bitmap.update(30, 1).unwrap();
+        assert_eq!(
+            bitmap.to_ticks(),
+            BTreeMap::from([(-60, i128::MAX), (120, 7)])
+        );
+
+        assert!(bitmap.update(-60, 1).is_none());
+        assert_eq!(bitmap.net_liquidity_at(-60), i128::MAX);
+
+        // Netting to zero uninitializes the tick
+        bitmap.update(-60, -i128::MAX).unwrap();
+        assert_eq!(bitmap.next_initialized_tick(100, true), None);
+        assert_eq!(
+            bitmap,
+            TickBitmap::from_ticks(&BTreeMap::from([(120, 7)]), 60)
+        );
+    }
+
+    #[test]
+    fn spacings_below_one_are_taken_as_one() {
+        assert_eq!(TickBitmap::default(), TickBitmap::new(1));
+        for tick_spacing in [0, -60] {
+            let mut bitmap = TickBitmap::new(tick_spacing);
+            assert_eq!(bitmap.tick_spacing(), 1);
+            bitmap.update(7, 3).unwrap();
+            assert_eq!(bitmap.next_initialized_tick(100, true), Some(7));
+        }
+        let json = r#"{"tick_spacing":0,"ticks":{"5":1}}"#;
+        let bitmap: TickBitmap = serde_json::from_str(json).unwrap();
+        assert_eq!(bitmap.next_initialized_tick(0, false), Some(5));
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let bitmap = TickBitmap::from_ticks(&BTreeMap::from([(-600, 9), (0, -4), (600, -5)]), 60);
+        let json = serde_json::to_string(&bitmap).unwrap();
+        assert_eq!(
+            json,
+            r#"{"tick_spacing":60,"ticks":{"-600":9,"0":-4,"600":-5}}"#
+        );
+        assert_eq!(serde_json::from_str::<TickBitmap>(&json).unwrap(), bitmap);
+    }
+}
@@ -0,0 +1,200 @@
+use crate::common::math::u256_to_f64;
+use alloy_primitives::{I256, U256, uint};
+use anyhow::{Result, ensure};
+
+/// The minimum tick that may be passed to `get_sqrt_ratio_at_tick` (log base 1.0001 of 2**-128)
+pub const MIN_TICK: i32 = -887272;
+/// The maximum tick that may be passed to `get_sqrt_ratio_at_tick` (log base 1.0001 of 2**128)
+pub const MAX_TICK: i32 = -MIN_TICK;
+
+/// `get_sqrt_ratio_at_tick(MIN_TICK)`
+pub const MIN_SQRT_RATIO: U256 = uint!(4295128739_U256);
+/// `get_sqrt_ratio_at_tick(MAX_TICK)`
+pub const MAX_SQRT_RATIO: U256 = uint!(1461446703485210103287273052203988822378723970342_U256);
+
+/// Calculates sqrt(1.0001^tick) * 2^96, bit-for-bit identical to Uniswap's TickMath
+pub fn get_sqrt_ratio_at_tick(tick: i32) -> Result<U256> {
+    let abs_tick = tick.unsigned_abs();
+    ensure!(abs_tick <= MAX_TICK as u32, "tick {tick} out of range");
+
+    // Each set bit of |tick| multiplies in a precomputed 1/sqrt(1.0001^(2^i)) as Q128.128
+    let mut ratio = if abs_tick & 0x1 != 0 {
+        uint!(0xfffcb933bd6fad37aa2d162d1a594001_U256)
+    } else {
+        uint!(0x100000000000000000000000000000000_U256)
+    };
+
+    const FACTORS: [(u32, U256); 19] = [
+        (0x2, uint!(0xfff97272373d413259a46990580e213a_U256)),
+        (0x4, uint!(0xfff2e50f5f656932ef12357cf3c7fdcc_U256)),
+        (0x8, uint!(0xffe5caca7e10e4e61c3624eaa0941cd0_U256)),
+        (0x10, uint!(0xffcb9843d60f6159c9db58835c926644_U256)),
+        (0x20, uint!(0xff973b41fa98c081472e6896dfb254c0_U256)),
+        (0x40, uint!(0xff2ea16466c96a3843ec78b326b52861_U256)),
+        (0x80, uint!(0xfe5dee046a99a2a811c461f1969c3053_U256)),
+        (0x100, uint!(0xfcbe86c7900a88aedcffc83b479aa3a4_U256)),
+        (0x200, uint!(0xf987a7253ac413176f2b074cf7815e54_U256)),
+        (0x400, uint!(0xf3392b0822b70005940c7a398e4b70f3_U256)),
+        (0x800, uint!(0xe7159475a2c29b7443b29c7fa6e889d9_U256)),
+        (0x1000, uint!(0xd097f3bdfd2022b8845ad8f792aa5825_U256)),
+        (0x2000, uint!(0xa9f746462d870fdf8a65dc1f90e061e5_U256)),
+        (0x4000, uint!(0x70d869a156d2a1b890bb3df62baf32f7_U256)),
+        (0x8000, uint!(0x31be135f97d08fd981231505542fcfa6_U256)),
+        (0x10000, uint!(0x9aa508b5b7a84e1c677de54f3e99bc9_U256)),
+        (0x20000, uint!(0x5d6af8dedb81196699c329225ee604_U256)),
+        (0x40000, uint!(0x2216e584f5fa1ea926041bedfe98_U256)),
+        (0x80000, uint!(0x48a170391f7dc42444e8fa2_U256)),
+    ];
+
+    for (mask, factor) in FACTORS {
+        if abs_tick & mask != 0 {
+            ratio = (ratio * factor) >> 128;
+        }
+    }
+
+    // Positive ticks use the reciprocal
+    if tick > 0 {
+        ratio = U256::MAX / ratio;
+    }
+
+    // Q128.128 -> Q64.96, rounding up so that get_tick_at_sqrt_ratio stays consistent
+    let round_up = if ratio % (U256::from(1) << 32) == U256::ZERO {
+        U256::ZERO
+    } else {
+        U256::from(1)
+    };
+    Ok((ratio >> 32) + round_up)
+}
+
+/// Calculates the greatest tick whose sqrt ratio is <= `sqrt_price_x96`, bit-for-bit identical
+/// to Uniswap's TickMath.getTickAtSqrtRatio
+pub fn get_tick_at_sqrt_ratio(sqrt_price_x96: U256) -> Result<i32> {
+    ensure!(
+        sqrt_price_x96 >= MIN_SQRT_RATIO && sqrt_price_x96 < MAX_SQRT_RATIO,
+        "sqrt price {sqrt_price_x96} out of range"
+    );
+
+    // log2 of the Q128.128 ratio, as a Q64.64 fixed-point number
+    let ratio: U256 = sqrt_price_x96 << 32;
+    let msb = ratio.bit_len() - 1;
+    let mut r = if msb >= 128 {
+        ratio >> (msb - 127)
+    } else {
+        ratio << (127 - msb)
+    };
+
+    let mut log_2: I256 = I256::try_from(msb as i64 - 128).expect("fits") << 64;
+    for shift in (50..=63).rev() {
+        r = (r * r) >> 127;
+        let f: U256 = r >> 128;
+        log_2 |= I256::from_raw(f << shift);
+        r >>= f.to::<usize>();
+    }
+
+    // Change of base to sqrt(1.0001), then bracket the tick within the approximation error
+    let log_sqrt10001: I256 = log_2 * I256::from_raw(uint!(255738958999603826347141_U256));
+    let tick_low = (log_sqrt10001
+        - I256::from_raw(uint!(3402992956809132418596140100660247210_U256)))
+    .asr(128)
+    .as_i32();
+    let tick_high = (log_sqrt10001
+        + I256::from_raw(uint!(291339464771989622907027621153398088495_U256)))
+    .asr(128)
+    .as_i32();
+
+    if tick_low == tick_high || get_sqrt_ratio_at_tick(tick_high)? > sqrt_price_x96 {
+        Ok(tick_low)
+    } else {
+        Ok(tick_high)
+    }
+}
+
+/// `get_sqrt_ratio_at_tick`, with `tick` clamped to [`MIN_TICK`, `MAX_TICK`]
+pub fn tick_to_sqrt_price(tick: i32) -> U256 {
+    get_sqrt_ratio_at_tick(tick.clamp(MIN_TICK, MAX_TICK)).expect("tick is in range")
+}
+
+/// `get_tick_at_sqrt_ratio`, with the price clamped to [`MIN_SQRT_RATIO`, `MAX_SQRT_RATIO`)
+pub fn sqrt_price_to_tick(sqrt_price_x96: U256) -> i32 {
+    let clamped = sqrt_price_x96.clamp(MIN_SQRT_RATIO, MAX_SQRT_RATIO - U256::from(1));
+    get_tick_at_sqrt_ratio(clamped).expect("sqrt price is in range")
+}
+
+/// Human price of token0 in units of token1: (sqrtP / 2^96)^2 scaled by 10^(dec0 - dec1)
+pub fn sqrt_price_to_price(sqrt_price_x96: U256, dec0: u8, dec1: u8) -> f64 {
+    let sqrt_price = u256_to_f64(sqrt_price_x96) / 2f64.powi(96);
+    sqrt_price * sqrt_price * 10f64.powi(dec0 as i32 - dec1 as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sqrt_ratio_at_the_bounds() {
+        assert_eq!(get_sqrt_ratio_at_tick(0).unwrap(), U256::from(1) << 96);
+        assert_eq!(get_sqrt_ratio_at_tick(MIN_TICK).unwrap(), MIN_SQRT_RATIO);
+        assert_eq!(get_sqrt_ratio_at_tick(MAX_TICK).unwrap(), MAX_SQRT_RATIO);
+        assert!(get_sqrt_ratio_at_tick(MIN_TICK - 1).is_err());
+        assert!(get_sqrt_ratio_at_tick(MAX_TICK + 1).is_err());
+    }
+
+    #[test]
+    fn tick_at_sqrt_ratio_inverts_sqrt_ratio_at_tick() {
+        assert_eq!(get_tick_at_sqrt_ratio(MIN_SQRT_RATIO).unwrap(), MIN_TICK);
+        for tick in [-500_000, -60, -1, 0, 1, 60, 500_000, MAX_TICK - 1] {
+            let sqrt_price = get_sqrt_ratio_at_tick(tick).unwrap();
+            assert_eq!(get_tick_at_sqrt_ratio(sqrt_price).unwrap(), tick);
+            // Just below a tick's price is still the tick below
+            let below = get_tick_at_sqrt_ratio(sqrt_price - U256::from(1)).unwrap();
+            assert_eq!(below, tick - 1);
+        }
+    }
+
+    #[test]
+    fn sqrt_ratio_matches_the_uniswap_reference() {
+        // From Uniswap v3-core's TickMath test snapshot
+        let vectors: [(i32, U256); 16] = [
+            (-1, uint!(79224201403219477170569942574_U256)),
+            (1, uint!(79232123823359799118286999568_U256)),
+            (50, uint!(79426470787362580746886972461_U256)),
+            (100, uint!(79625275426524748796330556128_U256)),
+            (250, uint!(80224679980005306637834519095_U256)),
+            (500, uint!(81233731461783161732293370115_U256)),
+            (1000, uint!(83290069058676223003182343270_U256)),
+            (2500, uint!(89776708723587163891445672585_U256)),
+            (3000, uint!(92049301871182272007977902845_U256)),
+            (4000, uint!(96768528593268422080558758223_U256)),
+            (5000, uint!(101729702841318637793976746270_U256)),
+            (50000, uint!(965075977353221155028623082916_U256)),
+            (150000, uint!(143194173941309278083010301478497_U256)),
+            (250000, uint!(21246587762933397357449903968194344_U256)),
+            (500000, uint!(5697689776495288729098254600827762987878_U256)),
+            (
+                738203,
+                uint!(847134979253254120489401328389043031315994541_U256),
+            ),
+        ];
+        for (tick, sqrt_price) in vectors {
+            assert_eq!(tick_to_sqrt_price(tick), sqrt_price, "tick {tick}");
+            assert_eq!(sqrt_price_to_tick(sqrt_price), tick, "tick {tick}");
+        }
+    }
+
+    #[test]
+    fn conversions_clamp_out_of_range_inputs() {
+        assert_eq!(tick_to_sqrt_price(MIN_TICK - 1), MIN_SQRT_RATIO);
+        assert_eq!(tick_to_sqrt_price(MAX_TICK + 1), MAX_SQRT_RATIO);
+        assert_eq!(sqrt_price_to_tick(U256::ZERO), MIN_TICK);
+        assert_eq!(sqrt_price_to_tick(U256::MAX), MAX_TICK - 1);
+    }
+
+    #[test]
+    fn price_is_scaled_by_the_decimals() {
+        let two = U256::from(2) << 96;
+        assert_eq!(sqrt_price_to_price(two, 18, 18), 4.0);
+        // e.g. a WETH (18) / USDC (6) pool prices raw units 1e12 apart from human ones
+        assert!((sqrt_price_to_price(two, 18, 6) / 4e12 - 1.0).abs() < 1e-15);
+        assert!((sqrt_price_to_price(two, 6, 18) / 4e-12 - 1.0).abs() < 1e-15);
+    }
+}
@@ -1,8 +1,10 @@
+use crate::common::chains::ChainConstants;
 use alloy_primitives::{Address, address};
+use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::hash::{Hash, Hasher};
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Token {
     /// The contract address (The unique ID)
     pub address: Address,
@@ -16,6 +18,11 @@ pub struct Token {
     /// Optimization: Cached flags to avoid repeated address checks
     pub is_weth: bool,
     pub is_native: bool, // For V4 which might support native ETH
+
+    /// Balances change without transfers (stETH, AMPL, ...), so pool reserves can't be trusted
+    /// between blocks; see `GraphManager::exclude_rebasing`
+    #[serde(default)]
+    pub is_rebasing: bool,
 }
 
 /// For an ultra-optimized version, use a fixed-size byte array for the symbol
@@ -26,25 +33,159 @@ pub struct TinyToken {
     pub decimals: u8,
 }
 
-impl Token {
-    /// Create a new token and auto-detect common flags
-    pub fn new(address: Address, symbol: String, decimals: u8) -> Self {
-        let is_weth = address == address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2"); // Mainnet WETH
-        let is_native = address == Address::ZERO; // V4 Convention for Native ETH
+/// WETH on Ethereum mainnet, the wrapped native token `Token::new` detects by default
+pub const MAINNET_WETH: Address = address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+/// WMATIC (now WPOL), the wrapped native token on Polygon PoS
+pub const POLYGON_WMATIC: Address = address!("0d500B1d8E8eF31E21C99d1Db9A6444d3ADf1270");
+
+/// Builds a `Token`, detecting `is_weth` against a chain-specific wrapped-native address
+/// (mainnet WETH unless set) and `is_native` against `Address::ZERO`. Either flag can also be
+/// forced explicitly.
+#[derive(Debug, Clone)]
+pub struct TokenBuilder {
+    address: Address,
+    symbol: String,
+    decimals: u8,
+    wrapped_native: Address,
+    is_weth: Option<bool>,
+    is_native: Option<bool>,
+    is_rebasing: bool,
+}
 
+impl TokenBuilder {
+    pub fn new(address: Address, symbol: String, decimals: u8) -> Self {
         Self {
             address,
             symbol,
             decimals,
+            wrapped_native: MAINNET_WETH,
+            is_weth: None,
+            is_native: None,
+            is_rebasing: false,
+        }
+    }
+
+    /// The chain's canonical wrapped native token (e.g. `POLYGON_WMATIC`), used to detect
+    /// `is_weth`
+    pub fn wrapped_native(mut self, wrapped_native: Address) -> Self {
+        self.wrapped_native = wrapped_native;
+        self
+    }
+
+    /// Detects `is_weth` against the wrapped native token of `chain_id` (see `ChainConstants`).
+    /// Unknown chains keep the current address.
+    pub fn chain(mut self, chain_id: u64) -> Self {
+        if let Some(chain) = ChainConstants::for_chain(chain_id) {
+            self.wrapped_native = chain.wrapped_native;
+        }
+        self
+    }
+
+    /// Overrides the detected `is_weth` flag
+    pub fn is_weth(mut self, is_weth: bool) -> Self {
+        self.is_weth = Some(is_weth);
+        self
+    }
+
+    /// Overrides the detected `is_native` flag
+    pub fn is_native(mut self, is_native: bool) -> Self {
+        self.is_native = Some(is_native);
+        self
+    }
+
+    /// Flags the token as rebasing (not detected, since nothing on-chain marks it)
+    pub fn is_rebasing(mut self, is_rebasing: bool) -> Self {
+        self.is_rebasing = is_rebasing;
+        self
+    }
+
+    pub fn build(self) -> Token {
+        let is_weth = self.is_weth.unwrap_or(self.address == self.wrapped_native);
+        // V4 Convention for Native ETH
+        let is_native = self.is_native.unwrap_or(self.address == Address::ZERO);
+
+        Token {
+            address: self.address,
+            symbol: self.symbol,
+            decimals: self.decimals,
             is_weth,
             is_native,
+            is_rebasing: self.is_rebasing,
         }
     }
+}
+
+impl Token {
+    /// Create a new token and auto-detect common flags (against mainnet WETH; use
+    /// `new_on_chain` or `TokenBuilder` for other chains)
+    pub fn new(address: Address, symbol: String, decimals: u8) -> Self {
+        TokenBuilder::new(address, symbol, decimals).build()
+    }
+
+    /// `Token::new` with the flags detected for `chain_id` (see `TokenBuilder::chain`)
+    pub fn new_on_chain(chain_id: u64, address: Address, symbol: String, decimals: u8) -> Self {
+        TokenBuilder::new(address, symbol, decimals)
+            .chain(chain_id)
+            .build()
+    }
 
     /// Helper to create a dummy token for testing or unitialized nodes
     pub fn empty() -> Self {
         Self::new(Address::ZERO, "UNK".to_string(), 18)
     }
+
+    /// Whether both refer to the same on-chain token (same address). This is what `==` checks.
+    pub fn same_identity(&self, other: &Self) -> bool {
+        self.address == other.address
+    }
+
+    /// Field-by-field equality: the address plus symbol, decimals and flags. For test
+    /// assertions and diffing graph snapshots, where a stale cached symbol should show up.
+    pub fn structurally_eq(&self, other: &Self) -> bool {
+        self.address == other.address
+            && self.symbol == other.symbol
+            && self.decimals == other.decimals
+            && self.is_weth == other.is_weth
+            && self.is_native == other.is_native
+            && self.is_rebasing == other.is_rebasing
+    }
+}
+
+impl TinyToken {
+    /// The packed symbol as a string, without the trailing NUL padding
+    pub fn symbol_str(&self) -> &str {
+        let len = self
+            .symbol
+            .iter()
+            .rposition(|&b| b != 0)
+            .map_or(0, |i| i + 1);
+        let bytes = &self.symbol[..len];
+
+        // The fields are public, so fall back to the longest valid prefix
+        match std::str::from_utf8(bytes) {
+            Ok(symbol) => symbol,
+            Err(e) => std::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or_default(),
+        }
+    }
+}
+
+impl From<&Token> for TinyToken {
+    fn from(token: &Token) -> Self {
+        // Truncate on a char boundary so the packed bytes stay valid UTF-8
+        let mut len = token.symbol.len().min(8);
+        while !token.symbol.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        let mut symbol = [0u8; 8];
+        symbol[..len].copy_from_slice(&token.symbol.as_bytes()[..len]);
+
+        Self {
+            address: token.address,
+            symbol,
+            decimals: token.decimals,
+        }
+    }
 }
 
 // --- Trait Implementations for HashMaps & Graph uniqueness ---
@@ -78,3 +219,89 @@ impl fmt::Display for Token {
         write!(f, "{}", self.symbol)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packed(symbol: &str) -> TinyToken {
+        TinyToken::from(&Token::new(Address::ZERO, symbol.to_string(), 18))
+    }
+
+    #[test]
+    fn short_symbols_are_nul_padded() {
+        let tiny = packed("DAI");
+        assert_eq!(&tiny.symbol, b"DAI\0\0\0\0\0");
+        assert_eq!(tiny.symbol_str(), "DAI");
+    }
+
+    #[test]
+    fn long_symbols_are_truncated_to_eight_bytes() {
+        assert_eq!(packed("MATICWET").symbol_str(), "MATICWET");
+        assert_eq!(packed("MATICWETH").symbol_str(), "MATICWET");
+    }
+
+    #[test]
+    fn truncation_keeps_multibyte_chars_whole() {
+        // 'é' takes bytes 7 and 8, so it is dropped rather than split
+        let tiny = packed("ABCDEFGé");
+        assert_eq!(tiny.symbol_str(), "ABCDEFG");
+        assert_eq!(tiny.symbol[7], 0);
+    }
+
+    #[test]
+    fn wmatic_is_the_wrapped_native_token_on_polygon() {
+        let wmatic = Token::new_on_chain(137, POLYGON_WMATIC, "WMATIC".to_string(), 18);
+        assert!(wmatic.is_weth && !wmatic.is_native);
+        let weth = Token::new_on_chain(137, MAINNET_WETH, "WETH".to_string(), 18);
+        assert!(!weth.is_weth);
+
+        let built = TokenBuilder::new(POLYGON_WMATIC, "WMATIC".to_string(), 18)
+            .wrapped_native(POLYGON_WMATIC)
+            .build();
+        assert!(built.is_weth);
+    }
+
+    #[test]
+    fn new_keeps_detecting_mainnet_weth() {
+        assert!(Token::new(MAINNET_WETH, "WETH".to_string(), 18).is_weth);
+        assert!(!Token::new(POLYGON_WMATIC, "WMATIC".to_string(), 18).is_weth);
+        assert!(Token::new(Address::ZERO, "ETH".to_string(), 18).is_native);
+    }
+
+    #[test]
+    fn explicit_flags_override_detection() {
+        let token = TokenBuilder::new(MAINNET_WETH, "WETH".to_string(), 18)
+            .is_weth(false)
+            .is_native(true)
+            .build();
+        assert!(!token.is_weth && token.is_native);
+
+        // Unknown chains keep the default wrapped-native address
+        let token = TokenBuilder::new(MAINNET_WETH, "WETH".to_string(), 18)
+            .chain(999_999)
+            .build();
+        assert!(token.is_weth);
+    }
+
+    #[test]
+    fn a_stale_symbol_is_equal_but_not_structurally_equal() {
+        let fresh = Token::new(POLYGON_WMATIC, "WPOL".to_string(), 18);
+        let stale = Token::new(POLYGON_WMATIC, "WMATIC".to_string(), 18);
+        assert_eq!(fresh, stale);
+        assert!(fresh.same_identity(&stale));
+        assert!(!fresh.structurally_eq(&stale));
+        assert!(fresh.structurally_eq(&fresh.clone()));
+
+        // Decimals and flags count too
+        let other_decimals = Token::new(POLYGON_WMATIC, "WPOL".to_string(), 6);
+        assert!(!fresh.structurally_eq(&other_decimals));
+        let mut rebasing = fresh.clone();
+        rebasing.is_rebasing = true;
+        assert!(fresh.same_identity(&rebasing) && !fresh.structurally_eq(&rebasing));
+
+        let elsewhere = Token::new(MAINNET_WETH, "WPOL".to_string(), 18);
+        assert_ne!(fresh, elsewhere);
+        assert!(!fresh.same_identity(&elsewhere) && !fresh.structurally_eq(&elsewhere));
+    }
+}
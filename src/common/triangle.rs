@@ -0,0 +1,131 @@
+use crate::common::graph::{ArbGraph, best_edge, best_out_edges, edge_cost};
+use crate::common::spfa::DEFAULT_MIN_PROFIT_THRESHOLD;
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use std::collections::HashMap;
+
+/// Enumerates every profitable `base -> B -> C -> base` cycle, cheapest first.
+///
+/// Each leg uses the cheapest of its parallel pools. A triangle and its reverse
+/// (`base -> C -> B -> base`) count as the same token triple, so only the more profitable
+/// direction is kept. Costs are decimals-normalized, and thresholded, like in the SPFA
+/// search.
+pub fn find_triangles(graph: &ArbGraph, base: NodeIndex) -> Vec<[EdgeIndex; 3]> {
+    if base.index() >= graph.node_count() {
+        return Vec::new();
+    }
+
+    let mut best: HashMap<(NodeIndex, NodeIndex), ([EdgeIndex; 3], f64)> = HashMap::new();
+
+    for (b, first, cost_ab) in best_out_edges(graph, base) {
+        if b == base {
+            continue;
+        }
+        for (c, second, cost_bc) in best_out_edges(graph, b) {
+            if c == base || c == b {
+                continue;
+            }
+            let Some(third) = best_edge(graph, c, base) else {
+                continue;
+            };
+
+            let total = cost_ab + cost_bc + edge_cost(graph, third);
+            if total >= -DEFAULT_MIN_PROFIT_THRESHOLD {
+                continue;
+            }
+
+            let key = (b.min(c), b.max(c));
+            let triangle = [first, second, third];
+            best.entry(key)
+                .and_modify(|current| {
+                    if total < current.1 {
+                        *current = (triangle, total);
+                    }
+                })
+                .or_insert((triangle, total));
+        }
+    }
+
+    let mut triangles: Vec<_> = best.into_values().collect();
+    triangles.sort_by(|a, b| a.1.total_cmp(&b.1));
+    triangles
+        .into_iter()
+        .map(|(triangle, _)| triangle)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::graph::GraphManager;
+    use crate::common::pool::{LiquidityPool, PoolVariant};
+    use crate::common::test_util::{addr, graph_with, v2_pool};
+
+    const E18: u128 = 1_000_000_000_000_000_000;
+
+    fn pool(address: u8, token0: u8, token1: u8, reserve1: u128) -> PoolVariant {
+        PoolVariant::V2(v2_pool(
+            addr(address),
+            addr(token0),
+            addr(token1),
+            1000 * E18,
+            reserve1 * E18,
+        ))
+    }
+
+    fn pools_of(manager: &GraphManager, triangle: &[EdgeIndex; 3]) -> Vec<u8> {
+        triangle
+            .iter()
+            .map(|&e| manager.graph[e].pool.address()[0])
+            .collect()
+    }
+
+    /// A -> B -> C -> A is 10% mispriced; A -> D -> B -> A and every other loop is balanced
+    fn mispriced_triangle() -> GraphManager {
+        graph_with([
+            pool(10, 1, 2, 1000),
+            pool(11, 2, 3, 1000),
+            pool(12, 3, 1, 1100),
+            pool(13, 1, 4, 1000),
+            pool(14, 4, 2, 1000),
+        ])
+    }
+
+    #[test]
+    fn finds_only_the_profitable_triangle() {
+        let manager = mispriced_triangle();
+        let a = manager.node_map[&addr(1)];
+        let triangles = find_triangles(&manager.graph, a);
+
+        assert_eq!(triangles.len(), 1);
+        assert_eq!(pools_of(&manager, &triangles[0]), [10, 11, 12]);
+        let cost: f64 = triangles[0]
+            .iter()
+            .map(|&e| edge_cost(&manager.graph, e))
+            .sum();
+        assert!(cost < 0.0);
+    }
+
+    #[test]
+    fn keeps_the_cheaper_direction_when_both_pay() {
+        // A parallel C/A pool paying only 0.9 A per C makes A -> C -> B -> A profitable too,
+        // and more so (1/0.9 > 1.1): only that direction is reported
+        let mut manager = mispriced_triangle();
+        manager.add_pool(pool(15, 3, 1, 900)).unwrap();
+        let a = manager.node_map[&addr(1)];
+
+        let triangles = find_triangles(&manager.graph, a);
+        assert_eq!(triangles.len(), 1);
+        assert_eq!(pools_of(&manager, &triangles[0]), [15, 11, 10]);
+    }
+
+    #[test]
+    fn balanced_graph_has_no_triangles() {
+        let manager = graph_with([
+            pool(10, 1, 2, 1000),
+            pool(11, 2, 3, 1000),
+            pool(12, 3, 1, 1000),
+        ]);
+        let a = manager.node_map[&addr(1)];
+        assert!(find_triangles(&manager.graph, a).is_empty());
+    }
+}
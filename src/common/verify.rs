@@ -0,0 +1,368 @@
+use crate::common::calldata::BALANCER_VAULT;
+use crate::common::graph::GraphEdge;
+use crate::common::loader::CallProvider;
+use crate::common::pool::{LiquidityPool, PoolVariant};
+use crate::common::solver::simulate_cycle;
+use alloy_primitives::{Address, I256, U256, address, aliases::U24};
+use alloy_sol_types::{SolCall, sol};
+use anyhow::{Context, Result, anyhow, ensure};
+
+/// Uniswap's QuoterV2 (same address on mainnet and Polygon)
+pub const V3_QUOTER_ADDRESS: Address = address!("61fFE014bA17989E743c5F6cB21bF9697530B21e");
+
+/// Uniswap's V4Quoter on Polygon
+pub const V4_QUOTER_ADDRESS: Address = address!("b3d5c3dfc3a7aebff71895a7191796bffc2c81b9");
+
+sol! {
+    interface IUniswapV2Pair {
+        function getReserves() external view returns (
+            uint112 reserve0,
+            uint112 reserve1,
+            uint32 blockTimestampLast
+        );
+    }
+
+    struct QuoteExactInputSingleParams {
+        address tokenIn;
+        address tokenOut;
+        uint256 amountIn;
+        uint24 fee;
+        uint160 sqrtPriceLimitX96;
+    }
+
+    interface IQuoterV2 {
+        function quoteExactInputSingle(QuoteExactInputSingleParams params) external returns (
+            uint256 amountOut,
+            uint160 sqrtPriceX96After,
+            uint32 initializedTicksCrossed,
+            uint256 gasEstimate
+        );
+    }
+
+    struct PoolKey {
+        address currency0;
+        address currency1;
+        uint24 fee;
+        int24 tickSpacing;
+        address hooks;
+    }
+
+    struct QuoteExactSingleParams {
+        PoolKey poolKey;
+        bool zeroForOne;
+        uint128 exactAmount;
+        bytes hookData;
+    }
+
+    interface IV4Quoter {
+        function quoteExactInputSingle(QuoteExactSingleParams params) external returns (
+            uint256 amountOut,
+            uint256 gasEstimate
+        );
+    }
+
+    interface ICurvePool {
+        function get_dy(int128 i, int128 j, uint256 dx) external view returns (uint256);
+    }
+
+    struct BatchSwapStep {
+        bytes32 poolId;
+        uint256 assetInIndex;
+        uint256 assetOutIndex;
+        uint256 amount;
+        bytes userData;
+    }
+
+    struct FundManagement {
+        address sender;
+        bool fromInternalBalance;
+        address recipient;
+        bool toInternalBalance;
+    }
+
+    interface IBalancerVault {
+        function queryBatchSwap(
+            uint8 kind,
+            BatchSwapStep[] swaps,
+            address[] assets,
+            FundManagement funds
+        ) external returns (int256[] assetDeltas);
+    }
+}
+
+/// Dry-runs `cycle` both locally and against the node, returning `(local, onchain)` outputs so
+/// callers can check they agree within their tolerance before sending a transaction.
+///
+/// The on-chain side quotes each hop with the previous hop's on-chain output, so a stale pool
+/// shows up in every hop after it:
+/// - V2: the pair's current `getReserves`, through the local formula (pairs have no quoter)
+/// - V3: QuoterV2 `quoteExactInputSingle` (Uniswap pools only; Algebra has its own quoter)
+/// - V4: V4Quoter `quoteExactInputSingle`
+/// - StableSwap: the pool's `get_dy`
+/// - Weighted: the Vault's `queryBatchSwap` (GIVEN_IN)
+pub async fn verify_route<P: CallProvider>(
+    provider: &P,
+    cycle: &[GraphEdge],
+    amount_in: U256,
+) -> Result<(U256, U256)> {
+    ensure!(!cycle.is_empty(), "empty route");
+    let local = simulate_cycle(cycle, amount_in)?;
+
+    let mut onchain = amount_in;
+    for (i, edge) in cycle.iter().enumerate() {
+        onchain = quote_hop(provider, edge, onchain)
+            .await
+            .with_context(|| format!("on-chain quote of hop {i} failed"))?;
+    }
+    Ok((local, onchain))
+}
+
+/// The node's output for swapping `amount_in` through one edge
+async fn quote_hop<P: CallProvider>(
+    provider: &P,
+    edge: &GraphEdge,
+    amount_in: U256,
+) -> Result<U256> {
+    let (token0, token1) = edge.pool.tokens();
+    let (token_in, token_out) = if edge.zero_for_one {
+        (token0, token1)
+    } else {
+        (token1, token0)
+    };
+
+    match &edge.pool {
+        PoolVariant::V2(pool) => {
+            let data = provider
+                .call(
+                    pool.address,
+                    IUniswapV2Pair::getReservesCall {}.abi_encode().into(),
+                )
+                .await?;
+            let reserves = IUniswapV2Pair::getReservesCall::abi_decode_returns(&data)?;
+            let mut fresh = pool.clone();
+            fresh.reserve0 = reserves.reserve0.to();
+            fresh.reserve1 = reserves.reserve1.to();
+            Ok(fresh.get_amount_out(amount_in, edge.zero_for_one)?)
+        }
+        PoolVariant::V3(pool) => {
+            ensure!(
+                pool.dynamic_fee.is_none(),
+                "no quoter for Algebra pool {}",
+                pool.address
+            );
+            let quote = IQuoterV2::quoteExactInputSingleCall {
+                params: QuoteExactInputSingleParams {
+                    tokenIn: token_in,
+                    tokenOut: token_out,
+                    amountIn: amount_in,
+                    fee: U24::from(pool.fee),
+                    // No limit
+                    sqrtPriceLimitX96: Default::default(),
+                },
+            };
+            let data = provider
+                .call(V3_QUOTER_ADDRESS, quote.abi_encode().into())
+                .await?;
+            Ok(IQuoterV2::quoteExactInputSingleCall::abi_decode_returns(&data)?.amountOut)
+        }
+        PoolVariant::V4(pool) => {
+            let key = &pool.key;
+            let quote = IV4Quoter::quoteExactInputSingleCall {
+                params: QuoteExactSingleParams {
+                    poolKey: PoolKey {
+                        currency0: key.currency0,
+                        currency1: key.currency1,
+                        fee: U24::from(key.fee),
+                        tickSpacing: key.tick_spacing.try_into().map_err(|_| {
+                            anyhow!("tick spacing {} overflows int24", key.tick_spacing)
+                        })?,
+                        hooks: key.hooks,
+                    },
+                    zeroForOne: edge.zero_for_one,
+                    exactAmount: u128::try_from(amount_in)
+                        .map_err(|_| anyhow!("amount {amount_in} overflows uint128"))?,
+                    hookData: Default::default(),
+                },
+            };
+            let data = provider
+                .call(V4_QUOTER_ADDRESS, quote.abi_encode().into())
+                .await?;
+            Ok(IV4Quoter::quoteExactInputSingleCall::abi_decode_returns(&data)?.amountOut)
+        }
+        PoolVariant::Stable(pool) => {
+            let (i, j) = if edge.zero_for_one { (0, 1) } else { (1, 0) };
+            let get_dy = ICurvePool::get_dyCall {
+                i,
+                j,
+                dx: amount_in,
+            };
+            let data = provider
+                .call(pool.address, get_dy.abi_encode().into())
+                .await?;
+            Ok(ICurvePool::get_dyCall::abi_decode_returns(&data)?)
+        }
+        PoolVariant::Weighted(pool) => {
+            let query = IBalancerVault::queryBatchSwapCall {
+                // SwapKind.GIVEN_IN
+                kind: 0,
+                swaps: vec![BatchSwapStep {
+                    poolId: pool.pool_id,
+                    assetInIndex: U256::ZERO,
+                    assetOutIndex: U256::from(1),
+                    amount: amount_in,
+                    userData: Default::default(),
+                }],
+                assets: vec![token_in, token_out],
+                funds: FundManagement {
+                    sender: Address::ZERO,
+                    fromInternalBalance: false,
+                    recipient: Address::ZERO,
+                    toInternalBalance: false,
+                },
+            };
+            let data = provider
+                .call(BALANCER_VAULT, query.abi_encode().into())
+                .await?;
+            let deltas = IBalancerVault::queryBatchSwapCall::abi_decode_returns(&data)?;
+            // The Vault's deltas: positive in, negative out
+            let out = deltas
+                .get(1)
+                .copied()
+                .ok_or_else(|| anyhow!("queryBatchSwap returned {} deltas", deltas.len()))?;
+            ensure!(
+                out <= I256::ZERO,
+                "queryBatchSwap returned a positive output delta"
+            );
+            Ok(out.unsigned_abs())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::pool::PRICE_X96_ONE;
+    use crate::common::test_util::{addr, v2_pool, v3_pool, v4_pool};
+    use alloy_primitives::Bytes;
+    use alloy_sol_types::SolValue;
+    use futures::executor::block_on;
+    use std::cell::RefCell;
+    use std::future::Future;
+
+    const E18: u128 = 1_000_000_000_000_000_000;
+
+    /// Answers each `eth_call` with `respond(to, calldata)`, where `None` reverts, and records
+    /// the calls it saw
+    struct MockNode<F> {
+        respond: F,
+        calls: RefCell<Vec<Address>>,
+    }
+
+    impl<F: Fn(Address, &[u8]) -> Option<Vec<u8>>> MockNode<F> {
+        fn new(respond: F) -> Self {
+            Self {
+                respond,
+                calls: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl<F: Fn(Address, &[u8]) -> Option<Vec<u8>>> CallProvider for MockNode<F> {
+        fn call(&self, to: Address, data: Bytes) -> impl Future<Output = Result<Bytes>> + Send {
+            self.calls.borrow_mut().push(to);
+            let result = (self.respond)(to, &data)
+                .map(Bytes::from)
+                .ok_or_else(|| anyhow!("execution reverted"));
+            std::future::ready(result)
+        }
+    }
+
+    /// V2 (1 -> 2), then V3 (2 -> 3), then V4 (3 -> 1)
+    fn route() -> Vec<GraphEdge> {
+        vec![
+            GraphEdge::new(
+                PoolVariant::V2(v2_pool(addr(10), addr(1), addr(2), 1000 * E18, 1000 * E18)),
+                true,
+            ),
+            GraphEdge::new(
+                PoolVariant::V3(v3_pool(addr(11), addr(2), addr(3), PRICE_X96_ONE, E18)),
+                true,
+            ),
+            GraphEdge::new(
+                PoolVariant::V4(v4_pool(addr(1), addr(3), 500, PRICE_X96_ONE, E18)),
+                false,
+            ),
+        ]
+    }
+
+    /// The V2 pair reports 2000:1000 reserves (twice the local reserve0), QuoterV2 quotes 9000
+    /// and the V4Quoter 8000, each checking it was asked for the previous hop's output
+    fn node(v2_out: U256) -> impl Fn(Address, &[u8]) -> Option<Vec<u8>> {
+        move |to, calldata| match to {
+            to if to == addr(10) => {
+                let reserves = (U256::from(2000 * E18), U256::from(1000 * E18), U256::ZERO);
+                Some(reserves.abi_encode_params())
+            }
+            V3_QUOTER_ADDRESS => {
+                let call = IQuoterV2::quoteExactInputSingleCall::abi_decode(calldata).unwrap();
+                assert_eq!(call.params.amountIn, v2_out);
+                assert_eq!(
+                    (call.params.tokenIn, call.params.tokenOut),
+                    (addr(2), addr(3))
+                );
+                let quote = (U256::from(9000), PRICE_X96_ONE, U256::ZERO, U256::ZERO);
+                Some(quote.abi_encode_params())
+            }
+            V4_QUOTER_ADDRESS => {
+                let call = IV4Quoter::quoteExactInputSingleCall::abi_decode(calldata).unwrap();
+                assert_eq!(call.params.exactAmount, 9000);
+                assert!(!call.params.zeroForOne);
+                Some((U256::from(8000), U256::ZERO).abi_encode_params())
+            }
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn returns_the_local_and_onchain_outputs() {
+        let route = route();
+        let amount_in = U256::from(E18);
+        // The pair's V2 quote from the node's reserves rather than the local ones
+        let fresh = v2_pool(addr(10), addr(1), addr(2), 2000 * E18, 1000 * E18);
+        let v2_out = fresh.get_amount_out(amount_in, true).unwrap();
+
+        let chain = MockNode::new(node(v2_out));
+        let (local, onchain) = block_on(verify_route(&chain, &route, amount_in)).unwrap();
+        assert_eq!(local, simulate_cycle(&route, amount_in).unwrap());
+        assert_eq!(onchain, U256::from(8000));
+        assert_eq!(
+            *chain.calls.borrow(),
+            [addr(10), V3_QUOTER_ADDRESS, V4_QUOTER_ADDRESS]
+        );
+    }
+
+    #[test]
+    fn a_reverting_quote_fails_naming_the_hop() {
+        let route = route();
+        let chain = MockNode::new(|to, calldata: &[u8]| {
+            (to == addr(10)).then(|| node(U256::ZERO)(to, calldata).unwrap())
+        });
+        let err = block_on(verify_route(&chain, &route, U256::from(E18))).unwrap_err();
+        assert_eq!(err.to_string(), "on-chain quote of hop 1 failed");
+    }
+
+    #[test]
+    fn algebra_hops_and_empty_routes_are_rejected() {
+        let chain = MockNode::new(|_: Address, _: &[u8]| None);
+        assert!(block_on(verify_route(&chain, &[], U256::from(1))).is_err());
+
+        let mut route = route();
+        let PoolVariant::V3(pool) = &mut route[1].pool else {
+            unreachable!()
+        };
+        pool.dynamic_fee = Some(100);
+        let err = block_on(verify_route(&chain, &route[1..], U256::from(1))).unwrap_err();
+        assert!(format!("{err:#}").contains("Algebra"), "{err:#}");
+        assert!(chain.calls.borrow().is_empty());
+    }
+}